@@ -5,30 +5,267 @@ use clap::ArgMatches;
 use clap::Command;
 use clap::value_parser;
 use goblin::Object;
+use goblin::elf::Elf;
 use goblin::elf::program_header::pt_to_str;
 use log::{LevelFilter, Log, Metadata, Record, debug, error, info};
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::OnceLock;
 use std::time::Instant;
 
-use crate::semihost::get_semihost_func;
-use crate::trace::format_trace_entry;
+use crate::board::{Memory, PeripheralDescription, Region};
+use crate::semihost::{SemihostOptions, get_semihost_func};
+use crate::trace::{
+    ACCESS_REGIONS, AccessRegion, InstructionTrace, MemoryTraceFilter, TRACE_FORMATS,
+    TRACE_REGISTERS, TraceEvents, TraceFormat, TraceRegisters, TraceRing, TraceTrigger,
+    TraceWindow, TraceWriter, exception_number, format_memory_access,
+};
 
-use std::cmp;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use tabwriter::TabWriter;
 use zmu_cortex_m::DeviceBus;
 use zmu_cortex_m::Processor;
 use zmu_cortex_m::core::fault::FaultTrapMode;
-use zmu_cortex_m::memory::map::MemoryMapConfig;
+use zmu_cortex_m::core::memory_trace::{MemoryAccess, MemoryTraceFunc};
+use zmu_cortex_m::core::trace_event::EventTraceFunc;
+use zmu_cortex_m::memory::region::MemoryRegion;
 
-use zmu_cortex_m::gdb::server::GdbServer;
+use zmu_cortex_m::gdb::server::{DEFAULT_GDB_PORT, GdbListen, GdbServer};
 use zmu_cortex_m::system::simulation::simulate;
 use zmu_cortex_m::system::simulation::simulate_trace;
+use zmu_cortex_m::system::simulation::{SnapshotOptions, SnapshotPoint};
+
+///
+/// Device related options given on the command line
+///
+#[derive(Default)]
+pub struct DeviceOptions {
+    /// Serial port backends as (port, backend) pairs, e.g. ("usart1", "stdio")
+    pub serial: Vec<(String, String)>,
+    /// Peripherals from the board description given with `--device`
+    pub peripherals: Vec<PeripheralDescription>,
+}
+
+///
+/// Snapshot related options given on the command line
+///
+#[derive(Default)]
+struct SnapshotArgs {
+    /// Snapshot to resume from
+    load: Option<PathBuf>,
+    /// Instruction count or symbol at which to save a snapshot
+    save_at: Option<String>,
+    /// File to save the snapshot to
+    save: Option<PathBuf>,
+}
+
+///
+/// Instruction trace options given on the command line
+///
+struct TraceArgs {
+    /// Trace from the start, also under gdb where `monitor trace` switches it
+    enabled: bool,
+    /// Trace from the instruction matching the trigger
+    start: Option<TraceTrigger>,
+    /// Stop tracing before the instruction matching the trigger
+    stop: Option<TraceTrigger>,
+    /// Trace at most this many instructions
+    count: Option<u64>,
+    /// Keep only the trace of the last instructions and write it at the end
+    ring: Option<usize>,
+    /// Registers shown on each line
+    registers: TraceRegisters,
+    /// Interleave processor events with the instructions
+    events: bool,
+    /// Output format of the trace
+    format: TraceFormat,
+    /// File the trace is written to instead of stdout
+    file: Option<PathBuf>,
+}
+
+///
+/// Memory trace options given on the command line
+///
+struct MemoryTraceArgs {
+    /// File the traced data accesses are written to
+    file: File,
+    /// Data accesses to trace
+    filter: MemoryTraceFilter,
+}
+
+type DeviceFactory = fn(&DeviceOptions) -> anyhow::Result<Option<DeviceBus>>;
+
+///
+/// Features of the emulated core that differ between the binaries
+///
+pub struct CoreConfig {
+    /// Bit-band aliases of the SRAM and peripheral regions are implemented
+    pub bit_band: bool,
+}
+
+///
+/// Device factory for targets without a device model
+///
+#[cfg(feature = "generic-device")]
+pub fn generic_device(options: &DeviceOptions) -> anyhow::Result<Option<DeviceBus>> {
+    if let Some((port, _)) = options.serial.first() {
+        anyhow::bail!("serial port '{port}' is not available on this target");
+    }
+    if options.peripherals.is_empty() {
+        return Ok(None);
+    }
+    let registers = crate::board::register_file::RegisterFile::new(&options.peripherals)?;
+    Ok(Some(Box::new(registers)))
+}
+
+fn parse_serial_arg(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((port, backend)) if !port.is_empty() && !backend.is_empty() => {
+            Ok((port.to_lowercase(), backend.to_string()))
+        }
+        _ => Err(format!("expected <PORT>=<BACKEND>, got '{value}'")),
+    }
+}
+
+fn parse_access_region(value: &str) -> Result<AccessRegion, String> {
+    ACCESS_REGIONS
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, region)| *region)
+        .ok_or_else(|| format!("expected flash, ram or peripheral, got '{value}'"))
+}
 
-type DeviceFactory = fn() -> Option<DeviceBus>;
+///
+/// Parse a `<BASE>:<SIZE>` memory region, numbers are decimal or `0x`
+/// prefixed hexadecimal and the size may have a `K` or `M` suffix.
+///
+fn parse_region_arg(value: &str) -> Result<Region, String> {
+    let region = value.split_once(':').and_then(|(base, size)| {
+        let region = Region {
+            base: parse_number(base)?,
+            size: parse_number(size)?,
+        };
+        (region.size > 0 && u64::from(region.base) + u64::from(region.size) <= 1 << 32)
+            .then_some(region)
+    });
+    region.ok_or_else(|| format!("expected <BASE>:<SIZE>, got '{value}'"))
+}
+
+///
+/// Parse a trace start or stop trigger: a decimal instruction count, a `0x`
+/// prefixed address, an address range `<BASE>:<SIZE>`,
+/// `exception:<NAME|NUMBER>` or a symbol name.
+///
+fn parse_trace_trigger(value: &str) -> Result<TraceTrigger, String> {
+    if let Some(exception) = value.strip_prefix("exception:") {
+        return exception
+            .parse()
+            .ok()
+            .or_else(|| exception_number(exception))
+            .map(TraceTrigger::Exception)
+            .ok_or_else(|| format!("unknown exception '{exception}'"));
+    }
+    if value.contains(':') {
+        return parse_region_arg(value).map(TraceTrigger::Range);
+    }
+    if value.starts_with("0x") || value.starts_with("0X") {
+        return parse_number(value)
+            .map(TraceTrigger::Address)
+            .ok_or_else(|| format!("expected an address, got '{value}'"));
+    }
+    Ok(match value.replace('_', "").parse() {
+        Ok(count) => TraceTrigger::InstructionCount(count),
+        Err(_) => TraceTrigger::Symbol(value.to_string()),
+    })
+}
+
+///
+/// Resolve the save point of `--save-snapshot-at`, a decimal instruction
+/// count or the name of a symbol looked up with `symbol`.
+///
+fn resolve_snapshot_options(
+    args: &SnapshotArgs,
+    symbol: impl Fn(&str) -> Option<u32>,
+) -> anyhow::Result<SnapshotOptions> {
+    let save = match (&args.save_at, &args.save) {
+        (Some(at), Some(path)) => {
+            let point = match at.replace('_', "").parse() {
+                Ok(count) => SnapshotPoint::InstructionCount(count),
+                Err(_) => SnapshotPoint::Address(
+                    symbol(at).with_context(|| format!("symbol '{at}' not found"))?,
+                ),
+            };
+            Some((point, path.clone()))
+        }
+        _ => None,
+    };
+    Ok(SnapshotOptions {
+        load: args.load.clone(),
+        save,
+    })
+}
+
+///
+/// Resolve the gdb server address from `--gdb-listen`, an IP address with
+/// or without a port or a Unix socket path, and `--gdb-port`.
+///
+fn resolve_gdb_listen(listen: Option<&str>, port: Option<u16>) -> anyhow::Result<GdbListen> {
+    let localhost = IpAddr::from([127, 0, 0, 1]);
+    let Some(listen) = listen else {
+        return Ok(GdbListen::Tcp(SocketAddr::new(
+            localhost,
+            port.unwrap_or(DEFAULT_GDB_PORT),
+        )));
+    };
+    if let Ok(ip) = listen.parse::<IpAddr>() {
+        return Ok(GdbListen::Tcp(SocketAddr::new(
+            ip,
+            port.unwrap_or(DEFAULT_GDB_PORT),
+        )));
+    }
+    if let Ok(addr) = listen.parse::<SocketAddr>() {
+        anyhow::ensure!(
+            port.is_none_or(|port| port == addr.port()),
+            "--gdb-port conflicts with the port of --gdb-listen {listen}"
+        );
+        return Ok(GdbListen::Tcp(addr));
+    }
+    #[cfg(unix)]
+    {
+        anyhow::ensure!(
+            port.is_none(),
+            "--gdb-port cannot be used with the Unix socket {listen}"
+        );
+        let path = listen.strip_prefix("unix:").unwrap_or(listen);
+        Ok(GdbListen::Unix(PathBuf::from(path)))
+    }
+    #[cfg(not(unix))]
+    anyhow::bail!("unsupported gdb listen address '{listen}'")
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.replace('_', "");
+    let (digits, multiplier) = match text.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1024),
+        None => match text.strip_suffix('M') {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (text.as_str(), 1),
+        },
+    };
+    let number = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    number.checked_mul(multiplier)
+}
 
 static LOGGER: OnceLock<StderrLogger> = OnceLock::new();
 
@@ -159,14 +396,66 @@ fn resolve_fault_trap_mode(run_matches: &ArgMatches) -> anyhow::Result<FaultTrap
     Ok(mode)
 }
 
+/// ELF header flag of big endian images with little endian code
+const EF_ARM_BE8: u32 = 0x0080_0000;
+
+/// RAM of the processor when the device description gives none
+const DEFAULT_RAM: Region = Region {
+    base: 0x2000_0000,
+    size: 128 * 1024,
+};
+
+///
+/// Memory layout for `SYS_HEAPINFO`: the heap starts after the image in
+/// the first RAM region and grows up, the stack starts at the end of the
+/// region and grows down towards the heap.
+///
+fn heap_info(memory: &Memory, elf: &Elf) -> [u32; 4] {
+    let ram = memory.ram.first().copied().unwrap_or(DEFAULT_RAM);
+    let ram_end = ram.base.wrapping_add(ram.size);
+    let heap_base = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == goblin::elf::program_header::PT_LOAD)
+        .map(|ph| (ph.p_vaddr + ph.p_memsz) as u32)
+        .filter(|end| (ram.base..=ram_end).contains(end))
+        .max()
+        .unwrap_or(ram.base);
+    let heap_base = (heap_base + 7) & !7;
+    [heap_base, ram_end, ram_end, heap_base]
+}
+
+/// Map the halfword addresses covered by each ELF symbol to its name.
+fn symbol_table<'a>(elf: &Elf<'a>) -> HashMap<u32, &'a str> {
+    let mut symboltable = HashMap::new();
+    for sym in &elf.syms {
+        if sym.st_type() != goblin::elf::sym::STT_FILE
+            && let Some(name) = elf.strtab.get_at(sym.st_name)
+        {
+            let mut count = 0;
+            let mut pos = sym.st_value as u32;
+            while count <= sym.st_size {
+                symboltable.insert(pos & 0xffff_fffe, name);
+                pos += 2;
+                count += 2;
+            }
+        }
+    }
+    symboltable
+}
+
+#[allow(clippy::too_many_arguments)]
 fn run_bin(
     buffer: &[u8],
-    trace: bool,
-    option_trace_start: Option<u64>,
+    trace: &TraceArgs,
     itm_file: Option<Box<dyn io::Write + 'static>>,
-    gdb: bool,
+    gdb: Option<&GdbListen>,
     fault_trap_mode: FaultTrapMode,
-    device_factory: DeviceFactory,
+    device: Option<DeviceBus>,
+    memory: &Memory,
+    snapshot: &SnapshotArgs,
+    mut semihost: SemihostOptions,
+    memory_trace: Option<MemoryTraceArgs>,
 ) -> anyhow::Result<u32> {
     let res = Object::parse(buffer).unwrap();
 
@@ -178,21 +467,21 @@ fn run_bin(
     };
 
     debug!("Detected ELF file.");
+    let big_endian = elf_big_endian(&elf, memory)?;
 
-    let mut min_address = 0xffff_ffff;
-    let mut max_address = 0;
+    let mut segments = Vec::new();
     debug!("Determining ELF code sections");
     for ph in &elf.program_headers {
         if ph.p_type == goblin::elf::program_header::PT_LOAD && ph.p_filesz > 0 {
-            let dst_addr = ph.p_paddr as usize;
-            let dst_end_addr = (ph.p_paddr + ph.p_filesz) as usize;
-
             debug!(
                 "PT_LOAD section at 0x{:08x} - 0x{:08x} (size = {} bytes)",
-                dst_addr, dst_end_addr, ph.p_filesz
+                ph.p_paddr,
+                ph.p_paddr + ph.p_filesz,
+                ph.p_filesz
             );
-            min_address = cmp::min(dst_addr, min_address);
-            max_address = cmp::max(dst_end_addr, max_address);
+            let src_addr = ph.p_offset as usize;
+            let src_end_addr = (ph.p_offset + ph.p_filesz) as usize;
+            segments.push((ph.p_paddr as u32, &buffer[src_addr..src_end_addr]));
         } else {
             debug!(
                 "ignoring section : {} (size = {} bytes)",
@@ -202,109 +491,173 @@ fn run_bin(
         }
     }
 
-    let flash_start_address = min_address as u32;
-    let flash_size = max_address - min_address;
-    info!(
-        "Auto configuring flash: address space is 0x{:x}..0x{:x}, size= {} bytes",
-        flash_start_address, max_address, flash_size
+    let regions = memory_regions(memory, &segments)?;
+    let memory_trace = memory_trace.map(|args| memory_trace_func(args, &regions));
+
+    let symbol_address = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| {
+                sym.st_type() != goblin::elf::sym::STT_FILE
+                    && elf.strtab.get_at(sym.st_name) == Some(name)
+            })
+            .map(|sym| sym.st_value as u32 & 0xffff_fffe)
+    };
+    let snapshot = resolve_snapshot_options(snapshot, symbol_address)?;
+
+    let resolve_trigger = |trigger: &Option<TraceTrigger>| {
+        trigger
+            .clone()
+            .map(|trigger| trigger.resolve(symbol_address))
+            .transpose()
+            .map_err(anyhow::Error::msg)
+    };
+    let mut window = TraceWindow::new(
+        resolve_trigger(&trace.start)?,
+        resolve_trigger(&trace.stop)?,
+        trace.count,
     );
-    let mut flash_mem = vec![0; flash_size];
-
-    for ph in &elf.program_headers {
-        if ph.p_type == goblin::elf::program_header::PT_LOAD && ph.p_filesz > 0 {
-            let dst_addr = (ph.p_paddr - u64::from(flash_start_address)) as usize;
-            let dst_end_addr =
-                ((ph.p_paddr + ph.p_filesz) - u64::from(flash_start_address)) as usize;
-
-            let src_addr = ph.p_offset as usize;
-            let src_end_addr = (ph.p_offset + ph.p_filesz) as usize;
-
-            flash_mem[dst_addr..dst_end_addr].copy_from_slice(&buffer[src_addr..src_end_addr]);
-        }
-    }
+    semihost.heap_info = heap_info(memory, &elf);
+    let semihost_func = Box::new(get_semihost_func(Instant::now(), semihost));
 
-    let trace_start = option_trace_start.unwrap_or(0);
-    let semihost_func = Box::new(get_semihost_func(Instant::now()));
+    if let Some(listen) = gdb {
+        anyhow::ensure!(
+            !window.needs_events(),
+            "exception trace triggers are not supported with --gdb"
+        );
+        let mut gdb = GdbServer::new(regions, device, semihost_func, fault_trap_mode, big_endian)?;
 
-    if gdb {
-        let gdb = GdbServer::new(
-            &flash_mem,
-            device_factory(),
-            semihost_func,
-            if flash_start_address != 0 {
-                Some(MemoryMapConfig::new(flash_start_address, 0, flash_size))
-            } else {
-                None
-            },
-            flash_size,
-            fault_trap_mode,
+        // `monitor trace on` starts the trace also when --trace was not given
+        let symboltable: HashMap<u32, String> = symbol_table(&elf)
+            .into_iter()
+            .map(|(address, name)| (address, name.to_string()))
+            .collect();
+        let mut trace_stdout = TabWriter::new(io::stdout()).minwidth(16).padding(1);
+        let mut instruction_trace = InstructionTrace::new(symboltable, trace.registers);
+        gdb.trace(
+            Box::new(move |processor: &Processor| {
+                if window.trace(processor) {
+                    let trace_entry = instruction_trace.format(processor);
+                    writeln!(&mut trace_stdout, "{}", trace_entry).unwrap();
+                    let _ = trace_stdout.flush();
+                }
+            }),
+            trace.enabled,
         );
 
-        let exit_code = gdb?.start()?;
+        let exit_code = gdb.start(listen)?;
         return Ok(exit_code);
     }
 
-    let statistics = if trace {
+    let statistics = if trace.enabled {
         debug!("Configuring tracing.");
 
-        let mut symboltable = HashMap::new();
-        let mut trace_stdout = TabWriter::new(io::stdout()).minwidth(16).padding(1);
+        let output: Box<dyn io::Write> = match &trace.file {
+            Some(path) => Box::new(io::BufWriter::new(
+                File::create(path).with_context(|| format!("trace '{}'", path.display()))?,
+            )),
+            None => Box::new(io::stdout()),
+        };
+        // the ring buffer is written to the trace output when the run ends
+        let ring = trace.ring.map(TraceRing::new);
+        let (output, mut ring_output) = match &ring {
+            Some(ring) => (ring.writer(), Some(output)),
+            None => (output, None),
+        };
+        let mut trace_writer =
+            TraceWriter::new(output, trace.format, symbol_table(&elf), trace.registers)?;
+        if let Some(ring) = &ring {
+            ring.end_header();
+            trace_writer.all_registers();
+        }
+        let trace_events = trace.events;
+        let events = Rc::new(RefCell::new(TraceEvents::default()));
+        let event_trace: Option<EventTraceFunc> = if trace.events || window.needs_events() {
+            let events = Rc::clone(&events);
+            Some(Box::new(move |event| events.borrow_mut().push(event)))
+        } else {
+            None
+        };
 
-        for sym in &elf.syms {
-            if sym.st_type() != goblin::elf::sym::STT_FILE
-                && let Some(maybe_name) = elf.strtab.get_at(sym.st_name)
-            {
-                let name = maybe_name;
-                let mut count = 0;
-                let mut pos = sym.st_value as u32;
-                while count <= sym.st_size {
-                    symboltable.insert(pos & 0xffff_fffe, name);
-                    pos += 2;
-                    count += 2;
+        // the records list the data accesses of each instruction
+        let accesses: Rc<RefCell<Vec<MemoryAccess>>> = Rc::default();
+        let memory_trace: Option<MemoryTraceFunc> = if trace.format == TraceFormat::Text {
+            memory_trace
+        } else {
+            let accesses = Rc::clone(&accesses);
+            let mut memory_trace = memory_trace;
+            Some(Box::new(move |access| {
+                accesses.borrow_mut().push(*access);
+                if let Some(func) = memory_trace.as_mut() {
+                    func(access);
                 }
-            }
-        }
+            }))
+        };
 
         let tracefunc = |processor: &Processor| {
-            if processor.instruction_count >= trace_start {
-                let trace_entry = format_trace_entry(processor, &symboltable);
-                writeln!(&mut trace_stdout, "{}", trace_entry).unwrap();
-                let _ = trace_stdout.flush();
+            let (before, after) = events.borrow_mut().take(processor.instruction_count);
+            let memory = std::mem::take(&mut *accesses.borrow_mut());
+            for event in &before {
+                window.event(event);
+            }
+            if window.trace(processor) {
+                for event in before.iter().filter(|_| trace_events) {
+                    trace_writer.event(event).unwrap();
+                }
+                trace_writer.instruction(processor, &memory).unwrap();
+                for event in after.iter().filter(|_| trace_events) {
+                    trace_writer.event(event).unwrap();
+                }
+                if let Some(ring) = &ring {
+                    trace_writer.flush().unwrap();
+                    ring.end_record();
+                }
+            }
+            for event in &after {
+                window.event(event);
             }
         };
         debug!("Starting simulation with trace.");
 
-        simulate_trace(
-            &flash_mem,
-            device_factory(),
+        let statistics = simulate_trace(
+            regions,
+            device,
             tracefunc,
+            event_trace,
             semihost_func,
             itm_file,
-            if flash_start_address != 0 {
-                Some(MemoryMapConfig::new(flash_start_address, 0, flash_size))
-            } else {
-                None
-            },
-            flash_size,
+            memory_trace,
             fault_trap_mode,
-        )?
+            big_endian,
+            &snapshot,
+        );
+        trace_writer.flush()?;
+        if let (Some(ring), Some(output)) = (&ring, ring_output.as_mut()) {
+            ring.dump(output)?;
+        }
+        statistics?
     } else {
         debug!("Starting simulation.");
         simulate(
-            &flash_mem,
-            device_factory(),
+            regions,
+            device,
             semihost_func,
             itm_file,
-            if flash_start_address != 0 {
-                Some(MemoryMapConfig::new(flash_start_address, 0, flash_size))
-            } else {
-                None
-            },
-            flash_size,
+            memory_trace,
             fault_trap_mode,
+            big_endian,
+            &snapshot,
         )?
     };
 
+    if let (true, Some((_, path))) = (statistics.snapshot_saved, &snapshot.save) {
+        info!(
+            "Snapshot saved to '{}' after {} instructions",
+            path.display(),
+            statistics.instruction_count
+        );
+    }
+
     let duration_in_secs = statistics.duration.as_secs() as f64
         + (f64::from(statistics.duration.subsec_nanos()) / 1_000_000_000f64);
     let instructions_per_sec = statistics.instruction_count as f64 / duration_in_secs;
@@ -324,6 +677,132 @@ fn run_bin(
     Ok(statistics.exit_code)
 }
 
+///
+/// Byte order of the data accesses, the device description must agree with
+/// the ELF image. Big endian images must be BE8, with little endian code.
+///
+fn elf_big_endian(elf: &Elf, memory: &Memory) -> anyhow::Result<bool> {
+    let elf_big_endian = !elf.little_endian;
+    anyhow::ensure!(
+        !elf_big_endian || elf.header.e_flags & EF_ARM_BE8 != 0,
+        "big endian ELF image is not BE8, link it with --be8"
+    );
+    let big_endian = memory.big_endian.unwrap_or(elf_big_endian);
+    anyhow::ensure!(
+        big_endian == elf_big_endian,
+        "{} endian ELF image on a {} endian device",
+        if elf_big_endian { "big" } else { "little" },
+        if big_endian { "big" } else { "little" }
+    );
+    Ok(big_endian)
+}
+
+///
+/// Build the memory regions from the device description and load the ELF
+/// segments into them. Without configured flash, a flash region spanning
+/// the segments is created. Flash that does not start at 0 is aliased
+/// there for booting unless another region already covers address 0.
+/// Configured regions take precedence over the bit-band aliases.
+///
+fn memory_regions(memory: &Memory, segments: &[(u32, &[u8])]) -> anyhow::Result<Vec<MemoryRegion>> {
+    let mut flash = memory.flash.clone();
+    if flash.is_empty() {
+        let min_address = segments
+            .iter()
+            .map(|(address, _)| *address)
+            .min()
+            .unwrap_or(0);
+        let max_address = segments
+            .iter()
+            .map(|(address, data)| address + data.len() as u32)
+            .max()
+            .unwrap_or(0);
+        info!(
+            "Auto configuring flash: address space is 0x{:x}..0x{:x}, size= {} bytes",
+            min_address,
+            max_address,
+            max_address - min_address
+        );
+        flash.push(Region {
+            base: min_address,
+            size: max_address - min_address,
+        });
+    }
+
+    let mut regions = Vec::new();
+    if memory.bit_band == Some(true) {
+        regions.push(MemoryRegion::bit_band(
+            "sram_bitband",
+            0x2200_0000,
+            0x0200_0000,
+            0x2000_0000,
+        ));
+        regions.push(MemoryRegion::bit_band(
+            "peripheral_bitband",
+            0x4200_0000,
+            0x0200_0000,
+            0x4000_0000,
+        ));
+    }
+    for (n, flash) in flash.iter().enumerate() {
+        regions.push(MemoryRegion::rom(
+            &region_name("flash", n),
+            flash.base,
+            flash.size,
+        ));
+    }
+    for (n, ram) in memory.ram.iter().enumerate() {
+        regions.push(MemoryRegion::ram(&region_name("ram", n), ram.base, ram.size).with_fill(0xcd));
+    }
+    for (n, alias) in memory.aliases.iter().enumerate() {
+        regions.push(MemoryRegion::alias(
+            &region_name("alias", n),
+            alias.base,
+            alias.size,
+            alias.target,
+        ));
+    }
+    if flash[0].base != 0 && !regions.iter().any(|region| region.contains(0)) {
+        regions.push(MemoryRegion::alias("boot", 0, flash[0].size, flash[0].base));
+    }
+
+    for (address, data) in segments {
+        anyhow::ensure!(
+            regions.iter_mut().any(|region| region.load(*address, data)),
+            "ELF segment at 0x{address:x}..0x{:x} is outside the configured flash and RAM",
+            u64::from(*address) + data.len() as u64
+        );
+    }
+    Ok(regions)
+}
+
+/// "flash", "flash1", "flash2"...
+fn region_name(kind: &str, n: usize) -> String {
+    if n == 0 {
+        kind.to_string()
+    } else {
+        format!("{kind}{n}")
+    }
+}
+
+///
+/// Memory trace writing the accesses selected by the filter to the file,
+/// one line per access.
+///
+fn memory_trace_func(args: MemoryTraceArgs, memory: &[MemoryRegion]) -> MemoryTraceFunc {
+    let mut memory = memory.to_vec();
+    if !memory.iter().any(|region| region.name == "ram") {
+        memory.push(MemoryRegion::ram("ram", DEFAULT_RAM.base, DEFAULT_RAM.size));
+    }
+    let mut output = io::BufWriter::new(args.file);
+    Box::new(move |access| {
+        let region = AccessRegion::of(&memory, access.address);
+        if args.filter.matches(access, region) {
+            let _ = writeln!(output, "{}", format_memory_access(access, region));
+        }
+    })
+}
+
 fn open_itm_file(filename: &str) -> Option<Box<dyn io::Write + 'static>> {
     let result = File::create(filename);
 
@@ -333,15 +812,31 @@ fn open_itm_file(filename: &str) -> Option<Box<dyn io::Write + 'static>> {
     }
 }
 
-fn run(args: &ArgMatches, device_factory: DeviceFactory) -> anyhow::Result<u32> {
+///
+/// Command line for `SYS_GET_CMDLINE`: the executable followed by the free
+/// arguments, arguments with spaces in double quotes.
+///
+fn semihost_cmdline<'a>(executable: &'a str, args: impl Iterator<Item = &'a str>) -> String {
+    std::iter::once(executable)
+        .chain(args)
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("\"{arg}\"")
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn run(args: &ArgMatches, device_factory: DeviceFactory, core: &CoreConfig) -> anyhow::Result<u32> {
     let exit_code = match args.subcommand() {
         Some(("run", run_matches)) => {
             let filename = run_matches
                 .get_one::<String>("EXECUTABLE")
                 .context("filename missing")?;
 
-            let trace_start = run_matches.get_one::<u64>("trace-start").copied();
-
             let itm_output = match run_matches.get_one::<String>("itm") {
                 Some(filename) => open_itm_file(filename),
                 None => None,
@@ -354,14 +849,127 @@ fn run(args: &ArgMatches, device_factory: DeviceFactory) -> anyhow::Result<u32>
                 v
             };
 
+            let mut board = match run_matches.get_one::<String>("device") {
+                Some(filename) => crate::board::load(filename.as_ref())?,
+                None => crate::board::Board::default(),
+            };
+            if let Some(flash) = run_matches.get_many::<Region>("flash") {
+                board.memory.flash = flash.copied().collect();
+            }
+            if let Some(ram) = run_matches.get_many::<Region>("ram") {
+                board.memory.ram = ram.copied().collect();
+            }
+            board.memory.bit_band.get_or_insert(core.bit_band);
+            if !board.name.is_empty() {
+                info!("Using device description '{}'", board.name);
+            }
+
+            let gdb_listen = if run_matches.get_flag("gdb") {
+                Some(resolve_gdb_listen(
+                    run_matches
+                        .get_one::<String>("gdb-listen")
+                        .map(String::as_str),
+                    run_matches.get_one::<u16>("gdb-port").copied(),
+                )?)
+            } else {
+                None
+            };
+
+            let semihost_root = match run_matches.get_one::<PathBuf>("semihost-root") {
+                Some(root) => Some(
+                    std::fs::canonicalize(root)
+                        .with_context(|| format!("semihost root '{}'", root.display()))?,
+                ),
+                None => None,
+            };
+            if let Some(root) = &semihost_root {
+                anyhow::ensure!(
+                    root.is_dir(),
+                    "semihost root '{}' is not a directory",
+                    root.display()
+                );
+            }
+
+            let memory_trace = match run_matches.get_one::<PathBuf>("mem-trace") {
+                Some(path) => Some(MemoryTraceArgs {
+                    file: File::create(path)
+                        .with_context(|| format!("memory trace '{}'", path.display()))?,
+                    filter: MemoryTraceFilter {
+                        ranges: run_matches
+                            .get_many::<Region>("mem-trace-range")
+                            .map(|ranges| ranges.copied().collect())
+                            .unwrap_or_default(),
+                        regions: run_matches
+                            .get_many::<AccessRegion>("mem-trace-region")
+                            .map(|regions| regions.copied().collect())
+                            .unwrap_or_default(),
+                        write: run_matches
+                            .get_one::<String>("mem-trace-access")
+                            .map(|access| access == "write"),
+                    },
+                }),
+                None => None,
+            };
+
+            let device_options = DeviceOptions {
+                serial: run_matches
+                    .get_many::<(String, String)>("serial")
+                    .map(|ports| ports.cloned().collect())
+                    .unwrap_or_default(),
+                peripherals: board.peripherals,
+            };
+
+            let trace = TraceArgs {
+                enabled: run_matches.get_flag("trace"),
+                start: run_matches.get_one::<TraceTrigger>("trace-start").cloned(),
+                stop: run_matches.get_one::<TraceTrigger>("trace-stop").cloned(),
+                count: run_matches.get_one::<u64>("trace-count").copied(),
+                ring: run_matches
+                    .get_one::<u64>("trace-ring")
+                    .map(|count| *count as usize),
+                registers: run_matches
+                    .get_one::<String>("trace-regs")
+                    .and_then(|name| TRACE_REGISTERS.iter().find(|(n, _)| n == name))
+                    .map_or(TraceRegisters::Core, |(_, registers)| *registers),
+                events: run_matches.get_flag("trace-events"),
+                format: run_matches
+                    .get_one::<String>("trace-format")
+                    .and_then(|name| TRACE_FORMATS.iter().find(|(n, _)| n == name))
+                    .map_or(TraceFormat::Text, |(_, format)| *format),
+                file: run_matches.get_one::<PathBuf>("trace-file").cloned(),
+            };
+            anyhow::ensure!(
+                !trace.events || matches!(trace.format, TraceFormat::Text | TraceFormat::Json),
+                "--trace-events needs the text or json trace format"
+            );
+
             run_bin(
                 &buffer,
-                run_matches.get_flag("trace"),
-                trace_start,
+                &trace,
                 itm_output,
-                run_matches.get_flag("gdb"),
+                gdb_listen.as_ref(),
                 resolve_fault_trap_mode(run_matches)?,
-                device_factory,
+                device_factory(&device_options)?,
+                &board.memory,
+                &SnapshotArgs {
+                    load: run_matches.get_one::<PathBuf>("load-snapshot").cloned(),
+                    save_at: run_matches.get_one::<String>("save-snapshot-at").cloned(),
+                    save: run_matches.get_one::<PathBuf>("save-snapshot").cloned(),
+                },
+                SemihostOptions {
+                    root: semihost_root,
+                    cmdline: semihost_cmdline(
+                        filename,
+                        run_matches
+                            .get_many::<String>("ARGS")
+                            .into_iter()
+                            .flatten()
+                            .map(String::as_str),
+                    ),
+                    heap_info: [0; 4],
+                    system: run_matches.get_flag("semihost-system"),
+                },
+                memory_trace,
             )?
         }
         Some((_, _)) => unreachable!(),
@@ -395,16 +1003,136 @@ fn build_command(bin_name: &'static str, about: &'static str, run_about: &'stati
                 .arg(
                     Arg::new("trace-start")
                         .long("trace-start")
-                        .help("Instruction on which to start tracing")
+                        .value_name("TRIGGER")
+                        .help("Start tracing at an instruction count, symbol, 0x address, BASE:SIZE address range or exception:NAME entry")
+                        .action(ArgAction::Set)
+                        .value_parser(parse_trace_trigger),
+                )
+                .arg(
+                    Arg::new("trace-stop")
+                        .long("trace-stop")
+                        .value_name("TRIGGER")
+                        .help("Stop tracing before the instruction matching the trigger, same forms as --trace-start")
+                        .requires("trace")
+                        .action(ArgAction::Set)
+                        .value_parser(parse_trace_trigger),
+                )
+                .arg(
+                    Arg::new("trace-count")
+                        .long("trace-count")
+                        .value_name("COUNT")
+                        .help("Trace at most this many instructions")
+                        .requires("trace")
                         .action(ArgAction::Set)
                         .value_parser(value_parser!(u64)),
                 )
+                .arg(
+                    Arg::new("trace-ring")
+                        .long("trace-ring")
+                        .value_name("COUNT")
+                        .help("Keep only the trace of the last instructions and write it when the run ends or a fault is trapped")
+                        .requires("trace")
+                        .conflicts_with("gdb")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("trace-regs")
+                        .long("trace-regs")
+                        .help("Registers on each trace line: core registers, all including floating point, or only the changed ones")
+                        .value_parser(clap::builder::PossibleValuesParser::new(
+                            TRACE_REGISTERS.map(|(name, _)| name),
+                        ))
+                        .default_value("core")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("trace-events")
+                        .long("trace-events")
+                        .help("Show exception entry and return, faults, sleep, SysTick wrap and semihosting calls in the trace")
+                        .requires("trace")
+                        .conflicts_with("gdb")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("trace-format")
+                        .long("trace-format")
+                        .help("Format of the instruction trace, see doc/trace_format.md for the json, csv and bin schema")
+                        .requires("trace")
+                        .conflicts_with("gdb")
+                        .value_parser(clap::builder::PossibleValuesParser::new(
+                            TRACE_FORMATS.map(|(name, _)| name),
+                        ))
+                        .default_value("text")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("trace-file")
+                        .long("trace-file")
+                        .value_name("FILE")
+                        .help("Write the instruction trace to a file instead of stdout")
+                        .requires("trace")
+                        .conflicts_with("gdb")
+                        .value_parser(value_parser!(PathBuf))
+                        .num_args(1),
+                )
                 .arg(
                     Arg::new("itm")
                         .long("itm")
                         .help("Name of file to which itm trace data is written to. ")
                         .num_args(1),
                 )
+                .arg(
+                    Arg::new("mem-trace")
+                        .long("mem-trace")
+                        .value_name("FILE")
+                        .help("Write the data accesses of loads, stores and exception stacking to a file")
+                        .conflicts_with("gdb")
+                        .value_parser(value_parser!(PathBuf))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("mem-trace-range")
+                        .long("mem-trace-range")
+                        .value_name("BASE:SIZE")
+                        .help("Trace only accesses to the address range, repeat for more ranges")
+                        .requires("mem-trace")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_region_arg)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("mem-trace-region")
+                        .long("mem-trace-region")
+                        .value_name("REGION")
+                        .help("Trace only accesses to flash, ram or peripheral memory, repeat for more")
+                        .requires("mem-trace")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_access_region)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("mem-trace-access")
+                        .long("mem-trace-access")
+                        .help("Trace only loads or stores")
+                        .requires("mem-trace")
+                        .value_parser(clap::builder::PossibleValuesParser::new(["read", "write"]))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("semihost-root")
+                        .long("semihost-root")
+                        .value_name("DIR")
+                        .help("Directory for the files the target opens with semihosting, paths may not lead out of it")
+                        .value_parser(value_parser!(PathBuf))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("semihost-system")
+                        .long("semihost-system")
+                        .action(ArgAction::SetTrue)
+                        .help("Allow the target to run host commands with SYS_SYSTEM"),
+                )
                 .arg(
                     Arg::new("EXECUTABLE")
                         .index(1)
@@ -447,6 +1175,84 @@ fn build_command(bin_name: &'static str, about: &'static str, run_about: &'stati
                         .long("gdb")
                         .help("Enable the gdb server")
                         .num_args(0),
+                )
+                .arg(
+                    Arg::new("gdb-port")
+                        .long("gdb-port")
+                        .value_name("PORT")
+                        .help("TCP port of the gdb server, 0 picks a free port [default: 9001]")
+                        .requires("gdb")
+                        .value_parser(value_parser!(u16))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("gdb-listen")
+                        .long("gdb-listen")
+                        .value_name("ADDR|PATH")
+                        .help("Address of the gdb server: an IP address, IP:PORT or a Unix socket path [default: 127.0.0.1]")
+                        .requires("gdb")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("serial")
+                        .long("serial")
+                        .value_name("PORT=BACKEND")
+                        .help("Connect a device serial port (e.g. usart1) to stdio, file:<path>, unix:<path> or pty")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_serial_arg)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("device")
+                        .long("device")
+                        .value_name("FILE")
+                        .help("Load memory layout and peripheral registers from a TOML, YAML or SVD device description")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("flash")
+                        .long("flash")
+                        .value_name("BASE:SIZE")
+                        .help("Place a flash region, repeat for more banks. Overrides the device description")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_region_arg)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("ram")
+                        .long("ram")
+                        .value_name("BASE:SIZE")
+                        .help("Place a RAM region, repeat for more banks. Overrides the device description")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_region_arg)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("save-snapshot-at")
+                        .long("save-snapshot-at")
+                        .value_name("COUNT|SYMBOL")
+                        .help("Save a snapshot and stop when the instruction count is reached or before executing the symbol")
+                        .requires("save-snapshot")
+                        .conflicts_with("gdb")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("save-snapshot")
+                        .long("save-snapshot")
+                        .value_name("FILE")
+                        .help("File to save the snapshot to")
+                        .requires("save-snapshot-at")
+                        .value_parser(value_parser!(PathBuf))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("load-snapshot")
+                        .long("load-snapshot")
+                        .value_name("FILE")
+                        .help("Resume from a snapshot saved with the same executable and memory layout")
+                        .conflicts_with("gdb")
+                        .value_parser(value_parser!(PathBuf))
+                        .num_args(1),
                 ),
         )
 }
@@ -456,6 +1262,7 @@ pub fn main_with_device(
     about: &'static str,
     run_about: &'static str,
     device_factory: DeviceFactory,
+    core: &CoreConfig,
 ) {
     let cmd = build_command(bin_name, about, run_about).get_matches();
 
@@ -463,13 +1270,13 @@ pub fn main_with_device(
 
     init_logger(module_path!(), verbose).unwrap();
 
-    let result = run(&cmd, device_factory);
+    let result = run(&cmd, device_factory, core);
     match result {
         Ok(exit_code) => {
             std::process::exit(exit_code as i32);
         }
         Err(ref e) => {
-            error!("{e}");
+            error!("{e:#}");
 
             ::std::process::exit(1);
         }
@@ -481,11 +1288,25 @@ mod tests {
     use super::build_command;
     use super::includes_module;
     use super::level_filter_for_verbosity;
+    use super::memory_regions;
+    use super::parse_region_arg;
+    use super::parse_serial_arg;
+    use super::parse_trace_trigger;
+    use super::resolve_gdb_listen;
+    use super::semihost_cmdline;
+    use super::{EF_ARM_BE8, elf_big_endian};
+    use super::{SnapshotArgs, resolve_snapshot_options};
+    use crate::board::Board;
+    use crate::board::Region;
+    use crate::trace::{AccessRegion, TraceTrigger};
 
     #[cfg(not(feature = "armv6m"))]
     use super::resolve_fault_trap_mode;
 
+    use goblin::elf::Elf;
     use log::LevelFilter;
+    use std::path::PathBuf;
+    use zmu_cortex_m::system::simulation::SnapshotPoint;
 
     #[cfg(not(feature = "armv6m"))]
     use zmu_cortex_m::core::exception::Exception;
@@ -508,6 +1329,230 @@ mod tests {
         assert!(!includes_module("zmu::app", "zmu_cortex_m::gdb"));
     }
 
+    #[test]
+    fn test_parse_serial_arg_splits_port_and_backend() {
+        assert_eq!(
+            parse_serial_arg("USART1=file:out.txt"),
+            Ok(("usart1".to_string(), "file:out.txt".to_string()))
+        );
+        assert!(parse_serial_arg("usart1").is_err());
+        assert!(parse_serial_arg("=stdio").is_err());
+        assert!(parse_serial_arg("usart2=").is_err());
+    }
+
+    #[test]
+    fn test_memory_regions_load_segments_and_alias_boot_flash() {
+        let segments: [(u32, &[u8]); 2] = [(0x0800_0000, &[1, 2, 3, 4]), (0x0800_0100, &[5, 6])];
+
+        let regions = memory_regions(&Board::default().memory, &segments).unwrap();
+
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].name, "flash");
+        assert_eq!((regions[0].base, regions[0].size), (0x0800_0000, 0x102));
+        assert_eq!(regions[1].name, "boot");
+        assert!(regions[1].contains(0x101));
+        assert!(!regions[1].contains(0x102));
+    }
+
+    #[test]
+    fn test_memory_regions_from_device_description() {
+        let board = Board::parse(
+            "[memory]
+            flash = [{ base = 0x0800_0000, size = 0x1000 }, { base = 0x0810_0000, size = 0x1000 }]
+            ram = { base = 0x2000_0000, size = 0x1000 }
+            alias = [{ base = 0, size = 0x1000, target = 0x0810_0000 }]",
+            "toml",
+        )
+        .unwrap();
+
+        let regions = memory_regions(&board.memory, &[(0x0810_0000, &[0; 4])]).unwrap();
+        let names: Vec<_> = regions.iter().map(|region| region.name.as_str()).collect();
+        assert_eq!(names, ["flash", "flash1", "ram", "alias"]);
+
+        assert!(memory_regions(&board.memory, &[(0x0800_0ffe, &[0; 4])]).is_err());
+        assert!(memory_regions(&board.memory, &[(0x2000_0000, &[0; 4])]).is_ok());
+    }
+
+    /// ELF header without program or section headers
+    fn elf_header(big_endian: bool, flags: u32) -> Vec<u8> {
+        let mut header = vec![0x7f, b'E', b'L', b'F', 1, if big_endian { 2 } else { 1 }, 1];
+        header.resize(16, 0);
+        // type, machine, version, entry, phoff, shoff, flags, ehsize,
+        // phentsize, phnum, shentsize, shnum, shstrndx
+        for (value, size) in [
+            (2, 2),
+            (40, 2),
+            (1, 4),
+            (0, 4),
+            (0, 4),
+            (0, 4),
+            (flags, 4),
+            (52, 2),
+            (32, 2),
+            (0, 2),
+            (40, 2),
+            (0, 2),
+            (0, 2),
+        ] {
+            if big_endian {
+                header.extend_from_slice(&u32::to_be_bytes(value)[4 - size..]);
+            } else {
+                header.extend_from_slice(&u32::to_le_bytes(value)[..size]);
+            }
+        }
+        header
+    }
+
+    #[test]
+    fn test_elf_big_endian_requires_be8_and_a_matching_device() {
+        let be8 = elf_header(true, 0x0500_0000 | EF_ARM_BE8);
+        let be32 = elf_header(true, 0x0500_0000);
+        let le = elf_header(false, 0x0500_0000);
+        let little = Board::parse("[memory]\nbig_endian = false", "toml").unwrap();
+        let elf = |bytes| Elf::parse(bytes).unwrap();
+
+        assert!(elf_big_endian(&elf(&be8), &Board::default().memory).unwrap());
+        assert!(!elf_big_endian(&elf(&le), &Board::default().memory).unwrap());
+        assert!(!elf_big_endian(&elf(&le), &little.memory).unwrap());
+        assert!(elf_big_endian(&elf(&be8), &little.memory).is_err());
+        assert!(elf_big_endian(&elf(&be32), &Board::default().memory).is_err());
+    }
+
+    #[test]
+    fn test_memory_regions_bit_band_yields_to_configured_regions() {
+        let board = Board::parse(
+            "[memory]
+            bit_band = true
+            ram = [{ base = 0x2000_0000, size = 0x1000 }, { base = 0x2200_0000, size = 0x100 }]",
+            "toml",
+        )
+        .unwrap();
+
+        let regions = memory_regions(&board.memory, &[(0x0800_0000, &[0; 4])]).unwrap();
+        let names: Vec<_> = regions.iter().map(|region| region.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "sram_bitband",
+                "peripheral_bitband",
+                "flash",
+                "ram",
+                "ram1",
+                "boot"
+            ]
+        );
+        assert_eq!(AccessRegion::of(&regions, 0x2201_0000), AccessRegion::Ram);
+        assert_eq!(
+            AccessRegion::of(&regions, 0x2220_0000),
+            AccessRegion::Peripheral
+        );
+        assert_eq!(
+            AccessRegion::of(&regions, 0x4200_0000),
+            AccessRegion::Peripheral
+        );
+    }
+
+    #[test]
+    fn test_parse_region_arg_accepts_hex_decimal_and_suffixes() {
+        let region = parse_region_arg("0x0800_0000:512K").unwrap();
+        assert_eq!((region.base, region.size), (0x0800_0000, 512 * 1024));
+        let region = parse_region_arg("268435456:0x10000").unwrap();
+        assert_eq!((region.base, region.size), (0x1000_0000, 0x1_0000));
+        let region = parse_region_arg("0xc0000000:1M").unwrap();
+        assert_eq!(region.size, 0x10_0000);
+
+        assert!(parse_region_arg("0x2000_0000").is_err());
+        assert!(parse_region_arg("0x2000_0000:0").is_err());
+        assert!(parse_region_arg("0xffff_0000:1M").is_err());
+        assert!(parse_region_arg("ram:64K").is_err());
+    }
+
+    #[test]
+    fn test_resolve_snapshot_options_count_or_symbol() {
+        let symbol = |name: &str| (name == "main").then_some(0x0800_0100);
+        let args = |at: &str| SnapshotArgs {
+            load: None,
+            save_at: Some(at.to_string()),
+            save: Some("state.snap".into()),
+        };
+
+        let options = resolve_snapshot_options(&args("1_000"), symbol).unwrap();
+        assert_eq!(
+            options.save.map(|(point, _)| point),
+            Some(SnapshotPoint::InstructionCount(1000))
+        );
+        let options = resolve_snapshot_options(&args("main"), symbol).unwrap();
+        assert_eq!(
+            options.save.map(|(point, _)| point),
+            Some(SnapshotPoint::Address(0x0800_0100))
+        );
+        assert!(resolve_snapshot_options(&args("missing"), symbol).is_err());
+        assert!(
+            resolve_snapshot_options(&SnapshotArgs::default(), symbol)
+                .unwrap()
+                .save
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_resolve_gdb_listen_address_and_port() {
+        use std::net::SocketAddr;
+        use zmu_cortex_m::gdb::server::GdbListen;
+
+        let tcp = |addr: &str| GdbListen::Tcp(addr.parse::<SocketAddr>().unwrap());
+
+        assert_eq!(
+            resolve_gdb_listen(None, None).unwrap(),
+            tcp("127.0.0.1:9001")
+        );
+        assert_eq!(
+            resolve_gdb_listen(None, Some(0)).unwrap(),
+            tcp("127.0.0.1:0")
+        );
+        assert_eq!(
+            resolve_gdb_listen(Some("0.0.0.0"), Some(3333)).unwrap(),
+            tcp("0.0.0.0:3333")
+        );
+        assert_eq!(
+            resolve_gdb_listen(Some("[::1]:4444"), None).unwrap(),
+            tcp("[::1]:4444")
+        );
+        assert!(resolve_gdb_listen(Some("127.0.0.1:4444"), Some(3333)).is_err());
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                resolve_gdb_listen(Some("/tmp/zmu-gdb.sock"), None).unwrap(),
+                GdbListen::Unix("/tmp/zmu-gdb.sock".into())
+            );
+            assert!(resolve_gdb_listen(Some("gdb.sock"), Some(3333)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_save_snapshot_at_requires_file() {
+        let command = || build_command("zmu-test", "test", "test run");
+
+        assert!(
+            command()
+                .try_get_matches_from(["zmu-test", "run", "--save-snapshot-at", "100", "a.elf"])
+                .is_err()
+        );
+        assert!(
+            command()
+                .try_get_matches_from([
+                    "zmu-test",
+                    "run",
+                    "--save-snapshot-at",
+                    "main",
+                    "--save-snapshot",
+                    "a.snap",
+                    "a.elf"
+                ])
+                .is_ok()
+        );
+    }
+
     #[cfg(not(feature = "armv6m"))]
     fn run_matches(args: &[&str]) -> clap::ArgMatches {
         build_command("zmu-test", "test", "test run")
@@ -577,4 +1622,158 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_command_parses_memory_trace_filters() {
+        let command = || build_command("zmu-test", "test", "test run");
+
+        let matches = command()
+            .try_get_matches_from([
+                "zmu-test",
+                "run",
+                "--mem-trace",
+                "mem.txt",
+                "--mem-trace-region",
+                "peripheral",
+                "--mem-trace-range",
+                "0x20000000:1K",
+                "--mem-trace-access",
+                "write",
+                "firmware.elf",
+            ])
+            .expect("argument parsing should succeed");
+        let without_file = command().try_get_matches_from([
+            "zmu-test",
+            "run",
+            "--mem-trace-region",
+            "ram",
+            "a.elf",
+        ]);
+        let with_gdb = command().try_get_matches_from([
+            "zmu-test",
+            "run",
+            "--gdb",
+            "--mem-trace",
+            "mem.txt",
+            "a.elf",
+        ]);
+
+        let (_, run_matches) = matches.subcommand().expect("run subcommand expected");
+        let regions: Vec<_> = run_matches
+            .get_many::<AccessRegion>("mem-trace-region")
+            .unwrap()
+            .collect();
+        assert_eq!(regions, [&AccessRegion::Peripheral]);
+        let range = run_matches.get_one::<Region>("mem-trace-range").unwrap();
+        assert_eq!((range.base, range.size), (0x2000_0000, 0x400));
+        assert!(without_file.is_err());
+        assert!(with_gdb.is_err());
+    }
+
+    #[test]
+    fn test_command_trace_events_need_the_instruction_trace() {
+        let command = || build_command("zmu-test", "test", "test run");
+
+        let without_trace =
+            command().try_get_matches_from(["zmu-test", "run", "--trace-events", "a.elf"]);
+        let matches = command()
+            .try_get_matches_from([
+                "zmu-test",
+                "run",
+                "-t",
+                "--trace-events",
+                "--trace-regs",
+                "changed",
+                "a.elf",
+            ])
+            .expect("argument parsing should succeed");
+
+        assert!(without_trace.is_err());
+        let (_, run_matches) = matches.subcommand().expect("run subcommand expected");
+        assert!(run_matches.get_flag("trace-events"));
+        assert_eq!(
+            run_matches
+                .get_one::<String>("trace-regs")
+                .map(String::as_str),
+            Some("changed")
+        );
+    }
+
+    #[test]
+    fn test_command_parses_trace_format_and_file() {
+        let command = || build_command("zmu-test", "test", "test run");
+
+        let without_trace =
+            command().try_get_matches_from(["zmu-test", "run", "--trace-format", "json", "a.elf"]);
+        let unknown_format = command().try_get_matches_from([
+            "zmu-test",
+            "run",
+            "-t",
+            "--trace-format",
+            "xml",
+            "a.elf",
+        ]);
+        let matches = command()
+            .try_get_matches_from([
+                "zmu-test",
+                "run",
+                "-t",
+                "--trace-format",
+                "bin",
+                "--trace-file",
+                "trace.bin",
+                "a.elf",
+            ])
+            .expect("argument parsing should succeed");
+
+        assert!(without_trace.is_err());
+        assert!(unknown_format.is_err());
+        let (_, run_matches) = matches.subcommand().expect("run subcommand expected");
+        assert_eq!(
+            run_matches
+                .get_one::<String>("trace-format")
+                .map(String::as_str),
+            Some("bin")
+        );
+        assert_eq!(
+            run_matches.get_one::<PathBuf>("trace-file"),
+            Some(&PathBuf::from("trace.bin"))
+        );
+    }
+
+    #[test]
+    fn test_parse_trace_trigger_forms() {
+        // Act
+        let count = parse_trace_trigger("1_000");
+        let address = parse_trace_trigger("0x0800_0100");
+        let range = parse_trace_trigger("0x08000000:4K");
+        let named = parse_trace_trigger("exception:HardFault");
+        let numbered = parse_trace_trigger("exception:15");
+        let symbol = parse_trace_trigger("main");
+        let unknown = parse_trace_trigger("exception:Bogus");
+
+        // Assert
+        assert_eq!(count, Ok(TraceTrigger::InstructionCount(1000)));
+        assert_eq!(address, Ok(TraceTrigger::Address(0x0800_0100)));
+        assert_eq!(
+            range,
+            Ok(TraceTrigger::Range(Region {
+                base: 0x0800_0000,
+                size: 0x1000
+            }))
+        );
+        assert_eq!(named, Ok(TraceTrigger::Exception(3)));
+        assert_eq!(numbered, Ok(TraceTrigger::Exception(15)));
+        assert_eq!(symbol, Ok(TraceTrigger::Symbol("main".to_string())));
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn test_semihost_cmdline_quotes_arguments_with_spaces() {
+        // Act
+        let cmdline = semihost_cmdline("fw.elf", ["-n", "3", "two words", ""].into_iter());
+
+        // Assert
+        assert_eq!(cmdline, "fw.elf -n 3 \"two words\" \"\"");
+    }
 }
@@ -0,0 +1,281 @@
+//!
+//! Declarative board descriptions.
+//!
+//! A board file describes the memory layout and a set of memory mapped
+//! peripherals as plain register files. TOML and YAML are accepted, as well
+//! as CMSIS-SVD files for the peripherals alone. The format is chosen from
+//! the file extension.
+//!
+
+#[cfg(feature = "generic-device")]
+pub mod register_file;
+pub mod svd;
+
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+
+fn all_bits() -> u32 {
+    0xffff_ffff
+}
+
+fn register_bits() -> u32 {
+    32
+}
+
+/// Accept either a single region or a list of them
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<Region>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Region),
+        Many(Vec<Region>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(region) => vec![region],
+        OneOrMany::Many(regions) => regions,
+    })
+}
+
+///
+/// Contents of a board description file
+///
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+    /// Human readable name of the board
+    #[serde(default)]
+    pub name: String,
+    /// Memory layout
+    #[serde(default)]
+    pub memory: Memory,
+    /// Memory mapped peripherals
+    #[serde(default, rename = "peripheral")]
+    pub peripherals: Vec<PeripheralDescription>,
+}
+
+///
+/// Flash and RAM placement plus address aliases
+///
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Memory {
+    /// Flash regions, sized from the loaded ELF image if not given
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub flash: Vec<Region>,
+    /// RAM regions, processor default if not given
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub ram: Vec<Region>,
+    /// Address ranges that mirror another range, e.g. flash boot alias at 0
+    #[serde(default, rename = "alias")]
+    pub aliases: Vec<Alias>,
+    /// SRAM and peripheral bit-band regions, core default if not given
+    #[serde(default)]
+    pub bit_band: Option<bool>,
+    /// Big endian (BE8) data accesses, from the ELF image if not given
+    #[serde(default)]
+    pub big_endian: Option<bool>,
+}
+
+///
+/// Base address and size of a memory region
+///
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    /// Start address
+    pub base: u32,
+    /// Size in bytes
+    pub size: u32,
+}
+
+///
+/// Accesses to `base..base + size` are served from `target..target + size`
+///
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Alias {
+    /// Start address of the alias
+    pub base: u32,
+    /// Size in bytes
+    pub size: u32,
+    /// Start address of the aliased memory
+    pub target: u32,
+}
+
+///
+/// Address window of a peripheral and its registers
+///
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PeripheralDescription {
+    /// Peripheral name, e.g. "USART1"
+    pub name: String,
+    /// Start address of the window
+    pub base: u32,
+    /// Size of the window in bytes
+    pub size: u32,
+    /// Registers within the window
+    #[cfg_attr(not(feature = "generic-device"), allow(dead_code))]
+    #[serde(default, rename = "register")]
+    pub registers: Vec<RegisterDescription>,
+    /// Interrupt lines driven by register contents
+    #[cfg_attr(not(feature = "generic-device"), allow(dead_code))]
+    #[serde(default, rename = "interrupt")]
+    pub interrupts: Vec<InterruptDescription>,
+}
+
+///
+/// A register in a peripheral window
+///
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "generic-device"), allow(dead_code))]
+pub struct RegisterDescription {
+    /// Register name, referenced by interrupts
+    pub name: String,
+    /// Offset from the start of the peripheral window, aligned to the register size
+    pub offset: u32,
+    /// Register width in bits: 8, 16 or 32
+    #[serde(default = "register_bits")]
+    pub size: u32,
+    /// Value after reset
+    #[serde(default)]
+    pub reset: u32,
+    /// Bits that read back, other bits read as zero
+    #[serde(default = "all_bits")]
+    pub read_mask: u32,
+    /// Bits that software can change, other bits keep their value
+    #[serde(default = "all_bits")]
+    pub write_mask: u32,
+}
+
+///
+/// An interrupt that is asserted while `status & mask` is non-zero and,
+/// if given, `enable & enable_mask` is non-zero.
+///
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "generic-device"), allow(dead_code))]
+pub struct InterruptDescription {
+    /// External interrupt number
+    pub irq: usize,
+    /// Name of the status register, the line is not driven without one
+    pub status: Option<String>,
+    /// Status bits that raise the interrupt
+    #[serde(default = "all_bits")]
+    pub mask: u32,
+    /// Name of the enable register
+    pub enable: Option<String>,
+    /// Enable bits, defaults to `mask`
+    pub enable_mask: Option<u32>,
+}
+
+impl Board {
+    ///
+    /// Parse a board description, `extension` selects between TOML, YAML and SVD.
+    ///
+    pub fn parse(text: &str, extension: &str) -> anyhow::Result<Self> {
+        let board = match extension {
+            "toml" => toml::from_str(text)?,
+            "yaml" | "yml" => serde_yaml::from_str(text)?,
+            "svd" | "xml" => svd::parse(text)?,
+            _ => {
+                anyhow::bail!(
+                    "unsupported device file format '{extension}', expected toml, yaml or svd"
+                )
+            }
+        };
+        Ok(board)
+    }
+}
+
+///
+/// Load a board description file.
+///
+pub fn load(path: &Path) -> anyhow::Result<Board> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read device file '{}'", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    Board::parse(&text, extension)
+        .with_context(|| format!("invalid device file '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml_board() {
+        let board = Board::parse(
+            r#"
+            name = "bluepill"
+
+            [memory]
+            flash = { base = 0x0800_0000, size = 0x1_0000 }
+            ram = { base = 0x2000_0000, size = 0x5000 }
+            alias = [{ base = 0, size = 0x1_0000, target = 0x0800_0000 }]
+
+            [[peripheral]]
+            name = "USART1"
+            base = 0x4001_3800
+            size = 0x400
+            register = [
+                { name = "SR", offset = 0x00, reset = 0xc0, write_mask = 0x360 },
+                { name = "CR1", offset = 0x0c },
+            ]
+            interrupt = [{ irq = 37, status = "SR", mask = 0x80, enable = "CR1" }]
+            "#,
+            "toml",
+        )
+        .unwrap();
+
+        assert_eq!(board.name, "bluepill");
+        assert_eq!(board.memory.flash[0].base, 0x0800_0000);
+        assert_eq!(board.memory.ram[0].size, 0x5000);
+        assert_eq!(board.memory.aliases[0].target, 0x0800_0000);
+        let usart = &board.peripherals[0];
+        assert_eq!(usart.registers[0].write_mask, 0x360);
+        assert_eq!(usart.registers[1].read_mask, 0xffff_ffff);
+        assert_eq!(usart.interrupts[0].irq, 37);
+        assert_eq!(usart.interrupts[0].enable.as_deref(), Some("CR1"));
+    }
+
+    #[test]
+    fn test_parse_yaml_board() {
+        let board = Board::parse(
+            "
+memory:
+  ram:
+    - { base: 0x20000000, size: 0x2000 }
+    - { base: 0x10000000, size: 0x1000 }
+peripheral:
+  - name: GPIOA
+    base: 0x40010800
+    size: 0x400
+    register:
+      - { name: CRL, offset: 0, reset: 0x44444444 }
+",
+            "yaml",
+        )
+        .unwrap();
+
+        assert!(board.memory.flash.is_empty());
+        assert_eq!(board.memory.ram[0].base, 0x2000_0000);
+        assert_eq!(board.memory.ram[1].base, 0x1000_0000);
+        assert_eq!(board.peripherals[0].registers[0].reset, 0x4444_4444);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_format_and_fields() {
+        assert!(Board::parse("", "json").is_err());
+        assert!(Board::parse("[memory]\nrom = { base = 0, size = 4 }", "toml").is_err());
+    }
+}
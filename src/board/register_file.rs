@@ -0,0 +1,575 @@
+//!
+//! Generic peripheral that serves register files loaded from a board description.
+//!
+
+use super::PeripheralDescription;
+use anyhow::Context;
+use log::{debug, trace};
+use std::io;
+use zmu_cortex_m::NVIC_IRQ_COUNT;
+use zmu_cortex_m::bus::{Bus, IrqLine, Peripheral};
+use zmu_cortex_m::core::fault::Fault;
+use zmu_cortex_m::snapshot::{StateReader, StateWriter};
+
+/// Peripheral region of the Cortex-M memory map
+const PERIPHERAL_REGION: std::ops::RangeInclusive<u32> = 0x4000_0000..=0x5fff_ffff;
+
+struct Register {
+    name: String,
+    offset: u32,
+    /// size in bytes
+    size: u32,
+    value: u32,
+    read_mask: u32,
+    write_mask: u32,
+}
+
+struct RegisterBlock {
+    name: String,
+    base: u32,
+    last: u32,
+    /// sorted by offset
+    registers: Vec<Register>,
+}
+
+/// Location of a register as (block index, register index)
+type RegisterRef = (usize, usize);
+
+struct Interrupt {
+    irqn: usize,
+    status: RegisterRef,
+    mask: u32,
+    enable: Option<(RegisterRef, u32)>,
+}
+
+///
+/// Memory mapped register files without side effects.
+///
+/// Registers keep their reset value until written, the read and write
+/// masks decide which bits are visible and which bits can be changed.
+/// Accesses to offsets without a register read as zero and ignore writes.
+/// The register file claims the whole peripheral region, accesses outside
+/// the described windows raise a precise bus error.
+///
+pub struct RegisterFile {
+    blocks: Vec<RegisterBlock>,
+    interrupts: Vec<Interrupt>,
+    /// sorted interrupt numbers the registers request, updated on writes
+    lines: Vec<usize>,
+    /// sorted interrupt numbers currently asserted
+    asserted: Vec<usize>,
+}
+
+impl RegisterBlock {
+    fn new(description: &PeripheralDescription) -> anyhow::Result<Self> {
+        let name = &description.name;
+        anyhow::ensure!(description.size > 0, "{name}: window size must not be zero");
+        let last = description
+            .base
+            .checked_add(description.size - 1)
+            .with_context(|| format!("{name}: window exceeds the address space"))?;
+
+        let mut registers = Vec::with_capacity(description.registers.len());
+        for register in &description.registers {
+            let size = match register.size {
+                8 => 1,
+                16 => 2,
+                32 => 4,
+                bits => anyhow::bail!("{name}.{}: unsupported size of {bits} bits", register.name),
+            };
+            anyhow::ensure!(
+                register.offset % size == 0
+                    && u64::from(register.offset) + u64::from(size) <= u64::from(description.size),
+                "{name}.{}: offset 0x{:x} is not aligned inside the window",
+                register.name,
+                register.offset
+            );
+            registers.push(Register {
+                name: register.name.clone(),
+                offset: register.offset,
+                size,
+                value: register.reset & lane_mask(size),
+                read_mask: register.read_mask,
+                write_mask: register.write_mask,
+            });
+        }
+        registers.sort_by_key(|register| register.offset);
+        if let Some(pair) = registers
+            .windows(2)
+            .find(|pair| pair[0].offset + pair[0].size > pair[1].offset)
+        {
+            anyhow::bail!(
+                "{name}: registers {} and {} overlap",
+                pair[0].name,
+                pair[1].name
+            );
+        }
+
+        Ok(Self {
+            name: name.clone(),
+            base: description.base,
+            last,
+            registers,
+        })
+    }
+
+    fn register_named(&self, name: &str) -> anyhow::Result<usize> {
+        self.registers
+            .iter()
+            .position(|register| register.name == name)
+            .with_context(|| format!("{}: unknown register '{name}'", self.name))
+    }
+
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && addr <= self.last
+    }
+
+    /// Register that contains the byte at `addr`
+    fn register(&self, addr: u32) -> Option<usize> {
+        let offset = addr - self.base;
+        let index = self
+            .registers
+            .partition_point(|register| register.offset <= offset)
+            .checked_sub(1)?;
+        let register = &self.registers[index];
+        (offset < register.offset + register.size).then_some(index)
+    }
+}
+
+/// Mask for the `bytes` lowest byte lanes
+fn lane_mask(bytes: u32) -> u32 {
+    u32::MAX >> (32 - bytes * 8)
+}
+
+impl RegisterFile {
+    ///
+    /// Build register files for the given peripherals.
+    ///
+    pub fn new(peripherals: &[PeripheralDescription]) -> anyhow::Result<Self> {
+        let mut blocks: Vec<RegisterBlock> = Vec::with_capacity(peripherals.len());
+        for description in peripherals {
+            let block = RegisterBlock::new(description)?;
+            if let Some(other) = blocks
+                .iter()
+                .find(|other| other.base <= block.last && block.base <= other.last)
+            {
+                anyhow::bail!("{} overlaps with {}", block.name, other.name);
+            }
+            blocks.push(block);
+        }
+
+        let mut interrupts = Vec::new();
+        for (index, description) in peripherals.iter().enumerate() {
+            let block = &blocks[index];
+            for interrupt in &description.interrupts {
+                let Some(status) = &interrupt.status else {
+                    debug!(
+                        "{}: interrupt {} has no status register and is not driven",
+                        description.name, interrupt.irq
+                    );
+                    continue;
+                };
+                anyhow::ensure!(
+                    interrupt.irq < NVIC_IRQ_COUNT,
+                    "{}: interrupt {} is not supported, the NVIC has {NVIC_IRQ_COUNT} lines",
+                    description.name,
+                    interrupt.irq
+                );
+                let enable = match &interrupt.enable {
+                    Some(name) => Some((
+                        (index, block.register_named(name)?),
+                        interrupt.enable_mask.unwrap_or(interrupt.mask),
+                    )),
+                    None => None,
+                };
+                interrupts.push(Interrupt {
+                    irqn: interrupt.irq,
+                    status: (index, block.register_named(status)?),
+                    mask: interrupt.mask,
+                    enable,
+                });
+            }
+        }
+
+        let mut registers = Self {
+            blocks,
+            interrupts,
+            lines: Vec::new(),
+            asserted: Vec::new(),
+        };
+        registers.update_lines();
+        Ok(registers)
+    }
+
+    fn block(&self, addr: u32) -> Result<usize, Fault> {
+        self.blocks
+            .iter()
+            .position(|block| block.contains(addr))
+            .ok_or(Fault::Preciserr)
+    }
+
+    fn value(&self, (block, register): RegisterRef) -> u32 {
+        self.blocks[block].registers[register].value
+    }
+
+    /// Read `bytes` bytes starting at `addr`, the access may span several registers
+    fn read(&self, addr: u32, bytes: u32) -> Result<u32, Fault> {
+        let block = &self.blocks[self.block(addr)?];
+        let mut value = 0;
+        let mut lane = 0;
+        while lane < bytes {
+            let lane_addr = addr + lane;
+            let Some(register) = block.register(lane_addr) else {
+                lane += 1;
+                continue;
+            };
+            let register = &block.registers[register];
+            let start = lane_addr - block.base - register.offset;
+            let count = (register.size - start).min(bytes - lane);
+            let data = ((register.value & register.read_mask) >> (start * 8)) & lane_mask(count);
+            value |= data << (lane * 8);
+            lane += count;
+        }
+        Ok(value)
+    }
+
+    /// Write `bytes` bytes starting at `addr`, the access may span several registers
+    fn write(&mut self, addr: u32, value: u32, bytes: u32) -> Result<(), Fault> {
+        let index = self.block(addr)?;
+        let block = &mut self.blocks[index];
+        let mut lane = 0;
+        while lane < bytes {
+            let lane_addr = addr + lane;
+            let Some(register) = block.register(lane_addr) else {
+                lane += 1;
+                continue;
+            };
+            let base = block.base;
+            let register = &mut block.registers[register];
+            let start = lane_addr - base - register.offset;
+            let count = (register.size - start).min(bytes - lane);
+            let mask = register.write_mask & (lane_mask(count) << (start * 8));
+            let data = ((value >> (lane * 8)) & lane_mask(count)) << (start * 8);
+            register.value = (register.value & !mask) | (data & mask);
+            lane += count;
+        }
+        self.update_lines();
+        Ok(())
+    }
+
+    /// Recompute the interrupt lines requested by the current register values
+    fn update_lines(&mut self) {
+        if self.interrupts.is_empty() {
+            return;
+        }
+        let mut lines = std::mem::take(&mut self.lines);
+        lines.clear();
+        lines.extend(
+            self.interrupts
+                .iter()
+                .filter(|interrupt| {
+                    self.value(interrupt.status) & interrupt.mask != 0
+                        && interrupt
+                            .enable
+                            .is_none_or(|(enable, mask)| self.value(enable) & mask != 0)
+                })
+                .map(|interrupt| interrupt.irqn),
+        );
+        lines.sort_unstable();
+        lines.dedup();
+        self.lines = lines;
+    }
+}
+
+impl Bus for RegisterFile {
+    fn read8(&self, addr: u32) -> Result<u8, Fault> {
+        trace!("read8 0x{addr:x}");
+        Ok(self.read(addr, 1)? as u8)
+    }
+
+    fn read16(&self, addr: u32) -> Result<u16, Fault> {
+        trace!("read16 0x{addr:x}");
+        Ok(self.read(addr, 2)? as u16)
+    }
+
+    fn read32(&mut self, addr: u32) -> Result<u32, Fault> {
+        trace!("read32 0x{addr:x}");
+        self.read(addr, 4)
+    }
+
+    fn write32(&mut self, addr: u32, value: u32) -> Result<(), Fault> {
+        trace!("write32 0x{addr:x} = 0x{value:x}");
+        self.write(addr, value, 4)
+    }
+
+    fn write16(&mut self, addr: u32, value: u16) -> Result<(), Fault> {
+        trace!("write16 0x{addr:x} = 0x{value:x}");
+        self.write(addr, u32::from(value), 2)
+    }
+
+    fn write8(&mut self, addr: u32, value: u8) -> Result<(), Fault> {
+        trace!("write8 0x{addr:x} = 0x{value:x}");
+        self.write(addr, u32::from(value), 1)
+    }
+
+    fn in_range(&self, addr: u32) -> bool {
+        PERIPHERAL_REGION.contains(&addr) || self.blocks.iter().any(|block| block.contains(addr))
+    }
+}
+
+impl Peripheral for RegisterFile {
+    fn tick(&mut self, _cycles: u32, irqs: &mut Vec<IrqLine>) {
+        if self.lines == self.asserted {
+            return;
+        }
+        for &irqn in &self.asserted {
+            if self.lines.binary_search(&irqn).is_err() {
+                irqs.push(IrqLine::deassert(irqn));
+            }
+        }
+        for &irqn in &self.lines {
+            if self.asserted.binary_search(&irqn).is_err() {
+                irqs.push(IrqLine::assert(irqn));
+            }
+        }
+        self.asserted.clone_from(&self.lines);
+    }
+
+    fn next_event(&self) -> Option<u32> {
+        // the interrupt lines only follow register writes
+        None
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> io::Result<()> {
+        for block in &self.blocks {
+            state.write_u32(block.registers.len() as u32)?;
+            for register in &block.registers {
+                state.write_u32(register.value)?;
+            }
+        }
+        state.write_u32(self.asserted.len() as u32)?;
+        for &irqn in &self.asserted {
+            state.write_u32(irqn as u32)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        for block in &mut self.blocks {
+            if state.read_u32()? as usize != block.registers.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: register count differs", block.name),
+                ));
+            }
+            for register in &mut block.registers {
+                register.value = state.read_u32()?;
+            }
+        }
+        let count = state.read_u32()?;
+        self.asserted = (0..count)
+            .map(|_| state.read_u32().map(|irqn| irqn as usize))
+            .collect::<io::Result<_>>()?;
+        self.update_lines();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    fn register_file(text: &str) -> anyhow::Result<RegisterFile> {
+        RegisterFile::new(&Board::parse(text, "toml")?.peripherals)
+    }
+
+    const USART: &str = r#"
+        [[peripheral]]
+        name = "USART1"
+        base = 0x4001_3800
+        size = 0x400
+        register = [
+            { name = "SR", offset = 0x00, reset = 0xc0, read_mask = 0x3ff, write_mask = 0x360 },
+            { name = "DR", offset = 0x04, write_mask = 0x1ff },
+            { name = "CR1", offset = 0x0c },
+        ]
+        interrupt = [{ irq = 5, status = "SR", mask = 0x80, enable = "CR1" }]
+    "#;
+
+    #[test]
+    fn test_register_file_reset_values_and_masks() {
+        // Arrange
+        let mut device = register_file(USART).unwrap();
+
+        // Act
+        device.write32(0x4001_3800, 0xffff_ffff).unwrap();
+        device.write32(0x4001_3804, 0xffff_ffff).unwrap();
+
+        // Assert
+        assert!(device.in_range(0x4001_3bff));
+        assert!(device.in_range(0x4001_3c00));
+        assert!(!device.in_range(0x6000_0000));
+        assert_eq!(device.read32(0x4001_3c00), Err(Fault::Preciserr));
+        assert_eq!(device.write8(0x4000_0000, 1), Err(Fault::Preciserr));
+        assert_eq!(device.read32(0x4001_3800).unwrap(), 0x3e0);
+        assert_eq!(device.read32(0x4001_3804).unwrap(), 0x1ff);
+        assert_eq!(device.read32(0x4001_3808).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_register_file_narrow_accesses() {
+        // Arrange
+        let mut device = register_file(USART).unwrap();
+
+        // Act
+        device.write8(0x4001_380d, 0x20).unwrap();
+        device.write16(0x4001_380e, 0x1234).unwrap();
+
+        // Assert
+        assert_eq!(device.read32(0x4001_380c).unwrap(), 0x1234_2000);
+        assert_eq!(device.read8(0x4001_380d).unwrap(), 0x20);
+        assert_eq!(device.read16(0x4001_380e).unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn test_register_file_narrow_registers() {
+        // Arrange
+        let mut device = register_file(
+            r#"
+            [[peripheral]]
+            name = "UART0"
+            base = 0x4000_2000
+            size = 0x10
+            register = [
+                { name = "DATA", offset = 0, size = 8 },
+                { name = "STATUS", offset = 1, size = 8, reset = 0x81, write_mask = 0 },
+                { name = "BAUD", offset = 2, size = 16, reset = 0x1234 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        // Act
+        device.write32(0x4000_2000, 0xaaaa_bb55).unwrap();
+
+        // Assert
+        assert_eq!(device.read8(0x4000_2000).unwrap(), 0x55);
+        assert_eq!(device.read8(0x4000_2001).unwrap(), 0x81);
+        assert_eq!(device.read16(0x4000_2002).unwrap(), 0xaaaa);
+        assert_eq!(device.read32(0x4000_2000).unwrap(), 0xaaaa_8155);
+    }
+
+    #[test]
+    fn test_register_file_drives_interrupt_from_status_and_enable() {
+        // Arrange
+        let mut device = register_file(USART).unwrap();
+        let mut irqs = Vec::new();
+
+        // Act & Assert
+        device.tick(1, &mut irqs);
+        assert!(irqs.is_empty());
+
+        device.write32(0x4001_380c, 0x80).unwrap();
+        device.tick(1, &mut irqs);
+        assert_eq!(irqs, vec![IrqLine::assert(5)]);
+
+        irqs.clear();
+        device.tick(1, &mut irqs);
+        assert!(irqs.is_empty());
+
+        device.write32(0x4001_380c, 0).unwrap();
+        device.tick(1, &mut irqs);
+        assert_eq!(irqs, vec![IrqLine::deassert(5)]);
+    }
+
+    #[cfg(not(feature = "armv6m"))]
+    #[test]
+    fn test_register_file_drives_interrupt_above_32() {
+        // Arrange
+        let mut device = register_file(&USART.replace("irq = 5", "irq = 37")).unwrap();
+        let mut irqs = Vec::new();
+
+        // Act
+        device.write32(0x4001_380c, 0x80).unwrap();
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(irqs, vec![IrqLine::assert(37)]);
+    }
+
+    #[test]
+    fn test_register_file_raises_interrupt_asserted_at_reset() {
+        // Arrange
+        let mut device = register_file(&USART.replace(
+            r#"{ name = "CR1", offset = 0x0c }"#,
+            r#"{ name = "CR1", offset = 0x0c, reset = 0x80 }"#,
+        ))
+        .unwrap();
+        let mut irqs = Vec::new();
+
+        // Act
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(irqs, vec![IrqLine::assert(5)]);
+    }
+
+    #[test]
+    fn test_register_file_rejects_invalid_descriptions() {
+        let overlapping = r#"
+            [[peripheral]]
+            name = "A"
+            base = 0x4000_0000
+            size = 0x400
+            [[peripheral]]
+            name = "B"
+            base = 0x4000_03fc
+            size = 0x400
+        "#;
+        let unknown_register = r#"
+            [[peripheral]]
+            name = "A"
+            base = 0x4000_0000
+            size = 0x400
+            interrupt = [{ irq = 1, status = "SR" }]
+        "#;
+        let misaligned = r#"
+            [[peripheral]]
+            name = "A"
+            base = 0x4000_0000
+            size = 0x400
+            register = [{ name = "SR", offset = 2 }]
+        "#;
+
+        assert!(register_file(overlapping).is_err());
+        assert!(register_file(unknown_register).is_err());
+        assert!(register_file(misaligned).is_err());
+    }
+
+    #[test]
+    fn test_register_file_state_round_trip() {
+        // Arrange
+        let mut device = register_file(USART).unwrap();
+        let mut irqs = Vec::new();
+        device.write32(0x4001_380c, 0x80).unwrap();
+        device.tick(1, &mut irqs);
+        assert_eq!(irqs, vec![IrqLine::assert(5)]);
+        let mut state = Vec::new();
+        device
+            .save_state(&mut StateWriter::new(&mut state))
+            .unwrap();
+
+        // Act
+        let mut restored = register_file(USART).unwrap();
+        restored
+            .load_state(&mut StateReader::new(&mut state.as_slice()))
+            .unwrap();
+        irqs.clear();
+        restored.tick(1, &mut irqs);
+
+        // Assert: the line is already asserted, no new level change
+        assert_eq!(restored.read32(0x4001_380c).unwrap(), 0x80);
+        assert!(irqs.is_empty());
+    }
+}
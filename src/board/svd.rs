@@ -0,0 +1,560 @@
+//!
+//! CMSIS-SVD import.
+//!
+//! Every peripheral of an SVD file becomes a register file: registers start
+//! from their reset value and read back what was written, limited by the
+//! access type of the register and its fields. Interrupt numbers are taken
+//! from the `<interrupt>` elements, the SVD does not say what raises them.
+//!
+
+use super::{Board, InterruptDescription, PeripheralDescription, RegisterDescription};
+use anyhow::Context;
+use log::{debug, warn};
+use roxmltree::{Document, Node};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl Access {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        match text {
+            "read-only" => Ok(Self::ReadOnly),
+            "write-only" | "writeOnce" => Ok(Self::WriteOnly),
+            "read-write" | "read-writeOnce" => Ok(Self::ReadWrite),
+            _ => anyhow::bail!("unknown access type '{text}'"),
+        }
+    }
+}
+
+///
+/// Register properties, inherited from device to peripheral, cluster and register
+///
+#[derive(Clone, Copy)]
+struct Properties {
+    size: u32,
+    access: Access,
+    reset_value: u32,
+    reset_mask: u32,
+}
+
+impl Properties {
+    fn inherit(self, node: Node) -> anyhow::Result<Self> {
+        Ok(Self {
+            size: optional_number(node, "size")?.unwrap_or(self.size),
+            access: match child_text(node, "access") {
+                Some(access) => Access::parse(access)?,
+                None => self.access,
+            },
+            reset_value: optional_number(node, "resetValue")?.unwrap_or(self.reset_value),
+            reset_mask: optional_number(node, "resetMask")?.unwrap_or(self.reset_mask),
+        })
+    }
+}
+
+///
+/// Parse a CMSIS-SVD file into a board with one peripheral per SVD peripheral.
+///
+pub fn parse(text: &str) -> anyhow::Result<Board> {
+    let document = Document::parse(text)?;
+    let device = document.root_element();
+    anyhow::ensure!(
+        device.has_tag_name("device"),
+        "root element is <{}>, expected <device>",
+        device.tag_name().name()
+    );
+
+    let defaults = Properties {
+        size: 32,
+        access: Access::ReadWrite,
+        reset_value: 0,
+        reset_mask: 0xffff_ffff,
+    }
+    .inherit(device)?;
+
+    let nodes: Vec<Node> = child(device, "peripherals")
+        .context("missing <peripherals>")?
+        .children()
+        .filter(|node| node.has_tag_name("peripheral"))
+        .collect();
+
+    let mut peripherals = Vec::new();
+    for node in &nodes {
+        let name = child_text(*node, "name").context("peripheral without <name>")?;
+        if let Some(peripheral) =
+            peripheral(*node, &nodes, defaults).with_context(|| format!("peripheral {name}"))?
+        {
+            peripherals.push(peripheral);
+        }
+    }
+
+    Ok(Board {
+        name: child_text(device, "name").unwrap_or_default().to_string(),
+        peripherals: without_overlaps(peripherals),
+        ..Board::default()
+    })
+}
+
+fn peripheral(
+    node: Node,
+    nodes: &[Node],
+    defaults: Properties,
+) -> anyhow::Result<Option<PeripheralDescription>> {
+    let name = child_text(node, "name").unwrap_or_default().to_string();
+    if child(node, "alternatePeripheral").is_some() {
+        debug!("{name}: alternate view of another peripheral, skipped");
+        return Ok(None);
+    }
+
+    let derived_from = match node.attribute("derivedFrom") {
+        Some(base) => Some(
+            *nodes
+                .iter()
+                .find(|other| child_text(**other, "name") == Some(base))
+                .with_context(|| format!("derived from unknown peripheral '{base}'"))?,
+        ),
+        None => None,
+    };
+    // elements missing from a derived peripheral come from its base
+    let inherited =
+        |tag: &str| child(node, tag).or_else(|| derived_from.and_then(|base| child(base, tag)));
+
+    let mut properties = defaults;
+    if let Some(base) = derived_from {
+        properties = properties.inherit(base)?;
+    }
+    properties = properties.inherit(node)?;
+
+    let mut registers = Vec::new();
+    if let Some(group) = inherited("registers") {
+        collect_registers(group, 0, "", properties, &mut registers)?;
+    }
+
+    let blocks = if node
+        .children()
+        .any(|child| child.has_tag_name("addressBlock"))
+    {
+        Some(node)
+    } else {
+        derived_from
+    };
+    let mut start = u32::MAX;
+    let mut end = 0_u64;
+    for block in blocks
+        .iter()
+        .flat_map(|blocks| blocks.children())
+        .filter(|child| child.has_tag_name("addressBlock"))
+    {
+        let offset = number(block, "offset")?;
+        start = start.min(offset);
+        end = end.max(u64::from(offset) + u64::from(number(block, "size")?));
+    }
+    for register in &registers {
+        start = start.min(register.offset);
+        end = end.max(u64::from(register.offset) + u64::from(register.size / 8));
+    }
+    if end == 0 {
+        warn!("{name}: no address block or registers, skipped");
+        return Ok(None);
+    }
+    for register in &mut registers {
+        register.offset -= start;
+    }
+
+    let interrupts = node
+        .children()
+        .filter(|child| child.has_tag_name("interrupt"))
+        .map(|interrupt| {
+            Ok(InterruptDescription {
+                irq: number(interrupt, "value")? as usize,
+                status: None,
+                mask: 0xffff_ffff,
+                enable: None,
+                enable_mask: None,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Some(PeripheralDescription {
+        base: number(node, "baseAddress")?
+            .checked_add(start)
+            .context("address block exceeds the address space")?,
+        size: u32::try_from(end - u64::from(start)).context("address block is too large")?,
+        name,
+        registers,
+        interrupts,
+    }))
+}
+
+///
+/// Append registers of a `<registers>` or `<cluster>` element, expanding
+/// register and cluster arrays. Cluster register names get the cluster name
+/// as prefix.
+///
+fn collect_registers(
+    parent: Node,
+    offset: u32,
+    prefix: &str,
+    properties: Properties,
+    registers: &mut Vec<RegisterDescription>,
+) -> anyhow::Result<()> {
+    for node in parent.children() {
+        if node.has_tag_name("register") {
+            let properties = properties.inherit(node)?;
+            for (name, address) in instances(node, offset)? {
+                let name = format!("{prefix}{name}");
+                let register = register(node, name, address, properties).with_context(|| {
+                    format!("register {}", child_text(node, "name").unwrap_or_default())
+                })?;
+                match register {
+                    Some(register) if registers.iter().any(|other| overlaps(other, &register)) => {
+                        debug!("{}: alternate register, skipped", register.name);
+                    }
+                    Some(register) => registers.push(register),
+                    None => {}
+                }
+            }
+        } else if node.has_tag_name("cluster") {
+            let properties = properties.inherit(node)?;
+            for (name, address) in instances(node, offset)? {
+                collect_registers(
+                    node,
+                    address,
+                    &format!("{prefix}{name}."),
+                    properties,
+                    registers,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn overlaps(a: &RegisterDescription, b: &RegisterDescription) -> bool {
+    a.offset < b.offset + b.size / 8 && b.offset < a.offset + a.size / 8
+}
+
+///
+/// Names and addresses of a register or cluster, several for `<dim>` arrays
+///
+fn instances(node: Node, offset: u32) -> anyhow::Result<Vec<(String, u32)>> {
+    let name = child_text(node, "name").context("missing <name>")?;
+    let address = offset
+        .checked_add(number(node, "addressOffset")?)
+        .context("address offset exceeds the address space")?;
+    let Some(dim) = optional_number(node, "dim")? else {
+        return Ok(vec![(name.to_string(), address)]);
+    };
+
+    let increment = number(node, "dimIncrement")?;
+    let indices: Vec<String> = match child_text(node, "dimIndex") {
+        Some(list) => match list.split_once('-') {
+            Some((first, last)) if !list.contains(',') => (parse_number(first)?
+                ..=parse_number(last)?)
+                .map(|index| index.to_string())
+                .collect(),
+            _ => list
+                .split(',')
+                .map(|index| index.trim().to_string())
+                .collect(),
+        },
+        None => (0..dim).map(|index| index.to_string()).collect(),
+    };
+    anyhow::ensure!(
+        indices.len() == dim as usize,
+        "<dimIndex> has {} entries, <dim> is {dim}",
+        indices.len()
+    );
+
+    (0..dim)
+        .zip(indices)
+        .map(|(n, index)| {
+            let address = n
+                .checked_mul(increment)
+                .and_then(|delta| address.checked_add(delta))
+                .context("array exceeds the address space")?;
+            Ok((name.replace("%s", &index), address))
+        })
+        .collect()
+}
+
+fn register(
+    node: Node,
+    name: String,
+    offset: u32,
+    properties: Properties,
+) -> anyhow::Result<Option<RegisterDescription>> {
+    let size_mask = match properties.size {
+        8 | 16 => (1 << properties.size) - 1,
+        32 => 0xffff_ffff,
+        bits => {
+            warn!("{name}: {bits} bit registers are not supported, skipped");
+            return Ok(None);
+        }
+    };
+
+    let fields: Vec<Node> = child(node, "fields")
+        .into_iter()
+        .flat_map(|fields| fields.children())
+        .filter(|field| field.has_tag_name("field"))
+        .collect();
+    let mut read_mask = 0xffff_ffff;
+    // only bits that belong to a field can be written
+    let mut write_mask = if fields.is_empty() { 0xffff_ffff } else { 0 };
+    for field in fields {
+        let mask = field_mask(field)?;
+        let access = match child_text(field, "access") {
+            Some(access) => Access::parse(access)?,
+            None => properties.access,
+        };
+        if access != Access::ReadOnly {
+            write_mask |= mask;
+        }
+        if access == Access::WriteOnly {
+            read_mask &= !mask;
+        }
+    }
+    match properties.access {
+        Access::ReadOnly => write_mask = 0,
+        Access::WriteOnly => read_mask = 0,
+        Access::ReadWrite => {}
+    }
+
+    Ok(Some(RegisterDescription {
+        name,
+        offset,
+        size: properties.size,
+        reset: properties.reset_value & properties.reset_mask & size_mask,
+        read_mask: read_mask & size_mask,
+        write_mask: write_mask & size_mask,
+    }))
+}
+
+///
+/// Bits of a field given as `<bitOffset>`/`<bitWidth>`, `<lsb>`/`<msb>` or `<bitRange>`
+///
+fn field_mask(field: Node) -> anyhow::Result<u32> {
+    let (lsb, msb) = if let Some(range) = child_text(field, "bitRange") {
+        let (msb, lsb) = range
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split_once(':')
+            .with_context(|| format!("invalid <bitRange> '{range}'"))?;
+        (parse_number(lsb)?, parse_number(msb)?)
+    } else if let Some(lsb) = optional_number(field, "lsb")? {
+        (lsb, number(field, "msb")?)
+    } else {
+        let offset = number(field, "bitOffset")?;
+        let width = optional_number(field, "bitWidth")?.unwrap_or(1);
+        anyhow::ensure!(width > 0, "field width must not be zero");
+        (offset, offset + width - 1)
+    };
+    anyhow::ensure!(lsb <= msb && msb < 32, "invalid field bits {msb}:{lsb}");
+    Ok((u32::MAX >> (31 - msb)) & (u32::MAX << lsb))
+}
+
+///
+/// Keep the first of peripherals that share addresses, like the secure and
+/// non-secure or differently named views some vendors publish.
+///
+fn without_overlaps(mut peripherals: Vec<PeripheralDescription>) -> Vec<PeripheralDescription> {
+    peripherals.sort_by_key(|peripheral| peripheral.base);
+    let mut result: Vec<PeripheralDescription> = Vec::with_capacity(peripherals.len());
+    for peripheral in peripherals {
+        if let Some(previous) = result.last()
+            && u64::from(peripheral.base) < u64::from(previous.base) + u64::from(previous.size)
+        {
+            warn!(
+                "{}: overlaps with {}, skipped",
+                peripheral.name, previous.name
+            );
+            continue;
+        }
+        result.push(peripheral);
+    }
+    result
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(tag))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    child(node, tag)
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+fn optional_number(node: Node, tag: &str) -> anyhow::Result<Option<u32>> {
+    child_text(node, tag)
+        .map(|text| parse_number(text).with_context(|| format!("invalid <{tag}> '{text}'")))
+        .transpose()
+}
+
+fn number(node: Node, tag: &str) -> anyhow::Result<u32> {
+    optional_number(node, tag)?.with_context(|| format!("missing <{tag}>"))
+}
+
+///
+/// Parse an SVD scaled non-negative integer: decimal, `0x` hexadecimal or
+/// `#` binary where `x` digits count as zero, with an optional k, M or G suffix.
+///
+fn parse_number(text: &str) -> anyhow::Result<u32> {
+    let text = text.trim();
+    let (digits, scale) = match text.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&text[..index], 1 << 10),
+        Some((index, 'm' | 'M')) => (&text[..index], 1 << 20),
+        Some((index, 'g' | 'G')) => (&text[..index], 1 << 30),
+        _ => (text, 1),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16)?
+    } else if let Some(binary) = digits.strip_prefix('#') {
+        u64::from_str_radix(&binary.replace(['x', 'X'], "0"), 2)?
+    } else {
+        digits.parse::<u64>()?
+    };
+    Ok(u32::try_from(value * scale)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.3">
+  <name>TESTMCU</name>
+  <size>32</size>
+  <access>read-write</access>
+  <resetValue>0x00000000</resetValue>
+  <resetMask>0xFFFFFFFF</resetMask>
+  <peripherals>
+    <peripheral>
+      <name>USART1</name>
+      <baseAddress>0x40013800</baseAddress>
+      <addressBlock><offset>0</offset><size>0x400</size><usage>registers</usage></addressBlock>
+      <interrupt><name>USART1</name><value>37</value></interrupt>
+      <registers>
+        <register>
+          <name>SR</name>
+          <addressOffset>0x0</addressOffset>
+          <resetValue>0xC0</resetValue>
+          <fields>
+            <field><name>TXE</name><bitOffset>7</bitOffset><bitWidth>1</bitWidth><access>read-only</access></field>
+            <field><name>RXNE</name><bitRange>[5:5]</bitRange></field>
+          </fields>
+        </register>
+        <register>
+          <name>DR</name>
+          <addressOffset>0x4</addressOffset>
+          <access>write-only</access>
+        </register>
+        <register>
+          <name>GTPR</name>
+          <addressOffset>0x18</addressOffset>
+          <size>16</size>
+          <resetValue>0x1234</resetValue>
+        </register>
+      </registers>
+    </peripheral>
+    <peripheral derivedFrom="USART1">
+      <name>USART2</name>
+      <baseAddress>0x40004400</baseAddress>
+      <interrupt><name>USART2</name><value>38</value></interrupt>
+    </peripheral>
+    <peripheral>
+      <name>DMA</name>
+      <baseAddress>0x40020000</baseAddress>
+      <registers>
+        <cluster>
+          <dim>2</dim>
+          <dimIncrement>0x14</dimIncrement>
+          <dimIndex>1-2</dimIndex>
+          <name>CH%s</name>
+          <addressOffset>0x8</addressOffset>
+          <register><name>CCR</name><addressOffset>0</addressOffset><resetValue>0x7</resetValue></register>
+          <register><name>CNDTR</name><addressOffset>4</addressOffset><resetMask>0xFFFF</resetMask><resetValue>0xABCD1234</resetValue></register>
+        </cluster>
+      </registers>
+    </peripheral>
+  </peripherals>
+</device>
+"#;
+
+    #[test]
+    fn test_parse_svd_peripherals() {
+        let board = parse(SVD).unwrap();
+
+        assert_eq!(board.name, "TESTMCU");
+        let names: Vec<&str> = board.peripherals.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["USART2", "USART1", "DMA"]);
+
+        let usart2 = &board.peripherals[0];
+        assert_eq!((usart2.base, usart2.size), (0x4000_4400, 0x400));
+        assert_eq!(usart2.registers.len(), 3);
+        assert_eq!(usart2.interrupts.len(), 1);
+        assert_eq!(usart2.interrupts[0].irq, 38);
+
+        let sr = &board.peripherals[1].registers[0];
+        assert_eq!(
+            (sr.reset, sr.read_mask, sr.write_mask),
+            (0xc0, 0xffff_ffff, 0x20)
+        );
+
+        let dma = &board.peripherals[2];
+        assert_eq!((dma.base, dma.size), (0x4002_0008, 0x1c));
+        let registers: Vec<(&str, u32, u32)> = dma
+            .registers
+            .iter()
+            .map(|r| (r.name.as_str(), r.offset, r.reset))
+            .collect();
+        assert_eq!(
+            registers,
+            [
+                ("CH1.CCR", 0, 7),
+                ("CH1.CNDTR", 4, 0x1234),
+                ("CH2.CCR", 0x14, 7),
+                ("CH2.CNDTR", 0x18, 0x1234)
+            ]
+        );
+    }
+
+    #[cfg(feature = "generic-device")]
+    #[test]
+    fn test_svd_registers_behave_like_ram_within_access_rules() {
+        use crate::board::register_file::RegisterFile;
+        use zmu_cortex_m::bus::Bus;
+        use zmu_cortex_m::core::fault::Fault;
+
+        // Arrange
+        let mut device = RegisterFile::new(&parse(SVD).unwrap().peripherals).unwrap();
+
+        // Act
+        device.write32(0x4001_3800, 0xffff_ffff).unwrap();
+        device.write32(0x4001_3804, 0x55).unwrap();
+        device.write16(0x4001_3818, 0x4321).unwrap();
+
+        // Assert
+        assert_eq!(device.read32(0x4001_3800).unwrap(), 0xe0);
+        assert_eq!(device.read32(0x4001_3804).unwrap(), 0);
+        assert_eq!(device.read32(0x4001_3818).unwrap(), 0x4321);
+        assert_eq!(device.read32(0x4000_4418).unwrap(), 0x1234);
+        assert_eq!(device.read32(0x4001_3c00), Err(Fault::Preciserr));
+    }
+
+    #[test]
+    fn test_parse_number_formats() {
+        assert_eq!(parse_number("42").unwrap(), 42);
+        assert_eq!(parse_number("0x40013800").unwrap(), 0x4001_3800);
+        assert_eq!(parse_number("0XFF").unwrap(), 0xff);
+        assert_eq!(parse_number("#1x1").unwrap(), 5);
+        assert_eq!(parse_number("4k").unwrap(), 4096);
+        assert!(parse_number("0x100000000").is_err());
+    }
+}
@@ -1 +1,2 @@
+pub mod serial;
 pub mod stm32f1xx;
@@ -0,0 +1,408 @@
+//!
+//! Host side backends for simulated serial ports.
+//!
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+///
+/// Host side of a simulated serial port.
+///
+pub trait SerialBackend {
+    /// Transmit a byte from the device to the host.
+    fn write(&mut self, byte: u8);
+
+    /// Receive a byte from the host if one is available. Must not block.
+    fn read(&mut self) -> Option<u8>;
+}
+
+///
+/// Open a backend from its command line description:
+/// `stdio`, `file:<path>`, `unix:<path>` or `pty`.
+///
+pub fn open_backend(port: &str, spec: &str) -> anyhow::Result<Box<dyn SerialBackend>> {
+    let backend: Box<dyn SerialBackend> = match spec.split_once(':') {
+        None if spec == "stdio" => Box::new(StdioBackend::new()),
+        Some(("file", path)) => Box::new(FileBackend::create(path)?),
+        #[cfg(unix)]
+        Some(("unix", path)) => Box::new(UnixSocketBackend::bind(path)?),
+        #[cfg(unix)]
+        None if spec == "pty" => {
+            let backend = PtyBackend::open()?;
+            eprintln!("{port}: connected to {}", backend.path);
+            Box::new(backend)
+        }
+        _ => anyhow::bail!(
+            "unsupported serial backend '{spec}' for {port}, expected stdio, file:<path>, unix:<path> or pty"
+        ),
+    };
+    Ok(backend)
+}
+
+///
+/// Keeps the bytes received from a backend so that replayed execution,
+/// e.g. when the debugger steps backwards, receives the same input again
+/// and does not transmit anything to the host.
+///
+pub struct RecordedBackend {
+    backend: Box<dyn SerialBackend>,
+    replaying: bool,
+    /// reads done by the device, part of the device state
+    reads: u64,
+    /// bytes received from the host as (read number, byte), oldest first
+    received: Vec<(u64, u8)>,
+}
+
+impl RecordedBackend {
+    ///
+    /// Record the traffic of `backend`.
+    ///
+    pub fn new(backend: Box<dyn SerialBackend>) -> Self {
+        Self {
+            backend,
+            replaying: false,
+            reads: 0,
+            received: Vec::new(),
+        }
+    }
+
+    /// Replay the recorded input instead of using the host
+    pub fn replay(&mut self, replaying: bool) {
+        self.replaying = replaying;
+    }
+
+    /// Number of reads done, saved with the device state
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    /// Continue from a restored device state
+    pub fn set_reads(&mut self, reads: u64) {
+        self.reads = reads;
+    }
+}
+
+impl SerialBackend for RecordedBackend {
+    fn write(&mut self, byte: u8) {
+        if !self.replaying {
+            self.backend.write(byte);
+        }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let read = self.reads;
+        self.reads += 1;
+        if self.replaying {
+            return self
+                .received
+                .binary_search_by_key(&read, |(read, _)| *read)
+                .ok()
+                .map(|index| self.received[index].1);
+        }
+        // the execution differs from the recording after this point
+        let recorded = self
+            .received
+            .partition_point(|(earlier, _)| *earlier < read);
+        self.received.truncate(recorded);
+        let byte = self.backend.read();
+        if let Some(byte) = byte {
+            self.received.push((read, byte));
+        }
+        byte
+    }
+}
+
+///
+/// Transmits to stdout and receives from stdin.
+///
+struct StdioBackend {
+    stdin: Option<Receiver<u8>>,
+}
+
+impl StdioBackend {
+    fn new() -> Self {
+        Self { stdin: None }
+    }
+}
+
+impl SerialBackend for StdioBackend {
+    fn write(&mut self, byte: u8) {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        // stdin is read on a separate thread so that the simulation never
+        // blocks. The thread is started only once the firmware listens.
+        let stdin = self.stdin.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for byte in io::stdin().lock().bytes() {
+                    match byte {
+                        Ok(byte) if sender.send(byte).is_ok() => {}
+                        _ => break,
+                    }
+                }
+            });
+            receiver
+        });
+        stdin.try_recv().ok()
+    }
+}
+
+///
+/// Transmits to a file, never receives anything.
+///
+struct FileBackend {
+    file: File,
+}
+
+impl FileBackend {
+    fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            file: File::create(path)?,
+        })
+    }
+}
+
+impl SerialBackend for FileBackend {
+    fn write(&mut self, byte: u8) {
+        let _ = self.file.write_all(&[byte]);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+///
+/// Listens on a Unix domain socket. A single client at a time is served,
+/// data transmitted while no client is connected is dropped.
+///
+#[cfg(unix)]
+struct UnixSocketBackend {
+    listener: UnixListener,
+    stream: Option<UnixStream>,
+}
+
+#[cfg(unix)]
+impl UnixSocketBackend {
+    fn bind(path: &str) -> anyhow::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        // Replace a stale socket left behind by an earlier run
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            anyhow::ensure!(
+                metadata.file_type().is_socket(),
+                "'{path}' exists and is not a socket"
+            );
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            stream: None,
+        })
+    }
+
+    fn client(&mut self) -> Option<&mut UnixStream> {
+        if self.stream.is_none()
+            && let Ok((stream, _)) = self.listener.accept()
+            && stream.set_nonblocking(true).is_ok()
+        {
+            self.stream = Some(stream);
+        }
+        self.stream.as_mut()
+    }
+}
+
+#[cfg(unix)]
+impl SerialBackend for UnixSocketBackend {
+    fn write(&mut self, byte: u8) {
+        if let Some(stream) = self.client()
+            && stream.write_all(&[byte]).is_err()
+        {
+            self.stream = None;
+        }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let stream = self.client()?;
+        let mut buf = [0; 1];
+        match stream.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+            _ => {
+                // client went away
+                self.stream = None;
+                None
+            }
+        }
+    }
+}
+
+///
+/// Pseudo-terminal in raw mode. Terminal programs connect to the
+/// secondary side reported by `path`.
+///
+#[cfg(unix)]
+struct PtyBackend {
+    master: File,
+    path: String,
+}
+
+#[cfg(unix)]
+impl PtyBackend {
+    fn open() -> io::Result<Self> {
+        use std::ffi::CStr;
+        use std::os::fd::FromRawFd;
+
+        // SAFETY: plain libc calls on a descriptor owned by this function,
+        // the name returned by ptsname is copied before any other call.
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(fd, &raw mut termios) == 0 {
+                libc::cfmakeraw(&raw mut termios);
+                libc::tcsetattr(fd, libc::TCSANOW, &raw const termios);
+            }
+
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self { master, path })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl SerialBackend for PtyBackend {
+    fn write(&mut self, byte: u8) {
+        // fails while no terminal is attached and the buffer is full
+        let _ = self.master.write(&[byte]);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let mut buf = [0; 1];
+        match self.master.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_open_backend_rejects_unknown_backend() {
+        assert!(open_backend("usart1", "tcp:1234").is_err());
+        assert!(open_backend("usart1", "stdout").is_err());
+    }
+
+    #[test]
+    fn test_file_backend_writes_transmitted_bytes() {
+        let path = std::env::temp_dir().join(format!("zmu-serial-{}.txt", std::process::id()));
+        let mut backend = open_backend("usart1", &format!("file:{}", path.display())).unwrap();
+
+        backend.write(b'o');
+        backend.write(b'k');
+        drop(backend);
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"ok");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Receives every second read the next even number
+    struct CountingBackend {
+        sent: Rc<RefCell<Vec<u8>>>,
+        next: u8,
+    }
+
+    impl SerialBackend for CountingBackend {
+        fn write(&mut self, byte: u8) {
+            self.sent.borrow_mut().push(byte);
+        }
+
+        fn read(&mut self) -> Option<u8> {
+            self.next += 1;
+            (self.next % 2 == 0).then_some(self.next)
+        }
+    }
+
+    #[test]
+    fn test_recorded_backend_replays_received_bytes() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let mut backend = RecordedBackend::new(Box::new(CountingBackend {
+            sent: Rc::clone(&sent),
+            next: 0,
+        }));
+        let received: Vec<_> = (0..4).map(|_| backend.read()).collect();
+        assert_eq!(received, [None, Some(2), None, Some(4)]);
+
+        backend.set_reads(1);
+        backend.replay(true);
+        backend.write(b'x');
+        let replayed: Vec<_> = (0..3).map(|_| backend.read()).collect();
+        backend.replay(false);
+
+        assert_eq!(replayed, [Some(2), None, Some(4)]);
+        assert!(sent.borrow().is_empty());
+        assert_eq!(backend.read(), None);
+        assert_eq!(backend.read(), Some(6));
+        backend.set_reads(3);
+        assert_eq!(backend.read(), None);
+        assert_eq!(backend.received, [(1, 2)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_backend_exchanges_bytes() {
+        let path = std::env::temp_dir().join(format!("zmu-serial-{}.sock", std::process::id()));
+        let mut backend = open_backend("usart1", &format!("unix:{}", path.display())).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+
+        backend.write(b'x');
+        client.write_all(b"y").unwrap();
+
+        let mut buf = [0; 1];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"x");
+        let mut received = None;
+        for _ in 0..100 {
+            received = backend.read();
+            if received.is_some() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(received, Some(b'y'));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
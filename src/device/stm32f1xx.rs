@@ -3,9 +3,15 @@
 //!
 //!
 
-use zmu_cortex_m::bus::Bus;
+use std::cell::Cell;
+use std::io;
+
+use super::serial::{RecordedBackend, SerialBackend};
+use log::{debug, trace};
+use zmu_cortex_m::bus::{Bus, IrqLine, Peripheral};
 use zmu_cortex_m::core::bits::Bits;
 use zmu_cortex_m::core::fault::Fault;
+use zmu_cortex_m::snapshot::{StateReader, StateWriter};
 
 const PERIPH_BASE: u32 = 0x4000_0000;
 
@@ -76,6 +82,13 @@ const RCC_BASE_END: u32 = RCC_BASE + 0x24;
 const FLASH_R_BASE: u32 = AHBPERIPH_BASE + 0x2000;
 const FLASH_R_BASE_END: u32 = FLASH_R_BASE + 0x04;
 
+const USART2_BASE: u32 = APB1PERIPH_BASE + 0x4400;
+const USART2_BASE_END: u32 = USART2_BASE + 0x18;
+const USART3_BASE: u32 = APB1PERIPH_BASE + 0x4800;
+const USART3_BASE_END: u32 = USART3_BASE + 0x18;
+const USART1_BASE: u32 = APB2PERIPH_BASE + 0x3800;
+const USART1_BASE_END: u32 = USART1_BASE + 0x18;
+
 #[allow(non_snake_case)]
 struct RCCRegisters {
     ///
@@ -194,49 +207,653 @@ enum BasicTimerType {
     TIM7,
 }
 
-#[allow(non_snake_case, dead_code)]
-struct MinimumTimerRegisters {
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimerKind {
+    /// TIM1 and TIM8
+    AdvancedControl,
+    /// TIM2 .. TIM5
+    GeneralPurpose,
+    /// TIM9 .. TIM14, with given number of capture/compare channels
+    GeneralPurpose2 { channels: usize },
+    /// TIM6 and TIM7
+    Basic,
+}
+
+const TIM_CR1_CEN: usize = 0;
+const TIM_CR1_UDIS: usize = 1;
+const TIM_CR1_URS: usize = 2;
+const TIM_CR1_OPM: usize = 3;
+const TIM_CR1_DIR: usize = 4;
+const TIM_CR1_ARPE: usize = 7;
+
+const TIM_SR_UIF: usize = 0;
+const TIM_SR_COMIF: usize = 5;
+const TIM_SR_TIF: usize = 6;
+const TIM_SR_BIF: usize = 7;
+
+const TIM_EGR_UG: usize = 0;
+
+///
+/// Register set of a single timer. All F1 timer types share the same
+/// register layout, the `kind` decides which of the registers exist.
+///
+#[allow(non_snake_case)]
+struct TimerRegisters {
+    kind: TimerKind,
+    ///
+    /// 0
+    ///
     CR1: u32,
+    ///
+    /// 0x4
+    ///
+    CR2: u32,
+    ///
+    /// 0x8
+    ///
+    SMCR: u32,
+    ///
+    /// 0xc
+    ///
     DIER: u32,
+    ///
+    /// 0x10
+    ///
     SR: u32,
-    EGR: u32,
+    ///
+    /// 0x18
+    ///
+    CCMR1: u32,
+    ///
+    /// 0x1c
+    ///
+    CCMR2: u32,
+    ///
+    /// 0x20
+    ///
+    CCER: u32,
+    ///
+    /// 0x24
+    ///
     CNT: u32,
+    ///
+    /// 0x28
+    ///
     PSC: u32,
+    ///
+    /// 0x2c
+    ///
     ARR: u32,
+    ///
+    /// 0x30
+    ///
+    RCR: u32,
+    ///
+    /// 0x34 .. 0x40
+    ///
+    CCR: [u32; 4],
+    ///
+    /// 0x44
+    ///
+    BDTR: u32,
+    ///
+    /// 0x48
+    ///
+    DCR: u32,
+    ///
+    /// 0x4c
+    ///
+    DMAR: u32,
+
+    // Shadow registers and internal counters, not visible to software
+    psc_shadow: u32,
+    arr_shadow: u32,
+    repetition_counter: u32,
+    prescaler_counter: u32,
 }
 
-#[allow(non_snake_case, dead_code)]
-struct BasicTimerRegisters {
-    min: MinimumTimerRegisters,
-    CR2: u32,
+impl TimerRegisters {
+    fn new(kind: TimerKind) -> Self {
+        Self {
+            kind,
+            CR1: 0,
+            CR2: 0,
+            SMCR: 0,
+            DIER: 0,
+            SR: 0,
+            CCMR1: 0,
+            CCMR2: 0,
+            CCER: 0,
+            CNT: 0,
+            PSC: 0,
+            ARR: 0x0000_ffff,
+            RCR: 0,
+            CCR: [0; 4],
+            BDTR: 0,
+            DCR: 0,
+            DMAR: 0,
+            psc_shadow: 0,
+            arr_shadow: 0x0000_ffff,
+            repetition_counter: 0,
+            prescaler_counter: 0,
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self.kind {
+            TimerKind::AdvancedControl | TimerKind::GeneralPurpose => 4,
+            TimerKind::GeneralPurpose2 { channels } => channels,
+            TimerKind::Basic => 0,
+        }
+    }
+
+    fn implements(&self, offset: u32) -> bool {
+        let advanced = self.kind == TimerKind::AdvancedControl;
+        match offset {
+            // CR1, DIER, SR, EGR, CNT, PSC, ARR
+            0x0 | 0xc | 0x10 | 0x14 | 0x24 | 0x28 | 0x2c => true,
+            0x4 => !matches!(self.kind, TimerKind::GeneralPurpose2 { .. }),
+            0x8 => self.kind != TimerKind::Basic && self.channels() > 1,
+            0x18 | 0x20 => self.channels() > 0,
+            0x1c => self.channels() > 2,
+            0x30 | 0x44 => advanced,
+            0x34..=0x40 => ((offset - 0x34) >> 2) < self.channels() as u32,
+            0x48 | 0x4c => advanced || self.kind == TimerKind::GeneralPurpose,
+            _ => false,
+        }
+    }
+
+    fn read32(&self, offset: u32) -> Result<u32, Fault> {
+        if !self.implements(offset) || offset & 3 != 0 {
+            return Err(Fault::DAccViol);
+        }
+        let result = match offset {
+            0x0 => self.CR1,
+            0x4 => self.CR2,
+            0x8 => self.SMCR,
+            0xc => self.DIER,
+            0x10 => self.SR,
+            0x18 => self.CCMR1,
+            0x1c => self.CCMR2,
+            0x20 => self.CCER,
+            0x24 => self.CNT,
+            0x28 => self.PSC,
+            0x2c => self.ARR,
+            0x30 => self.RCR,
+            0x34..=0x40 => self.CCR[((offset - 0x34) >> 2) as usize],
+            0x44 => self.BDTR,
+            0x48 => self.DCR,
+            0x4c => self.DMAR,
+            // EGR is write only
+            _ => 0,
+        };
+        Ok(result)
+    }
+
+    fn write32(&mut self, offset: u32, value: u32) -> Result<(), Fault> {
+        if !self.implements(offset) || offset & 3 != 0 {
+            return Err(Fault::DAccViol);
+        }
+        let value = value & 0xffff;
+        match offset {
+            0x0 => self.CR1 = value & 0x3ff,
+            0x4 => self.CR2 = value,
+            0x8 => self.SMCR = value,
+            0xc => self.DIER = value,
+            // rc_w0: writing zero clears a flag, one has no effect
+            0x10 => self.SR &= value,
+            0x14 => self.generate_events(value),
+            0x18 => self.CCMR1 = value,
+            0x1c => self.CCMR2 = value,
+            0x20 => self.CCER = value,
+            0x24 => self.CNT = value,
+            0x28 => self.PSC = value,
+            0x2c => {
+                self.ARR = value;
+                if !self.CR1.get_bit(TIM_CR1_ARPE) {
+                    self.arr_shadow = value;
+                }
+            }
+            0x30 => self.RCR = value & 0xff,
+            0x34..=0x40 => self.CCR[((offset - 0x34) >> 2) as usize] = value,
+            0x44 => self.BDTR = value,
+            0x48 => self.DCR = value,
+            0x4c => self.DMAR = value,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Software event generation via EGR
+    fn generate_events(&mut self, egr: u32) {
+        if egr.get_bit(TIM_EGR_UG) {
+            self.prescaler_counter = 0;
+            self.CNT = if self.counting_down() && !self.center_aligned() {
+                self.arr_shadow
+            } else {
+                0
+            };
+            if !self.CR1.get_bit(TIM_CR1_UDIS) {
+                self.load_shadow_registers();
+                if !self.CR1.get_bit(TIM_CR1_URS) {
+                    self.SR.set_bit(TIM_SR_UIF, true);
+                }
+            }
+        }
+        for channel in 0..self.channels() {
+            if egr.get_bit(channel + 1) {
+                self.SR.set_bit(channel + 1, true);
+            }
+        }
+        if self.kind != TimerKind::Basic && egr.get_bit(TIM_SR_TIF) {
+            self.SR.set_bit(TIM_SR_TIF, true);
+        }
+        if self.kind == TimerKind::AdvancedControl {
+            for bit in [TIM_SR_COMIF, TIM_SR_BIF] {
+                if egr.get_bit(bit) {
+                    self.SR.set_bit(bit, true);
+                }
+            }
+        }
+    }
+
+    fn load_shadow_registers(&mut self) {
+        self.psc_shadow = self.PSC;
+        self.arr_shadow = self.ARR;
+        self.repetition_counter = self.RCR;
+    }
+
+    fn counting_down(&self) -> bool {
+        self.kind != TimerKind::Basic && self.CR1.get_bit(TIM_CR1_DIR)
+    }
+
+    fn center_aligned(&self) -> bool {
+        self.kind != TimerKind::Basic && self.CR1.get_bits(5..7) != 0
+    }
+
+    /// Channel is in output compare mode (CCxS = 00)
+    fn output_compare(&self, channel: usize) -> bool {
+        let ccmr = if channel < 2 { self.CCMR1 } else { self.CCMR2 };
+        let shift = (channel % 2) * 8;
+        ccmr.get_bits(shift..shift + 2) == 0
+    }
+
+    ///
+    /// Advance the timer by `cycles` core clock cycles. `divider` is the
+    /// ratio between the core clock and the timer kernel clock.
+    ///
+    fn tick(&mut self, cycles: u32, divider: u32) {
+        if !self.CR1.get_bit(TIM_CR1_CEN) {
+            return;
+        }
+        let period = (self.psc_shadow + 1) * divider;
+        self.prescaler_counter += cycles;
+        while self.prescaler_counter >= period {
+            self.prescaler_counter -= period;
+            self.count();
+            if !self.CR1.get_bit(TIM_CR1_CEN) {
+                // one pulse mode stopped the counter
+                self.prescaler_counter = 0;
+                break;
+            }
+        }
+    }
+
+    ///
+    /// Core clock cycles until the counter may next set a status flag,
+    /// `None` when it is stopped. `divider` is the same as for `tick`.
+    ///
+    fn next_event(&self, divider: u32) -> Option<u32> {
+        if !self.CR1.get_bit(TIM_CR1_CEN) || self.arr_shadow == 0 {
+            return None;
+        }
+        let period = u64::from(self.psc_shadow + 1) * u64::from(divider);
+        let prescaler_counter = u64::from(self.prescaler_counter);
+        if prescaler_counter >= period {
+            return Some(1);
+        }
+        let cycles = (self.counts_to_event() - 1) * period + (period - prescaler_counter);
+        Some(u32::try_from(cycles).unwrap_or(u32::MAX))
+    }
+
+    ///
+    /// Counter steps until the next overflow or compare match. Counted for
+    /// an edge aligned up counter, other modes report the next step.
+    ///
+    fn counts_to_event(&self) -> u64 {
+        if self.center_aligned() || self.counting_down() {
+            return 1;
+        }
+        let arr = u64::from(self.arr_shadow);
+        let cnt = u64::from(self.CNT);
+        // the counter takes the values cnt + 1..=arr and wraps to 0 on the overflow
+        let overflow = arr.saturating_sub(cnt) + 1;
+        (0..self.channels())
+            .filter(|channel| self.output_compare(*channel))
+            .filter_map(|channel| match u64::from(self.CCR[channel]) {
+                0 => Some(overflow),
+                ccr if ccr > cnt && ccr <= arr => Some(ccr - cnt),
+                _ => None,
+            })
+            .fold(overflow, u64::min)
+    }
+
+    /// Single step of the counter, driven by the prescaler output
+    fn count(&mut self) {
+        let arr = self.arr_shadow;
+        if arr == 0 {
+            // counter is blocked while the auto-reload value is null
+            return;
+        }
+
+        let overflow = if self.center_aligned() {
+            if self.CR1.get_bit(TIM_CR1_DIR) {
+                self.CNT = self.CNT.saturating_sub(1);
+                if self.CNT == 0 {
+                    self.CR1.set_bit(TIM_CR1_DIR, false);
+                }
+                self.CNT == 0
+            } else {
+                self.CNT += 1;
+                if self.CNT >= arr {
+                    self.CNT = arr;
+                    self.CR1.set_bit(TIM_CR1_DIR, true);
+                }
+                self.CNT == arr
+            }
+        } else if self.counting_down() {
+            if self.CNT == 0 {
+                self.CNT = arr;
+                true
+            } else {
+                self.CNT -= 1;
+                false
+            }
+        } else if self.CNT >= arr {
+            self.CNT = 0;
+            true
+        } else {
+            self.CNT += 1;
+            false
+        };
+
+        for channel in 0..self.channels() {
+            if self.output_compare(channel) && self.CNT == self.CCR[channel] {
+                self.SR.set_bit(channel + 1, true);
+            }
+        }
+
+        if overflow {
+            if self.repetition_counter > 0 {
+                self.repetition_counter -= 1;
+            } else {
+                self.update_event();
+            }
+        }
+    }
+
+    fn update_event(&mut self) {
+        if self.CR1.get_bit(TIM_CR1_UDIS) {
+            return;
+        }
+        self.load_shadow_registers();
+        self.SR.set_bit(TIM_SR_UIF, true);
+        if self.CR1.get_bit(TIM_CR1_OPM) {
+            self.CR1.set_bit(TIM_CR1_CEN, false);
+        }
+    }
+
+    /// Status flags that have their interrupt enabled
+    fn pending_interrupts(&self) -> u32 {
+        self.SR & self.DIER & 0xff
+    }
 }
 
-#[allow(non_snake_case, dead_code)]
-struct GeneralPurposeTimerRegisters {
-    min: MinimumTimerRegisters,
-    SMCR: u32,
-    CCMR1: u32,
-    CCER: u32,
-    CCR1: u32,
+/// NVIC lines of an advanced control timer
+struct AdvancedTimerIrqs {
+    brk: usize,
+    up: usize,
+    trg_com: usize,
+    cc: usize,
 }
 
-#[allow(non_snake_case, dead_code)]
-struct GeneralPurposeTimer2Registers {
-    gp: GeneralPurposeTimerRegisters,
+const TIM1_8_IRQN: [AdvancedTimerIrqs; 2] = [
+    AdvancedTimerIrqs {
+        brk: 24,
+        up: 25,
+        trg_com: 26,
+        cc: 27,
+    },
+    AdvancedTimerIrqs {
+        brk: 43,
+        up: 44,
+        trg_com: 45,
+        cc: 46,
+    },
+];
+const TIM2_5_IRQN: [usize; 4] = [28, 29, 30, 50];
+const TIM6_7_IRQN: [usize; 2] = [54, 55];
+// TIM9..TIM14 share the lines of TIM1 and TIM8 BRK, UP and TRG_COM
+const TIM9_14_IRQN: [usize; 6] = [24, 25, 26, 43, 44, 45];
+
+///
+/// Ratio between core clock and the timer kernel clock for given APB
+/// prescaler setting. Timer clock is doubled when the APB is divided.
+///
+fn timer_clock_divider(ppre: u32) -> u32 {
+    if ppre < 0b100 { 1 } else { 1 << (ppre - 0b100) }
+}
+
+const USART_SR_PE: usize = 0;
+const USART_SR_ORE: usize = 3;
+const USART_SR_RXNE: usize = 5;
+const USART_SR_TC: usize = 6;
+const USART_SR_TXE: usize = 7;
+
+const USART_CR1_RE: usize = 2;
+const USART_CR1_TE: usize = 3;
+const USART_CR1_RXNEIE: usize = 5;
+const USART_CR1_PEIE: usize = 8;
+const USART_CR1_M: usize = 12;
+const USART_CR1_UE: usize = 13;
+
+/// Minimum interval in core cycles between polls of the host backend
+const USART_RX_POLL_CYCLES: u32 = 1000;
+
+const USART_IRQN: [usize; 3] = [37, 38, 39];
+
+///
+/// Universal synchronous asynchronous receiver transmitter. Data moves
+/// between the data register and the host backend at the configured
+/// baud rate.
+///
+#[allow(non_snake_case)]
+struct USARTRegisters {
+    ///
+    /// 0, data register reads clear flags so this needs interior mutability
+    ///
+    SR: Cell<u32>,
+    ///
+    /// 0x8
+    ///
+    BRR: u32,
+    ///
+    /// 0xc
+    ///
+    CR1: u32,
+    ///
+    /// 0x10
+    ///
     CR2: u32,
-    CCMR2: u32,
-    CCR2: u32,
-    CCR3: u32,
-    CCR4: u32,
-    DCR: u32,
-    DMAR: u32,
+    ///
+    /// 0x14
+    ///
+    CR3: u32,
+    ///
+    /// 0x18
+    ///
+    GTPR: u32,
+
+    // Receive data, transmit data and shift registers behind DR
+    rdr: u32,
+    tdr: Option<u8>,
+    tx_shift: Option<u8>,
+    tx_cycles: u32,
+    rx_cycles: u32,
+
+    backend: Option<RecordedBackend>,
 }
 
-#[allow(non_snake_case, dead_code)]
-struct AdvancedControlTimerRegisters {
-    gp: GeneralPurposeTimer2Registers,
-    RCR: u32,
-    BDTR: u32,
+impl USARTRegisters {
+    fn new() -> Self {
+        Self {
+            SR: Cell::new(0xc0),
+            BRR: 0,
+            CR1: 0,
+            CR2: 0,
+            CR3: 0,
+            GTPR: 0,
+            rdr: 0,
+            tdr: None,
+            tx_shift: None,
+            tx_cycles: 0,
+            rx_cycles: 0,
+            backend: None,
+        }
+    }
+
+    fn read(&self, offset: u32) -> Result<u32, Fault> {
+        let result = match offset {
+            0x0 => self.SR.get(),
+            0x4 => {
+                // reading DR acknowledges the received byte and the errors
+                let mut sr = self.SR.get();
+                sr.set_bits(0..6, 0);
+                self.SR.set(sr);
+                self.rdr
+            }
+            0x8 => self.BRR,
+            0xc => self.CR1,
+            0x10 => self.CR2,
+            0x14 => self.CR3,
+            0x18 => self.GTPR,
+            _ => return Err(Fault::DAccViol),
+        };
+        Ok(result)
+    }
+
+    fn write(&mut self, offset: u32, value: u32, apb_divider: u32) -> Result<(), Fault> {
+        let value = value & 0xffff;
+        match offset {
+            // rc_w0 for CTS, LBD, TC and RXNE, the rest is read only
+            0x0 => self.SR.set(self.SR.get() & (value | !0x360)),
+            0x4 => {
+                let byte = value as u8;
+                let mut sr = self.SR.get();
+                sr.set_bit(USART_SR_TC, false);
+                if self.tx_shift.is_none() {
+                    self.tx_shift = Some(byte);
+                    self.tx_cycles = self.frame_cycles(apb_divider);
+                } else {
+                    self.tdr = Some(byte);
+                    sr.set_bit(USART_SR_TXE, false);
+                }
+                self.SR.set(sr);
+            }
+            0x8 => self.BRR = value,
+            0xc => self.CR1 = value & 0x3fff,
+            0x10 => self.CR2 = value,
+            0x14 => self.CR3 = value,
+            0x18 => self.GTPR = value,
+            _ => return Err(Fault::DAccViol),
+        }
+        Ok(())
+    }
+
+    /// Duration of a single character in core clock cycles
+    fn frame_cycles(&self, apb_divider: u32) -> u32 {
+        let bits = if self.CR1.get_bit(USART_CR1_M) {
+            11
+        } else {
+            10
+        };
+        self.BRR * bits * apb_divider
+    }
+
+    fn tick(&mut self, cycles: u32, apb_divider: u32) {
+        if !self.CR1.get_bit(USART_CR1_UE) {
+            return;
+        }
+
+        if self.CR1.get_bit(USART_CR1_TE)
+            && let Some(byte) = self.tx_shift
+        {
+            self.tx_cycles = self.tx_cycles.saturating_sub(cycles);
+            if self.tx_cycles == 0 {
+                if let Some(backend) = self.backend.as_mut() {
+                    backend.write(byte);
+                }
+                let mut sr = self.SR.get();
+                self.tx_shift = self.tdr.take();
+                if self.tx_shift.is_some() {
+                    self.tx_cycles = self.frame_cycles(apb_divider);
+                    sr.set_bit(USART_SR_TXE, true);
+                } else {
+                    sr.set_bit(USART_SR_TC, true);
+                }
+                self.SR.set(sr);
+            }
+        }
+
+        if self.CR1.get_bit(USART_CR1_RE) {
+            self.rx_cycles = self.rx_cycles.saturating_sub(cycles);
+            if self.rx_cycles == 0 {
+                self.rx_cycles = self.frame_cycles(apb_divider).max(USART_RX_POLL_CYCLES);
+                if let Some(byte) = self.backend.as_mut().and_then(|b| b.read()) {
+                    let mut sr = self.SR.get();
+                    if sr.get_bit(USART_SR_RXNE) {
+                        // previous byte not read yet, the new one is lost
+                        sr.set_bit(USART_SR_ORE, true);
+                    } else {
+                        self.rdr = u32::from(byte);
+                        sr.set_bit(USART_SR_RXNE, true);
+                    }
+                    self.SR.set(sr);
+                }
+            }
+        }
+    }
+
+    /// Core clock cycles until the next character is sent or received
+    fn next_event(&self) -> Option<u32> {
+        if !self.CR1.get_bit(USART_CR1_UE) {
+            return None;
+        }
+        let tx =
+            (self.CR1.get_bit(USART_CR1_TE) && self.tx_shift.is_some()).then_some(self.tx_cycles);
+        let rx = self.CR1.get_bit(USART_CR1_RE).then_some(self.rx_cycles);
+        [tx, rx]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|cycles| cycles.max(1))
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        let sr = self.SR.get();
+        // IDLE, RXNE, TC and TXE share bit positions with their enables
+        self.CR1.get_bit(USART_CR1_UE)
+            && ((sr & self.CR1 & 0xf0) != 0
+                || (sr.get_bit(USART_SR_PE) && self.CR1.get_bit(USART_CR1_PEIE))
+                || (sr.get_bit(USART_SR_ORE) && self.CR1.get_bit(USART_CR1_RXNEIE)))
+    }
+}
+
+///
+/// Ratio between core clock and the APB clock for given APB prescaler setting.
+///
+fn apb_clock_divider(ppre: u32) -> u32 {
+    if ppre < 0b100 { 1 } else { 1 << (ppre - 0b011) }
 }
 
 ///
@@ -246,10 +863,12 @@ pub struct Device {
     rcc: RCCRegisters,
     gpio: [GPIORegisters; 7],
     flash: FLASHRegisters,
-    tim1_8: [AdvancedControlTimerRegisters; 2],
-    tim2_5: [GeneralPurposeTimer2Registers; 4],
-    tim6_7: [BasicTimerRegisters; 2],
-    tim9_14: [GeneralPurposeTimerRegisters; 6],
+    tim1_8: [TimerRegisters; 2],
+    tim2_5: [TimerRegisters; 4],
+    tim6_7: [TimerRegisters; 2],
+    tim9_14: [TimerRegisters; 6],
+    usart: [USARTRegisters; 3],
+    irq_asserted: u128,
 }
 
 impl Device {
@@ -258,7 +877,7 @@ impl Device {
     ///
     #[allow(clippy::too_many_lines)]
     pub fn new() -> Self {
-        println!("initialize stm32f1xx");
+        debug!("initialize stm32f1xx");
         Self {
             afio: AFIORegisters {
                 EVCR: 0,
@@ -331,281 +950,83 @@ impl Device {
             ],
             flash: FLASHRegisters { ACR: 0x30 },
             tim1_8: [
-                AdvancedControlTimerRegisters {
-                    gp: GeneralPurposeTimer2Registers {
-                        gp: GeneralPurposeTimerRegisters {
-                            min: MinimumTimerRegisters {
-                                CR1: 0x0,
-                                CNT: 0x0,
-                                DIER: 0x0,
-                                EGR: 0x0,
-                                PSC: 0x0,
-                                SR: 0x0,
-                                ARR: 0x0000_ffff,
-                            },
-                            CCER: 0x0,
-                            CCMR1: 0x0,
-                            CCR1: 0x0,
-                            SMCR: 0x0,
-                        },
-                        CCMR2: 0x0,
-                        CCR2: 0x0,
-                        CCR3: 0x0,
-                        CCR4: 0x0,
-                        CR2: 0x0,
-                        DMAR: 0x0,
-                        DCR: 0x0,
-                    },
-                    BDTR: 0x0,
-                    RCR: 0x0,
-                },
-                AdvancedControlTimerRegisters {
-                    gp: GeneralPurposeTimer2Registers {
-                        gp: GeneralPurposeTimerRegisters {
-                            min: MinimumTimerRegisters {
-                                CR1: 0x0,
-                                CNT: 0x0,
-                                DIER: 0x0,
-                                EGR: 0x0,
-                                PSC: 0x0,
-                                SR: 0x0,
-                                ARR: 0x0000_ffff,
-                            },
-                            CCER: 0x0,
-                            CCMR1: 0x0,
-                            CCR1: 0x0,
-                            SMCR: 0x0,
-                        },
-                        CCMR2: 0x0,
-                        CCR2: 0x0,
-                        CCR3: 0x0,
-                        CCR4: 0x0,
-                        CR2: 0x0,
-                        DMAR: 0x0,
-                        DCR: 0x0,
-                    },
-                    BDTR: 0x0,
-                    RCR: 0x0,
-                },
+                TimerRegisters::new(TimerKind::AdvancedControl),
+                TimerRegisters::new(TimerKind::AdvancedControl),
             ],
-
             tim2_5: [
-                GeneralPurposeTimer2Registers {
-                    gp: GeneralPurposeTimerRegisters {
-                        min: MinimumTimerRegisters {
-                            CR1: 0x0,
-                            CNT: 0x0,
-                            DIER: 0x0,
-                            EGR: 0x0,
-                            PSC: 0x0,
-                            SR: 0x0,
-                            ARR: 0x0000_ffff,
-                        },
-                        CCER: 0x0,
-                        CCMR1: 0x0,
-                        CCR1: 0x0,
-                        SMCR: 0x0,
-                    },
-                    CCMR2: 0x0,
-                    CCR2: 0x0,
-                    CCR3: 0x0,
-                    CCR4: 0x0,
-                    CR2: 0x0,
-                    DMAR: 0x0,
-                    DCR: 0x0,
-                },
-                GeneralPurposeTimer2Registers {
-                    gp: GeneralPurposeTimerRegisters {
-                        min: MinimumTimerRegisters {
-                            CR1: 0x0,
-                            CNT: 0x0,
-                            DIER: 0x0,
-                            EGR: 0x0,
-                            PSC: 0x0,
-                            SR: 0x0,
-                            ARR: 0x0000_ffff,
-                        },
-                        CCER: 0x0,
-                        CCMR1: 0x0,
-                        CCR1: 0x0,
-                        SMCR: 0x0,
-                    },
-                    CCMR2: 0x0,
-                    CCR2: 0x0,
-                    CCR3: 0x0,
-                    CCR4: 0x0,
-                    CR2: 0x0,
-                    DMAR: 0x0,
-                    DCR: 0x0,
-                },
-                GeneralPurposeTimer2Registers {
-                    gp: GeneralPurposeTimerRegisters {
-                        min: MinimumTimerRegisters {
-                            CR1: 0x0,
-                            CNT: 0x0,
-                            DIER: 0x0,
-                            EGR: 0x0,
-                            PSC: 0x0,
-                            SR: 0x0,
-                            ARR: 0x0000_ffff,
-                        },
-                        CCER: 0x0,
-                        CCMR1: 0x0,
-                        CCR1: 0x0,
-                        SMCR: 0x0,
-                    },
-                    CCMR2: 0x0,
-                    CCR2: 0x0,
-                    CCR3: 0x0,
-                    CCR4: 0x0,
-                    CR2: 0x0,
-                    DMAR: 0x0,
-                    DCR: 0x0,
-                },
-                GeneralPurposeTimer2Registers {
-                    gp: GeneralPurposeTimerRegisters {
-                        min: MinimumTimerRegisters {
-                            CR1: 0x0,
-                            CNT: 0x0,
-                            DIER: 0x0,
-                            EGR: 0x0,
-                            PSC: 0x0,
-                            SR: 0x0,
-                            ARR: 0x0000_ffff,
-                        },
-                        CCER: 0x0,
-                        CCMR1: 0x0,
-                        CCR1: 0x0,
-                        SMCR: 0x0,
-                    },
-                    CCMR2: 0x0,
-                    CCR2: 0x0,
-                    CCR3: 0x0,
-                    CCR4: 0x0,
-                    CR2: 0x0,
-                    DMAR: 0x0,
-                    DCR: 0x0,
-                },
+                TimerRegisters::new(TimerKind::GeneralPurpose),
+                TimerRegisters::new(TimerKind::GeneralPurpose),
+                TimerRegisters::new(TimerKind::GeneralPurpose),
+                TimerRegisters::new(TimerKind::GeneralPurpose),
             ],
             tim6_7: [
-                BasicTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CR2: 0x0,
-                },
-                BasicTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CR2: 0x0,
-                },
+                TimerRegisters::new(TimerKind::Basic),
+                TimerRegisters::new(TimerKind::Basic),
             ],
             tim9_14: [
-                GeneralPurposeTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CCER: 0x0,
-                    CCMR1: 0x0,
-                    CCR1: 0x0,
-                    SMCR: 0x0,
-                },
-                GeneralPurposeTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CCER: 0x0,
-                    CCMR1: 0x0,
-                    CCR1: 0x0,
-                    SMCR: 0x0,
-                },
-                GeneralPurposeTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CCER: 0x0,
-                    CCMR1: 0x0,
-                    CCR1: 0x0,
-                    SMCR: 0x0,
-                },
-                GeneralPurposeTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CCER: 0x0,
-                    CCMR1: 0x0,
-                    CCR1: 0x0,
-                    SMCR: 0x0,
-                },
-                GeneralPurposeTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CCER: 0x0,
-                    CCMR1: 0x0,
-                    CCR1: 0x0,
-                    SMCR: 0x0,
-                },
-                GeneralPurposeTimerRegisters {
-                    min: MinimumTimerRegisters {
-                        CR1: 0x0,
-                        CNT: 0x0,
-                        DIER: 0x0,
-                        EGR: 0x0,
-                        PSC: 0x0,
-                        SR: 0x0,
-                        ARR: 0x0000_ffff,
-                    },
-                    CCER: 0x0,
-                    CCMR1: 0x0,
-                    CCR1: 0x0,
-                    SMCR: 0x0,
-                },
+                TimerRegisters::new(TimerKind::GeneralPurpose2 { channels: 2 }),
+                TimerRegisters::new(TimerKind::GeneralPurpose2 { channels: 1 }),
+                TimerRegisters::new(TimerKind::GeneralPurpose2 { channels: 1 }),
+                TimerRegisters::new(TimerKind::GeneralPurpose2 { channels: 2 }),
+                TimerRegisters::new(TimerKind::GeneralPurpose2 { channels: 1 }),
+                TimerRegisters::new(TimerKind::GeneralPurpose2 { channels: 1 }),
             ],
+            usart: [
+                USARTRegisters::new(),
+                USARTRegisters::new(),
+                USARTRegisters::new(),
+            ],
+            irq_asserted: 0,
+        }
+    }
+
+    ///
+    /// Connect USART ```index``` (0 = USART1) to a host side backend.
+    ///
+    pub fn usart_backend(&mut self, index: usize, backend: Box<dyn SerialBackend>) -> &mut Self {
+        self.usart[index].backend = Some(RecordedBackend::new(backend));
+        self
+    }
+
+    fn irq_lines(&self) -> u128 {
+        let mut lines = self.timer_irq_lines();
+        for (usart, irqn) in self.usart.iter().zip(USART_IRQN) {
+            if usart.interrupt_pending() {
+                lines |= 1 << irqn;
+            }
         }
+        lines
+    }
+
+    fn timer_irq_lines(&self) -> u128 {
+        let mut lines = 0;
+        for (timer, irqn) in self.tim1_8.iter().zip(&TIM1_8_IRQN) {
+            let pending = timer.pending_interrupts();
+            if pending.get_bit(TIM_SR_BIF) {
+                lines |= 1 << irqn.brk;
+            }
+            if pending.get_bit(TIM_SR_UIF) {
+                lines |= 1 << irqn.up;
+            }
+            if pending.get_bit(TIM_SR_COMIF) || pending.get_bit(TIM_SR_TIF) {
+                lines |= 1 << irqn.trg_com;
+            }
+            if pending.get_bits(1..5) != 0 {
+                lines |= 1 << irqn.cc;
+            }
+        }
+        let timers = self
+            .tim2_5
+            .iter()
+            .zip(TIM2_5_IRQN)
+            .chain(self.tim6_7.iter().zip(TIM6_7_IRQN))
+            .chain(self.tim9_14.iter().zip(TIM9_14_IRQN));
+        for (timer, irqn) in timers {
+            if timer.pending_interrupts() != 0 {
+                lines |= 1 << irqn;
+            }
+        }
+        lines
     }
 }
 
@@ -629,6 +1050,14 @@ trait FLASH {
     fn flash_read32(&mut self, offset: u32) -> Result<u32, Fault>;
 }
 
+#[allow(clippy::upper_case_acronyms)]
+trait USART {
+    fn usart_write32(&mut self, index: usize, offset: u32, value: u32) -> Result<(), Fault>;
+    fn usart_read32(&self, index: usize, offset: u32) -> Result<u32, Fault>;
+    fn usart_tick(&mut self, cycles: u32);
+    fn usart_next_event(&self) -> Option<u32>;
+}
+
 trait TIM {
     fn tim_advanced_control_timer_write32(
         &mut self,
@@ -670,6 +1099,8 @@ trait TIM {
         value: u32,
     ) -> Result<(), Fault>;
     fn tim_basic_timer_read32(&mut self, timer: BasicTimerType, offset: u32) -> Result<u32, Fault>;
+    fn tim_tick(&mut self, cycles: u32);
+    fn tim_next_event(&self) -> Option<u32>;
 }
 
 impl RCC for Device {
@@ -845,12 +1276,7 @@ impl TIM for Device {
             AdvancedControlTimerType::TIM8 => 1,
         };
 
-        match offset {
-            0x0 => self.tim1_8[index].gp.gp.min.CR1 = value,
-            _ => return Err(Fault::DAccViol),
-        }
-
-        Ok(())
+        self.tim1_8[index].write32(offset, value)
     }
 
     fn tim_advanced_control_timer_read32(
@@ -863,12 +1289,7 @@ impl TIM for Device {
             AdvancedControlTimerType::TIM8 => 1,
         };
 
-        let result = match offset {
-            0x0 => self.tim1_8[index].gp.gp.min.CR1,
-            _ => return Err(Fault::DAccViol),
-        };
-
-        Ok(result)
+        self.tim1_8[index].read32(offset)
     }
 
     fn tim_general_purpose_timer_write32(
@@ -884,16 +1305,7 @@ impl TIM for Device {
             GeneralPurposeTimerType::TIM5 => 3,
         };
 
-        match offset {
-            0x0 => self.tim2_5[index].gp.min.CR1 = value & 0xffff,
-            0x18 => self.tim2_5[index].gp.CCMR1 = value & 0xffff,
-            0x1c => self.tim2_5[index].CCMR2 = value & 0xffff,
-            0x28 => self.tim2_5[index].gp.min.PSC = value & 0xffff,
-            0x2c => self.tim2_5[index].gp.min.ARR = value & 0xffff,
-            _ => return Err(Fault::DAccViol),
-        }
-
-        Ok(())
+        self.tim2_5[index].write32(offset, value)
     }
 
     fn tim_general_purpose_timer_read32(
@@ -908,16 +1320,7 @@ impl TIM for Device {
             GeneralPurposeTimerType::TIM5 => 3,
         };
 
-        let result = match offset {
-            0x0 => self.tim2_5[index].gp.min.CR1,
-            0x18 => self.tim2_5[index].gp.CCMR1,
-            0x1c => self.tim2_5[index].CCMR2,
-            0x28 => self.tim2_5[index].gp.min.PSC,
-            0x2C => self.tim2_5[index].gp.min.ARR,
-            _ => return Err(Fault::DAccViol),
-        };
-
-        Ok(result)
+        self.tim2_5[index].read32(offset)
     }
 
     fn tim_general_purpose_timer2_write32(
@@ -930,17 +1333,12 @@ impl TIM for Device {
             GeneralPurposeTimer2Type::TIM9 => 0,
             GeneralPurposeTimer2Type::TIM10 => 1,
             GeneralPurposeTimer2Type::TIM11 => 2,
-            GeneralPurposeTimer2Type::TIM12 => 4,
-            GeneralPurposeTimer2Type::TIM13 => 5,
-            GeneralPurposeTimer2Type::TIM14 => 6,
+            GeneralPurposeTimer2Type::TIM12 => 3,
+            GeneralPurposeTimer2Type::TIM13 => 4,
+            GeneralPurposeTimer2Type::TIM14 => 5,
         };
 
-        match offset {
-            0x0 => self.tim9_14[index].min.CR1 = value,
-            _ => return Err(Fault::DAccViol),
-        }
-
-        Ok(())
+        self.tim9_14[index].write32(offset, value)
     }
 
     fn tim_general_purpose_timer2_read32(
@@ -952,17 +1350,12 @@ impl TIM for Device {
             GeneralPurposeTimer2Type::TIM9 => 0,
             GeneralPurposeTimer2Type::TIM10 => 1,
             GeneralPurposeTimer2Type::TIM11 => 2,
-            GeneralPurposeTimer2Type::TIM12 => 4,
-            GeneralPurposeTimer2Type::TIM13 => 5,
-            GeneralPurposeTimer2Type::TIM14 => 6,
+            GeneralPurposeTimer2Type::TIM12 => 3,
+            GeneralPurposeTimer2Type::TIM13 => 4,
+            GeneralPurposeTimer2Type::TIM14 => 5,
         };
 
-        let result = match offset {
-            0x0 => self.tim9_14[index].min.CR1,
-            _ => return Err(Fault::DAccViol),
-        };
-
-        Ok(result)
+        self.tim9_14[index].read32(offset)
     }
 
     fn tim_basic_timer_write32(
@@ -976,12 +1369,7 @@ impl TIM for Device {
             BasicTimerType::TIM7 => 1,
         };
 
-        match offset {
-            0x0 => self.tim6_7[index].min.CR1 = value,
-            _ => return Err(Fault::DAccViol),
-        }
-
-        Ok(())
+        self.tim6_7[index].write32(offset, value)
     }
 
     fn tim_basic_timer_read32(&mut self, timer: BasicTimerType, offset: u32) -> Result<u32, Fault> {
@@ -990,28 +1378,114 @@ impl TIM for Device {
             BasicTimerType::TIM7 => 1,
         };
 
-        let result = match offset {
-            0x0 => self.tim6_7[index].min.CR1,
-            _ => return Err(Fault::DAccViol),
+        self.tim6_7[index].read32(offset)
+    }
+
+    fn tim_tick(&mut self, cycles: u32) {
+        let apb1_divider = timer_clock_divider(self.rcc.CFGR.get_bits(8..11));
+        let apb2_divider = timer_clock_divider(self.rcc.CFGR.get_bits(11..14));
+
+        for timer in &mut self.tim1_8 {
+            timer.tick(cycles, apb2_divider);
+        }
+        for timer in self.tim2_5.iter_mut().chain(&mut self.tim6_7) {
+            timer.tick(cycles, apb1_divider);
+        }
+        for (index, timer) in self.tim9_14.iter_mut().enumerate() {
+            // TIM9..TIM11 are on APB2, TIM12..TIM14 on APB1
+            let divider = if index < 3 {
+                apb2_divider
+            } else {
+                apb1_divider
+            };
+            timer.tick(cycles, divider);
+        }
+    }
+
+    fn tim_next_event(&self) -> Option<u32> {
+        let apb1_divider = timer_clock_divider(self.rcc.CFGR.get_bits(8..11));
+        let apb2_divider = timer_clock_divider(self.rcc.CFGR.get_bits(11..14));
+
+        let apb2 = self.tim1_8.iter().chain(&self.tim9_14[..3]);
+        let apb1 = self
+            .tim2_5
+            .iter()
+            .chain(&self.tim6_7)
+            .chain(&self.tim9_14[3..]);
+        apb2.filter_map(|timer| timer.next_event(apb2_divider))
+            .chain(apb1.filter_map(|timer| timer.next_event(apb1_divider)))
+            .min()
+    }
+}
+
+impl USART for Device {
+    fn usart_write32(&mut self, index: usize, offset: u32, value: u32) -> Result<(), Fault> {
+        let divider = self.usart_apb_divider(index);
+        self.usart[index].write(offset, value, divider)
+    }
+
+    fn usart_read32(&self, index: usize, offset: u32) -> Result<u32, Fault> {
+        self.usart[index].read(offset)
+    }
+
+    fn usart_tick(&mut self, cycles: u32) {
+        for index in 0..self.usart.len() {
+            let divider = self.usart_apb_divider(index);
+            self.usart[index].tick(cycles, divider);
+        }
+    }
+
+    fn usart_next_event(&self) -> Option<u32> {
+        self.usart
+            .iter()
+            .filter_map(USARTRegisters::next_event)
+            .min()
+    }
+}
+
+impl Device {
+    fn usart_apb_divider(&self, index: usize) -> u32 {
+        // USART1 is on APB2, the others on APB1
+        let ppre = if index == 0 {
+            self.rcc.CFGR.get_bits(11..14)
+        } else {
+            self.rcc.CFGR.get_bits(8..11)
         };
+        apb_clock_divider(ppre)
+    }
 
-        Ok(result)
+    fn usart_index(addr: u32) -> Option<(usize, u32)> {
+        match addr {
+            USART1_BASE..=USART1_BASE_END => Some((0, addr - USART1_BASE)),
+            USART2_BASE..=USART2_BASE_END => Some((1, addr - USART2_BASE)),
+            USART3_BASE..=USART3_BASE_END => Some((2, addr - USART3_BASE)),
+            _ => None,
+        }
     }
 }
 
 impl Bus for Device {
     fn read8(&self, bus_addr: u32) -> Result<u8, Fault> {
-        println!("read8 0x{bus_addr:x}");
+        trace!("read8 0x{bus_addr:x}");
+        if let Some((index, offset)) = Self::usart_index(bus_addr) {
+            return Ok(self.usart_read32(index, offset)? as u8);
+        }
         Ok(0)
     }
 
     fn read16(&self, bus_addr: u32) -> Result<u16, Fault> {
-        println!("read16 0x{bus_addr:x}");
+        trace!("read16 0x{bus_addr:x}");
+        if let Some((index, offset)) = Self::usart_index(bus_addr) {
+            return Ok(self.usart_read32(index, offset)? as u16);
+        }
         Ok(0)
     }
 
     fn read32(&mut self, bus_addr: u32) -> Result<u32, Fault> {
-        println!("read32 0x{bus_addr:x}");
+        trace!("read32 0x{bus_addr:x}");
+        if let Some((index, offset)) = Self::usart_index(bus_addr) {
+            return self.usart_read32(index, offset);
+        }
         match bus_addr {
             AFIO_BASE..=AFIO_BASE_END => self.afio_read32(bus_addr - AFIO_BASE),
             RCC_BASE..=RCC_BASE_END => self.rcc_read32(bus_addr - RCC_BASE),
@@ -1082,7 +1556,10 @@ impl Bus for Device {
     }
 
     fn write32(&mut self, addr: u32, value: u32) -> Result<(), Fault> {
-        println!("write32 0x{addr:x}=0x{value:x}");
+        trace!("write32 0x{addr:x}=0x{value:x}");
+        if let Some((index, offset)) = Self::usart_index(addr) {
+            return self.usart_write32(index, offset, value);
+        }
         match addr {
             AFIO_BASE..=AFIO_BASE_END => self.afio_write32(addr - AFIO_BASE, value),
             RCC_BASE..=RCC_BASE_END => self.rcc_write32(addr - RCC_BASE, value),
@@ -1165,12 +1642,18 @@ impl Bus for Device {
     }
 
     fn write16(&mut self, addr: u32, value: u16) -> Result<(), Fault> {
-        println!("write16 0x{addr:x}=0x{value:x}");
+        trace!("write16 0x{addr:x}=0x{value:x}");
+        if let Some((index, offset)) = Self::usart_index(addr) {
+            return self.usart_write32(index, offset, u32::from(value));
+        }
         Ok(())
     }
 
     fn write8(&mut self, addr: u32, value: u8) -> Result<(), Fault> {
-        println!("write8 0x{addr:x}=0x{value:x}");
+        trace!("write8 0x{addr:x}=0x{value:x}");
+        if let Some((index, offset)) = Self::usart_index(addr) {
+            return self.usart_write32(index, offset, u32::from(value));
+        }
         Ok(())
     }
 
@@ -1180,10 +1663,253 @@ impl Bus for Device {
     }
 }
 
+impl Peripheral for Device {
+    fn tick(&mut self, cycles: u32, irqs: &mut Vec<IrqLine>) {
+        self.tim_tick(cycles);
+        self.usart_tick(cycles);
+
+        let lines = self.irq_lines();
+        let mut changed = lines ^ self.irq_asserted;
+        while changed != 0 {
+            let irqn = changed.trailing_zeros() as usize;
+            irqs.push(IrqLine {
+                irqn,
+                asserted: lines & (1 << irqn) != 0,
+            });
+            changed &= changed - 1;
+        }
+        self.irq_asserted = lines;
+    }
+
+    fn next_event(&self) -> Option<u32> {
+        [self.tim_next_event(), self.usart_next_event()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> io::Result<()> {
+        let afio = &self.afio;
+        let rcc = &self.rcc;
+        let mut words = vec![afio.EVCR, afio.MAPR, afio.MAPR2];
+        words.extend(afio.EXTICR);
+        words.extend([
+            rcc.CR,
+            rcc.CFGR,
+            rcc.CIR,
+            rcc.APB2RSTR,
+            rcc.APB1RSTR,
+            rcc.AHBENR,
+            rcc.APB2ENR,
+            rcc.APB1ENR,
+            rcc.BDCR,
+            rcc.CSR,
+        ]);
+        for gpio in &self.gpio {
+            words.extend([gpio.CRL, gpio.CRH, gpio.IDR, gpio.ODR, gpio.LCKR]);
+        }
+        words.push(self.flash.ACR);
+        for value in words {
+            state.write_u32(value)?;
+        }
+        for timer in self.timers() {
+            timer.save_state(state)?;
+        }
+        for usart in &self.usart {
+            usart.save_state(state)?;
+        }
+        state.write_u64(self.irq_asserted as u64)?;
+        state.write_u64((self.irq_asserted >> 64) as u64)
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        let afio = &mut self.afio;
+        let rcc = &mut self.rcc;
+        let mut words = vec![&mut afio.EVCR, &mut afio.MAPR, &mut afio.MAPR2];
+        words.extend(&mut afio.EXTICR);
+        words.extend([
+            &mut rcc.CR,
+            &mut rcc.CFGR,
+            &mut rcc.CIR,
+            &mut rcc.APB2RSTR,
+            &mut rcc.APB1RSTR,
+            &mut rcc.AHBENR,
+            &mut rcc.APB2ENR,
+            &mut rcc.APB1ENR,
+            &mut rcc.BDCR,
+            &mut rcc.CSR,
+        ]);
+        for gpio in &mut self.gpio {
+            words.extend([
+                &mut gpio.CRL,
+                &mut gpio.CRH,
+                &mut gpio.IDR,
+                &mut gpio.ODR,
+                &mut gpio.LCKR,
+            ]);
+        }
+        words.push(&mut self.flash.ACR);
+        for value in words {
+            *value = state.read_u32()?;
+        }
+        for timer in self
+            .tim1_8
+            .iter_mut()
+            .chain(&mut self.tim2_5)
+            .chain(&mut self.tim6_7)
+            .chain(&mut self.tim9_14)
+        {
+            timer.load_state(state)?;
+        }
+        for usart in &mut self.usart {
+            usart.load_state(state)?;
+        }
+        let low = state.read_u64()?;
+        self.irq_asserted = u128::from(low) | u128::from(state.read_u64()?) << 64;
+        Ok(())
+    }
+
+    fn replay(&mut self, replaying: bool) {
+        for backend in self
+            .usart
+            .iter_mut()
+            .filter_map(|usart| usart.backend.as_mut())
+        {
+            backend.replay(replaying);
+        }
+    }
+}
+
+impl Device {
+    fn timers(&self) -> impl Iterator<Item = &TimerRegisters> {
+        self.tim1_8
+            .iter()
+            .chain(&self.tim2_5)
+            .chain(&self.tim6_7)
+            .chain(&self.tim9_14)
+    }
+}
+
+impl TimerRegisters {
+    fn save_state(&self, state: &mut StateWriter) -> io::Result<()> {
+        for value in [
+            self.CR1,
+            self.CR2,
+            self.SMCR,
+            self.DIER,
+            self.SR,
+            self.CCMR1,
+            self.CCMR2,
+            self.CCER,
+            self.CNT,
+            self.PSC,
+            self.ARR,
+            self.RCR,
+            self.BDTR,
+            self.DCR,
+            self.DMAR,
+            self.psc_shadow,
+            self.arr_shadow,
+            self.repetition_counter,
+            self.prescaler_counter,
+        ]
+        .iter()
+        .chain(&self.CCR)
+        {
+            state.write_u32(*value)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        for value in [
+            &mut self.CR1,
+            &mut self.CR2,
+            &mut self.SMCR,
+            &mut self.DIER,
+            &mut self.SR,
+            &mut self.CCMR1,
+            &mut self.CCMR2,
+            &mut self.CCER,
+            &mut self.CNT,
+            &mut self.PSC,
+            &mut self.ARR,
+            &mut self.RCR,
+            &mut self.BDTR,
+            &mut self.DCR,
+            &mut self.DMAR,
+            &mut self.psc_shadow,
+            &mut self.arr_shadow,
+            &mut self.repetition_counter,
+            &mut self.prescaler_counter,
+        ]
+        .into_iter()
+        .chain(&mut self.CCR)
+        {
+            *value = state.read_u32()?;
+        }
+        Ok(())
+    }
+}
+
+impl USARTRegisters {
+    /// The serial backend is host state and stays attached, only its
+    /// position in the received input is saved
+    fn save_state(&self, state: &mut StateWriter) -> io::Result<()> {
+        for value in [
+            self.SR.get(),
+            self.BRR,
+            self.CR1,
+            self.CR2,
+            self.CR3,
+            self.GTPR,
+            self.rdr,
+            self.tx_cycles,
+            self.rx_cycles,
+        ] {
+            state.write_u32(value)?;
+        }
+        for byte in [self.tdr, self.tx_shift] {
+            state.write_bool(byte.is_some())?;
+            state.write_u8(byte.unwrap_or(0))?;
+        }
+        state.write_u64(self.backend.as_ref().map_or(0, RecordedBackend::reads))
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.SR.set(state.read_u32()?);
+        for value in [
+            &mut self.BRR,
+            &mut self.CR1,
+            &mut self.CR2,
+            &mut self.CR3,
+            &mut self.GTPR,
+            &mut self.rdr,
+            &mut self.tx_cycles,
+            &mut self.rx_cycles,
+        ] {
+            *value = state.read_u32()?;
+        }
+        for byte in [&mut self.tdr, &mut self.tx_shift] {
+            let present = state.read_bool()?;
+            let value = state.read_u8()?;
+            *byte = present.then_some(value);
+        }
+        let reads = state.read_u64()?;
+        if let Some(backend) = self.backend.as_mut() {
+            backend.set_reads(reads);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
 
     #[test]
     fn test_rcc_cr_init() {
@@ -1227,4 +1953,397 @@ mod tests {
             Ok(())
         }
     }
+
+    const TIM2_CR1: u32 = TIM2_BASE;
+    const TIM2_DIER: u32 = TIM2_BASE + 0xc;
+    const TIM2_SR: u32 = TIM2_BASE + 0x10;
+    const TIM2_EGR: u32 = TIM2_BASE + 0x14;
+    const TIM2_CNT: u32 = TIM2_BASE + 0x24;
+    const TIM2_PSC: u32 = TIM2_BASE + 0x28;
+    const TIM2_ARR: u32 = TIM2_BASE + 0x2c;
+    const TIM2_CCR1: u32 = TIM2_BASE + 0x34;
+
+    #[test]
+    fn test_tim_counts_with_prescaler() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        device.write32(TIM2_PSC, 1)?;
+        device.write32(TIM2_ARR, 9)?;
+        device.write32(TIM2_EGR, 1)?;
+        device.write32(TIM2_SR, 0)?;
+
+        // Act
+        device.write32(TIM2_CR1, 1)?;
+        device.tick(7, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(TIM2_CNT)?, 3);
+        assert_eq!(device.read32(TIM2_SR)?, 0);
+
+        // Act
+        device.tick(14, &mut irqs);
+
+        // Assert: 10 counts per period, overflowed once
+        assert_eq!(device.read32(TIM2_CNT)?, 0);
+        assert_eq!(device.read32(TIM2_SR)? & 1, 1);
+        assert!(irqs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tim_update_interrupt() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        device.write32(TIM2_ARR, 4)?;
+        device.write32(TIM2_DIER, 1)?;
+        device.write32(TIM2_CR1, 1)?;
+
+        // Act
+        device.tick(4, &mut irqs);
+
+        // Assert
+        assert!(irqs.is_empty());
+
+        // Act
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(irqs, vec![IrqLine::assert(28)]);
+
+        // Act
+        irqs.clear();
+        device.write32(TIM2_SR, !1)?;
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(irqs, vec![IrqLine::deassert(28)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_round_trip_resumes_timer() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        device.write32(TIM2_PSC, 1)?;
+        device.write32(TIM2_ARR, 9)?;
+        device.write32(TIM2_DIER, 1)?;
+        device.write32(TIM2_CR1, 1)?;
+        device.tick(7, &mut irqs);
+        let mut state = Vec::new();
+        device
+            .save_state(&mut StateWriter::new(&mut state))
+            .unwrap();
+
+        // Act
+        let mut restored = Device::new();
+        restored
+            .load_state(&mut StateReader::new(&mut state.as_slice()))
+            .unwrap();
+        restored.tick(13, &mut irqs);
+
+        // Assert: the prescaler phase carried over, the counter wrapped once
+        assert_eq!(restored.read32(TIM2_CNT)?, 0);
+        assert_eq!(irqs, vec![IrqLine::assert(28)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tim_one_pulse_mode_stops_counter() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        device.write32(TIM2_ARR, 4)?;
+
+        // Act
+        device.write32(TIM2_CR1, 0b1001)?;
+        device.tick(100, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(TIM2_CR1)? & 1, 0);
+        assert_eq!(device.read32(TIM2_CNT)?, 0);
+        assert_eq!(device.read32(TIM2_SR)? & 1, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tim_compare_match_sets_ccif() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        device.write32(TIM2_CCR1, 3)?;
+        device.write32(TIM2_DIER, 0b10)?;
+        device.write32(TIM2_CR1, 1)?;
+
+        // Act
+        device.tick(3, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(TIM2_SR)?, 0b10);
+        assert_eq!(irqs, vec![IrqLine::assert(28)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tim_next_event_is_the_next_interrupt() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        device.write32(TIM2_PSC, 1)?;
+        device.write32(TIM2_ARR, 9)?;
+        device.write32(TIM2_EGR, 1)?;
+        device.write32(TIM2_SR, 0)?;
+        device.write32(TIM2_CCR1, 6)?;
+        device.write32(TIM2_DIER, 0b11)?;
+        device.write32(TIM2_CR1, 1)?;
+        device.tick(3, &mut irqs);
+        let mut single = Device::new();
+        single.write32(TIM2_PSC, 1)?;
+        single.write32(TIM2_ARR, 9)?;
+        single.write32(TIM2_EGR, 1)?;
+        single.write32(TIM2_SR, 0)?;
+        single.write32(TIM2_CCR1, 6)?;
+        single.write32(TIM2_DIER, 0b11)?;
+        single.write32(TIM2_CR1, 1)?;
+        single.tick(3, &mut irqs);
+
+        // Act
+        let compare = device.next_event();
+        let mut cycles = 0;
+        while irqs.is_empty() {
+            single.tick(1, &mut irqs);
+            cycles += 1;
+        }
+        device.write32(TIM2_CCR1, 0x100)?;
+        let overflow = device.next_event();
+
+        // Assert: CNT is 1 with one prescaler cycle done, CCR1 matches 5
+        // counts later and the overflow 9 counts later once CCR1 is out of range
+        assert_eq!(compare, Some(cycles));
+        assert_eq!(compare, Some(9));
+        assert_eq!(overflow, Some(17));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tim_apb_prescaler_slows_timer_clock() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+        let mut irqs = Vec::new();
+        // APB1 divided by 4 -> timer clock is core clock / 2
+        device.rcc_write32(0x4, 0b101 << 8)?;
+        device.write32(TIM2_CR1, 1)?;
+
+        // Act
+        device.tick(10, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(TIM2_CNT)?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tim12_to_tim14_registers() -> Result<(), Fault> {
+        // Arrange
+        let mut device = Device::new();
+
+        // Act
+        device.write32(TIM14_BASE + 0x2c, 0x1234)?;
+
+        // Assert
+        assert_eq!(device.read32(TIM14_BASE + 0x2c)?, 0x1234);
+        assert_eq!(device.read32(TIM14_BASE + 0x1c), Err(Fault::DAccViol));
+        Ok(())
+    }
+
+    type Sent = Rc<RefCell<Vec<u8>>>;
+    type Input = Rc<RefCell<VecDeque<u8>>>;
+
+    struct LoopbackBackend {
+        sent: Sent,
+        input: Input,
+    }
+
+    impl SerialBackend for LoopbackBackend {
+        fn write(&mut self, byte: u8) {
+            self.sent.borrow_mut().push(byte);
+        }
+
+        fn read(&mut self) -> Option<u8> {
+            self.input.borrow_mut().pop_front()
+        }
+    }
+
+    fn device_with_usart1() -> (Device, Sent, Input) {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let input = Rc::new(RefCell::new(VecDeque::new()));
+        let mut device = Device::new();
+        device.usart_backend(
+            0,
+            Box::new(LoopbackBackend {
+                sent: sent.clone(),
+                input: input.clone(),
+            }),
+        );
+        (device, sent, input)
+    }
+
+    const USART1_SR: u32 = USART1_BASE;
+    const USART1_DR: u32 = USART1_BASE + 0x4;
+    const USART1_BRR: u32 = USART1_BASE + 0x8;
+    const USART1_CR1: u32 = USART1_BASE + 0xc;
+
+    #[test]
+    fn test_usart_transmits_at_baud_rate() -> Result<(), Fault> {
+        // Arrange
+        let (mut device, sent, _) = device_with_usart1();
+        let mut irqs = Vec::new();
+        device.write32(USART1_BRR, 8)?;
+        device.write32(USART1_CR1, 0x2008)?;
+
+        // Act
+        device.write32(USART1_DR, u32::from(b'a'))?;
+        device.write32(USART1_DR, u32::from(b'b'))?;
+
+        // Assert: second byte waits in the data register
+        assert_eq!(device.read32(USART1_SR)? & 0xc0, 0);
+
+        // Act
+        device.tick(79, &mut irqs);
+
+        // Assert
+        assert!(sent.borrow().is_empty());
+
+        // Act
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(*sent.borrow(), b"a");
+        assert_eq!(device.read32(USART1_SR)? & 0xc0, 0x80);
+
+        // Act
+        device.tick(80, &mut irqs);
+
+        // Assert
+        assert_eq!(*sent.borrow(), b"ab");
+        assert_eq!(device.read32(USART1_SR)? & 0xc0, 0xc0);
+        assert!(irqs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_usart_receive_raises_interrupt() -> Result<(), Fault> {
+        // Arrange
+        let (mut device, _, input) = device_with_usart1();
+        let mut irqs = Vec::new();
+        input.borrow_mut().extend(b"xy");
+        // UE, RXNEIE, RE
+        device.write32(USART1_CR1, 0x2024)?;
+
+        // Act
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(USART1_SR)? & 0x20, 0x20);
+        assert_eq!(irqs, vec![IrqLine::assert(37)]);
+
+        // Act
+        irqs.clear();
+        let data = device.read16(USART1_DR)?;
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(data, u16::from(b'x'));
+        assert_eq!(device.read32(USART1_SR)? & 0x20, 0);
+        assert_eq!(irqs, vec![IrqLine::deassert(37)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usart_replay_repeats_recorded_traffic() -> Result<(), Fault> {
+        // Arrange
+        let (mut device, sent, input) = device_with_usart1();
+        let mut irqs = Vec::new();
+        input.borrow_mut().extend(b"a");
+        device.write32(USART1_BRR, 1)?;
+        // UE, TE, RE
+        device.write32(USART1_CR1, 0x200c)?;
+        let mut start = Vec::new();
+        device
+            .save_state(&mut StateWriter::new(&mut start))
+            .unwrap();
+        device.write32(USART1_DR, u32::from(b'x'))?;
+        device.tick(1000, &mut irqs);
+        let mut recorded = Vec::new();
+        device
+            .save_state(&mut StateWriter::new(&mut recorded))
+            .unwrap();
+        input.borrow_mut().extend(b"b");
+
+        // Act
+        device
+            .load_state(&mut StateReader::new(&mut start.as_slice()))
+            .unwrap();
+        device.replay(true);
+        device.write32(USART1_DR, u32::from(b'x'))?;
+        device.tick(1000, &mut irqs);
+        device.replay(false);
+
+        // Assert
+        let mut replayed = Vec::new();
+        device
+            .save_state(&mut StateWriter::new(&mut replayed))
+            .unwrap();
+        assert_eq!(replayed, recorded);
+        assert_eq!(*sent.borrow(), b"x");
+        assert_eq!(device.read16(USART1_DR)?, u16::from(b'a'));
+
+        // Act
+        device.tick(1000, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read16(USART1_DR)?, u16::from(b'b'));
+        Ok(())
+    }
+
+    #[test]
+    fn test_disabled_usart_does_not_interrupt() -> Result<(), Fault> {
+        // Arrange
+        let (mut device, _, input) = device_with_usart1();
+        let mut irqs = Vec::new();
+        input.borrow_mut().extend(b"x");
+        device.write32(USART1_CR1, 0x2024)?;
+        device.tick(1, &mut irqs);
+        irqs.clear();
+
+        // Act: clear UE, RXNE and RXNEIE stay set
+        device.write32(USART1_CR1, 0x0024)?;
+        device.tick(1, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(USART1_SR)? & 0x20, 0x20);
+        assert_eq!(irqs, vec![IrqLine::deassert(37)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usart_overrun_keeps_first_byte() -> Result<(), Fault> {
+        // Arrange
+        let (mut device, _, input) = device_with_usart1();
+        let mut irqs = Vec::new();
+        input.borrow_mut().extend(b"12");
+        device.write32(USART1_CR1, 0x2004)?;
+
+        // Act
+        device.tick(USART_RX_POLL_CYCLES, &mut irqs);
+        device.tick(USART_RX_POLL_CYCLES, &mut irqs);
+
+        // Assert
+        assert_eq!(device.read32(USART1_SR)? & 0x28, 0x28);
+        assert_eq!(device.read32(USART1_DR)?, u32::from(b'1'));
+        assert_eq!(device.read32(USART1_SR)? & 0x28, 0);
+        Ok(())
+    }
 }
@@ -1,18 +1,14 @@
 mod app;
+mod board;
 mod semihost;
 mod trace;
 
-use zmu_cortex_m::DeviceBus;
-
-fn create_device() -> Option<DeviceBus> {
-    None
-}
-
 fn main() {
     app::main_with_device(
         "zmu-cortex-m0",
         "Cortex-M0 emulator",
         "Load and run <EXECUTABLE> on a Cortex-M0 target",
-        create_device,
+        app::generic_device,
+        &app::CoreConfig { bit_band: false },
     );
 }
@@ -1,12 +1,30 @@
 mod app;
+mod board;
 mod device;
 mod semihost;
 mod trace;
 
+use app::DeviceOptions;
+use device::serial::open_backend;
+use device::stm32f1xx::Device;
 use zmu_cortex_m::DeviceBus;
 
-fn create_device() -> Option<DeviceBus> {
-    Some(Box::new(device::stm32f1xx::Device::new()))
+fn create_device(options: &DeviceOptions) -> anyhow::Result<Option<DeviceBus>> {
+    anyhow::ensure!(
+        options.peripherals.is_empty(),
+        "device file peripherals are not supported with the built-in STM32F103 model"
+    );
+    let mut device = Device::new();
+    for (port, backend) in &options.serial {
+        let index = match port.as_str() {
+            "usart1" => 0,
+            "usart2" => 1,
+            "usart3" => 2,
+            _ => anyhow::bail!("unknown serial port '{port}', expected usart1, usart2 or usart3"),
+        };
+        device.usart_backend(index, open_backend(port, backend)?);
+    }
+    Ok(Some(Box::new(device)))
 }
 
 fn main() {
@@ -15,5 +33,6 @@ fn main() {
         "STM32F103 emulator",
         "Load and run <EXECUTABLE> on an STM32F103 target",
         create_device,
+        &app::CoreConfig { bit_band: true },
     );
 }
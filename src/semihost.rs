@@ -1,7 +1,14 @@
 use std::cmp::min;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
 use std::io;
+use std::io::SeekFrom;
 use std::io::prelude::*;
-use std::time::Instant;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::time::{Instant, SystemTime};
+
+use log::warn;
 
 use zmu_cortex_m::semihosting::{SemihostingCommand, SemihostingResponse, SysExceptionReason};
 
@@ -19,8 +26,259 @@ const SEMIHOST_FEATURES_HANDLE: u32 = 4;
 */
 static FEATURE_DATA: [u8; 5] = [0x53, 0x48, 0x46, 0x42, 3];
 
-pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> SemihostingResponse {
+/// First handle given to a host file
+const FIRST_FILE_HANDLE: u32 = 5;
+
+/// Nominal core clock, `SYS_ELAPSED` counts one tick per simulated cycle
+const TICK_FREQUENCY: u32 = 100_000_000;
+
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const EINVAL: i32 = 22;
+
+fn errno(error: &io::Error) -> i32 {
+    error.raw_os_error().unwrap_or(EIO)
+}
+
+///
+/// Host files opened by the target. Names given by the target are
+/// resolved under the root directory and may not lead out of it, without
+/// a root only the console streams can be opened.
+///
+struct HostFiles {
+    root: Option<PathBuf>,
+    files: HashMap<u32, File>,
+    next_handle: u32,
+    errno: i32,
+}
+
+impl HostFiles {
+    fn new(root: Option<PathBuf>) -> Self {
+        Self {
+            root,
+            files: HashMap::new(),
+            next_handle: FIRST_FILE_HANDLE,
+            errno: 0,
+        }
+    }
+
+    /// Record the error code for `SYS_ERRNO`
+    fn fail<T>(&mut self, errno: i32) -> Result<T, i32> {
+        self.errno = errno;
+        Err(-1)
+    }
+
+    fn resolve(&self, name: &str) -> Result<PathBuf, i32> {
+        let Some(root) = &self.root else {
+            return Err(ENOENT);
+        };
+        // absolute names are relative to the root too
+        let mut path = root.clone();
+        for component in Path::new(name).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(EACCES),
+            }
+        }
+        if path == *root {
+            return Err(EACCES);
+        }
+
+        // symbolic links may not lead out of the root either
+        let parent = path.parent().unwrap_or(root);
+        let parent = fs::canonicalize(parent).map_err(|e| errno(&e))?;
+        let target = match fs::canonicalize(&path) {
+            Ok(target) => target,
+            Err(_) if fs::symlink_metadata(&path).is_ok() => return Err(EACCES),
+            Err(_) => parent.clone(),
+        };
+        if !parent.starts_with(root) || !target.starts_with(root) {
+            return Err(EACCES);
+        }
+        Ok(path)
+    }
+
+    fn open(&mut self, name: &str, mode: u32) -> Result<u32, i32> {
+        let mut options = OpenOptions::new();
+        // modes are the fopen modes r, rb, r+, r+b, w, wb, w+, w+b, a, ab, a+, a+b
+        match mode {
+            0 | 1 => options.read(true),
+            2 | 3 => options.read(true).write(true),
+            4 | 5 => options.write(true).create(true).truncate(true),
+            6 | 7 => options.read(true).write(true).create(true).truncate(true),
+            8 | 9 => options.append(true).create(true),
+            10 | 11 => options.read(true).append(true).create(true),
+            _ => return self.fail(EINVAL),
+        };
+        let path = match self.resolve(name) {
+            Ok(path) => path,
+            Err(errno) => return self.fail(errno),
+        };
+        match options.open(path) {
+            Ok(file) => {
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.files.insert(handle, file);
+                Ok(handle)
+            }
+            Err(e) => self.fail(errno(&e)),
+        }
+    }
+
+    fn close(&mut self, handle: u32) -> bool {
+        if self.files.remove(&handle).is_some() {
+            true
+        } else {
+            self.errno = EBADF;
+            false
+        }
+    }
+
+    fn file(&mut self, handle: u32) -> Result<&mut File, i32> {
+        match self.files.get_mut(&handle) {
+            Some(file) => Ok(file),
+            None => {
+                self.errno = EBADF;
+                Err(-1)
+            }
+        }
+    }
+
+    fn flen(&mut self, handle: u32) -> Result<u32, i32> {
+        match self.file(handle)?.metadata() {
+            Ok(metadata) => Ok(metadata.len() as u32),
+            Err(e) => self.fail(errno(&e)),
+        }
+    }
+
+    /// Returns the data and the number of bytes not read
+    fn read(&mut self, handle: u32, len: u32) -> Result<(Vec<u8>, u32), i32> {
+        let mut data = Vec::new();
+        match self
+            .file(handle)?
+            .take(u64::from(len))
+            .read_to_end(&mut data)
+        {
+            Ok(n) => Ok((data, len - n as u32)),
+            Err(e) => self.fail(errno(&e)),
+        }
+    }
+
+    /// Returns the number of bytes not written
+    fn write(&mut self, handle: u32, data: &[u8]) -> Result<u32, i32> {
+        let unwritten = data.len() as i32;
+        let Ok(file) = self.file(handle) else {
+            return Err(unwritten);
+        };
+        match file.write_all(data) {
+            Ok(()) => Ok(0),
+            Err(e) => {
+                self.errno = errno(&e);
+                Err(unwritten)
+            }
+        }
+    }
+
+    fn seek(&mut self, handle: u32, position: u32) -> bool {
+        let result = match self.file(handle) {
+            Ok(file) => file.seek(SeekFrom::Start(u64::from(position))),
+            Err(_) => return false,
+        };
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                self.errno = errno(&e);
+                false
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &str) -> bool {
+        let result = self
+            .resolve(name)
+            .and_then(|path| fs::remove_file(path).map_err(|e| errno(&e)));
+        self.record(result)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> bool {
+        let result = self.resolve(from).and_then(|from| {
+            let to = self.resolve(to)?;
+            fs::rename(from, to).map_err(|e| errno(&e))
+        });
+        self.record(result)
+    }
+
+    fn record(&mut self, result: Result<(), i32>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(errno) => {
+                self.errno = errno;
+                false
+            }
+        }
+    }
+
+    /// Name of temporary file `id` with a terminating zero
+    fn tmpnam(&mut self, id: u32, len: u32) -> Result<Vec<u8>, i32> {
+        if self.root.is_none() {
+            return self.fail(ENOENT);
+        }
+        let mut name = format!("zmu-tmp-{id:03}").into_bytes();
+        name.push(0);
+        if id > 255 || name.len() > len as usize {
+            return self.fail(EINVAL);
+        }
+        Ok(name)
+    }
+}
+
+///
+/// Host side configuration of semihosting
+///
+#[derive(Debug, Default, Clone)]
+pub struct SemihostOptions {
+    /// Directory for the files of the target, without it only the console
+    /// and the feature data are available
+    pub root: Option<PathBuf>,
+    /// Command line returned by `SYS_GET_CMDLINE`, program name first
+    pub cmdline: String,
+    /// Heap base, heap limit, stack base and stack limit for `SYS_HEAPINFO`
+    pub heap_info: [u32; 4],
+    /// Allow `SYS_SYSTEM` to run commands on the host
+    pub system: bool,
+}
+
+fn run_system(command: &str, cwd: Option<&Path>) -> io::Result<process::ExitStatus> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    if let Some(cwd) = cwd {
+        shell.current_dir(cwd);
+    }
+    shell.status()
+}
+
+///
+/// Create the host side of semihosting.
+///
+pub fn get_semihost_func(
+    start: Instant,
+    options: SemihostOptions,
+) -> impl FnMut(&SemihostingCommand) -> SemihostingResponse {
     let mut semihost_features_position: u32 = 0;
+    let mut files = HostFiles::new(options.root);
+    let mut cmdline = options.cmdline.into_bytes();
+    cmdline.push(0);
 
     move |semihost_cmd: &SemihostingCommand| -> SemihostingResponse {
         match semihost_cmd {
@@ -46,7 +304,9 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                         result: Ok(SEMIHOST_FEATURES_HANDLE),
                     }
                 } else {
-                    SemihostingResponse::SysOpen { result: Err(-1) }
+                    SemihostingResponse::SysOpen {
+                        result: files.open(name, *mode),
+                    }
                 }
             }
             SemihostingCommand::SysClose { handle } => {
@@ -55,7 +315,9 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                     semihost_features_position = 0;
                 }
 
-                SemihostingResponse::SysClose { success: true }
+                SemihostingResponse::SysClose {
+                    success: *handle < FIRST_FILE_HANDLE || files.close(*handle),
+                }
             }
             SemihostingCommand::SysFlen { handle } => {
                 // println!("filelen for handle '{}'", handle);
@@ -65,7 +327,9 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                 } else if *handle == SEMIHOST_FEATURES_HANDLE {
                     SemihostingResponse::SysFlen { result: Ok(5) }
                 } else {
-                    SemihostingResponse::SysFlen { result: Err(-1) }
+                    SemihostingResponse::SysFlen {
+                        result: files.flen(*handle),
+                    }
                 }
             }
             SemihostingCommand::SysIstty { handle } => {
@@ -76,7 +340,7 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                     || *handle == TT_HANDLE_STDERR
                 {
                     SemihostingResponse::SysIstty { result: Ok(1) }
-                } else if *handle == SEMIHOST_FEATURES_HANDLE {
+                } else if *handle == SEMIHOST_FEATURES_HANDLE || files.files.contains_key(handle) {
                     SemihostingResponse::SysIstty { result: Ok(0) }
                 } else {
                     SemihostingResponse::SysIstty { result: Err(-1) }
@@ -95,8 +359,13 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                     print!("{}", String::from_utf8_lossy(text));
                     io::stdout().flush().expect("Could not flush stdout");
                     SemihostingResponse::SysWrite { result: Ok(0) }
+                } else if *handle == TT_HANDLE_STDERR {
+                    eprint!("{}", String::from_utf8_lossy(data));
+                    SemihostingResponse::SysWrite { result: Ok(0) }
                 } else {
-                    SemihostingResponse::SysWrite { result: Err(-1) }
+                    SemihostingResponse::SysWrite {
+                        result: files.write(*handle, data),
+                    }
                 }
             }
             SemihostingCommand::SysRead {
@@ -129,7 +398,11 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                         result: Ok((*memoryptr, data, diff)),
                     }
                 } else {
-                    SemihostingResponse::SysRead { result: Err(-1) }
+                    SemihostingResponse::SysRead {
+                        result: files
+                            .read(*handle, *len)
+                            .map(|(data, diff)| (*memoryptr, data, diff)),
+                    }
                 }
             }
             SemihostingCommand::SysSeek { handle, position } => {
@@ -143,9 +416,22 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
                         SemihostingResponse::SysSeek { success: false }
                     }
                 } else {
-                    SemihostingResponse::SysSeek { success: false }
+                    SemihostingResponse::SysSeek {
+                        success: files.seek(*handle, *position),
+                    }
+                }
+            }
+            SemihostingCommand::SysTmpnam { memoryptr, id, len } => {
+                SemihostingResponse::SysTmpnam {
+                    result: files.tmpnam(*id, *len).map(|name| (*memoryptr, name)),
                 }
             }
+            SemihostingCommand::SysRemove { name } => SemihostingResponse::SysRemove {
+                success: files.remove(name),
+            },
+            SemihostingCommand::SysRename { from, to } => SemihostingResponse::SysRename {
+                success: files.rename(from, to),
+            },
             SemihostingCommand::SysClock => {
                 // println!("sysclock");
                 let elapsed = start.elapsed();
@@ -192,8 +478,277 @@ pub fn get_semihost_func(start: Instant) -> impl FnMut(&SemihostingCommand) -> S
             SemihostingCommand::SysErrno => {
                 // println!("syserrno");
 
-                SemihostingResponse::SysErrno { result: 0 }
+                SemihostingResponse::SysErrno {
+                    result: files.errno as u32,
+                }
+            }
+            SemihostingCommand::SysWrite0 { data } => {
+                print!("{}", String::from_utf8_lossy(data));
+                io::stdout().flush().expect("Could not flush stdout");
+                SemihostingResponse::SysWrite { result: Ok(0) }
             }
+            SemihostingCommand::SysReadC => {
+                let mut buf = [0u8; 1];
+                SemihostingResponse::SysReadC {
+                    result: match io::stdin().read(&mut buf) {
+                        Ok(1) => Ok(buf[0]),
+                        _ => Err(-1),
+                    },
+                }
+            }
+            SemihostingCommand::SysTime => SemihostingResponse::SysTime {
+                result: SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_secs() as u32)
+                    .map_err(|_| -1),
+            },
+            SemihostingCommand::SysElapsed { cycles } => SemihostingResponse::SysElapsed {
+                result: Ok(*cycles),
+            },
+            SemihostingCommand::SysTickFreq => SemihostingResponse::SysTickFreq {
+                result: Ok(TICK_FREQUENCY),
+            },
+            SemihostingCommand::SysIserror { status } => SemihostingResponse::SysIserror {
+                result: u32::from((*status as i32) < 0),
+            },
+            SemihostingCommand::SysSystem { command } => {
+                let result = if options.system {
+                    match run_system(command, files.root.as_deref()) {
+                        Ok(status) => Ok(status.code().unwrap_or(-1) as u32),
+                        Err(e) => files.fail(errno(&e)),
+                    }
+                } else {
+                    warn!("SYS_SYSTEM '{command}' refused, enable it with --semihost-system");
+                    files.fail(EACCES)
+                };
+                SemihostingResponse::SysSystem { result }
+            }
+            SemihostingCommand::SysGetCmdline { memoryptr, len } => {
+                SemihostingResponse::SysGetCmdline {
+                    result: if cmdline.len() <= *len as usize {
+                        Ok((*memoryptr, cmdline.clone()))
+                    } else {
+                        Err(-1)
+                    },
+                }
+            }
+            SemihostingCommand::SysHeapinfo { memoryptr } => SemihostingResponse::SysHeapinfo {
+                memoryptr: *memoryptr,
+                heap_info: options.heap_info,
+            },
+            SemihostingCommand::Unknown { operation } => {
+                warn!("unsupported semihosting operation {operation:#x}");
+                SemihostingResponse::Unknown
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("zmu-semihost-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        fs::canonicalize(path).unwrap()
+    }
+
+    fn rooted(root: &Path) -> SemihostOptions {
+        SemihostOptions {
+            root: Some(root.to_path_buf()),
+            ..SemihostOptions::default()
         }
     }
+
+    fn open(
+        semihost: &mut impl FnMut(&SemihostingCommand) -> SemihostingResponse,
+        name: &str,
+        mode: u32,
+    ) -> Result<u32, i32> {
+        match semihost(&SemihostingCommand::SysOpen {
+            name: name.to_string(),
+            mode,
+        }) {
+            SemihostingResponse::SysOpen { result } => result,
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn test_file_write_read_seek_and_remove() {
+        // Arrange
+        let root = root("files");
+        let mut semihost = get_semihost_func(Instant::now(), rooted(&root));
+
+        // Act
+        let handle = open(&mut semihost, "/out/../result.bin", 4);
+        let escaped = handle.is_err();
+        let handle = open(&mut semihost, "result.bin", 5).unwrap();
+        let write = semihost(&SemihostingCommand::SysWrite {
+            handle,
+            data: b"hello world".to_vec(),
+        });
+        semihost(&SemihostingCommand::SysClose { handle });
+        let handle = open(&mut semihost, "/result.bin", 1).unwrap();
+        let flen = semihost(&SemihostingCommand::SysFlen { handle });
+        semihost(&SemihostingCommand::SysSeek {
+            handle,
+            position: 6,
+        });
+        let read = semihost(&SemihostingCommand::SysRead {
+            handle,
+            memoryptr: 0x2000_0000,
+            len: 8,
+        });
+        semihost(&SemihostingCommand::SysClose { handle });
+        let remove = semihost(&SemihostingCommand::SysRemove {
+            name: "result.bin".to_string(),
+        });
+
+        // Assert
+        assert!(escaped);
+        assert_eq!(write, SemihostingResponse::SysWrite { result: Ok(0) });
+        assert_eq!(flen, SemihostingResponse::SysFlen { result: Ok(11) });
+        assert_eq!(
+            read,
+            SemihostingResponse::SysRead {
+                result: Ok((0x2000_0000, b"world".to_vec(), 3))
+            }
+        );
+        assert_eq!(remove, SemihostingResponse::SysRemove { success: true });
+        assert!(!root.join("result.bin").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_paths_may_not_leave_the_root() {
+        // Arrange
+        let root = root("escape");
+        fs::write(root.with_extension("secret"), b"secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.with_extension("secret"), root.join("link")).unwrap();
+        let mut semihost = get_semihost_func(Instant::now(), rooted(&root));
+        let mut no_root = get_semihost_func(Instant::now(), SemihostOptions::default());
+
+        // Act
+        let parent = open(&mut semihost, "../escape.secret", 0);
+        let errno = semihost(&SemihostingCommand::SysErrno);
+        #[cfg(unix)]
+        let link = open(&mut semihost, "link", 0);
+        let rename = semihost(&SemihostingCommand::SysRename {
+            from: "link".to_string(),
+            to: "../moved".to_string(),
+        });
+        let without_root = open(&mut no_root, "input.bin", 0);
+
+        // Assert
+        assert_eq!(parent, Err(-1));
+        assert_eq!(
+            errno,
+            SemihostingResponse::SysErrno {
+                result: EACCES as u32
+            }
+        );
+        #[cfg(unix)]
+        assert_eq!(link, Err(-1));
+        assert_eq!(rename, SemihostingResponse::SysRename { success: false });
+        assert_eq!(without_root, Err(-1));
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_file(root.with_extension("secret")).unwrap();
+    }
+
+    #[test]
+    fn test_tmpnam_names_a_file_under_the_root() {
+        // Arrange
+        let root = root("tmpnam");
+        let mut semihost = get_semihost_func(Instant::now(), rooted(&root));
+
+        // Act
+        let tmpnam = semihost(&SemihostingCommand::SysTmpnam {
+            memoryptr: 0x2000_0000,
+            id: 7,
+            len: 32,
+        });
+        let too_short = semihost(&SemihostingCommand::SysTmpnam {
+            memoryptr: 0x2000_0000,
+            id: 7,
+            len: 8,
+        });
+        let handle = open(&mut semihost, "zmu-tmp-007", 6).unwrap();
+        semihost(&SemihostingCommand::SysClose { handle });
+        let rename = semihost(&SemihostingCommand::SysRename {
+            from: "zmu-tmp-007".to_string(),
+            to: "kept".to_string(),
+        });
+
+        // Assert
+        assert_eq!(
+            tmpnam,
+            SemihostingResponse::SysTmpnam {
+                result: Ok((0x2000_0000, b"zmu-tmp-007\0".to_vec()))
+            }
+        );
+        assert_eq!(
+            too_short,
+            SemihostingResponse::SysTmpnam { result: Err(-1) }
+        );
+        assert_eq!(rename, SemihostingResponse::SysRename { success: true });
+        assert!(root.join("kept").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_cmdline_heapinfo_and_system_refusal() {
+        // Arrange
+        let mut semihost = get_semihost_func(
+            Instant::now(),
+            SemihostOptions {
+                cmdline: "test.elf -v".to_string(),
+                heap_info: [0x2000_0100, 0x2002_0000, 0x2002_0000, 0x2000_0100],
+                ..SemihostOptions::default()
+            },
+        );
+
+        // Act
+        let cmdline = semihost(&SemihostingCommand::SysGetCmdline {
+            memoryptr: 0x2000_0000,
+            len: 80,
+        });
+        let too_short = semihost(&SemihostingCommand::SysGetCmdline {
+            memoryptr: 0x2000_0000,
+            len: 11,
+        });
+        let heapinfo = semihost(&SemihostingCommand::SysHeapinfo {
+            memoryptr: 0x2000_0040,
+        });
+        let system = semihost(&SemihostingCommand::SysSystem {
+            command: "true".to_string(),
+        });
+        let iserror = semihost(&SemihostingCommand::SysIserror { status: u32::MAX });
+        let unknown = semihost(&SemihostingCommand::Unknown { operation: 0x99 });
+
+        // Assert
+        assert_eq!(
+            cmdline,
+            SemihostingResponse::SysGetCmdline {
+                result: Ok((0x2000_0000, b"test.elf -v\0".to_vec()))
+            }
+        );
+        assert_eq!(
+            too_short,
+            SemihostingResponse::SysGetCmdline { result: Err(-1) }
+        );
+        assert_eq!(
+            heapinfo,
+            SemihostingResponse::SysHeapinfo {
+                memoryptr: 0x2000_0040,
+                heap_info: [0x2000_0100, 0x2002_0000, 0x2002_0000, 0x2000_0100]
+            }
+        );
+        assert_eq!(system, SemihostingResponse::SysSystem { result: Err(-1) });
+        assert_eq!(iserror, SemihostingResponse::SysIserror { result: 1 });
+        assert_eq!(unknown, SemihostingResponse::Unknown);
+    }
 }
@@ -0,0 +1,710 @@
+extern crate zmu_cortex_m;
+
+mod record;
+mod ring;
+mod trigger;
+
+pub use record::{RecordWriter, TRACE_FORMATS, TraceFormat};
+pub use ring::TraceRing;
+pub use trigger::{TraceTrigger, TraceWindow, exception_number};
+
+use crate::board::Region;
+use pad::PadStr;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use tabwriter::TabWriter;
+use zmu_cortex_m::Processor;
+use zmu_cortex_m::core::condition::decode_itstate;
+use zmu_cortex_m::core::exception::Exception;
+use zmu_cortex_m::core::memory_trace::MemoryAccess;
+use zmu_cortex_m::core::register::{Apsr, PSR};
+use zmu_cortex_m::core::thumb::ThumbCode;
+use zmu_cortex_m::core::trace_event::{TraceEvent, TraceEventKind};
+use zmu_cortex_m::decoder::Decoder;
+use zmu_cortex_m::memory::region::{MemoryRegion, RegionKind};
+
+///
+/// Registers shown on the lines of the instruction trace
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceRegisters {
+    /// r0 - r12, msp, psp and lr
+    Core,
+    /// Core registers followed by FPSCR and the floating point registers
+    All,
+    /// Registers changed by the instruction, including floating point
+    Changed,
+}
+
+/// Names of the register modes on the command line
+pub const TRACE_REGISTERS: [(&str, TraceRegisters); 3] = [
+    ("core", TraceRegisters::Core),
+    ("all", TraceRegisters::All),
+    ("changed", TraceRegisters::Changed),
+];
+
+const CORE_REGISTER_LABELS: [&str; 16] = [
+    "r0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "msp", "psp", "lr",
+];
+
+const CORE_REGISTER_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "msp", "psp",
+    "lr",
+];
+
+#[cfg(any(feature = "fpv4-sp-d16", feature = "fpv5-sp-d16"))]
+const FP_REGISTER_NAMES: [&str; 32] = [
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "s12", "s13", "s14",
+    "s15", "s16", "s17", "s18", "s19", "s20", "s21", "s22", "s23", "s24", "s25", "s26", "s27",
+    "s28", "s29", "s30", "s31",
+];
+
+#[cfg(feature = "fpv5-d16")]
+const FP_REGISTER_NAMES: [&str; 16] = [
+    "d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "d8", "d9", "d10", "d11", "d12", "d13", "d14",
+    "d15",
+];
+
+///
+/// Register value shown in the trace
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub name: &'static str,
+    pub value: u64,
+    /// Width of the value in hexadecimal digits
+    pub digits: usize,
+}
+
+impl Register {
+    fn word(name: &'static str, value: u32) -> Self {
+        Self {
+            name,
+            value: u64::from(value),
+            digits: 8,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{:0width$x}",
+            self.name,
+            self.value,
+            width = self.digits
+        )
+    }
+}
+
+///
+/// Formats the lines of the instruction trace, remembers the registers of
+/// the previous line to show only the changed ones.
+///
+pub struct InstructionTrace<S> {
+    symboltable: HashMap<u32, S>,
+    registers: TraceRegisters,
+    previous: Vec<Register>,
+}
+
+impl<S: AsRef<str>> InstructionTrace<S> {
+    pub fn new(symboltable: HashMap<u32, S>, registers: TraceRegisters) -> Self {
+        Self {
+            symboltable,
+            registers,
+            previous: Vec::new(),
+        }
+    }
+
+    /// Trace line of the instruction executed last
+    pub fn format(&mut self, processor: &Processor) -> String {
+        let instruction = format_instruction(processor, &self.symboltable);
+        let itstate = format_itstate(processor);
+        let core = core_registers(processor);
+        match self.registers {
+            TraceRegisters::Core => {
+                format!(
+                    "{instruction} {} {itstate}",
+                    format_registers(&CORE_REGISTER_LABELS, &core)
+                )
+            }
+            TraceRegisters::All => format!(
+                "{instruction} {}{} {itstate}",
+                format_registers(&CORE_REGISTER_LABELS, &core),
+                fp_registers(processor)
+                    .iter()
+                    .map(|register| format!(" {register}"))
+                    .collect::<String>()
+            ),
+            TraceRegisters::Changed => {
+                let mut registers: Vec<Register> = CORE_REGISTER_NAMES
+                    .iter()
+                    .zip(core)
+                    .map(|(name, value)| Register::word(name, value))
+                    .collect();
+                registers.extend(fp_registers(processor));
+                let mut line = instruction;
+                for (_, register) in changed_registers(&self.previous, &registers) {
+                    let _ = write!(line, " {register}");
+                }
+                self.previous = registers;
+                line.push(' ');
+                line.push_str(&itstate);
+                line
+            }
+        }
+    }
+}
+
+///
+/// Destination of the instruction trace: text lines or machine readable
+/// records
+///
+pub enum TraceWriter<S> {
+    Text {
+        trace: InstructionTrace<S>,
+        output: TabWriter<Box<dyn Write>>,
+    },
+    Records(RecordWriter<Box<dyn Write>, S>),
+}
+
+impl<S: AsRef<str>> TraceWriter<S> {
+    pub fn new(
+        output: Box<dyn Write>,
+        format: TraceFormat,
+        symboltable: HashMap<u32, S>,
+        registers: TraceRegisters,
+    ) -> io::Result<Self> {
+        Ok(match format {
+            TraceFormat::Text => Self::Text {
+                trace: InstructionTrace::new(symboltable, registers),
+                output: TabWriter::new(output).minwidth(16).padding(1),
+            },
+            format => Self::Records(RecordWriter::new(output, format, symboltable)?),
+        })
+    }
+
+    /// Write the instruction executed last, `memory` lists its data accesses.
+    pub fn instruction(
+        &mut self,
+        processor: &Processor,
+        memory: &[MemoryAccess],
+    ) -> io::Result<()> {
+        match self {
+            Self::Text { trace, output } => {
+                writeln!(output, "{}", trace.format(processor))?;
+                output.flush()
+            }
+            Self::Records(records) => records.instruction(processor, memory),
+        }
+    }
+
+    /// List all registers in every record instead of the changed ones.
+    pub fn all_registers(&mut self) -> &mut Self {
+        if let Self::Records(records) = self {
+            records.all_registers();
+        }
+        self
+    }
+
+    pub fn event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        match self {
+            Self::Text { output, .. } => writeln!(output, "{}", format_trace_event(event)),
+            Self::Records(records) => records.event(event),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Text { output, .. } => output.flush(),
+            Self::Records(records) => records.flush(),
+        }
+    }
+}
+
+/// Opcode, instruction, address, symbol, instruction count and flags
+fn format_instruction<S: AsRef<str>>(
+    processor: &Processor,
+    symboltable: &HashMap<u32, S>,
+) -> String {
+    let pc = processor.last_pc;
+
+    let thumb = processor.fetch_code(pc).unwrap();
+    let instruction = processor.decode(thumb);
+
+    let opcode_str = match thumb {
+        ThumbCode::Thumb32 { opcode } => format!("{:08X}", opcode).with_exact_width(8),
+        ThumbCode::Thumb16 { opcode } => format!("{:04X}", opcode).with_exact_width(8),
+        ThumbCode::Undefined => "undef".to_string().with_exact_width(8),
+    };
+
+    let instruction_str = format!("{}", instruction).with_exact_width(32);
+
+    let symbol = symboltable
+        .get(&pc)
+        .map_or("", AsRef::as_ref)
+        .with_exact_width(20);
+
+    let psr = PSR {
+        value: processor.psr.value,
+    };
+
+    format!(
+        "{0:}  {1:} {2:08X}  {3:}  {4:} {5:}{6:}{7:}{8:}{9:}",
+        opcode_str,
+        instruction_str,
+        pc,
+        symbol,
+        processor.instruction_count,
+        if psr.get_q() { 'Q' } else { 'q' },
+        if psr.get_v() { 'V' } else { 'v' },
+        if psr.get_c() { 'C' } else { 'c' },
+        if psr.get_z() { 'Z' } else { 'z' },
+        if psr.get_n() { 'N' } else { 'n' },
+    )
+}
+
+fn format_itstate(processor: &Processor) -> String {
+    let itstate = processor.get_itstate();
+    match decode_itstate(itstate) {
+        Some(decoded) => format!("IT:{:02x}({})", itstate, decoded),
+        None => format!("IT:{:02x}      ", itstate),
+    }
+}
+
+fn core_registers(processor: &Processor) -> [u32; 16] {
+    let mut registers = [0; 16];
+    registers[..13].copy_from_slice(&processor.r0_12);
+    registers[13] = processor.msp;
+    registers[14] = processor.psp;
+    registers[15] = processor.lr;
+    registers
+}
+
+fn format_registers(labels: &[&str], values: &[u32]) -> String {
+    labels
+        .iter()
+        .zip(values)
+        .map(|(label, value)| format!("{label}:{value:08x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+///
+/// Registers that differ from `previous` with their index in `registers`,
+/// all of them when `previous` is empty
+///
+fn changed_registers<'a>(
+    previous: &'a [Register],
+    registers: &'a [Register],
+) -> impl Iterator<Item = (usize, &'a Register)> {
+    registers
+        .iter()
+        .enumerate()
+        .filter(move |(index, register)| previous.get(*index) != Some(register))
+}
+
+///
+/// Registers in the order of the machine readable trace formats: r0 - r12,
+/// msp, psp, lr, xpsr, then FPSCR and the floating point registers.
+///
+fn trace_registers(processor: &Processor) -> Vec<Register> {
+    let mut registers: Vec<Register> = CORE_REGISTER_NAMES
+        .iter()
+        .zip(core_registers(processor))
+        .map(|(name, value)| Register::word(name, value))
+        .collect();
+    registers.push(Register::word("xpsr", processor.psr.value));
+    registers.extend(fp_registers(processor));
+    registers
+}
+
+/// FPSCR and the single precision registers
+#[cfg(any(feature = "fpv4-sp-d16", feature = "fpv5-sp-d16"))]
+fn fp_registers(processor: &Processor) -> Vec<Register> {
+    std::iter::once(Register::word("fpscr", processor.fpscr))
+        .chain(
+            FP_REGISTER_NAMES
+                .iter()
+                .zip(processor.fp_regs)
+                .map(|(name, value)| Register::word(name, value)),
+        )
+        .collect()
+}
+
+/// FPSCR and the double precision registers
+#[cfg(feature = "fpv5-d16")]
+fn fp_registers(processor: &Processor) -> Vec<Register> {
+    std::iter::once(Register::word("fpscr", processor.fpscr))
+        .chain(
+            FP_REGISTER_NAMES
+                .iter()
+                .zip(processor.fp_regs.chunks(2))
+                .map(|(name, words)| Register {
+                    name,
+                    value: u64::from(words[1]) << 32 | u64::from(words[0]),
+                    digits: 16,
+                }),
+        )
+        .collect()
+}
+
+#[cfg(not(any(feature = "fpv4-sp-d16", feature = "fpv5-sp-d16", feature = "fpv5-d16")))]
+fn fp_registers(_processor: &Processor) -> Vec<Register> {
+    Vec::new()
+}
+
+fn exception_name(exception: Exception) -> String {
+    match exception {
+        Exception::Interrupt { n } => format!("IRQ{n}"),
+        exception => format!("{exception:?}"),
+    }
+}
+
+/// Trace line of a processor event
+pub fn format_trace_event(event: &TraceEvent) -> String {
+    let description = match event.kind {
+        TraceEventKind::ExceptionEntry {
+            exception,
+            return_address,
+            exc_return,
+        } => format!(
+            "exception entry {} ({}), return address {return_address:08x}, EXC_RETURN {exc_return:08x}",
+            exception_name(exception),
+            usize::from(exception)
+        ),
+        TraceEventKind::ExceptionReturn {
+            exception,
+            exc_return,
+        } => format!(
+            "exception return {} ({}), EXC_RETURN {exc_return:08x}",
+            exception_name(exception),
+            usize::from(exception)
+        ),
+        TraceEventKind::Fault { fault, exception } if fault.exception() != exception => {
+            format!("fault {fault:?} escalated to {}", exception_name(exception))
+        }
+        TraceEventKind::Fault { fault, exception } => {
+            format!("fault {fault:?} -> {}", exception_name(exception))
+        }
+        TraceEventKind::Sleep => "sleep".to_string(),
+        TraceEventKind::Wake => "wake up".to_string(),
+        TraceEventKind::SysTickWrap => "SysTick wrap".to_string(),
+        TraceEventKind::Semihosting {
+            operation,
+            parameter,
+        } => format!("semihosting call {operation:#04x}, parameter {parameter:08x}"),
+    };
+    format!("-- {description}")
+}
+
+///
+/// Orders the processor events with the instruction lines: the events
+/// reported while executing an instruction follow its line, the events
+/// between instructions precede the line of the next instruction.
+///
+#[derive(Default)]
+pub struct TraceEvents {
+    pending: Vec<TraceEvent>,
+}
+
+impl TraceEvents {
+    /// Remember an event until the line of its instruction is written
+    pub fn push(&mut self, event: &TraceEvent) {
+        self.pending.push(*event);
+    }
+
+    ///
+    /// Take the events to write before and after the line of the
+    /// instruction with number `instruction_count`.
+    ///
+    pub fn take(&mut self, instruction_count: u64) -> (Vec<TraceEvent>, Vec<TraceEvent>) {
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|event| event.instruction_count < instruction_count)
+    }
+}
+
+///
+/// Kind of memory a data access goes to
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessRegion {
+    Flash,
+    Ram,
+    Peripheral,
+}
+
+/// Names of the kinds of memory on the command line and in the trace
+pub const ACCESS_REGIONS: [(&str, AccessRegion); 3] = [
+    ("flash", AccessRegion::Flash),
+    ("ram", AccessRegion::Ram),
+    ("peripheral", AccessRegion::Peripheral),
+];
+
+impl AccessRegion {
+    /// Name of the kind of memory
+    pub fn name(self) -> &'static str {
+        ACCESS_REGIONS
+            .iter()
+            .find(|(_, region)| *region == self)
+            .map_or("", |(name, _)| name)
+    }
+
+    ///
+    /// Classify `address` by the memory region holding it, accesses to
+    /// aliases and bit-band aliases are classified by the aliased memory and
+    /// addresses outside the flash and RAM regions are peripheral accesses.
+    ///
+    pub fn of(memory: &[MemoryRegion], address: u32) -> Self {
+        let kind = |address: u32| {
+            memory
+                .iter()
+                .rfind(|region| region.contains(address))
+                .map(|region| (region, region.kind))
+        };
+        let resolved = match kind(address) {
+            Some((region, RegionKind::Alias { target })) => {
+                kind(target.wrapping_add(address - region.base))
+            }
+            Some((region, RegionKind::BitBand { target })) => {
+                kind(target.wrapping_add((address - region.base) >> 5))
+            }
+            other => other,
+        };
+        match resolved.map(|(_, kind)| kind) {
+            Some(RegionKind::Rom) => AccessRegion::Flash,
+            Some(RegionKind::Ram) => AccessRegion::Ram,
+            _ => AccessRegion::Peripheral,
+        }
+    }
+}
+
+///
+/// Selection of the data accesses written to the memory trace, an empty
+/// list or `None` selects everything.
+///
+#[derive(Debug, Default, Clone)]
+pub struct MemoryTraceFilter {
+    /// Address ranges to trace
+    pub ranges: Vec<Region>,
+    /// Kinds of memory to trace
+    pub regions: Vec<AccessRegion>,
+    /// Trace only stores (`Some(true)`) or only loads (`Some(false)`)
+    pub write: Option<bool>,
+}
+
+impl MemoryTraceFilter {
+    /// Check if `access` to memory of kind `region` is traced
+    pub fn matches(&self, access: &MemoryAccess, region: AccessRegion) -> bool {
+        let in_range = |range: &Region| {
+            let start = u64::from(access.address);
+            let base = u64::from(range.base);
+            start < base + u64::from(range.size) && base < start + u64::from(access.size)
+        };
+        self.write.is_none_or(|write| write == access.write)
+            && (self.regions.is_empty() || self.regions.contains(&region))
+            && (self.ranges.is_empty() || self.ranges.iter().any(in_range))
+    }
+}
+
+///
+/// Format a data access as `<pc> <R|W><size> <address> <value> <region>`,
+/// e.g. `08000124 W4 20000ff8 0800010d ram`.
+///
+pub fn format_memory_access(access: &MemoryAccess, region: AccessRegion) -> String {
+    format!(
+        "{:08x} {}{} {:08x} {:0width$x} {}",
+        access.pc,
+        if access.write { 'W' } else { 'R' },
+        access.size,
+        access.address,
+        access.value,
+        region.name(),
+        width = access.size as usize * 2
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zmu_cortex_m::core::fault::Fault;
+
+    fn traced_processor() -> Processor {
+        let mut processor = Processor::new();
+        // movs r0, #1
+        processor.flash_memory(0x100, &[0x01, 0x20]);
+        processor
+    }
+
+    #[test]
+    fn test_changed_registers_mode_shows_only_changed_registers() {
+        // Arrange
+        let mut processor = traced_processor();
+        let mut trace =
+            InstructionTrace::new(HashMap::<u32, String>::new(), TraceRegisters::Changed);
+
+        // Act
+        let first = trace.format(&processor);
+        processor.r0_12[0] = 1;
+        let second = trace.format(&processor);
+        let third = trace.format(&processor);
+
+        // Assert
+        assert!(first.contains(" r0:00000000 r1:00000000 "));
+        assert!(first.contains(" lr:00000000"));
+        assert!(second.ends_with("qvczn r0:00000001 IT:00      "));
+        assert!(third.ends_with("qvczn IT:00      "));
+    }
+
+    #[test]
+    fn test_core_registers_mode_keeps_the_full_line() {
+        // Arrange
+        let processor = traced_processor();
+        let mut trace = InstructionTrace::new(HashMap::<u32, String>::new(), TraceRegisters::Core);
+
+        // Act
+        let line = trace.format(&processor);
+
+        // Assert
+        assert_eq!(
+            line,
+            "2001      mov r0, #1                       00000000                        0 qvczn \
+             r0:00000000 1:00000000 2:00000000 3:00000000 4:00000000 5:00000000 6:00000000 \
+             7:00000000 8:00000000 9:00000000 10:00000000 11:00000000 12:00000000 msp:00000000 \
+             psp:00000000 lr:00000000 IT:00      "
+        );
+    }
+
+    #[test]
+    fn test_trace_events_split_around_the_instruction_line() {
+        // Arrange
+        let mut events = TraceEvents::default();
+        let event = |instruction_count, kind| TraceEvent {
+            instruction_count,
+            kind,
+        };
+        events.push(&event(4, TraceEventKind::Wake));
+        events.push(&event(5, TraceEventKind::SysTickWrap));
+
+        // Act
+        let (before, after) = events.take(5);
+        let (empty_before, empty_after) = events.take(6);
+
+        // Assert
+        assert_eq!(before, [event(4, TraceEventKind::Wake)]);
+        assert_eq!(after, [event(5, TraceEventKind::SysTickWrap)]);
+        assert!(empty_before.is_empty() && empty_after.is_empty());
+    }
+
+    #[test]
+    fn test_format_trace_event_names_exceptions_and_escalation() {
+        // Arrange
+        let event = |kind| TraceEvent {
+            instruction_count: 0,
+            kind,
+        };
+
+        // Act
+        let entry = format_trace_event(&event(TraceEventKind::ExceptionEntry {
+            exception: Exception::Interrupt { n: 3 },
+            return_address: 0x0800_0124,
+            exc_return: 0xffff_fff9,
+        }));
+        let escalated = format_trace_event(&event(TraceEventKind::Fault {
+            fault: Fault::UndefInstr,
+            exception: Exception::HardFault,
+        }));
+
+        // Assert
+        assert_eq!(
+            entry,
+            "-- exception entry IRQ3 (19), return address 08000124, EXC_RETURN fffffff9"
+        );
+        #[cfg(not(feature = "armv6m"))]
+        assert_eq!(escalated, "-- fault UndefInstr escalated to HardFault");
+        // ARMv6-M has no UsageFault, undefined instructions go to HardFault
+        #[cfg(feature = "armv6m")]
+        assert_eq!(escalated, "-- fault UndefInstr -> HardFault");
+    }
+
+    fn access(address: u32, size: u32, write: bool) -> MemoryAccess {
+        MemoryAccess {
+            pc: 0x0800_0124,
+            address,
+            size,
+            write,
+            value: 0x0800_010d,
+        }
+    }
+
+    #[test]
+    fn test_access_region_resolves_aliases_and_peripherals() {
+        // Arrange
+        let memory = [
+            MemoryRegion::rom("flash", 0x0800_0000, 0x1000),
+            MemoryRegion::ram("ram", 0x2000_0000, 0x1000),
+            MemoryRegion::alias("boot", 0, 0x1000, 0x0800_0000),
+        ];
+
+        // Act
+        let regions =
+            [0x0800_0010, 0x2000_0ffc, 0x10, 0x4001_3800].map(|a| AccessRegion::of(&memory, a));
+
+        // Assert
+        assert_eq!(
+            regions,
+            [
+                AccessRegion::Flash,
+                AccessRegion::Ram,
+                AccessRegion::Flash,
+                AccessRegion::Peripheral
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memory_trace_filter_combines_range_region_and_direction() {
+        // Arrange
+        let filter = MemoryTraceFilter {
+            ranges: vec![Region {
+                base: 0x2000_0010,
+                size: 0x10,
+            }],
+            regions: vec![AccessRegion::Ram],
+            write: Some(true),
+        };
+
+        // Act
+        let overlapping = filter.matches(&access(0x2000_000e, 4, true), AccessRegion::Ram);
+        let read = filter.matches(&access(0x2000_0010, 4, false), AccessRegion::Ram);
+        let outside = filter.matches(&access(0x2000_0020, 1, true), AccessRegion::Ram);
+        let everything =
+            MemoryTraceFilter::default().matches(&access(0, 1, false), AccessRegion::Peripheral);
+
+        // Assert
+        assert!(overlapping);
+        assert!(!read);
+        assert!(!outside);
+        assert!(everything);
+    }
+
+    #[test]
+    fn test_format_memory_access_pads_value_to_width() {
+        // Act
+        let word = format_memory_access(&access(0x2000_0ff8, 4, true), AccessRegion::Ram);
+        let byte = format_memory_access(
+            &MemoryAccess {
+                value: 0x0a,
+                ..access(0x4001_3804, 1, false)
+            },
+            AccessRegion::Peripheral,
+        );
+
+        // Assert
+        assert_eq!(word, "08000124 W4 20000ff8 0800010d ram");
+        assert_eq!(byte, "08000124 R1 40013804 0a peripheral");
+    }
+}
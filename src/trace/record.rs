@@ -0,0 +1,467 @@
+//!
+//! Machine readable instruction trace, the schema is described in
+//! doc/trace_format.md
+//!
+
+use super::{Register, changed_registers, exception_name, trace_registers};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use zmu_cortex_m::Processor;
+use zmu_cortex_m::core::memory_trace::MemoryAccess;
+use zmu_cortex_m::core::thumb::ThumbCode;
+use zmu_cortex_m::core::trace_event::{TraceEvent, TraceEventKind};
+use zmu_cortex_m::decoder::Decoder;
+
+///
+/// Output format of the instruction trace
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Tab aligned lines for reading
+    Text,
+    /// One JSON object per line
+    Json,
+    /// Comma separated values with a header line
+    Csv,
+    /// Little endian binary records
+    Bin,
+}
+
+/// Names of the trace formats on the command line
+pub const TRACE_FORMATS: [(&str, TraceFormat); 4] = [
+    ("text", TraceFormat::Text),
+    ("json", TraceFormat::Json),
+    ("csv", TraceFormat::Csv),
+    ("bin", TraceFormat::Bin),
+];
+
+/// First bytes of a binary trace
+pub const BIN_MAGIC: &[u8; 8] = b"ZMUTRACE";
+
+/// Version of the binary trace layout
+pub const BIN_VERSION: u32 = 1;
+
+/// Tag of an instruction record in the binary trace
+const BIN_INSTRUCTION: u8 = 1;
+
+const CSV_HEADER: &str = "instruction_count,cycle,pc,opcode,size,mnemonic,symbol,registers,memory";
+
+///
+/// Writes instruction records in one of the machine readable formats.
+/// Each record lists the registers changed since the previous record, the
+/// first record lists all of them.
+///
+pub struct RecordWriter<W: Write, S> {
+    output: W,
+    format: TraceFormat,
+    symboltable: HashMap<u32, S>,
+    previous: Vec<Register>,
+    all_registers: bool,
+}
+
+impl<W: Write, S: AsRef<str>> RecordWriter<W, S> {
+    ///
+    /// Create the writer and write the header of the format: the column
+    /// names of CSV or the magic and version of the binary trace.
+    ///
+    pub fn new(
+        mut output: W,
+        format: TraceFormat,
+        symboltable: HashMap<u32, S>,
+    ) -> io::Result<Self> {
+        match format {
+            TraceFormat::Csv => writeln!(output, "{CSV_HEADER}")?,
+            TraceFormat::Bin => {
+                output.write_all(BIN_MAGIC)?;
+                output.write_all(&BIN_VERSION.to_le_bytes())?;
+            }
+            TraceFormat::Text | TraceFormat::Json => {}
+        }
+        Ok(Self {
+            output,
+            format,
+            symboltable,
+            previous: Vec::new(),
+            all_registers: false,
+        })
+    }
+
+    ///
+    /// List all registers in every record, for traces that do not start
+    /// with the first record such as the dump of the trace ring buffer.
+    ///
+    pub fn all_registers(&mut self) -> &mut Self {
+        self.all_registers = true;
+        self
+    }
+
+    /// Write the record of the instruction executed last and its data accesses.
+    pub fn instruction(
+        &mut self,
+        processor: &Processor,
+        memory: &[MemoryAccess],
+    ) -> io::Result<()> {
+        let pc = processor.last_pc;
+        let (opcode, size) = match processor.fetch_code(pc) {
+            Ok(ThumbCode::Thumb32 { opcode }) => (opcode, 4),
+            Ok(ThumbCode::Thumb16 { opcode }) => (u32::from(opcode), 2),
+            Ok(ThumbCode::Undefined) | Err(_) => (0, 0),
+        };
+        let mnemonic = match processor.fetch_code(pc) {
+            Ok(thumb) => processor.decode(thumb).to_string(),
+            Err(_) => String::new(),
+        };
+        let symbol = self.symboltable.get(&pc).map_or("", AsRef::as_ref);
+
+        let registers = trace_registers(processor);
+        let previous: &[Register] = if self.all_registers {
+            &[]
+        } else {
+            &self.previous
+        };
+        let changed: Vec<(usize, &Register)> = changed_registers(previous, &registers).collect();
+
+        let record = Instruction {
+            instruction_count: processor.instruction_count,
+            cycle: processor.cycle_count,
+            pc,
+            opcode,
+            size,
+            mnemonic: &mnemonic,
+            symbol,
+            registers: &changed,
+            memory,
+        };
+        match self.format {
+            TraceFormat::Json => writeln!(self.output, "{}", record.json())?,
+            TraceFormat::Csv => writeln!(self.output, "{}", record.csv())?,
+            TraceFormat::Bin => self.output.write_all(&record.bin())?,
+            // text lines are written by `InstructionTrace`
+            TraceFormat::Text => {}
+        }
+        self.previous = registers;
+        Ok(())
+    }
+
+    /// Write a processor event, events are only recorded in the JSON format.
+    pub fn event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if self.format == TraceFormat::Json {
+            writeln!(self.output, "{}", json_event(event))?;
+        }
+        Ok(())
+    }
+
+    /// Flush the buffered records.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+struct Instruction<'a> {
+    instruction_count: u64,
+    cycle: u64,
+    pc: u32,
+    opcode: u32,
+    size: u8,
+    mnemonic: &'a str,
+    symbol: &'a str,
+    registers: &'a [(usize, &'a Register)],
+    memory: &'a [MemoryAccess],
+}
+
+impl Instruction<'_> {
+    fn json(&self) -> String {
+        let registers = self
+            .registers
+            .iter()
+            .map(|(_, register)| format!("\"{}\":{}", register.name, register.value))
+            .collect::<Vec<_>>()
+            .join(",");
+        let memory = self
+            .memory
+            .iter()
+            .map(|access| {
+                format!(
+                    "{{\"pc\":{},\"address\":{},\"size\":{},\"write\":{},\"value\":{}}}",
+                    access.pc, access.address, access.size, access.write, access.value
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"type\":\"instruction\",\"instruction_count\":{},\"cycle\":{},\"pc\":{},\"opcode\":{},\"size\":{},\"mnemonic\":{},\"symbol\":{},\"registers\":{{{registers}}},\"memory\":[{memory}]}}",
+            self.instruction_count,
+            self.cycle,
+            self.pc,
+            self.opcode,
+            self.size,
+            json_string(self.mnemonic),
+            json_string(self.symbol),
+        )
+    }
+
+    fn csv(&self) -> String {
+        let registers = self
+            .registers
+            .iter()
+            .map(|(_, register)| {
+                format!(
+                    "{}=0x{:0width$x}",
+                    register.name,
+                    register.value,
+                    width = register.digits
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        let memory = self
+            .memory
+            .iter()
+            .map(|access| {
+                format!(
+                    "{}{}:0x{:08x}:0x{:0width$x}",
+                    if access.write { 'W' } else { 'R' },
+                    access.size,
+                    access.address,
+                    access.value,
+                    width = access.size as usize * 2
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        format!(
+            "{},{},0x{:08x},0x{:0width$x},{},{},{},{},{}",
+            self.instruction_count,
+            self.cycle,
+            self.pc,
+            self.opcode,
+            self.size,
+            csv_string(self.mnemonic),
+            csv_string(self.symbol),
+            csv_string(&registers),
+            csv_string(&memory),
+            width = usize::from(self.size) * 2
+        )
+    }
+
+    fn bin(&self) -> Vec<u8> {
+        let mut record = vec![BIN_INSTRUCTION];
+        record.extend_from_slice(&self.instruction_count.to_le_bytes());
+        record.extend_from_slice(&self.cycle.to_le_bytes());
+        record.extend_from_slice(&self.pc.to_le_bytes());
+        record.extend_from_slice(&self.opcode.to_le_bytes());
+        record.push(self.size);
+        for text in [self.mnemonic, self.symbol] {
+            let bytes = &text.as_bytes()[..text.len().min(usize::from(u16::MAX))];
+            record.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+            record.extend_from_slice(bytes);
+        }
+        record.push(self.registers.len() as u8);
+        for (id, register) in self.registers {
+            record.push(*id as u8);
+            record.extend_from_slice(&register.value.to_le_bytes());
+        }
+        record.extend_from_slice(&(self.memory.len() as u16).to_le_bytes());
+        for access in self.memory {
+            record.extend_from_slice(&access.pc.to_le_bytes());
+            record.extend_from_slice(&access.address.to_le_bytes());
+            record.push(access.size as u8);
+            record.push(u8::from(access.write));
+            record.extend_from_slice(&access.value.to_le_bytes());
+        }
+        record
+    }
+}
+
+fn json_event(event: &TraceEvent) -> String {
+    let fields = match event.kind {
+        TraceEventKind::ExceptionEntry {
+            exception,
+            return_address,
+            exc_return,
+        } => format!(
+            "\"event\":\"exception_entry\",\"exception\":{},\"name\":{},\"return_address\":{return_address},\"exc_return\":{exc_return}",
+            usize::from(exception),
+            json_string(&exception_name(exception))
+        ),
+        TraceEventKind::ExceptionReturn {
+            exception,
+            exc_return,
+        } => format!(
+            "\"event\":\"exception_return\",\"exception\":{},\"name\":{},\"exc_return\":{exc_return}",
+            usize::from(exception),
+            json_string(&exception_name(exception))
+        ),
+        TraceEventKind::Fault { fault, exception } => format!(
+            "\"event\":\"fault\",\"fault\":{},\"exception\":{},\"name\":{}",
+            json_string(&format!("{fault:?}")),
+            usize::from(exception),
+            json_string(&exception_name(exception))
+        ),
+        TraceEventKind::Sleep => "\"event\":\"sleep\"".to_string(),
+        TraceEventKind::Wake => "\"event\":\"wake\"".to_string(),
+        TraceEventKind::SysTickWrap => "\"event\":\"systick_wrap\"".to_string(),
+        TraceEventKind::Semihosting {
+            operation,
+            parameter,
+        } => {
+            format!("\"event\":\"semihosting\",\"operation\":{operation},\"parameter\":{parameter}")
+        }
+    };
+    format!(
+        "{{\"type\":\"event\",\"instruction_count\":{},{fields}}}",
+        event.instruction_count
+    )
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", u32::from(c));
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zmu_cortex_m::core::exception::Exception;
+
+    fn register(name: &'static str, value: u64) -> Register {
+        Register {
+            name,
+            value,
+            digits: 8,
+        }
+    }
+
+    fn store() -> ([(usize, Register); 2], [MemoryAccess; 1]) {
+        (
+            [(0, register("r0", 1)), (16, register("xpsr", 0x0100_0000))],
+            [MemoryAccess {
+                pc: 0x0800_0010,
+                address: 0x2000_0ff8,
+                size: 4,
+                write: true,
+                value: 1,
+            }],
+        )
+    }
+
+    fn record<'a>(
+        registers: &'a [(usize, &'a Register)],
+        memory: &'a [MemoryAccess],
+    ) -> Instruction<'a> {
+        Instruction {
+            instruction_count: 3,
+            cycle: 5,
+            pc: 0x0800_0010,
+            opcode: 0x9000,
+            size: 2,
+            mnemonic: "str r0, [sp, #0]",
+            symbol: "main",
+            registers,
+            memory,
+        }
+    }
+
+    #[test]
+    fn test_json_and_csv_records() {
+        // Arrange
+        let (registers, memory) = store();
+        let registers: Vec<(usize, &Register)> = registers.iter().map(|(id, r)| (*id, r)).collect();
+        let record = record(&registers, &memory);
+
+        // Act
+        let json = record.json();
+        let csv = record.csv();
+
+        // Assert
+        assert_eq!(
+            json,
+            "{\"type\":\"instruction\",\"instruction_count\":3,\"cycle\":5,\"pc\":134217744,\
+             \"opcode\":36864,\"size\":2,\"mnemonic\":\"str r0, [sp, #0]\",\"symbol\":\"main\",\
+             \"registers\":{\"r0\":1,\"xpsr\":16777216},\"memory\":[{\"pc\":134217744,\
+             \"address\":536875000,\"size\":4,\"write\":true,\"value\":1}]}"
+        );
+        assert_eq!(
+            csv,
+            "3,5,0x08000010,0x9000,2,\"str r0, [sp, #0]\",\"main\",\
+             \"r0=0x00000001;xpsr=0x01000000\",\"W4:0x20000ff8:0x00000001\""
+        );
+    }
+
+    #[test]
+    fn test_bin_record_layout() {
+        // Arrange
+        let (registers, memory) = store();
+        let registers: Vec<(usize, &Register)> = registers.iter().map(|(id, r)| (*id, r)).collect();
+        let record = record(&registers, &memory);
+
+        // Act
+        let bin = record.bin();
+
+        // Assert
+        let mut expected = vec![BIN_INSTRUCTION];
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(&5u64.to_le_bytes());
+        expected.extend_from_slice(&0x0800_0010u32.to_le_bytes());
+        expected.extend_from_slice(&0x9000u32.to_le_bytes());
+        expected.push(2);
+        expected.extend_from_slice(&16u16.to_le_bytes());
+        expected.extend_from_slice(b"str r0, [sp, #0]");
+        expected.extend_from_slice(&4u16.to_le_bytes());
+        expected.extend_from_slice(b"main");
+        expected.push(2);
+        expected.push(0);
+        expected.extend_from_slice(&1u64.to_le_bytes());
+        expected.push(16);
+        expected.extend_from_slice(&0x0100_0000u64.to_le_bytes());
+        expected.extend_from_slice(&1u16.to_le_bytes());
+        expected.extend_from_slice(&0x0800_0010u32.to_le_bytes());
+        expected.extend_from_slice(&0x2000_0ff8u32.to_le_bytes());
+        expected.extend_from_slice(&[4, 1]);
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        assert_eq!(bin, expected);
+    }
+
+    #[test]
+    fn test_json_events_and_string_escapes() {
+        // Arrange
+        let event = TraceEvent {
+            instruction_count: 7,
+            kind: TraceEventKind::ExceptionEntry {
+                exception: Exception::SVCall,
+                return_address: 0x0800_0034,
+                exc_return: 0xffff_fff9,
+            },
+        };
+
+        // Act
+        let json = json_event(&event);
+        let escaped = json_string("a\"b\\c\n");
+
+        // Assert
+        assert_eq!(
+            json,
+            "{\"type\":\"event\",\"instruction_count\":7,\"event\":\"exception_entry\",\
+             \"exception\":11,\"name\":\"SVCall\",\"return_address\":134217780,\
+             \"exc_return\":4294967289}"
+        );
+        assert_eq!(escaped, "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
@@ -0,0 +1,111 @@
+//!
+//! Ring buffer keeping the trace of the last instructions
+//!
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+#[derive(Debug, Default)]
+struct Records {
+    header: Vec<u8>,
+    records: VecDeque<Vec<u8>>,
+    current: Vec<u8>,
+}
+
+///
+/// Keeps the output of the last `capacity` trace records in memory. The
+/// trace writer writes to the `Write` half returned by `writer`, each
+/// record is closed with `end_record` and the kept records are written out
+/// with `dump`.
+///
+#[derive(Debug, Clone)]
+pub struct TraceRing {
+    capacity: usize,
+    records: Rc<RefCell<Records>>,
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Rc::default(),
+        }
+    }
+
+    /// Writer that stores its output in the ring.
+    pub fn writer(&self) -> Box<dyn Write> {
+        Box::new(RingWriter {
+            records: Rc::clone(&self.records),
+        })
+    }
+
+    /// Keep the output written so far as the header that is always dumped.
+    pub fn end_header(&self) {
+        let mut records = self.records.borrow_mut();
+        records.header = std::mem::take(&mut records.current);
+    }
+
+    /// Close the current record, the oldest record is dropped when the ring is full.
+    pub fn end_record(&self) {
+        let mut records = self.records.borrow_mut();
+        let record = std::mem::take(&mut records.current);
+        if records.records.len() == self.capacity {
+            records.records.pop_front();
+        }
+        records.records.push_back(record);
+    }
+
+    /// Write the header and the kept records to `output`.
+    pub fn dump(&self, output: &mut dyn Write) -> io::Result<()> {
+        let records = self.records.borrow();
+        output.write_all(&records.header)?;
+        for record in &records.records {
+            output.write_all(record)?;
+        }
+        output.write_all(&records.current)?;
+        output.flush()
+    }
+}
+
+struct RingWriter {
+    records: Rc<RefCell<Records>>,
+}
+
+impl Write for RingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.borrow_mut().current.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_keeps_header_and_last_records() {
+        // Arrange
+        let ring = TraceRing::new(2);
+        let mut writer = ring.writer();
+        writer.write_all(b"header\n").unwrap();
+        ring.end_header();
+
+        // Act
+        for record in ["1\n", "2\n", "3\n"] {
+            writer.write_all(record.as_bytes()).unwrap();
+            ring.end_record();
+        }
+        writer.write_all(b"event\n").unwrap();
+        let mut output = Vec::new();
+        ring.dump(&mut output).unwrap();
+
+        // Assert
+        assert_eq!(output, b"header\n2\n3\nevent\n");
+    }
+}
@@ -0,0 +1,218 @@
+//!
+//! Start and stop conditions of the instruction trace
+//!
+
+use super::exception_name;
+use crate::board::Region;
+use zmu_cortex_m::Processor;
+use zmu_cortex_m::core::exception::Exception;
+use zmu_cortex_m::core::trace_event::{TraceEvent, TraceEventKind};
+
+///
+/// Condition that starts or stops the instruction trace
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceTrigger {
+    /// Instruction with this instruction count and the ones after it
+    InstructionCount(u64),
+    /// Instruction at the address
+    Address(u32),
+    /// Instruction in the address range
+    Range(Region),
+    /// First instruction of the handler of the exception number
+    Exception(usize),
+    /// Instruction at the symbol, resolved to an `Address` before the run
+    Symbol(String),
+}
+
+impl TraceTrigger {
+    ///
+    /// Resolve a `Symbol` trigger to the address of the symbol, `symbol`
+    /// looks up the names.
+    ///
+    pub fn resolve(self, symbol: impl Fn(&str) -> Option<u32>) -> Result<Self, String> {
+        match self {
+            Self::Symbol(name) => symbol(&name)
+                .map(Self::Address)
+                .ok_or_else(|| format!("symbol '{name}' not found")),
+            trigger => Ok(trigger),
+        }
+    }
+
+    fn matches(&self, processor: &Processor, entered: &[usize]) -> bool {
+        let pc = processor.last_pc;
+        match self {
+            Self::InstructionCount(count) => processor.instruction_count >= *count,
+            Self::Address(address) => pc == *address,
+            Self::Range(range) => {
+                pc >= range.base && u64::from(pc) < u64::from(range.base) + u64::from(range.size)
+            }
+            Self::Exception(number) => entered.contains(number),
+            Self::Symbol(_) => false,
+        }
+    }
+}
+
+///
+/// Exception number of a name as shown in the trace, e.g. `HardFault`,
+/// `SysTick` or `IRQ5`, the case is ignored.
+///
+pub fn exception_number(name: &str) -> Option<usize> {
+    if let Some(n) = name
+        .strip_prefix("IRQ")
+        .or_else(|| name.strip_prefix("irq"))
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        return Some(16 + n);
+    }
+    (1..16).find(|number| exception_name(Exception::from(*number)).eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum WindowState {
+    Waiting,
+    Tracing { traced: u64 },
+    Done,
+}
+
+///
+/// Decides which instructions are traced: the trace starts at the first
+/// instruction matching the start trigger and ends before the first one
+/// matching the stop trigger or when the count limit is reached. It does
+/// not restart.
+///
+#[derive(Debug, Clone)]
+pub struct TraceWindow {
+    start: Option<TraceTrigger>,
+    stop: Option<TraceTrigger>,
+    count: Option<u64>,
+    state: WindowState,
+    entered: Vec<usize>,
+}
+
+impl TraceWindow {
+    pub fn new(
+        start: Option<TraceTrigger>,
+        stop: Option<TraceTrigger>,
+        count: Option<u64>,
+    ) -> Self {
+        Self {
+            start,
+            stop,
+            count,
+            state: WindowState::Waiting,
+            entered: Vec::new(),
+        }
+    }
+
+    /// Check if the window needs the processor events to find exception entries.
+    pub fn needs_events(&self) -> bool {
+        [&self.start, &self.stop]
+            .into_iter()
+            .any(|trigger| matches!(trigger, Some(TraceTrigger::Exception(_))))
+    }
+
+    /// Note a processor event that happened before the next instruction.
+    pub fn event(&mut self, event: &TraceEvent) {
+        if let TraceEventKind::ExceptionEntry { exception, .. } = event.kind {
+            self.entered.push(usize::from(exception));
+        }
+    }
+
+    /// Check if the instruction executed last is traced.
+    pub fn trace(&mut self, processor: &Processor) -> bool {
+        let entered = std::mem::take(&mut self.entered);
+        let matches = |trigger: &Option<TraceTrigger>| {
+            trigger
+                .as_ref()
+                .is_some_and(|t| t.matches(processor, &entered))
+        };
+
+        if self.state == WindowState::Waiting && (self.start.is_none() || matches(&self.start)) {
+            self.state = WindowState::Tracing { traced: 0 };
+        }
+        if let WindowState::Tracing { traced } = self.state {
+            if matches(&self.stop) || self.count.is_some_and(|count| traced >= count) {
+                self.state = WindowState::Done;
+            } else {
+                self.state = WindowState::Tracing { traced: traced + 1 };
+            }
+        }
+        matches!(self.state, WindowState::Tracing { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(window: &mut TraceWindow, processor: &mut Processor, pc: u32) -> bool {
+        processor.instruction_count += 1;
+        processor.last_pc = pc;
+        window.trace(processor)
+    }
+
+    #[test]
+    fn test_window_starts_at_address_and_stops_at_range() {
+        // Arrange
+        let mut processor = Processor::new();
+        let mut window = TraceWindow::new(
+            Some(TraceTrigger::Address(0x0800_0100)),
+            Some(TraceTrigger::Range(Region {
+                base: 0x0800_0200,
+                size: 0x10,
+            })),
+            None,
+        );
+
+        // Act
+        let traced: Vec<bool> = [
+            0x0800_0000,
+            0x0800_0100,
+            0x0800_0000,
+            0x0800_020e,
+            0x0800_0100,
+        ]
+        .into_iter()
+        .map(|pc| step(&mut window, &mut processor, pc))
+        .collect();
+
+        // Assert
+        assert_eq!(traced, [false, true, true, false, false]);
+    }
+
+    #[test]
+    fn test_window_starts_at_exception_entry_and_limits_count() {
+        // Arrange
+        let mut processor = Processor::new();
+        let mut window = TraceWindow::new(
+            Some(TraceTrigger::Exception(
+                exception_number("hardfault").unwrap(),
+            )),
+            None,
+            Some(2),
+        );
+        let entry = TraceEvent {
+            instruction_count: 1,
+            kind: TraceEventKind::ExceptionEntry {
+                exception: Exception::HardFault,
+                return_address: 0x0800_0000,
+                exc_return: 0xffff_fff9,
+            },
+        };
+
+        // Act
+        let before = step(&mut window, &mut processor, 0x0800_0000);
+        window.event(&entry);
+        let traced: Vec<bool> = (0..3)
+            .map(|_| step(&mut window, &mut processor, 0x0800_0100))
+            .collect();
+
+        // Assert
+        assert!(window.needs_events());
+        assert!(!before);
+        assert_eq!(traced, [true, true, false]);
+        assert_eq!(exception_number("IRQ5"), Some(21));
+        assert_eq!(exception_number("bogus"), None);
+    }
+}
@@ -5,12 +5,14 @@
 use crate::Processor;
 
 use crate::core::fault::Fault;
-use crate::memory::map::MapMemory;
 use crate::peripheral::dwt::Dwt;
 use crate::peripheral::itm::InstrumentationTraceMacrocell;
+use crate::peripheral::mpu::Mpu;
 use crate::peripheral::nvic::NVIC;
 use crate::peripheral::scb::SystemControlBlock;
 use crate::peripheral::systick::SysTick;
+use crate::snapshot::{StateReader, StateWriter};
+use std::io;
 
 ///
 /// Trait for reading and writing via a memory bus.
@@ -45,16 +47,86 @@ pub trait Bus {
     fn in_range(&self, addr: u32) -> bool;
 }
 
-impl Bus for Processor {
-    #[inline(always)]
-    fn read8(&self, bus_addr: u32) -> Result<u8, Fault> {
-        let addr = self.map_address(bus_addr);
+///
+/// Level change of an external interrupt line driven by a peripheral.
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct IrqLine {
+    /// External interrupt number (0 = first interrupt after `SysTick`)
+    pub irqn: usize,
+    /// New level of the line, true when asserted
+    pub asserted: bool,
+}
 
-        if self.sram.in_range(addr) {
-            return self.sram.read8(addr);
+impl IrqLine {
+    /// Line `irqn` went high
+    pub fn assert(irqn: usize) -> Self {
+        Self {
+            irqn,
+            asserted: true,
         }
-        if self.code.in_range(addr) {
-            return self.code.read8(addr);
+    }
+
+    /// Line `irqn` went low
+    pub fn deassert(irqn: usize) -> Self {
+        Self {
+            irqn,
+            asserted: false,
+        }
+    }
+}
+
+///
+/// Trait for devices attached to the processor bus.
+///
+/// In addition to the register interface a peripheral advances with the
+/// simulated time and can drive NVIC interrupt lines.
+///
+pub trait Peripheral: Bus {
+    /// Advances the peripheral by given amount of core clock cycles.
+    ///
+    /// Interrupt lines that changed level during the tick are appended to `irqs`.
+    ///
+    fn tick(&mut self, _cycles: u32, _irqs: &mut Vec<IrqLine>) {}
+
+    /// Core clock cycles until the peripheral may next change an interrupt
+    /// line, `None` when nothing is scheduled.
+    ///
+    /// A sleeping processor is advanced to the nearest deadline with a single
+    /// `tick`, which must have the same effect as ticking cycle by cycle. The
+    /// default of one cycle keeps the peripheral ticked on every cycle.
+    ///
+    fn next_event(&self) -> Option<u32> {
+        Some(1)
+    }
+
+    /// Saves the internal state of the peripheral into a snapshot.
+    ///
+    fn save_state(&self, _state: &mut StateWriter) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Restores the state written by `save_state`.
+    ///
+    fn load_state(&mut self, _state: &mut StateReader) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called with `true` while the simulation runs again steps that have
+    /// already run, e.g. when the debugger steps backwards, and with `false`
+    /// once execution goes past them.
+    ///
+    /// While replaying, a peripheral must not repeat its host side output
+    /// and must see the same host input as the first time.
+    ///
+    fn replay(&mut self, _replaying: bool) {}
+}
+
+impl Bus for Processor {
+    #[inline(always)]
+    fn read8(&self, addr: u32) -> Result<u8, Fault> {
+        if let Some(result) = self.memory.read8(addr, self.device.as_ref()) {
+            return result;
         }
         if let Some(device) = self.device.as_ref()
             && device.in_range(addr)
@@ -78,14 +150,9 @@ impl Bus for Processor {
     }
 
     #[inline(always)]
-    fn read16(&self, bus_addr: u32) -> Result<u16, Fault> {
-        let addr = self.map_address(bus_addr);
-
-        if self.sram.in_range(addr) {
-            return self.sram.read16(addr);
-        }
-        if self.code.in_range(addr) {
-            return self.code.read16(addr);
+    fn read16(&self, addr: u32) -> Result<u16, Fault> {
+        if let Some(result) = self.memory.read16(addr, self.device.as_ref()) {
+            return result;
         }
         if let Some(device) = self.device.as_ref()
             && device.in_range(addr)
@@ -114,14 +181,9 @@ impl Bus for Processor {
     }
 
     #[inline(always)]
-    fn read32(&mut self, bus_addr: u32) -> Result<u32, Fault> {
-        let addr = self.map_address(bus_addr);
-
-        if self.sram.in_range(addr) {
-            return self.sram.read32(addr);
-        }
-        if self.code.in_range(addr) {
-            return self.code.read32(addr);
+    fn read32(&mut self, addr: u32) -> Result<u32, Fault> {
+        if let Some(result) = self.memory.read32(addr, self.device.as_mut()) {
+            return result;
         }
         if let Some(device) = self.device.as_mut()
             && device.in_range(addr)
@@ -129,6 +191,7 @@ impl Bus for Processor {
             return device.read32(addr);
         }
 
+        self.sync_block();
         let result = match addr {
             0xE000_0000 => self.read_stim0(),
 
@@ -176,6 +239,16 @@ impl Bus for Processor {
             #[cfg(feature = "has-fp")]
             0xE000_ED88 => self.cpacr,
 
+            0xE000_ED90 => self.mpu_read_type(),
+            0xE000_ED94 => self.mpu_ctrl,
+            0xE000_ED98 => self.mpu_rnr,
+            0xE000_ED9C => self.mpu_read_rbar(),
+            0xE000_EDA0 => self.mpu_read_rasr(),
+            #[cfg(not(feature = "armv6m"))]
+            0xE000_EDA4 | 0xE000_EDAC | 0xE000_EDB4 => self.mpu_read_rbar(),
+            #[cfg(not(feature = "armv6m"))]
+            0xE000_EDA8 | 0xE000_EDB0 | 0xE000_EDB8 => self.mpu_read_rasr(),
+
             #[cfg(feature = "has-fp")]
             0xE000_EF34 => self.fpccr,
             #[cfg(feature = "has-fp")]
@@ -200,14 +273,12 @@ impl Bus for Processor {
     }
 
     #[inline(always)]
-    fn write32(&mut self, bus_addr: u32, value: u32) -> Result<(), Fault> {
-        let addr = self.map_address(bus_addr);
-
-        if self.sram.in_range(addr) {
-            return self.sram.write32(addr, value);
-        }
-        if self.code.in_range(addr) {
-            return self.code.write32(addr, value);
+    fn write32(&mut self, addr: u32, value: u32) -> Result<(), Fault> {
+        if let Some(result) = self.memory.write32(addr, value, self.device.as_mut()) {
+            if result.is_ok() && self.lazy_instructions_cached {
+                self.invalidate_instructions(addr, 4);
+            }
+            return result;
         }
         if let Some(device) = self.device.as_mut()
             && device.in_range(addr)
@@ -215,6 +286,7 @@ impl Bus for Processor {
             return device.write32(addr, value);
         }
 
+        self.sync_block();
         match addr {
             0xE000_0000..=0xE000_007C => {
                 self.write_stim_u32(((addr - 0xE000_0000) >> 2) as u8, value);
@@ -241,6 +313,14 @@ impl Bus for Processor {
             0xE000_ED20 => self.write_shpr3(value),
             #[cfg(feature = "has-fp")]
             0xE000_ED88 => self.write_cpacr(value)?,
+            0xE000_ED94 => self.mpu_write_ctrl(value),
+            0xE000_ED98 => self.mpu_write_rnr(value),
+            0xE000_ED9C => self.mpu_write_rbar(value),
+            0xE000_EDA0 => self.mpu_write_rasr(value),
+            #[cfg(not(feature = "armv6m"))]
+            0xE000_EDA4 | 0xE000_EDAC | 0xE000_EDB4 => self.mpu_write_rbar(value),
+            #[cfg(not(feature = "armv6m"))]
+            0xE000_EDA8 | 0xE000_EDB0 | 0xE000_EDB8 => self.mpu_write_rasr(value),
             #[cfg(feature = "has-fp")]
             0xE000_EF34 => self.write_fpccr(value)?,
             #[cfg(feature = "has-fp")]
@@ -277,14 +357,12 @@ impl Bus for Processor {
     }
 
     #[inline(always)]
-    fn write16(&mut self, bus_addr: u32, value: u16) -> Result<(), Fault> {
-        let addr = self.map_address(bus_addr);
-
-        if self.sram.in_range(addr) {
-            return self.sram.write16(addr, value);
-        }
-        if self.code.in_range(addr) {
-            return self.code.write16(addr, value);
+    fn write16(&mut self, addr: u32, value: u16) -> Result<(), Fault> {
+        if let Some(result) = self.memory.write16(addr, value, self.device.as_mut()) {
+            if result.is_ok() && self.lazy_instructions_cached {
+                self.invalidate_instructions(addr, 2);
+            }
+            return result;
         }
         if let Some(device) = self.device.as_mut()
             && device.in_range(addr)
@@ -292,6 +370,7 @@ impl Bus for Processor {
             return device.write16(addr, value);
         }
 
+        self.sync_block();
         match addr {
             0xE000_0000..=0xE000_007C => {
                 self.write_stim_u16(((addr - 0xE000_0000) >> 2) as u8, value);
@@ -317,14 +396,12 @@ impl Bus for Processor {
     }
 
     #[inline(always)]
-    fn write8(&mut self, bus_addr: u32, value: u8) -> Result<(), Fault> {
-        let addr = self.map_address(bus_addr);
-
-        if self.sram.in_range(addr) {
-            return self.sram.write8(addr, value);
-        }
-        if self.code.in_range(addr) {
-            return self.code.write8(addr, value);
+    fn write8(&mut self, addr: u32, value: u8) -> Result<(), Fault> {
+        if let Some(result) = self.memory.write8(addr, value, self.device.as_mut()) {
+            if result.is_ok() && self.lazy_instructions_cached {
+                self.invalidate_instructions(addr, 1);
+            }
+            return result;
         }
         if let Some(device) = self.device.as_mut()
             && device.in_range(addr)
@@ -332,6 +409,7 @@ impl Bus for Processor {
             return device.write8(addr, value);
         }
 
+        self.sync_block();
         match addr {
             0xE000_0000..=0xE000_007C => {
                 self.write_stim_u8(((addr - 0xE000_0000) >> 2) as u8, value);
@@ -352,8 +430,7 @@ impl Bus for Processor {
 
     #[allow(unused)]
     fn in_range(&self, addr: u32) -> bool {
-        self.code.in_range(addr)
-            || self.sram.in_range(addr)
+        self.memory.in_range(addr)
             || self
                 .device
                 .as_ref()
@@ -392,6 +469,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_big_endian_is_reported_in_aircr_and_leaves_ppb_little_endian() {
+        let mut processor = Processor::new();
+        assert_eq!(processor.read32(0xE000_ED0C), Ok(0));
+
+        processor.big_endian_data(true);
+        processor.write32(0xE000_E014, 0x0012_3456).unwrap();
+        processor.write32(0x2000_0000, 0x0012_3456).unwrap();
+
+        assert_eq!(processor.read32(0xE000_ED0C), Ok(0x0000_8000));
+        assert_eq!(processor.read32(0xE000_E014), Ok(0x0012_3456));
+        assert_eq!(processor.read8(0x2000_0001), Ok(0x12));
+    }
+
     #[test]
     #[cfg(feature = "armv6m")]
     fn test_armv6m_exposes_shcsr() {
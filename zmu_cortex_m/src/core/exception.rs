@@ -8,14 +8,17 @@ use crate::ProcessorMode;
 use crate::bus::Bus;
 use crate::core::bits::Bits;
 use crate::core::fault::Fault;
+use crate::core::monitor::Monitor;
 #[cfg(feature = "has-fp")]
 use crate::core::register::ExtensionRegOperations;
 #[cfg(feature = "has-fp")]
 use crate::core::register::SingleReg;
 use crate::core::register::{BaseReg, Ipsr, Reg};
 use crate::core::reset::Reset;
+use crate::core::trace_event::TraceEventKind;
 #[cfg(feature = "has-fp")]
 use crate::executor::FloatingPointChecks;
+use crate::peripheral::mpu::{AccType, Mpu};
 use crate::peripheral::nvic::NVIC;
 use crate::peripheral::scb::CCR_STKALIGN;
 #[cfg(feature = "has-fp")]
@@ -29,9 +32,9 @@ use crate::peripheral::scb::{
 /// Status information for an exception
 ///
 pub struct ExceptionState {
-    priority: i16,
-    pending: bool,
-    active: bool,
+    pub(crate) priority: i16,
+    pub(crate) pending: bool,
+    pub(crate) active: bool,
     exception_number: usize,
 }
 
@@ -117,6 +120,22 @@ impl Processor {
             .any(|e| e.pending && e.priority < wakeup_priority)
     }
 
+    fn read32_stack(&mut self, address: u32) -> Result<u32, Fault> {
+        let address = self.validate_address(address, AccType::Normal, false)?;
+        self.watch_access(address, 4, false);
+        let value = self.read32(address)?;
+        self.trace_access(address, 4, false, value);
+        Ok(value)
+    }
+
+    fn write32_stack(&mut self, address: u32, value: u32) -> Result<(), Fault> {
+        let address = self.validate_address(address, AccType::Normal, true)?;
+        self.watch_access(address, 4, true);
+        self.write32(address, value)?;
+        self.trace_access(address, 4, true, value);
+        Ok(())
+    }
+
     #[cfg(all(test, not(feature = "armv6m")))]
     pub(crate) fn test_set_active_exception(&mut self, exception: Exception) {
         self.psr.set_isr_number(exception.into());
@@ -382,16 +401,16 @@ impl ExceptionHandlingHelpers for Processor {
 
         let ret_addr = self.return_address(exception_type, return_address);
 
-        self.write32(frameptr, r0)?;
-        self.write32(frameptr.wrapping_add(0x4), r1)?;
-        self.write32(frameptr.wrapping_add(0x8), r2)?;
-        self.write32(frameptr.wrapping_add(0xc), r3)?;
-        self.write32(frameptr.wrapping_add(0x10), r12)?;
-        self.write32(frameptr.wrapping_add(0x14), lr)?;
-        self.write32(frameptr.wrapping_add(0x18), ret_addr)?;
+        self.write32_stack(frameptr, r0)?;
+        self.write32_stack(frameptr.wrapping_add(0x4), r1)?;
+        self.write32_stack(frameptr.wrapping_add(0x8), r2)?;
+        self.write32_stack(frameptr.wrapping_add(0xc), r3)?;
+        self.write32_stack(frameptr.wrapping_add(0x10), r12)?;
+        self.write32_stack(frameptr.wrapping_add(0x14), lr)?;
+        self.write32_stack(frameptr.wrapping_add(0x18), ret_addr)?;
         let xpsr =
             (self.psr.value & 0b1111_1111_1111_1111_1111_1101_1111_1111) | frameptralign << 9;
-        self.write32(frameptr.wrapping_add(0x1c), xpsr)?;
+        self.write32_stack(frameptr.wrapping_add(0x1c), xpsr)?;
 
         #[cfg(feature = "has-fp")]
         if self.control.fpca {
@@ -400,10 +419,10 @@ impl ExceptionHandlingHelpers for Processor {
                 for i in 0..16 {
                     let reg = SingleReg::from(i as u8);
                     let value = self.get_sr(reg);
-                    self.write32(frameptr.wrapping_add(0x20 + i * 4), value)?;
+                    self.write32_stack(frameptr.wrapping_add(0x20 + i * 4), value)?;
                 }
                 // write FPSCR:
-                self.write32(frameptr.wrapping_add(0x60), self.fpscr)?;
+                self.write32_stack(frameptr.wrapping_add(0x60), self.fpscr)?;
             } else {
                 self.update_fpccr(frameptr)?;
             }
@@ -500,14 +519,14 @@ impl ExceptionHandlingHelpers for Processor {
         #[cfg(not(feature = "has-fp"))]
         let (frame_size, forcealign): (u32, bool) = (0x20, self.ccr.get_bit(CCR_STKALIGN));
 
-        let r0 = self.read32(frameptr)?;
-        let r1 = self.read32(frameptr.wrapping_add(0x4))?;
-        let r2 = self.read32(frameptr.wrapping_add(0x8))?;
-        let r3 = self.read32(frameptr.wrapping_add(0xc))?;
-        let r12 = self.read32(frameptr.wrapping_add(0x10))?;
-        let lr = self.read32(frameptr.wrapping_add(0x14))?;
-        let pc = self.read32(frameptr.wrapping_add(0x18))?;
-        let psr = self.read32(frameptr.wrapping_add(0x1c))?;
+        let r0 = self.read32_stack(frameptr)?;
+        let r1 = self.read32_stack(frameptr.wrapping_add(0x4))?;
+        let r2 = self.read32_stack(frameptr.wrapping_add(0x8))?;
+        let r3 = self.read32_stack(frameptr.wrapping_add(0xc))?;
+        let r12 = self.read32_stack(frameptr.wrapping_add(0x10))?;
+        let lr = self.read32_stack(frameptr.wrapping_add(0x14))?;
+        let pc = self.read32_stack(frameptr.wrapping_add(0x18))?;
+        let psr = self.read32_stack(frameptr.wrapping_add(0x1c))?;
 
         #[cfg(feature = "has-fp")]
         {
@@ -517,11 +536,11 @@ impl ExceptionHandlingHelpers for Processor {
                 } else {
                     self.check_vfp_enabled()?;
                     for i in 0..16 {
-                        let value = self.read32(frameptr.wrapping_add(0x20 + i * 4))?;
+                        let value = self.read32_stack(frameptr.wrapping_add(0x20 + i * 4))?;
                         let reg = SingleReg::from(i as u8);
                         self.set_sr(reg, value);
                     }
-                    self.fpscr = self.read32(frameptr.wrapping_add(0x60))?;
+                    self.fpscr = self.read32_stack(frameptr.wrapping_add(0x60))?;
                 }
             }
             self.control.fpca = !exc_return.get_bit(4);
@@ -671,9 +690,16 @@ impl ExceptionHandling for Processor {
             if let Exception::Interrupt { n } = exception {
                 self.nvic_unpend_interrupt(n);
             }
+            self.clear_exclusive_local();
             self.push_stack(exception, return_address)
                 .map_err(Fault::on_exception_entry_stack)?;
-            self.exception_taken(exception)
+            self.exception_taken(exception)?;
+            self.trace_event(TraceEventKind::ExceptionEntry {
+                exception,
+                return_address: self.return_address(exception, return_address),
+                exc_return: self.lr,
+            });
+            Ok(())
         }
     }
 
@@ -727,14 +753,24 @@ impl ExceptionHandling for Processor {
                 }
             }
 
+            self.clear_exclusive_local();
             self.deactivate(returning_exception_number);
-            self.pop_stack(frameptr, exc_return)?;
+            if let Exception::Interrupt { n } = Exception::from(returning_exception_number) {
+                self.nvic_interrupt_returned(n);
+            }
+            self.pop_stack(frameptr, exc_return)
+                .map_err(Fault::on_exception_return_stack)?;
+            self.trace_event(TraceEventKind::ExceptionReturn {
+                exception: Exception::from(returning_exception_number),
+                exc_return: 0xf000_0000 | exc_return,
+            });
 
             if self.mode == ProcessorMode::ThreadMode
                 && nested_activation == 1 // deactivate() reduced one
                 && self.scr.get_bit(1)
             {
                 self.sleeping = true;
+                self.trace_event(TraceEventKind::Sleep);
             }
 
             Ok(())
@@ -746,7 +782,10 @@ impl ExceptionHandling for Processor {
     #[inline(always)]
     fn check_exceptions(&mut self) {
         if let Some(exception) = self.get_pending_exception() {
-            self.sleeping = false;
+            if self.sleeping {
+                self.sleeping = false;
+                self.trace_event(TraceEventKind::Wake);
+            }
             self.clear_pending_exception(exception);
             let pc = self.get_pc();
             if let Err(fault) = self.exception_entry(exception, pc) {
@@ -756,6 +795,10 @@ impl ExceptionHandling for Processor {
                 };
                 self.record_fault_status(fault, crate::core::fault::FaultStatusContext::default());
                 self.set_hfsr_forced();
+                self.trace_event(TraceEventKind::Fault {
+                    fault,
+                    exception: Exception::HardFault,
+                });
                 let trap_reason = if exception == Exception::HardFault
                     || matches!(
                         active_exception,
@@ -775,6 +818,7 @@ impl ExceptionHandling for Processor {
             }
         } else if self.sleeping && self.has_wakeup_condition() {
             self.sleeping = false;
+            self.trace_event(TraceEventKind::Wake);
         }
     }
 }
@@ -1276,6 +1320,21 @@ mod tests {
         assert_eq!(processor.nvic_read_ispr(0), 0);
     }
 
+    #[test]
+    fn test_exception_entry_clears_exclusive_monitor() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.reset().unwrap();
+        processor.set_msp(0x2000_0100);
+        processor.set_exclusive_monitors(0x2000_0000, 4);
+
+        // Act
+        let _ = processor.exception_entry(Exception::PendSV, 0);
+
+        // Assert
+        assert!(!processor.exclusive_monitors_pass(0x2000_0000, 4));
+    }
+
     #[test]
     #[cfg(not(feature = "armv6m"))]
     fn test_check_exceptions_escalates_entry_stack_fault_to_hardfault() {
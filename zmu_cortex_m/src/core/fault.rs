@@ -44,6 +44,11 @@ pub enum Fault {
     #[error("Bus fault on exception return stack operations")]
     Msunskerr,
     ///
+    /// Memmanage fault on exception return stack operations
+    ///
+    #[error("Memmanage fault on exception return stack operations")]
+    Munstkerr,
+    ///
     /// Memmanage fault on data access
     ///
     #[error("Memmanage fault on data access")]
@@ -258,7 +263,9 @@ impl Fault {
     #[must_use]
     pub fn on_instruction_fetch(self) -> Self {
         match self {
-            Self::DAccViol | Self::IAccViol | Self::Mstkerr | Self::MlspErr => Self::IAccViol,
+            Self::DAccViol | Self::IAccViol | Self::Mstkerr | Self::Munstkerr | Self::MlspErr => {
+                Self::IAccViol
+            }
             Self::Preciserr
             | Self::Impreciseerr
             | Self::Stkerr
@@ -282,7 +289,9 @@ impl Fault {
     #[must_use]
     pub fn on_exception_entry_stack(self) -> Self {
         match self {
-            Self::DAccViol | Self::IAccViol | Self::MlspErr | Self::Mstkerr => Self::Mstkerr,
+            Self::DAccViol | Self::IAccViol | Self::MlspErr | Self::Mstkerr | Self::Munstkerr => {
+                Self::Mstkerr
+            }
             Self::Preciserr
             | Self::Impreciseerr
             | Self::IBusErr
@@ -294,6 +303,24 @@ impl Fault {
         }
     }
 
+    /// Map exception-return stack access failures to unstacking faults.
+    #[must_use]
+    pub fn on_exception_return_stack(self) -> Self {
+        match self {
+            Self::DAccViol | Self::IAccViol | Self::MlspErr | Self::Mstkerr | Self::Munstkerr => {
+                Self::Munstkerr
+            }
+            Self::Preciserr
+            | Self::Impreciseerr
+            | Self::IBusErr
+            | Self::Stkerr
+            | Self::Msunskerr
+            | Self::LspErr
+            | Self::VectorTable => Self::Msunskerr,
+            other => other,
+        }
+    }
+
     /// Map a fault to the architecturally visible exception.
     pub fn exception(self) -> Exception {
         #[cfg(feature = "armv6m")]
@@ -305,9 +332,11 @@ impl Fault {
         {
             match self {
                 Self::Forced | Self::DebugEvt | Self::VectorTable => Exception::HardFault,
-                Self::DAccViol | Self::IAccViol | Self::Mstkerr | Self::MlspErr => {
-                    Exception::MemoryManagementFault
-                }
+                Self::DAccViol
+                | Self::IAccViol
+                | Self::Mstkerr
+                | Self::Munstkerr
+                | Self::MlspErr => Exception::MemoryManagementFault,
                 Self::Stkerr
                 | Self::Msunskerr
                 | Self::IBusErr
@@ -358,4 +387,17 @@ mod tests {
         assert_eq!(Fault::Preciserr.on_exception_entry_stack(), Fault::Stkerr);
         assert_eq!(Fault::IBusErr.on_exception_entry_stack(), Fault::Stkerr);
     }
+
+    #[test]
+    fn test_exception_return_stack_mapping() {
+        assert_eq!(
+            Fault::DAccViol.on_exception_return_stack(),
+            Fault::Munstkerr
+        );
+        assert_eq!(
+            Fault::Preciserr.on_exception_return_stack(),
+            Fault::Msunskerr
+        );
+        assert_eq!(Fault::Invstate.on_exception_return_stack(), Fault::Invstate);
+    }
 }
@@ -11,10 +11,15 @@ use crate::{Processor, decoder::is_thumb32};
 impl Processor {
     /// Read instruction halfwords without mutating fault-status side channels.
     pub fn fetch_code(&self, pc: u32) -> Result<ThumbCode, Fault> {
-        let hw = self.read16(pc).map_err(Fault::on_instruction_fetch)?;
+        if !self.memory_regions().executable(pc) {
+            return Err(Fault::IAccViol);
+        }
+        let hw = self.read_code16(pc).map_err(Fault::on_instruction_fetch)?;
 
         if is_thumb32(hw) {
-            let hw2 = self.read16(pc + 2).map_err(Fault::on_instruction_fetch)?;
+            let hw2 = self
+                .read_code16(pc + 2)
+                .map_err(Fault::on_instruction_fetch)?;
             Ok(ThumbCode::Thumb32 {
                 opcode: (u32::from(hw) << 16) + u32::from(hw2),
             })
@@ -22,6 +27,16 @@ impl Processor {
             Ok(ThumbCode::Thumb16 { opcode: hw })
         }
     }
+
+    /// Instructions are little endian also on a big endian (BE8) processor
+    fn read_code16(&self, pc: u32) -> Result<u16, Fault> {
+        let hw = self.read16(pc)?;
+        Ok(if self.big_endian() {
+            hw.swap_bytes()
+        } else {
+            hw
+        })
+    }
 }
 
 ///
@@ -53,4 +68,37 @@ mod tests {
 
         assert_eq!(processor.fetch(0x6000_0000), Err(Fault::IAccViol));
     }
+
+    #[test]
+    fn test_fetch_requires_executable_region() {
+        use crate::memory::region::{MemoryRegion, Permissions};
+
+        let mut processor = Processor::new();
+        processor.memory_region(
+            MemoryRegion::ram("ram", 0x2000_0000, 0x100).with_permissions(Permissions::RW),
+        );
+        assert_eq!(processor.fetch(0x2000_0000), Err(Fault::IAccViol));
+
+        processor.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        assert!(processor.fetch(0x2000_0000).is_ok());
+    }
+
+    #[test]
+    fn test_fetch_is_little_endian_on_big_endian_processor() {
+        let mut processor = Processor::new();
+        processor.flash_memory(0x100, &[0x00, 0xbf, 0x4f, 0xf0, 0x01, 0x00]);
+        processor.big_endian_data(true);
+
+        assert_eq!(
+            processor.fetch(0),
+            Ok(ThumbCode::Thumb16 { opcode: 0xbf00 })
+        );
+        assert_eq!(
+            processor.fetch(2),
+            Ok(ThumbCode::Thumb32 {
+                opcode: 0xf04f_0001
+            })
+        );
+        assert_eq!(processor.read16(0), Ok(0x00bf));
+    }
 }
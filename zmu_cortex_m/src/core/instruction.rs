@@ -1313,7 +1313,8 @@ pub enum Instruction {
     // Group: Miscellaneous instructions
     //
     // --------------------------------------------
-    //CLREX
+    /// Clear exclusive monitor
+    CLREX,
     //DBG
     /// Data Memory Barrier
     DMB,
@@ -2014,6 +2015,7 @@ impl fmt::Display for Instruction {
             Self::CMN_imm { params } => write!(f, "cmn.W {}, #{}", params.r, params.imm32),
             Self::CBZ { params } => write!(f, "cbz {}, #{}", params.rn, params.imm32,),
             Self::CBNZ { params } => write!(f, "cbnz {}, #{}", params.rn, params.imm32,),
+            Self::CLREX => write!(f, "clrex"),
             Self::CLZ { params } => write!(f, "clz {},{}", params.rd, params.rm),
             Self::CMP_imm { params, thumb32 } => write!(
                 f,
@@ -2989,7 +2991,7 @@ pub fn instruction_size(instruction: &Instruction) -> usize {
         Instruction::CBZ { .. } => 2,
         Instruction::CBNZ { .. } => 2,
         //CDP
-        //CLREX
+        Instruction::CLREX => 4,
         Instruction::CLZ { .. } => 4,
         Instruction::CMN_imm { .. } => 4,
         Instruction::CMN_reg { thumb32, .. } => isize_t(*thumb32),
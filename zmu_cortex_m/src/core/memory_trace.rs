@@ -0,0 +1,132 @@
+//!
+//! Trace of the data accesses done by the processor
+//!
+
+use crate::Processor;
+
+///
+/// Data access done by a load, store or exception stacking
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct MemoryAccess {
+    /// Address of the instruction doing the access. Stacking on exception
+    /// entry is reported at the faulting or `SVC` instruction, or at the
+    /// return address for interrupts taken between instructions.
+    pub pc: u32,
+    /// Accessed address
+    pub address: u32,
+    /// Access width in bytes: 1, 2 or 4
+    pub size: u32,
+    /// True for stores, false for loads
+    pub write: bool,
+    /// Loaded or stored value, zero extended
+    pub value: u32,
+}
+
+/// Function called with each data access when the memory trace is enabled
+pub type MemoryTraceFunc = Box<dyn FnMut(&MemoryAccess) + 'static>;
+
+impl Processor {
+    /// Call `func` with each successful data access, `None` stops the trace.
+    pub fn memory_trace(&mut self, func: Option<MemoryTraceFunc>) -> &mut Self {
+        self.memory_trace_func = func;
+        self
+    }
+
+    ///
+    /// Report a completed data access to the memory trace. Called at the
+    /// same places as `watch_access`, after the access succeeded.
+    ///
+    #[inline(always)]
+    pub(crate) fn trace_access(&mut self, address: u32, size: u32, write: bool, value: u32) {
+        if let Some(func) = self.memory_trace_func.as_mut() {
+            func(&MemoryAccess {
+                pc: self.pc,
+                address,
+                size,
+                write,
+                value,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::exception::{Exception, ExceptionHandling};
+    use crate::core::register::BaseReg;
+    use crate::memory::region::MemoryRegion;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn traced_core() -> (Processor, Rc<RefCell<Vec<MemoryAccess>>>) {
+        let mut core = Processor::new();
+        core.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let trace = Rc::clone(&accesses);
+        core.memory_trace(Some(Box::new(move |access| {
+            trace.borrow_mut().push(*access);
+        })));
+        (core, accesses)
+    }
+
+    #[test]
+    fn test_loads_and_stores_are_traced_with_value_and_pc() {
+        // Arrange
+        let (mut core, accesses) = traced_core();
+        core.set_pc(0x0800_0010);
+
+        // Act
+        core.write16_data(0x2000_0010, 0xbeef).unwrap();
+        let value = core.read8_data(0x2000_0011).unwrap();
+        core.read32_data(0x3000_0000).unwrap_err();
+
+        // Assert
+        assert_eq!(value, 0xbe);
+        assert_eq!(
+            *accesses.borrow(),
+            [
+                MemoryAccess {
+                    pc: 0x0800_0010,
+                    address: 0x2000_0010,
+                    size: 2,
+                    write: true,
+                    value: 0xbeef,
+                },
+                MemoryAccess {
+                    pc: 0x0800_0010,
+                    address: 0x2000_0011,
+                    size: 1,
+                    write: false,
+                    value: 0xbe,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exception_stacking_is_traced() {
+        // Arrange
+        let (mut core, accesses) = traced_core();
+        core.set_msp(0x2000_0100);
+        core.set_pc(0x0800_0020);
+
+        // Act
+        let _ = core.exception_entry(Exception::SVCall, 0x0800_0020);
+
+        // Assert
+        let accesses = accesses.borrow();
+        assert_eq!(accesses.len(), 8);
+        assert!(
+            accesses
+                .iter()
+                .all(|access| access.write && access.size == 4)
+        );
+        assert!(
+            accesses
+                .iter()
+                .any(|access| access.address == 0x2000_00f8 && access.value == 0x0800_0020)
+        );
+    }
+}
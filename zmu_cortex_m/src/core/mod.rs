@@ -8,11 +8,14 @@ pub mod exception;
 pub mod fault;
 pub mod fetch;
 pub mod instruction;
+pub mod memory_trace;
 pub mod monitor;
 pub mod operation;
 pub mod register;
 pub mod reset;
 pub mod thumb;
+pub mod trace_event;
+pub mod watchpoint;
 
 // FP extension registers
 pub mod fpregister;
@@ -4,6 +4,31 @@
 
 use crate::Processor;
 
+///
+/// Size in bytes of the exclusives reservation granule, the smallest block
+/// of memory tagged by the local monitor (the architectural minimum of two words)
+///
+pub const EXCLUSIVE_RESERVATION_GRANULE: u32 = 8;
+
+///
+/// Memory block tagged by a load exclusive operation
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct ExclusiveAccess {
+    /// Address of the load exclusive access
+    pub address: u32,
+    /// Size of the load exclusive access in bytes
+    pub size: usize,
+}
+
+impl ExclusiveAccess {
+    fn granule_overlaps(&self, address: u32, size: usize) -> bool {
+        let granule = u64::from(self.address & !(EXCLUSIVE_RESERVATION_GRANULE - 1));
+        let start = u64::from(address);
+        start < granule + u64::from(EXCLUSIVE_RESERVATION_GRANULE) && granule < start + size as u64
+    }
+}
+
 ///
 /// Memory monitoring for atomic instructions (STREX, LDREX, ...)
 ///
@@ -12,6 +37,8 @@ pub trait Monitor {
     /// check if there has not been changes to given `address` with given bit `size` since
     /// last time monitors were set.
     ///
+    /// The local monitor returns to open access state whether the check passes or not.
+    ///
     /// Return
     ///  true if monitor pass (no changes to the given area), false otherwise.
     ///
@@ -21,12 +48,39 @@ pub trait Monitor {
     /// sets a monitor for load exclusive operation
     ///
     fn set_exclusive_monitors(&mut self, address: u32, size: usize);
+
+    ///
+    /// clears the local monitor (CLREX, exception entry and return)
+    ///
+    fn clear_exclusive_local(&mut self);
+
+    ///
+    /// clears the local monitor if a store of `size` bytes to `address`
+    /// touches the tagged reservation granule
+    ///
+    fn clear_exclusive_by_address(&mut self, address: u32, size: usize);
 }
 
 impl Monitor for Processor {
-    fn exclusive_monitors_pass(&mut self, _address: u32, _size: usize) -> bool {
-        true
+    fn exclusive_monitors_pass(&mut self, address: u32, size: usize) -> bool {
+        let tagged = self.exclusive_monitor.take();
+        tagged == Some(ExclusiveAccess { address, size })
     }
 
-    fn set_exclusive_monitors(&mut self, _address: u32, _size: usize) {}
+    fn set_exclusive_monitors(&mut self, address: u32, size: usize) {
+        self.exclusive_monitor = Some(ExclusiveAccess { address, size });
+    }
+
+    fn clear_exclusive_local(&mut self) {
+        self.exclusive_monitor = None;
+    }
+
+    #[inline(always)]
+    fn clear_exclusive_by_address(&mut self, address: u32, size: usize) {
+        if let Some(tagged) = self.exclusive_monitor
+            && tagged.granule_overlaps(address, size)
+        {
+            self.exclusive_monitor = None;
+        }
+    }
 }
@@ -7,6 +7,7 @@ use crate::ProcessorMode;
 use crate::bus::Bus;
 use crate::core::exception::ExceptionHandling;
 use crate::core::fault::Fault;
+use crate::core::monitor::Monitor;
 use crate::core::register::{BaseReg, PSR};
 
 /// Trait for processor reset
@@ -58,10 +59,12 @@ impl Reset for Processor {
         self.exceptions_reset();
         self.reset_scb_fault_state();
         self.reset_fp_system_state();
+        self.reset_mpu_state();
 
         //self.event_reg.clear();
 
         self.itstate = 0;
+        self.clear_exclusive_local();
         self.execution_priority = self.get_execution_priority();
 
         let reset_vector = self.read32(vtor + 4).map_err(Fault::on_vector_read)?;
@@ -0,0 +1,167 @@
+//!
+//! Processor events reported to the instruction trace
+//!
+
+use crate::Processor;
+use crate::core::exception::Exception;
+use crate::core::fault::Fault;
+
+///
+/// Change in the processor state that is not visible in the registers of
+/// a single instruction
+///
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum TraceEventKind {
+    /// An exception was taken, `exc_return` is the value written to LR
+    ExceptionEntry {
+        /// Entered exception
+        exception: Exception,
+        /// Stacked return address
+        return_address: u32,
+        /// `EXC_RETURN` value of the handler
+        exc_return: u32,
+    },
+    /// The handler of an exception returned
+    ExceptionReturn {
+        /// Exception that returned
+        exception: Exception,
+        /// `EXC_RETURN` value used for the return
+        exc_return: u32,
+    },
+    /// A fault was raised. It escalated to `HardFault` if `exception` is
+    /// not the exception of the fault.
+    Fault {
+        /// Raised fault
+        fault: Fault,
+        /// Exception the fault is delivered to
+        exception: Exception,
+    },
+    /// The processor went to sleep on `WFI` or on exit from a handler
+    Sleep,
+    /// A pending exception woke up the processor
+    Wake,
+    /// The `SysTick` counter reached zero
+    SysTickWrap,
+    /// A semihosting call was made
+    Semihosting {
+        /// Operation number from r0
+        operation: u32,
+        /// Parameter from r1
+        parameter: u32,
+    },
+}
+
+///
+/// Processor event with the number of the instruction it happened at
+///
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct TraceEvent {
+    /// Instructions executed when the event happened, events of an
+    /// instruction have its instruction count, events between instructions
+    /// have the count of the previous instruction.
+    pub instruction_count: u64,
+    /// What happened
+    pub kind: TraceEventKind,
+}
+
+/// Function called with each processor event when the event trace is enabled
+pub type EventTraceFunc = Box<dyn FnMut(&TraceEvent) + 'static>;
+
+impl Processor {
+    /// Call `func` with each processor event, `None` stops the trace.
+    pub fn event_trace(&mut self, func: Option<EventTraceFunc>) -> &mut Self {
+        self.event_trace_func = func;
+        self
+    }
+
+    /// Report an event to the event trace.
+    #[inline(always)]
+    pub(crate) fn trace_event(&mut self, kind: TraceEventKind) {
+        if let Some(func) = self.event_trace_func.as_mut() {
+            func(&TraceEvent {
+                instruction_count: self.instruction_count,
+                kind,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::exception::ExceptionHandling;
+    use crate::core::register::BaseReg;
+    use crate::core::reset::Reset;
+    use crate::executor::Executor;
+    use crate::memory::region::MemoryRegion;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn traced_core() -> (Processor, Rc<RefCell<Vec<TraceEventKind>>>) {
+        let mut core = Processor::new();
+        core.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        core.reset().unwrap();
+        core.set_msp(0x2000_0100);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let trace = Rc::clone(&events);
+        core.event_trace(Some(Box::new(move |event| {
+            trace.borrow_mut().push(event.kind);
+        })));
+        (core, events)
+    }
+
+    #[test]
+    fn test_exception_entry_and_return_report_exc_return() {
+        // Arrange
+        let (mut core, events) = traced_core();
+        core.psr.value = 0x0100_0000;
+
+        // Act
+        core.exception_entry(Exception::SVCall, 0x0800_0020)
+            .unwrap();
+        core.exception_return(0xffff_fff9).unwrap();
+
+        // Assert
+        assert_eq!(
+            *events.borrow(),
+            [
+                TraceEventKind::ExceptionEntry {
+                    exception: Exception::SVCall,
+                    return_address: 0x0800_0020,
+                    exc_return: 0xffff_fff9,
+                },
+                TraceEventKind::ExceptionReturn {
+                    exception: Exception::SVCall,
+                    exc_return: 0xffff_fff9,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_systick_wrap_wakes_sleeping_processor() {
+        // Arrange
+        let (mut core, events) = traced_core();
+        core.syst_cvr = 2;
+        core.syst_csr = 0b011; // ENABLE | TICKINT
+        core.sleeping = true;
+
+        // Act
+        core.step_sleep();
+        core.step_sleep();
+
+        // Assert
+        assert!(!core.sleeping);
+        assert_eq!(
+            events.borrow()[..2],
+            [TraceEventKind::SysTickWrap, TraceEventKind::Wake]
+        );
+        assert!(matches!(
+            events.borrow()[2],
+            TraceEventKind::ExceptionEntry {
+                exception: Exception::SysTick,
+                ..
+            }
+        ));
+    }
+}
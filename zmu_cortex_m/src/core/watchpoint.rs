@@ -0,0 +1,228 @@
+//!
+//! Debugger data watchpoints
+//!
+
+use crate::Processor;
+
+///
+/// Kind of data access that triggers a watchpoint
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum WatchKind {
+    /// Stores to the watched memory
+    Write,
+    /// Loads from the watched memory
+    Read,
+    /// Loads and stores
+    Access,
+}
+
+///
+/// Watched memory range
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct Watchpoint {
+    /// First watched address
+    pub address: u32,
+    /// Number of watched bytes
+    pub len: u32,
+    /// Accesses that trigger the watchpoint
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, address: u32, size: u32, write: bool) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Write => write,
+            WatchKind::Read => !write,
+            WatchKind::Access => true,
+        };
+        let start = u64::from(address);
+        let watched = u64::from(self.address);
+        kind_matches
+            && start < watched + u64::from(self.len.max(1))
+            && watched < start + u64::from(size)
+    }
+}
+
+///
+/// Watchpoint triggered by a data access
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct WatchpointHit {
+    /// Kind of the triggered watchpoint
+    pub kind: WatchKind,
+    /// Accessed address within the watched range
+    pub address: u32,
+}
+
+impl Processor {
+    /// Watch data accesses to a memory range.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Stop watching a memory range, returns false if it was not watched.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        match self.watchpoints.iter().position(|w| *w == watchpoint) {
+            Some(index) => {
+                self.watchpoints.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take the first watchpoint triggered since the previous call, if any.
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    ///
+    /// Check a data access of `size` bytes at `address` against the
+    /// watchpoints. Called for loads, stores and exception stacking, but
+    /// not for instruction fetches or debugger accesses.
+    ///
+    #[inline(always)]
+    pub(crate) fn watch_access(&mut self, address: u32, size: u32, write: bool) {
+        if self.watchpoints.is_empty() || self.watchpoint_hit.is_some() {
+            return;
+        }
+        if let Some(watchpoint) = self
+            .watchpoints
+            .iter()
+            .find(|w| w.matches(address, size, write))
+        {
+            self.watchpoint_hit = Some(WatchpointHit {
+                kind: watchpoint.kind,
+                address: address.max(watchpoint.address),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::core::exception::{Exception, ExceptionHandling};
+    use crate::core::register::BaseReg;
+    use crate::memory::region::MemoryRegion;
+
+    fn watched_core(kind: WatchKind) -> Processor {
+        let mut core = Processor::new();
+        core.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        core.add_watchpoint(Watchpoint {
+            address: 0x2000_0010,
+            len: 8,
+            kind,
+        });
+        core
+    }
+
+    #[test]
+    fn test_write_watchpoint_ignores_reads_and_other_addresses() {
+        // Arrange
+        let mut core = watched_core(WatchKind::Write);
+
+        // Act
+        core.read32_data(0x2000_0010).unwrap();
+        core.write32_data(0x2000_0008, 0).unwrap();
+        core.write8_data(0x2000_0018, 0).unwrap();
+
+        // Assert
+        assert_eq!(core.take_watchpoint_hit(), None);
+    }
+
+    #[test]
+    fn test_access_overlapping_watched_range_is_reported() {
+        // Arrange
+        let mut core = watched_core(WatchKind::Write);
+
+        // Act
+        core.write32_data(0x2000_000e, 0).unwrap();
+        core.write8_data(0x2000_0014, 0).unwrap();
+
+        // Assert
+        assert_eq!(
+            core.take_watchpoint_hit(),
+            Some(WatchpointHit {
+                kind: WatchKind::Write,
+                address: 0x2000_0010
+            })
+        );
+        assert_eq!(core.take_watchpoint_hit(), None);
+    }
+
+    #[test]
+    fn test_read_and_access_watchpoints() {
+        // Arrange
+        let mut read = watched_core(WatchKind::Read);
+        let mut access = watched_core(WatchKind::Access);
+
+        // Act
+        read.write16_data(0x2000_0012, 0).unwrap();
+        read.read16_data(0x2000_0012).unwrap();
+        access.write16_data(0x2000_0016, 0).unwrap();
+
+        // Assert
+        assert_eq!(
+            read.take_watchpoint_hit(),
+            Some(WatchpointHit {
+                kind: WatchKind::Read,
+                address: 0x2000_0012
+            })
+        );
+        assert_eq!(
+            access.take_watchpoint_hit(),
+            Some(WatchpointHit {
+                kind: WatchKind::Access,
+                address: 0x2000_0016
+            })
+        );
+    }
+
+    #[test]
+    fn test_exception_stacking_triggers_write_watchpoint() {
+        // Arrange
+        let mut core = Processor::new();
+        core.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        core.set_msp(0x2000_0100);
+        core.add_watchpoint(Watchpoint {
+            address: 0x2000_00f8,
+            len: 4,
+            kind: WatchKind::Write,
+        });
+
+        // Act
+        let _ = core.exception_entry(Exception::PendSV, 0);
+
+        // Assert
+        assert_eq!(
+            core.take_watchpoint_hit(),
+            Some(WatchpointHit {
+                kind: WatchKind::Write,
+                address: 0x2000_00f8
+            })
+        );
+    }
+
+    #[test]
+    fn test_debugger_access_does_not_trigger() {
+        // Arrange
+        let mut core = watched_core(WatchKind::Access);
+
+        // Act
+        core.write32(0x2000_0010, 1).unwrap();
+        core.read32(0x2000_0010).unwrap();
+
+        // Assert
+        assert_eq!(core.take_watchpoint_hit(), None);
+        assert!(core.remove_watchpoint(Watchpoint {
+            address: 0x2000_0010,
+            len: 8,
+            kind: WatchKind::Access
+        }));
+        assert!(core.watchpoints.is_empty());
+    }
+}
@@ -6,6 +6,12 @@ fn test_decode_dmb() {
     assert_eq!(decode_32(0xf3bf_8f5f), Instruction::DMB);
 }
 
+#[test]
+fn test_decode_clrex() {
+    //  f3bf 8f2f       clrex
+    assert_eq!(decode_32(0xf3bf_8f2f), Instruction::CLREX);
+}
+
 #[test]
 fn test_decode_it() {
     // ITT MI
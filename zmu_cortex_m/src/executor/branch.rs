@@ -4,12 +4,9 @@ use crate::executor::{ExecuteSuccess, ExecutorHelper};
 
 use super::ExecuteResult;
 
-use crate::{
-    bus::Bus,
-    core::{
-        instruction::{CondBranchParams, ParamsRegImm32, Reg2RnRmParams},
-        register::{BaseReg, Reg},
-    },
+use crate::core::{
+    instruction::{CondBranchParams, ParamsRegImm32, Reg2RnRmParams},
+    register::{BaseReg, Reg},
 };
 
 /// Branching operations
@@ -90,7 +87,7 @@ impl IsaBranch for Processor {
             let rn = self.get_r(params.rn);
             let rm = self.get_r(params.rm);
             let pc = self.get_r(Reg::PC);
-            let halfwords = u32::from(self.read8(rn + rm)?);
+            let halfwords = u32::from(self.read8_data(rn + rm)?);
 
             self.branch_write_pc(pc + 2 * halfwords);
 
@@ -104,7 +101,7 @@ impl IsaBranch for Processor {
             let rn = self.get_r(params.rn);
             let rm = self.get_r(params.rm);
             let pc = self.get_r(Reg::PC);
-            let halfwords = u32::from(self.read16(rn + (rm << 1))?);
+            let halfwords = u32::from(self.read16_data(rn + (rm << 1))?);
 
             self.branch_write_pc(pc + 2 * halfwords);
 
@@ -3,6 +3,7 @@ use crate::Processor;
 use crate::{
     core::exception::{Exception, ExceptionHandling},
     core::register::{BaseReg, Reg},
+    core::trace_event::TraceEventKind,
     executor::{ExecuteSuccess, ExecutorHelper},
     semihosting::{decode_semihostcmd, semihost_return},
 };
@@ -29,6 +30,10 @@ impl IsaException for Processor {
         if imm32 == 0xab {
             let r0 = self.get_r(Reg::R0);
             let r1 = self.get_r(Reg::R1);
+            self.trace_event(TraceEventKind::Semihosting {
+                operation: r0,
+                parameter: r1,
+            });
             let semihost_cmd = decode_semihostcmd(r0, r1, self)?;
 
             if let Some(sh_func) = &mut self.semihost_func {
@@ -137,10 +137,14 @@ impl Processor {
                 self.validate_address(self.fpcar.wrapping_add(i * 4), acctype, true)?;
             let reg = SingleReg::from(i as u8);
             let value = self.get_sr(reg);
+            self.watch_access(memaddrdesc, 4, true);
             self.write32(memaddrdesc, value)?;
+            self.trace_access(memaddrdesc, 4, true, value);
         }
         let memaddrdesc = self.validate_address(self.fpcar.wrapping_add(0x40), acctype, true)?;
+        self.watch_access(memaddrdesc, 4, true);
         self.write32(memaddrdesc, self.fpscr)?;
+        self.trace_access(memaddrdesc, 4, true, self.fpscr);
         self.fpccr.set_bit(FPCCR_LSPACT, false);
         Ok(())
     }
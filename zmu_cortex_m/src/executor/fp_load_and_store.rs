@@ -4,15 +4,12 @@ use crate::core::instruction::{
 };
 
 use super::ExecuteResult;
+use crate::core::{
+    instruction::VLoadAndStoreParams,
+    register::{BaseReg, ExtensionReg, ExtensionRegOperations, Reg},
+};
 use crate::executor::fp_generic::FloatingPointChecks;
 use crate::executor::{ExecuteSuccess, ExecutorHelper};
-use crate::{
-    bus::Bus,
-    core::{
-        instruction::VLoadAndStoreParams,
-        register::{BaseReg, ExtensionReg, ExtensionRegOperations, Reg},
-    },
-};
 
 /// Multiply operations
 pub trait IsaFloatingPointLoadAndStore {
@@ -43,12 +40,12 @@ impl IsaFloatingPointLoadAndStore for Processor {
             };
             match params.dd {
                 ExtensionReg::Single { reg } => {
-                    let data = self.read32(address)?;
+                    let data = self.read32_data(address)?;
                     self.set_sr(reg, data);
                 }
                 ExtensionReg::Double { reg } => {
-                    let word1 = self.read32(address)?;
-                    let word2 = self.read32(address + 4)?;
+                    let word1 = self.read32_data(address)?;
+                    let word2 = self.read32_data(address + 4)?;
                     self.set_dr(reg, word1, word2);
                 }
             }
@@ -72,12 +69,12 @@ impl IsaFloatingPointLoadAndStore for Processor {
             match params.dd {
                 ExtensionReg::Single { reg } => {
                     let value = self.get_sr(reg);
-                    self.write32(address, value)?;
+                    self.write32_data(address, value)?;
                 }
                 ExtensionReg::Double { reg } => {
                     let (low_word, high_word) = self.get_dr(reg);
-                    self.write32(address, low_word)?;
-                    self.write32(address + 4, high_word)?;
+                    self.write32_data(address, low_word)?;
+                    self.write32_data(address + 4, high_word)?;
                 }
             }
 
@@ -105,8 +102,8 @@ impl IsaFloatingPointLoadAndStore for Processor {
             }
 
             for reg in &params.list {
-                let low_word = self.read32(address)?;
-                let high_word = self.read32(address + 4)?;
+                let low_word = self.read32_data(address)?;
+                let high_word = self.read32_data(address + 4)?;
                 if self.big_endian() {
                     self.set_dr(reg, high_word, low_word);
                 } else {
@@ -139,7 +136,7 @@ impl IsaFloatingPointLoadAndStore for Processor {
             }
 
             for reg in &params.list {
-                let value = self.read32(address)?;
+                let value = self.read32_data(address)?;
                 self.set_sr(reg, value);
                 address = address.wrapping_add(4);
             }
@@ -160,18 +157,18 @@ impl IsaFloatingPointLoadAndStore for Processor {
             if params.single_regs {
                 for reg in &params.single_precision_registers {
                     let value = self.get_sr(reg);
-                    self.write32(address, value)?;
+                    self.write32_data(address, value)?;
                     address += 4;
                 }
             } else {
                 for reg in &params.double_precision_registers {
                     let (low_word, high_word) = self.get_dr(reg);
                     if self.big_endian() {
-                        self.write32(address, high_word)?;
-                        self.write32(address + 4, low_word)?;
+                        self.write32_data(address, high_word)?;
+                        self.write32_data(address + 4, low_word)?;
                     } else {
-                        self.write32(address, low_word)?;
-                        self.write32(address + 4, high_word)?;
+                        self.write32_data(address, low_word)?;
+                        self.write32_data(address + 4, high_word)?;
                     }
                     address += 8;
                 }
@@ -191,14 +188,14 @@ impl IsaFloatingPointLoadAndStore for Processor {
             self.set_r(Reg::SP, sp + params.imm32);
             if params.single_regs {
                 for reg in &params.single_precision_registers {
-                    let value = self.read32(address)?;
+                    let value = self.read32_data(address)?;
                     self.set_sr(reg, value);
                     address += 4;
                 }
             } else {
                 for reg in &params.double_precision_registers {
-                    let low_word = self.read32(address)?;
-                    let high_word = self.read32(address + 4)?;
+                    let low_word = self.read32_data(address)?;
+                    let high_word = self.read32_data(address + 4)?;
                     if self.big_endian() {
                         self.set_dr(reg, high_word, low_word);
                     } else {
@@ -235,11 +232,11 @@ impl IsaFloatingPointLoadAndStore for Processor {
             for reg in &params.list {
                 let (low_word, high_word) = self.get_dr(reg);
                 if self.big_endian() {
-                    self.write32(address, high_word)?;
-                    self.write32(address + 4, low_word)?;
+                    self.write32_data(address, high_word)?;
+                    self.write32_data(address + 4, low_word)?;
                 } else {
-                    self.write32(address, low_word)?;
-                    self.write32(address + 4, high_word)?;
+                    self.write32_data(address, low_word)?;
+                    self.write32_data(address + 4, high_word)?;
                 }
                 address += 8;
             }
@@ -270,7 +267,7 @@ impl IsaFloatingPointLoadAndStore for Processor {
 
             for reg in &params.list {
                 let value = self.get_sr(reg);
-                self.write32(address, value)?;
+                self.write32_data(address, value)?;
                 address += 4;
             }
 
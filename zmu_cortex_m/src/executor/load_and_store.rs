@@ -2,6 +2,7 @@ use crate::Processor;
 
 use crate::core::fault::FaultStatusContext;
 use crate::executor::{ExecuteSuccess, ExecutorHelper};
+use crate::peripheral::mpu::{AccType, Mpu};
 
 use super::{ExecuteResult, resolve_addressing};
 
@@ -21,40 +22,85 @@ use crate::{
 };
 
 impl Processor {
-    fn read8_data(&mut self, address: u32) -> Result<u8, Fault> {
-        self.read8(address).map_err(|fault| {
-            self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
-        })
-    }
-
-    fn read16_data(&mut self, address: u32) -> Result<u16, Fault> {
-        self.read16(address).map_err(|fault| {
-            self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
-        })
-    }
-
-    fn read32_data(&mut self, address: u32) -> Result<u32, Fault> {
-        self.read32(address).map_err(|fault| {
-            self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
-        })
-    }
-
-    fn write8_data(&mut self, address: u32, value: u8) -> Result<(), Fault> {
-        self.write8(address, value).map_err(|fault| {
-            self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
-        })
-    }
-
-    fn write16_data(&mut self, address: u32, value: u16) -> Result<(), Fault> {
-        self.write16(address, value).map_err(|fault| {
-            self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
-        })
-    }
-
-    fn write32_data(&mut self, address: u32, value: u32) -> Result<(), Fault> {
-        self.write32(address, value).map_err(|fault| {
-            self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
-        })
+    pub(crate) fn read8_data(&mut self, address: u32) -> Result<u8, Fault> {
+        self.validate_address(address, AccType::Normal, false)
+            .and_then(|address| {
+                self.watch_access(address, 1, false);
+                let value = self.read8(address)?;
+                self.trace_access(address, 1, false, u32::from(value));
+                Ok(value)
+            })
+            .map_err(|fault| {
+                self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
+            })
+    }
+
+    pub(crate) fn read16_data(&mut self, address: u32) -> Result<u16, Fault> {
+        self.validate_address(address, AccType::Normal, false)
+            .and_then(|address| {
+                self.watch_access(address, 2, false);
+                let value = self.read16(address)?;
+                self.trace_access(address, 2, false, u32::from(value));
+                Ok(value)
+            })
+            .map_err(|fault| {
+                self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
+            })
+    }
+
+    pub(crate) fn read32_data(&mut self, address: u32) -> Result<u32, Fault> {
+        self.validate_address(address, AccType::Normal, false)
+            .and_then(|address| {
+                self.watch_access(address, 4, false);
+                let value = self.read32(address)?;
+                self.trace_access(address, 4, false, value);
+                Ok(value)
+            })
+            .map_err(|fault| {
+                self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
+            })
+    }
+
+    pub(crate) fn write8_data(&mut self, address: u32, value: u8) -> Result<(), Fault> {
+        self.clear_exclusive_by_address(address, 1);
+        self.validate_address(address, AccType::Normal, true)
+            .and_then(|address| {
+                self.watch_access(address, 1, true);
+                self.write8(address, value)?;
+                self.trace_access(address, 1, true, u32::from(value));
+                Ok(())
+            })
+            .map_err(|fault| {
+                self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
+            })
+    }
+
+    pub(crate) fn write16_data(&mut self, address: u32, value: u16) -> Result<(), Fault> {
+        self.clear_exclusive_by_address(address, 2);
+        self.validate_address(address, AccType::Normal, true)
+            .and_then(|address| {
+                self.watch_access(address, 2, true);
+                self.write16(address, value)?;
+                self.trace_access(address, 2, true, u32::from(value));
+                Ok(())
+            })
+            .map_err(|fault| {
+                self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
+            })
+    }
+
+    pub(crate) fn write32_data(&mut self, address: u32, value: u32) -> Result<(), Fault> {
+        self.clear_exclusive_by_address(address, 4);
+        self.validate_address(address, AccType::Normal, true)
+            .and_then(|address| {
+                self.watch_access(address, 4, true);
+                self.write32(address, value)?;
+                self.trace_access(address, 4, true, value);
+                Ok(())
+            })
+            .map_err(|fault| {
+                self.fault_with_status(fault, FaultStatusContext::with_fault_address(address))
+            })
     }
 }
 
@@ -600,6 +646,7 @@ impl IsaLoadAndStore for Processor {
 mod tests {
     use super::*;
     use crate::core::{fault::Fault, instruction::SRType};
+    use crate::executor::misc::IsaMisc;
 
     #[test]
     fn test_exec_str_reg_wraps_address_calculation_instead_of_panicking() {
@@ -622,4 +669,148 @@ mod tests {
 
         assert_eq!(result, Err(Fault::DAccViol));
     }
+
+    fn ldrex_r1_r0(core: &mut Processor) {
+        core.exec_ldrex(Reg2RtRnImm32Params {
+            rt: Reg::R1,
+            rn: Reg::R0,
+            imm32: 0,
+        })
+        .unwrap();
+    }
+
+    fn strex_r2_r1_r0(core: &mut Processor) -> u32 {
+        core.exec_strex(Reg3RdRtRnImm32Params {
+            rd: Reg::R2,
+            rt: Reg::R1,
+            rn: Reg::R0,
+            imm32: 0,
+        })
+        .unwrap();
+        core.get_r(Reg::R2)
+    }
+
+    #[test]
+    fn test_strex_succeeds_once_after_ldrex() {
+        // Arrange
+        let mut core = Processor::new();
+        core.set_r(Reg::R0, 0x2000_0000);
+
+        // Act & Assert
+        assert_eq!(strex_r2_r1_r0(&mut core), 1);
+        ldrex_r1_r0(&mut core);
+        assert_eq!(strex_r2_r1_r0(&mut core), 0);
+        assert_eq!(strex_r2_r1_r0(&mut core), 1);
+    }
+
+    #[test]
+    fn test_strex_fails_after_ldrex_of_other_address_or_size() {
+        // Arrange
+        let mut core = Processor::new();
+        core.set_r(Reg::R0, 0x2000_0000);
+        ldrex_r1_r0(&mut core);
+        core.set_r(Reg::R0, 0x2000_0004);
+
+        // Act & Assert
+        assert_eq!(strex_r2_r1_r0(&mut core), 1);
+
+        ldrex_r1_r0(&mut core);
+        core.exec_strexb(Reg3RdRtRnParams {
+            rd: Reg::R2,
+            rt: Reg::R1,
+            rn: Reg::R0,
+        })
+        .unwrap();
+        assert_eq!(core.get_r(Reg::R2), 1);
+    }
+
+    #[test]
+    fn test_store_to_reservation_granule_clears_monitor() {
+        // Arrange
+        let mut core = Processor::new();
+        core.set_r(Reg::R0, 0x2000_0000);
+
+        // Act & Assert
+        ldrex_r1_r0(&mut core);
+        core.write32_data(0x2000_0008, 0).unwrap();
+        assert_eq!(strex_r2_r1_r0(&mut core), 0);
+
+        ldrex_r1_r0(&mut core);
+        core.write8_data(0x2000_0007, 0).unwrap();
+        assert_eq!(strex_r2_r1_r0(&mut core), 1);
+    }
+
+    #[test]
+    fn test_clrex_clears_monitor() {
+        // Arrange
+        let mut core = Processor::new();
+        core.set_r(Reg::R0, 0x2000_0000);
+        ldrex_r1_r0(&mut core);
+
+        // Act
+        core.exec_clrex().unwrap();
+
+        // Assert
+        assert_eq!(strex_r2_r1_r0(&mut core), 1);
+    }
+
+    fn imm(rt: Reg, imm32: u32) -> Reg2FullParams {
+        Reg2FullParams {
+            rt,
+            rn: Reg::R0,
+            imm32,
+            index: true,
+            add: true,
+            wback: false,
+        }
+    }
+
+    #[test]
+    fn test_big_endian_loads_are_byte_reversed() {
+        use crate::core::instruction::Reg2RdRmParams;
+        use crate::executor::misc_data_processing::IsaMiscDataProcessing;
+
+        // Arrange
+        let mut core = Processor::new();
+        core.big_endian_data(true);
+        core.set_r(Reg::R0, 0x2000_0000);
+        core.set_r(Reg::R1, 0x1122_8344);
+        core.exec_str_imm(&imm(Reg::R1, 0)).unwrap();
+
+        // Act
+        core.exec_ldrb_imm(&imm(Reg::R2, 0)).unwrap();
+        core.exec_ldrh_imm(&imm(Reg::R3, 2)).unwrap();
+        core.exec_ldrsh_imm(&imm(Reg::R4, 2)).unwrap();
+        let bytes: Vec<u8> = (0..4)
+            .map(|n| core.read8(0x2000_0000 + n).unwrap())
+            .collect();
+        core.write32(0x2000_0004, u32::from_le_bytes([0x11, 0x22, 0x83, 0x44]))
+            .unwrap();
+        core.exec_ldr_imm(&imm(Reg::R5, 4)).unwrap();
+        core.exec_rev(Reg2RdRmParams {
+            rd: Reg::R6,
+            rm: Reg::R5,
+        })
+        .unwrap();
+        core.exec_rev16(Reg2RdRmParams {
+            rd: Reg::R7,
+            rm: Reg::R3,
+        })
+        .unwrap();
+        core.exec_revsh(Reg2RdRmParams {
+            rd: Reg::R8,
+            rm: Reg::R3,
+        })
+        .unwrap();
+
+        // Assert
+        assert_eq!(bytes, [0x11, 0x22, 0x83, 0x44]);
+        assert_eq!(core.get_r(Reg::R2), 0x11);
+        assert_eq!(core.get_r(Reg::R3), 0x8344);
+        assert_eq!(core.get_r(Reg::R4), 0xffff_8344);
+        assert_eq!(core.get_r(Reg::R5), 0x4483_2211);
+        assert_eq!(core.get_r(Reg::R6), 0x1122_8344);
+        assert_eq!(core.get_r(Reg::R7), 0x4483);
+        assert_eq!(core.get_r(Reg::R8), 0x4483);
+    }
 }
@@ -2,12 +2,9 @@ use super::ExecuteResult;
 use crate::Processor;
 use crate::executor::{ExecuteSuccess, ExecutorHelper};
 
-use crate::{
-    bus::Bus,
-    core::{
-        instruction::LoadAndStoreMultipleParams,
-        register::{BaseReg, Reg},
-    },
+use crate::core::{
+    instruction::LoadAndStoreMultipleParams,
+    register::{BaseReg, Reg},
 };
 use enum_set::EnumSet;
 
@@ -29,7 +26,7 @@ impl IsaLoadAndStoreMultiple for Processor {
 
             for reg in &params.registers {
                 let r = self.get_r(reg);
-                self.write32(address, r)?;
+                self.write32_data(address, r)?;
                 address += 4;
             }
 
@@ -51,7 +48,7 @@ impl IsaLoadAndStoreMultiple for Processor {
 
             for reg in &params.registers {
                 let r = self.get_r(reg);
-                self.write32(address, r)?;
+                self.write32_data(address, r)?;
                 address += 4;
             }
 
@@ -73,7 +70,7 @@ impl IsaLoadAndStoreMultiple for Processor {
 
             let mut branched = false;
             for reg in &params.registers {
-                let value = self.read32(address)?;
+                let value = self.read32_data(address)?;
                 if reg == Reg::PC {
                     self.load_write_pc(value)?;
                     branched = true;
@@ -103,7 +100,7 @@ impl IsaLoadAndStoreMultiple for Processor {
 
             for reg in &registers {
                 let value = self.get_r(reg);
-                self.write32(address, value)?;
+                self.write32_data(address, value)?;
                 address += 4;
             }
 
@@ -124,7 +121,7 @@ impl IsaLoadAndStoreMultiple for Processor {
             self.set_r(Reg::SP, sp + regs_size);
 
             for reg in &registers {
-                let val = self.read32(address)?;
+                let val = self.read32_data(address)?;
                 if reg == Reg::PC {
                     self.bx_write_pc(val)?;
                 } else {
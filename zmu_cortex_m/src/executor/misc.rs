@@ -1,7 +1,10 @@
 use crate::Processor;
 
 use crate::{
-    core::{condition::Condition, exception::ExceptionHandling},
+    core::{
+        condition::Condition, exception::ExceptionHandling, monitor::Monitor,
+        trace_event::TraceEventKind,
+    },
     executor::{ExecuteSuccess, ExecutorHelper},
 };
 
@@ -9,6 +12,7 @@ use super::ExecuteResult;
 
 /// Branching operations
 pub trait IsaMisc {
+    fn exec_clrex(&mut self) -> ExecuteResult;
     fn exec_dmb(&self) -> ExecuteResult;
     fn exec_dsb(&self) -> ExecuteResult;
     fn exec_isb(&self) -> ExecuteResult;
@@ -23,6 +27,13 @@ pub trait IsaMisc {
 }
 
 impl IsaMisc for Processor {
+    fn exec_clrex(&mut self) -> ExecuteResult {
+        if self.condition_passed() {
+            self.clear_exclusive_local();
+            return Ok(ExecuteSuccess::Taken { cycles: 1 });
+        }
+        Ok(ExecuteSuccess::NotTaken)
+    }
     fn exec_dmb(&self) -> ExecuteResult {
         if self.condition_passed() {
             return Ok(ExecuteSuccess::Taken { cycles: 4 });
@@ -100,6 +111,7 @@ impl IsaMisc for Processor {
         if self.condition_passed() {
             if self.get_pending_exception().is_none() && !self.has_wakeup_condition() {
                 self.sleeping = true;
+                self.trace_event(TraceEventKind::Sleep);
             }
             return Ok(ExecuteSuccess::Taken { cycles: 1 });
         }
@@ -11,9 +11,14 @@ use crate::core::instruction::{Imm32Carry, Instruction, SetFlags, instruction_si
 
 use crate::core::operation::condition_test;
 use crate::core::register::{Apsr, BaseReg, Ipsr};
+use crate::core::trace_event::TraceEventKind;
 use crate::decoder::Decoder;
-use crate::memory::map::MapMemory;
-use crate::peripheral::{dwt::Dwt, systick::SysTick};
+use crate::peripheral::{
+    dwt::Dwt,
+    mpu::{AccType, Mpu},
+    nvic::NVIC,
+    systick::SysTick,
+};
 
 use crate::{CachedInstruction, Processor};
 
@@ -82,6 +87,22 @@ pub trait Executor {
     ///
     fn step_sleep(&mut self);
 
+    ///
+    /// Run a sleeping processor forward to the next cycle on which `SysTick`
+    /// or a peripheral may wake it, in one step. The result is the same as
+    /// calling `step_sleep` for each of the cycles.
+    ///
+    fn step_sleep_until_event(&mut self);
+
+    ///
+    /// Run processor forward by a block of decoded instructions, ending at
+    /// the first branch, exception, access to the private peripheral bus
+    /// or `SysTick` wrap. `SysTick`, DWT and exception checks are done once
+    /// for the block, the result is the same as calling `step` for each of
+    /// the instructions.
+    ///
+    fn step_block(&mut self);
+
     ///
     /// Execute given instruction. Returns number of clock cycles burn.
     ///
@@ -169,6 +190,66 @@ impl Processor {
         }
     }
 
+    fn device_tick(&mut self, cycles: u32) {
+        let mut irq_lines = std::mem::take(&mut self.irq_lines);
+        if let Some(device) = self.device.as_mut() {
+            device.tick(cycles, &mut irq_lines);
+        }
+        for line in irq_lines.drain(..) {
+            self.nvic_set_irq_line(line.irqn, line.asserted);
+        }
+        self.irq_lines = irq_lines;
+    }
+
+    /// Cycles until `SysTick` or the device may next raise an interrupt
+    fn cycles_to_next_event(&self) -> u32 {
+        let systick = self.syst_cycles_to_wrap();
+        let device = self.device.as_ref().and_then(|device| device.next_event());
+        [systick, device]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(u32::MAX)
+            .max(1)
+    }
+
+    fn sleep_cycles(&mut self, cycles: u32) {
+        self.cycle_count += u64::from(cycles);
+        if (self.syst_csr & 1) != 0 {
+            self.syst_step(cycles);
+        }
+        if self.device.is_some() {
+            self.device_tick(cycles);
+        }
+        self.check_exceptions();
+        if (self.dwt_ctrl & 1) != 0 {
+            self.dwt_tick(cycles);
+        }
+    }
+
+    ///
+    /// Count the cycles of the running block to `SysTick` and DWT before an
+    /// access to their registers or other system registers, the block ends
+    /// after the instruction.
+    ///
+    #[inline(always)]
+    pub(crate) fn sync_block(&mut self) {
+        if self.in_block {
+            self.in_block = false;
+            self.block_tick();
+        }
+    }
+
+    fn block_tick(&mut self) {
+        let cycles = std::mem::take(&mut self.block_cycles);
+        if (self.dwt_ctrl & 1) != 0 {
+            self.dwt_tick(cycles);
+        }
+        if (self.syst_csr & 1) != 0 {
+            self.syst_step(cycles);
+        }
+    }
+
     #[cfg(not(feature = "armv6m"))]
     fn fault_delivery_exception(&mut self, fault: Fault) -> Exception {
         let mapped_exception = fault.exception();
@@ -226,6 +307,7 @@ impl Processor {
     ) -> u32 {
         let exception = self.fault_delivery_exception(fault);
         let active_exception = self.active_exception();
+        self.trace_event(TraceEventKind::Fault { fault, exception });
 
         self.record_fault_status(fault, status);
 
@@ -257,6 +339,10 @@ impl Processor {
             Err(entry_fault) => {
                 self.record_fault_status(entry_fault, FaultStatusContext::default());
                 self.set_hfsr_forced();
+                self.trace_event(TraceEventKind::Fault {
+                    fault: entry_fault,
+                    exception: Exception::HardFault,
+                });
                 let trap_reason = if exception == Exception::HardFault
                     || Self::is_lockup(Exception::HardFault, active_exception)
                 {
@@ -632,6 +718,7 @@ impl ExecutorHelper for Processor {
             // Group: Miscellaneous
             //
             // --------------------------------------------
+            Instruction::CLREX => self.exec_clrex(),
             Instruction::DMB => self.exec_dmb(),
             Instruction::DSB => self.exec_dsb(),
             Instruction::ISB => self.exec_isb(),
@@ -797,38 +884,135 @@ impl ExecutorHelper for Processor {
 impl Executor for Processor {
     #[inline(always)]
     fn step_sleep(&mut self) {
-        if (self.syst_csr & 1) != 0 {
-            self.syst_step(1);
+        self.sleep_cycles(1);
+    }
+
+    fn step_sleep_until_event(&mut self) {
+        // an exception that is already pending wakes the processor on the next cycle
+        let cycles = if self.get_pending_exception().is_some() || self.has_wakeup_condition() {
+            1
+        } else {
+            self.cycles_to_next_event()
+        };
+        self.sleep_cycles(cycles);
+    }
+
+    fn step_block(&mut self) {
+        // exceptions that may be taken are checked after each instruction
+        if self.pending_exception_count != 0 || self.sleeping {
+            self.step();
+            return;
         }
-        self.check_exceptions();
-        if (self.dwt_ctrl & 1) != 0 {
-            self.dwt_tick(1);
+        let mut pc = self.get_pc();
+        let Some((region, offset)) = self.memory.resolve_code(pc).filter(|(region, _)| {
+            self.instruction_cache
+                .get(*region)
+                .is_some_and(|cache| !cache.is_empty())
+        }) else {
+            self.step();
+            return;
+        };
+        let budget = self.syst_cycles_to_wrap().unwrap_or(u32::MAX);
+        let mut index = (offset >> 1) as usize;
+        let mut cycles = 0;
+        let mut executed = false;
+
+        // the instructions are executed by reference from the cache of the
+        // region, writes to the region are invalidated when the block ends
+        let mut cache = std::mem::take(&mut self.instruction_cache[region]);
+        self.block_region = Some(region);
+        self.in_block = true;
+        while let Some(entry) = cache.get_mut(index) {
+            if self.validate_address(pc, AccType::IFetch, false).is_err() {
+                break;
+            }
+            if matches!(entry, CachedInstruction::Invalid) {
+                *entry = self.cached_instruction(pc);
+                self.lazy_instructions_cached = true;
+            }
+            let CachedInstruction::Decoded {
+                instruction,
+                instruction_size,
+            } = entry
+            else {
+                break;
+            };
+            let instruction_size = *instruction_size;
+            let count = self.execute(instruction, instruction_size);
+            executed = true;
+            self.cycle_count += u64::from(count);
+            self.block_cycles += count;
+            cycles += count;
+            if self.device.is_some() {
+                self.device_tick(count);
+            }
+
+            pc = pc.wrapping_add(instruction_size as u32);
+            index += instruction_size >> 1;
+            if self.get_pc() != pc
+                || !self.in_block
+                || cycles >= budget
+                || self.pending_exception_count != 0
+                || self.sleeping
+                || !self.running
+                || self.pending_fault_trap.is_some()
+                || !self.block_invalidated.is_empty()
+            {
+                break;
+            }
+        }
+        self.in_block = false;
+        self.block_region = None;
+        for range in self.block_invalidated.drain(..) {
+            cache[range].fill(CachedInstruction::Invalid);
+        }
+        self.instruction_cache[region] = cache;
+
+        if !executed {
+            // fetch faults are handled by the single step
+            self.step();
+            return;
+        }
+        self.block_tick();
+        if self.pending_exception_count != 0 || self.sleeping {
+            self.check_exceptions();
         }
     }
 
     #[inline(always)]
     fn step(&mut self) {
         let pc = self.get_pc();
-        let mapped_pc = if self.mem_map.is_some() {
-            (self.map_address(pc) >> 1) as usize
+        let count = if let Err(fault) = self.validate_address(pc, AccType::IFetch, false) {
+            self.handle_fault_with_status(fault, pc, FaultStatusContext::with_fault_address(pc))
         } else {
-            (pc >> 1) as usize
-        };
-        let count = match self.instruction_cache.get(mapped_pc).copied() {
-            Some(CachedInstruction::Decoded {
-                instruction,
-                instruction_size,
-            }) => self.execute(&instruction, instruction_size),
-            Some(CachedInstruction::FetchFault { fault, status }) => {
-                self.handle_fault_with_status(fault, pc, status)
-            }
-            None => match self.fetch(pc) {
-                Ok(thumb) => {
-                    let instruction = self.decode(thumb);
-                    self.execute(&instruction, instruction_size(&instruction))
+            let cached = self.memory.resolve_code(pc).and_then(|(region, offset)| {
+                let index = (offset >> 1) as usize;
+                match *self.instruction_cache.get(region)?.get(index)? {
+                    CachedInstruction::Invalid => {
+                        let entry = self.cached_instruction(pc);
+                        self.instruction_cache[region][index] = entry;
+                        self.lazy_instructions_cached = true;
+                        Some(entry)
+                    }
+                    entry => Some(entry),
                 }
-                Err(fault) => self.handle_fault(fault, pc),
-            },
+            });
+            match cached {
+                Some(CachedInstruction::Decoded {
+                    instruction,
+                    instruction_size,
+                }) => self.execute(&instruction, instruction_size),
+                Some(CachedInstruction::FetchFault { fault, status }) => {
+                    self.handle_fault_with_status(fault, pc, status)
+                }
+                Some(CachedInstruction::Invalid) | None => match self.fetch(pc) {
+                    Ok(thumb) => {
+                        let instruction = self.decode(thumb);
+                        self.execute(&instruction, instruction_size(&instruction))
+                    }
+                    Err(fault) => self.handle_fault(fault, pc),
+                },
+            }
         };
         self.cycle_count += u64::from(count);
         if (self.dwt_ctrl & 1) != 0 {
@@ -837,6 +1021,9 @@ impl Executor for Processor {
         if (self.syst_csr & 1) != 0 {
             self.syst_step(count);
         }
+        if self.device.is_some() {
+            self.device_tick(count);
+        }
         if self.pending_exception_count != 0 || self.sleeping {
             self.check_exceptions();
         }
@@ -926,6 +1113,171 @@ mod tests {
     #[cfg(not(feature = "armv6m"))]
     const SHCSR_USGFAULTENA: u32 = 1 << 18;
 
+    fn sleeping_core() -> Processor {
+        use crate::core::reset::Reset;
+
+        let mut core = Processor::new();
+        core.memory_region(crate::memory::region::MemoryRegion::ram(
+            "ram",
+            0x2000_0000,
+            0x100,
+        ));
+        core.reset().unwrap();
+        core.set_msp(0x2000_0100);
+        core.psr.value = 0x0100_0000;
+        core.syst_rvr = 1000;
+        core.syst_cvr = 300;
+        core.syst_csr = 0b011; // ENABLE | TICKINT
+        core.dwt_ctrl = 1;
+        core.sleeping = true;
+        core
+    }
+
+    #[test]
+    fn test_store_to_cached_ram_code_is_executed() {
+        // Arrange
+        let mut ram = crate::memory::region::MemoryRegion::ram("ram", 0x2000_0000, 0x100);
+        ram.load(
+            0x2000_0000,
+            &[
+                0x01, 0x20, // 0x00: movs r0, #1
+                0x11, 0x80, // 0x02: strh r1, [r2]
+                0xfc, 0xe7, // 0x04: b 0x00
+            ],
+        );
+        let mut core = Processor::new();
+        core.memory_region(ram);
+        core.cache_instructions();
+        core.psr.value = 0x0100_0000;
+        core.set_pc(0x2000_0000);
+        core.set_r(Reg::R1, 0x2002); // movs r0, #2
+        core.set_r(Reg::R2, 0x2000_0000);
+
+        // Act
+        core.step();
+        let before = core.get_r(Reg::R0);
+        for _ in 0..3 {
+            core.step();
+        }
+
+        // Assert
+        assert_eq!(before, 1);
+        assert_eq!(core.get_r(Reg::R0), 2);
+    }
+
+    fn block_test_core() -> Processor {
+        use crate::core::reset::Reset;
+        use crate::memory::region::MemoryRegion;
+
+        let mut code = vec![0u8; 0x100];
+        let mut put = |address: usize, halfwords: &[u16]| {
+            for (n, halfword) in halfwords.iter().enumerate() {
+                code[address + 2 * n..address + 2 * n + 2].copy_from_slice(&halfword.to_le_bytes());
+            }
+        };
+        put(0x00, &[0x0400, 0x2000, 0x0041, 0x0000]); // SP, reset
+        put(0x0c, &[0x0081, 0x0000]); // HardFault
+        put(0x3c, &[0x0061, 0x0000]); // SysTick
+        put(
+            0x40,
+            &[
+                0x2000, // movs r0, #0
+                0x2100, // movs r1, #0
+                0x3001, // 0x44: adds r0, #1
+                0x2832, // cmp r0, #50
+                #[cfg(not(feature = "armv6m"))]
+                0xbf08, // it eq
+                #[cfg(feature = "armv6m")]
+                0xd100, // bne 0x4c
+                0x2101, // mov r1, #1
+                0x2901, // cmp r1, #1
+                0xd001, // beq 0x54
+                0xe7f8, // b 0x44
+                0xbf00, // nop
+                0xde00, // 0x54: udf #0
+            ],
+        );
+        put(0x60, &[0x3501, 0x4770]); // adds r5, #1; bx lr
+        put(0x80, &[0xe7fe]); // b 0x80
+
+        let mut flash = MemoryRegion::rom("flash", 0, 0x100);
+        flash.load(0, &code);
+        let mut core = Processor::new();
+        core.memory_region(flash);
+        core.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x400));
+        core.cache_instructions();
+        core.reset().unwrap();
+        core.syst_rvr = 45; // the interrupts do not hit the IT block
+        core.syst_csr = 0b011; // ENABLE | TICKINT
+        core.dwt_ctrl = 1;
+        core
+    }
+
+    #[test]
+    fn test_step_block_matches_single_steps() {
+        // Arrange
+        let mut single = block_test_core();
+        let mut block = block_test_core();
+
+        // Act
+        let mut states = Vec::new();
+        for _ in 0..200 {
+            block.step_block();
+            while single.instruction_count < block.instruction_count {
+                single.step();
+            }
+            states.push((
+                single.get_pc(),
+                single.r0_12,
+                single.psr.value,
+                single.cycle_count,
+                single.syst_cvr,
+                single.dwt_cyccnt,
+                single.get_itstate(),
+            ));
+            assert_eq!(
+                states.last(),
+                Some(&(
+                    block.get_pc(),
+                    block.r0_12,
+                    block.psr.value,
+                    block.cycle_count,
+                    block.syst_cvr,
+                    block.dwt_cyccnt,
+                    block.get_itstate(),
+                ))
+            );
+        }
+
+        // Assert: SysTick interrupted the loop and the udf ended in HardFault
+        assert!(block.get_r(Reg::R5) > 0);
+        assert_eq!(block.get_r(Reg::R0), 50);
+        assert_eq!(block.get_pc(), 0x80);
+        assert!(block.instruction_count < 200 * 4);
+    }
+
+    #[test]
+    fn test_step_sleep_until_event_matches_single_cycles() {
+        // Arrange
+        let mut single = sleeping_core();
+        let mut fast = sleeping_core();
+
+        // Act
+        while single.sleeping {
+            single.step_sleep();
+        }
+        fast.step_sleep_until_event();
+
+        // Assert
+        assert!(!fast.sleeping);
+        assert_eq!(fast.cycle_count, 300);
+        assert_eq!(fast.cycle_count, single.cycle_count);
+        assert_eq!(fast.dwt_cyccnt, single.dwt_cyccnt);
+        assert_eq!(fast.syst_cvr, single.syst_cvr);
+        assert_eq!(fast.psr.get_isr_number(), single.psr.get_isr_number());
+        assert_eq!(fast.psr.get_isr_number(), 15);
+    }
+
     #[test]
     fn test_execute_internal_udf_returns_undefinstr_fault() {
         let mut core = Processor::new();
@@ -1403,6 +1755,96 @@ mod tests {
         assert_eq!(core.mmfar, 0x6000_0000);
     }
 
+    #[cfg(not(feature = "armv6m"))]
+    fn mpu_protected_core(rasr: u32) -> Processor {
+        let mut core = Processor::new();
+        core.fault_trap_mode(FaultTrapMode::none());
+        core.set_msp(0x2000_0100);
+        core.write32(0xE000_ED24, SHCSR_MEMFAULTENA).unwrap();
+        // region 0: 0x2000_0000, 1 KiB
+        core.write32(0xE000_ED9C, 0x2000_0000 | 0x10).unwrap();
+        core.write32(0xE000_EDA0, rasr | (9 << 1) | 1).unwrap();
+        // ENABLE | PRIVDEFENA
+        core.write32(0xE000_ED94, 0b101).unwrap();
+        core
+    }
+
+    #[cfg(not(feature = "armv6m"))]
+    fn str_r0_to_r1() -> Instruction {
+        Instruction::STR_imm {
+            params: crate::core::instruction::Reg2FullParams {
+                rt: Reg::R0,
+                rn: Reg::R1,
+                imm32: 0,
+                index: true,
+                add: true,
+                wback: false,
+            },
+            thumb32: false,
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "armv6m"))]
+    fn test_mpu_unprivileged_store_outside_region_raises_daccviol() {
+        // Arrange
+        let mut core = mpu_protected_core(0b011 << 24);
+        core.control.n_priv = true;
+        core.set_r(Reg::R1, 0x2000_0400);
+        let instruction = str_r0_to_r1();
+
+        // Act
+        core.execute(&instruction, instruction_size(&instruction));
+
+        // Assert
+        assert_eq!(
+            core.psr.get_isr_number(),
+            Exception::MemoryManagementFault.into()
+        );
+        assert_eq!(core.cfsr, CFSR_DACCVIOL | CFSR_MMARVALID);
+        assert_eq!(core.mmfar, 0x2000_0400);
+    }
+
+    #[test]
+    #[cfg(not(feature = "armv6m"))]
+    fn test_mpu_fetch_from_execute_never_region_raises_iaccviol() {
+        // Arrange
+        let mut core = mpu_protected_core((1 << 28) | (0b011 << 24));
+        core.set_pc(0x2000_0200);
+
+        // Act
+        core.step();
+
+        // Assert
+        assert_eq!(
+            core.psr.get_isr_number(),
+            Exception::MemoryManagementFault.into()
+        );
+        assert_eq!(core.cfsr, CFSR_IACCVIOL | CFSR_MMARVALID);
+        assert_eq!(core.mmfar, 0x2000_0200);
+    }
+
+    #[test]
+    #[cfg(not(feature = "armv6m"))]
+    fn test_mpu_stacking_outside_region_raises_mstkerr() {
+        // Arrange
+        let mut core = mpu_protected_core(0b011 << 24);
+        core.control.n_priv = true;
+        core.control.sp_sel = true;
+        core.set_psp(0x2000_0800);
+        core.set_r(Reg::R1, 0x2000_0400);
+        let instruction = str_r0_to_r1();
+
+        // Act
+        core.execute(&instruction, instruction_size(&instruction));
+
+        // Assert
+        let trap = core.take_pending_fault_trap().expect("fault trap expected");
+        assert_eq!(trap.fault, Fault::Mstkerr);
+        assert_eq!(core.cfsr, CFSR_DACCVIOL | CFSR_MMARVALID | CFSR_MSTKERR);
+        assert_eq!(core.mmfar, 0x2000_0400);
+    }
+
     #[test]
     #[cfg(not(feature = "armv6m"))]
     fn test_handle_fault_sets_bfar_for_precise_bus_fault() {
@@ -0,0 +1,467 @@
+//!
+//! Cortex-M architecture description for gdb
+//!
+//! The registers follow the M-profile target description features
+//! `org.gnu.gdb.arm.m-profile`, `org.gnu.gdb.arm.m-system` and, with a
+//! floating point unit, `org.gnu.gdb.arm.vfp`. gdb derives the single
+//! precision registers S0-S31 from D0-D15, also for the single precision FPUs.
+//!
+
+use std::fmt::Write;
+use std::num::NonZeroUsize;
+use std::sync::OnceLock;
+
+use gdbstub::arch::{Arch, RegId, Registers};
+use gdbstub_arch::arm::ArmBreakpointKind;
+
+///
+/// Register identifiers, the raw id is the index in `REGISTERS`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CortexMRegId {
+    /// General purpose registers (R0-R12)
+    Gpr(u8),
+    /// Stack Pointer (R13), the active one of MSP and PSP
+    Sp,
+    /// Link Register (R14)
+    Lr,
+    /// Program Counter (R15)
+    Pc,
+    /// Combined program status register
+    Xpsr,
+    /// Main stack pointer
+    Msp,
+    /// Process stack pointer
+    Psp,
+    /// Priority mask
+    Primask,
+    /// Base priority mask
+    #[cfg(not(feature = "armv6m"))]
+    Basepri,
+    /// Fault mask
+    #[cfg(not(feature = "armv6m"))]
+    Faultmask,
+    /// Control register
+    Control,
+    /// Double precision registers (D0-D15)
+    #[cfg(feature = "has-fp")]
+    D(u8),
+    /// Floating point status and control register
+    #[cfg(feature = "has-fp")]
+    Fpscr,
+}
+
+#[cfg(not(feature = "armv6m"))]
+use CortexMRegId::{Basepri, Faultmask};
+use CortexMRegId::{Control, Gpr, Lr, Msp, Pc, Primask, Psp, Sp, Xpsr};
+#[cfg(feature = "has-fp")]
+use CortexMRegId::{D, Fpscr};
+
+/// Registers in the order of the `g` packet
+const REGISTERS: &[CortexMRegId] = &[
+    Gpr(0),
+    Gpr(1),
+    Gpr(2),
+    Gpr(3),
+    Gpr(4),
+    Gpr(5),
+    Gpr(6),
+    Gpr(7),
+    Gpr(8),
+    Gpr(9),
+    Gpr(10),
+    Gpr(11),
+    Gpr(12),
+    Sp,
+    Lr,
+    Pc,
+    Xpsr,
+    Msp,
+    Psp,
+    Primask,
+    #[cfg(not(feature = "armv6m"))]
+    Basepri,
+    #[cfg(not(feature = "armv6m"))]
+    Faultmask,
+    Control,
+    #[cfg(feature = "has-fp")]
+    D(0),
+    #[cfg(feature = "has-fp")]
+    D(1),
+    #[cfg(feature = "has-fp")]
+    D(2),
+    #[cfg(feature = "has-fp")]
+    D(3),
+    #[cfg(feature = "has-fp")]
+    D(4),
+    #[cfg(feature = "has-fp")]
+    D(5),
+    #[cfg(feature = "has-fp")]
+    D(6),
+    #[cfg(feature = "has-fp")]
+    D(7),
+    #[cfg(feature = "has-fp")]
+    D(8),
+    #[cfg(feature = "has-fp")]
+    D(9),
+    #[cfg(feature = "has-fp")]
+    D(10),
+    #[cfg(feature = "has-fp")]
+    D(11),
+    #[cfg(feature = "has-fp")]
+    D(12),
+    #[cfg(feature = "has-fp")]
+    D(13),
+    #[cfg(feature = "has-fp")]
+    D(14),
+    #[cfg(feature = "has-fp")]
+    D(15),
+    #[cfg(feature = "has-fp")]
+    Fpscr,
+];
+
+impl CortexMRegId {
+    fn name(self) -> String {
+        match self {
+            Gpr(n) => format!("r{n}"),
+            Sp => "sp".to_string(),
+            Lr => "lr".to_string(),
+            Pc => "pc".to_string(),
+            Xpsr => "xpsr".to_string(),
+            Msp => "msp".to_string(),
+            Psp => "psp".to_string(),
+            Primask => "primask".to_string(),
+            #[cfg(not(feature = "armv6m"))]
+            Basepri => "basepri".to_string(),
+            #[cfg(not(feature = "armv6m"))]
+            Faultmask => "faultmask".to_string(),
+            Control => "control".to_string(),
+            #[cfg(feature = "has-fp")]
+            D(n) => format!("d{n}"),
+            #[cfg(feature = "has-fp")]
+            Fpscr => "fpscr".to_string(),
+        }
+    }
+
+    /// Size in bytes
+    fn size(self) -> usize {
+        match self {
+            #[cfg(feature = "has-fp")]
+            D(_) => 8,
+            _ => 4,
+        }
+    }
+
+    /// Target description feature and the extra attributes of the register
+    fn feature(self) -> (&'static str, &'static str) {
+        match self {
+            Sp => ("m-profile", r#" type="data_ptr""#),
+            Pc => ("m-profile", r#" type="code_ptr""#),
+            Gpr(_) | Lr | Xpsr => ("m-profile", ""),
+            Msp | Psp => ("m-system", r#" type="data_ptr" group="system""#),
+            #[cfg(feature = "has-fp")]
+            D(_) => ("vfp", r#" type="ieee_double""#),
+            #[cfg(feature = "has-fp")]
+            Fpscr => ("vfp", r#" group="float""#),
+            _ => ("m-system", r#" group="system""#),
+        }
+    }
+}
+
+impl RegId for CortexMRegId {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        let reg = *REGISTERS.get(id)?;
+        Some((reg, NonZeroUsize::new(reg.size())))
+    }
+
+    fn to_raw_id(&self) -> Option<usize> {
+        REGISTERS.iter().position(|reg| reg == self)
+    }
+}
+
+fn target_description() -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><architecture>arm</architecture>"#,
+    );
+    let mut feature = "";
+    for (regnum, reg) in REGISTERS.iter().enumerate() {
+        let (name, attributes) = reg.feature();
+        if name != feature {
+            if !feature.is_empty() {
+                xml.push_str("</feature>");
+            }
+            write!(xml, r#"<feature name="org.gnu.gdb.arm.{name}">"#).unwrap();
+            feature = name;
+        }
+        write!(
+            xml,
+            r#"<reg name="{}" bitsize="{}" regnum="{regnum}"{attributes}/>"#,
+            reg.name(),
+            reg.size() * 8
+        )
+        .unwrap();
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+///
+/// Register values exchanged with gdb
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CortexMRegs {
+    /// General purpose registers (R0-R12)
+    pub r: [u32; 13],
+    /// Stack Pointer (R13)
+    pub sp: u32,
+    /// Link Register (R14)
+    pub lr: u32,
+    /// Program Counter (R15)
+    pub pc: u32,
+    /// Combined program status register
+    pub xpsr: u32,
+    /// Main stack pointer
+    pub msp: u32,
+    /// Process stack pointer
+    pub psp: u32,
+    /// Priority mask
+    pub primask: u32,
+    /// Base priority mask
+    pub basepri: u32,
+    /// Fault mask
+    pub faultmask: u32,
+    /// Control register
+    pub control: u32,
+    /// Single precision registers (S0-S31), D(n) is S(2n+1):S(2n)
+    pub s: [u32; 32],
+    /// Floating point status and control register
+    pub fpscr: u32,
+}
+
+impl CortexMRegs {
+    fn get(&self, reg: CortexMRegId) -> u64 {
+        let value = match reg {
+            Gpr(n) => self.r[n as usize],
+            Sp => self.sp,
+            Lr => self.lr,
+            Pc => self.pc,
+            Xpsr => self.xpsr,
+            Msp => self.msp,
+            Psp => self.psp,
+            Primask => self.primask,
+            #[cfg(not(feature = "armv6m"))]
+            Basepri => self.basepri,
+            #[cfg(not(feature = "armv6m"))]
+            Faultmask => self.faultmask,
+            Control => self.control,
+            #[cfg(feature = "has-fp")]
+            D(n) => {
+                let n = usize::from(n) * 2;
+                return u64::from(self.s[n]) | u64::from(self.s[n + 1]) << 32;
+            }
+            #[cfg(feature = "has-fp")]
+            Fpscr => self.fpscr,
+        };
+        u64::from(value)
+    }
+
+    fn set(&mut self, reg: CortexMRegId, value: u64) {
+        let low = value as u32;
+        match reg {
+            Gpr(n) => self.r[n as usize] = low,
+            Sp => self.sp = low,
+            Lr => self.lr = low,
+            Pc => self.pc = low,
+            Xpsr => self.xpsr = low,
+            Msp => self.msp = low,
+            Psp => self.psp = low,
+            Primask => self.primask = low,
+            #[cfg(not(feature = "armv6m"))]
+            Basepri => self.basepri = low,
+            #[cfg(not(feature = "armv6m"))]
+            Faultmask => self.faultmask = low,
+            Control => self.control = low,
+            #[cfg(feature = "has-fp")]
+            D(n) => {
+                let n = usize::from(n) * 2;
+                self.s[n] = low;
+                self.s[n + 1] = (value >> 32) as u32;
+            }
+            #[cfg(feature = "has-fp")]
+            Fpscr => self.fpscr = low,
+        }
+    }
+}
+
+impl CortexMRegs {
+    ///
+    /// Reverse the byte order of each register. The `g` packet carries the
+    /// registers in target byte order, a big endian target swaps them after
+    /// reading and before writing the processor registers.
+    ///
+    pub fn swap_bytes(&mut self) {
+        for reg in REGISTERS {
+            let value = self.get(*reg);
+            let swapped = if reg.size() == 8 {
+                value.swap_bytes()
+            } else {
+                u64::from((value as u32).swap_bytes())
+            };
+            self.set(*reg, swapped);
+        }
+    }
+}
+
+impl Registers for CortexMRegs {
+    type ProgramCounter = u32;
+
+    fn pc(&self) -> Self::ProgramCounter {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for reg in REGISTERS {
+            let bytes = self.get(*reg).to_le_bytes();
+            for byte in &bytes[..reg.size()] {
+                write_byte(Some(*byte));
+            }
+        }
+    }
+
+    fn gdb_deserialize(&mut self, mut bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() != REGISTERS.iter().map(|reg| reg.size()).sum::<usize>() {
+            return Err(());
+        }
+        for reg in REGISTERS {
+            let (value, rest) = bytes.split_at(reg.size());
+            let mut le = [0; 8];
+            le[..value.len()].copy_from_slice(value);
+            self.set(*reg, u64::from_le_bytes(le));
+            bytes = rest;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Cortex-M profile of the enabled architecture features
+///
+pub enum CortexM {}
+
+impl Arch for CortexM {
+    type Usize = u32;
+    type Registers = CortexMRegs;
+    type RegId = CortexMRegId;
+    type BreakpointKind = ArmBreakpointKind;
+
+    fn target_description_xml() -> Option<&'static str> {
+        static XML: OnceLock<String> = OnceLock::new();
+        Some(XML.get_or_init(target_description))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_description_features() {
+        // Act
+        let xml = CortexM::target_description_xml().unwrap();
+
+        // Assert
+        assert!(xml.contains(
+            r#"<feature name="org.gnu.gdb.arm.m-profile"><reg name="r0" bitsize="32" regnum="0"/>"#
+        ));
+        assert!(xml.contains(r#"<reg name="xpsr" bitsize="32" regnum="16"/></feature>"#));
+        assert!(xml.contains(r#"<feature name="org.gnu.gdb.arm.m-system"><reg name="msp""#));
+        assert_eq!(xml.contains("faultmask"), cfg!(not(feature = "armv6m")));
+        assert_eq!(
+            xml.contains("org.gnu.gdb.arm.vfp"),
+            cfg!(feature = "has-fp")
+        );
+        assert!(xml.ends_with("</feature></target>"));
+    }
+
+    #[test]
+    fn test_registers_round_trip_through_g_packet() {
+        // Arrange
+        let mut regs = CortexMRegs {
+            pc: 0x0800_0100,
+            xpsr: 0x0100_0000,
+            control: 2,
+            ..Default::default()
+        };
+        regs.r[7] = 0x1234_5678;
+        if cfg!(feature = "has-fp") {
+            regs.s[2] = 0x3f80_0000;
+            regs.s[3] = 0x4000_0000;
+            regs.fpscr = 0x0300_0000;
+        }
+        let mut bytes = Vec::new();
+
+        // Act
+        regs.gdb_serialize(|byte| bytes.push(byte.unwrap()));
+        let mut decoded = CortexMRegs::default();
+        decoded.gdb_deserialize(&bytes).unwrap();
+
+        // Assert
+        assert_eq!(decoded, regs);
+        assert_eq!(&bytes[7 * 4..8 * 4], &[0x78, 0x56, 0x34, 0x12]);
+        assert!(decoded.gdb_deserialize(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_swapped_registers_serialize_big_endian() {
+        // Arrange
+        let mut regs = CortexMRegs {
+            pc: 0x0800_0100,
+            ..Default::default()
+        };
+        regs.r[7] = 0x1234_5678;
+        if cfg!(feature = "has-fp") {
+            regs.s[2] = 0x3f80_0000;
+            regs.s[3] = 0x4000_0000;
+        }
+        let mut bytes = Vec::new();
+
+        // Act
+        let mut swapped = regs.clone();
+        swapped.swap_bytes();
+        swapped.gdb_serialize(|byte| bytes.push(byte.unwrap()));
+
+        // Assert
+        assert_eq!(&bytes[7 * 4..8 * 4], &[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(&bytes[15 * 4..16 * 4], &[0x08, 0x00, 0x01, 0x00]);
+        #[cfg(feature = "has-fp")]
+        {
+            let d1 = Control.to_raw_id().unwrap() * 4 + 4 + 8;
+            assert_eq!(
+                &bytes[d1..d1 + 8],
+                &[0x40, 0x00, 0x00, 0x00, 0x3f, 0x80, 0x00, 0x00]
+            );
+        }
+        swapped.swap_bytes();
+        assert_eq!(swapped, regs);
+    }
+
+    #[test]
+    fn test_reg_id_sizes() {
+        // Act & Assert
+        assert_eq!(
+            CortexMRegId::from_raw_id(16).map(|(reg, size)| (reg, size.unwrap().get())),
+            Some((Xpsr, 4))
+        );
+        assert_eq!(
+            Control.to_raw_id(),
+            Some(REGISTERS.len() - if cfg!(feature = "has-fp") { 18 } else { 1 })
+        );
+        #[cfg(feature = "has-fp")]
+        assert_eq!(
+            CortexMRegId::from_raw_id(Control.to_raw_id().unwrap() + 1)
+                .map(|(reg, size)| (reg, size.unwrap().get())),
+            Some((D(0), 8))
+        );
+        assert!(CortexMRegId::from_raw_id(REGISTERS.len()).is_none());
+    }
+}
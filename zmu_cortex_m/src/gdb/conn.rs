@@ -1,87 +1,224 @@
 use gdbstub::conn::{Connection, ConnectionExt};
 use std::{
-    io::Read,
-    net::{Shutdown, TcpListener, TcpStream},
-    str,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
 };
 
-pub struct TcpConnection {
-    stream: TcpStream,
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Port of the gdb server when none is given
+pub const DEFAULT_GDB_PORT: u16 = 9001;
+
+///
+/// Address the gdb server listens on for the debugger connection
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GdbListen {
+    /// TCP socket, port 0 picks a free port
+    Tcp(SocketAddr),
+    /// Unix domain socket at the given path
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Default for GdbListen {
+    fn default() -> Self {
+        GdbListen::Tcp(SocketAddr::from(([127, 0, 0, 1], DEFAULT_GDB_PORT)))
+    }
+}
+
+///
+/// Socket waiting for the debugger to connect
+///
+pub enum GdbListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
 }
 
-impl TcpConnection {
-    pub fn new_localhost(port: u16) -> Result<TcpConnection, &'static str> {
-        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+impl GdbListener {
+    pub fn bind(listen: &GdbListen) -> io::Result<Self> {
+        match listen {
+            GdbListen::Tcp(addr) => Ok(GdbListener::Tcp(TcpListener::bind(addr)?)),
+            #[cfg(unix)]
+            GdbListen::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
 
-        let (stream, _addr) = listener
-            .accept()
-            .map_err(|_| "Error accepting socket connection")?;
+                // Replace a stale socket left behind by an earlier run
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("'{}' exists and is not a socket", path.display()),
+                        ));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                Ok(GdbListener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+        }
+    }
+
+    /// Address for the debugger, including the port picked for port 0
+    pub fn local_addr(&self) -> io::Result<String> {
+        match self {
+            GdbListener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            GdbListener::Unix(_, path) => Ok(path.display().to_string()),
+        }
+    }
 
-        stream
-            .set_read_timeout(Some(std::time::Duration::from_millis(1)))
-            .map_err(|_| "Error setting timeout")?;
-        Ok(TcpConnection { stream })
+    pub fn accept(&self) -> io::Result<GdbConnection> {
+        let stream = match self {
+            GdbListener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Stream::Tcp(stream)
+            }
+            #[cfg(unix)]
+            GdbListener::Unix(listener, _) => Stream::Unix(listener.accept()?.0),
+        };
+        Ok(GdbConnection {
+            stream,
+            lookahead: None,
+            outgoing: Vec::new(),
+        })
     }
 }
 
-impl Drop for TcpConnection {
+#[cfg(unix)]
+impl Drop for GdbListener {
     fn drop(&mut self) {
-        self.stream
-            .shutdown(Shutdown::Both)
-            .expect("shutdown failed");
+        if let GdbListener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => Read::read(stream, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Read::read(stream, buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => Write::write(stream, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Write::write(stream, buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => Write::flush(stream),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Write::flush(stream),
+        }
+    }
+}
+
+///
+/// Connection to the debugger. Checking for incoming data does not block,
+/// the byte read by the check is kept for the next read. Writes are
+/// buffered until flushed.
+///
+pub struct GdbConnection {
+    stream: Stream,
+    lookahead: Option<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Drop for GdbConnection {
+    fn drop(&mut self) {
+        // the debugger may have closed the connection already
+        let _ = self.stream.shutdown();
     }
 }
 
-impl Connection for TcpConnection {
+impl Connection for GdbConnection {
     type Error = &'static str;
 
     fn write(&mut self, b: u8) -> Result<(), &'static str> {
-        match self.stream.write(b) {
-            Ok(()) => Ok(()),
-            Err(_) => Err("socket write failed"),
-        }
+        self.outgoing.push(b);
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), &'static str> {
+        self.outgoing.extend_from_slice(buf);
+        Ok(())
     }
 
     fn flush(&mut self) -> Result<(), &'static str> {
-        match self.stream.flush() {
-            Ok(()) => Ok(()),
-            Err(_) => Err("socket flush failed"),
-        }
+        self.stream
+            .write_all(&self.outgoing)
+            .map_err(|_| "socket write failed")?;
+        self.outgoing.clear();
+        self.stream.flush().map_err(|_| "socket flush failed")
     }
 }
 
-impl ConnectionExt for TcpConnection {
+impl ConnectionExt for GdbConnection {
     fn read(&mut self) -> std::result::Result<u8, Self::Error> {
-        let mut buf: [u8; 1] = [0];
-        loop {
-            match self.stream.read_exact(&mut buf) {
-                Ok(()) => break,
-                Err(e) => match e.kind() {
-                    #[cfg(windows)]
-                    std::io::ErrorKind::TimedOut => {}
-                    #[cfg(unix)]
-                    std::io::ErrorKind::WouldBlock => {}
-                    _ => return Err("socket read failed"),
-                },
-            }
+        if let Some(byte) = self.lookahead.take() {
+            return Ok(byte);
         }
+        let mut buf: [u8; 1] = [0];
+        self.stream
+            .read_exact(&mut buf)
+            .map_err(|_| "socket read failed")?;
         Ok(buf[0])
     }
 
     fn peek(&mut self) -> std::result::Result<Option<u8>, Self::Error> {
-        let mut buf: [u8; 1] = [0];
-        loop {
-            match self.stream.peek(&mut buf) {
-                Ok(_) => break,
-                Err(e) => match e.kind() {
-                    #[cfg(windows)]
-                    std::io::ErrorKind::TimedOut => {}
-                    #[cfg(unix)]
-                    std::io::ErrorKind::WouldBlock => {}
-                    _ => return Err("socket peek failed"),
-                },
+        if self.lookahead.is_none() {
+            let mut buf: [u8; 1] = [0];
+            self.stream
+                .set_nonblocking(true)
+                .map_err(|_| "socket peek failed")?;
+            let result = self.stream.read(&mut buf);
+            self.stream
+                .set_nonblocking(false)
+                .map_err(|_| "socket peek failed")?;
+            match result {
+                Ok(1) => self.lookahead = Some(buf[0]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                _ => return Err("socket peek failed"),
             }
         }
-        Ok(Some(buf[0]))
+        Ok(self.lookahead)
     }
 }
@@ -0,0 +1,180 @@
+//!
+//! Execution history for reverse debugging
+//!
+//! The history keeps snapshots of the processor taken at regular step
+//! intervals. Going backwards restores the closest snapshot before the
+//! target step and replays the steps in between. Semihosting responses are
+//! logged so that a replay sees the same results and does not repeat the
+//! host side effects. The device is told when steps are replayed and keeps
+//! its own log of the host input, see `Peripheral::replay`.
+//!
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::Processor;
+use crate::semihosting::SemihostingCommand;
+use crate::semihosting::SemihostingResponse;
+
+/// Number of steps between snapshots
+const CHECKPOINT_INTERVAL: u64 = 10_000;
+
+/// Upper limit for the memory used by the snapshots, the oldest are dropped first
+const MAX_HISTORY_BYTES: usize = 64 * 1024 * 1024;
+
+type SemihostFunc = Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>;
+
+struct SemihostingLog {
+    /// step being executed
+    step: u64,
+    /// steps before this have run and their responses are in the log
+    frontier: u64,
+    responses: VecDeque<(u64, SemihostingResponse)>,
+    host: SemihostFunc,
+}
+
+impl SemihostingLog {
+    fn call(&mut self, command: &SemihostingCommand) -> SemihostingResponse {
+        if self.step < self.frontier {
+            return match self
+                .responses
+                .binary_search_by_key(&self.step, |(step, _)| *step)
+            {
+                Ok(index) => self.responses[index].1.clone(),
+                Err(_) => (self.host)(command),
+            };
+        }
+        let response = (self.host)(command);
+        self.responses.push_back((self.step, response.clone()));
+        response
+    }
+}
+
+///
+/// Recorded execution of the simulation
+///
+pub struct History {
+    /// number of steps run since the simulation started
+    step: u64,
+    /// processor snapshots as (step, snapshot), oldest first
+    checkpoints: VecDeque<(u64, Vec<u8>)>,
+    checkpoint_bytes: usize,
+    semihosting: Rc<RefCell<SemihostingLog>>,
+}
+
+impl History {
+    ///
+    /// Start an empty history. Returns the history and the semihosting
+    /// function to attach to the processor in place of `host`.
+    ///
+    pub fn new(host: SemihostFunc) -> (Self, SemihostFunc) {
+        let semihosting = Rc::new(RefCell::new(SemihostingLog {
+            step: 0,
+            frontier: 0,
+            responses: VecDeque::new(),
+            host,
+        }));
+        let log = Rc::clone(&semihosting);
+        let history = Self {
+            step: 0,
+            checkpoints: VecDeque::new(),
+            checkpoint_bytes: 0,
+            semihosting,
+        };
+        (
+            history,
+            Box::new(move |command| log.borrow_mut().call(command)),
+        )
+    }
+
+    /// Number of steps run
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// True while the step to run has already run before
+    pub fn replaying(&self) -> bool {
+        self.step < self.semihosting.borrow().frontier
+    }
+
+    ///
+    /// Called before the processor runs a step, takes a snapshot when one
+    /// is due.
+    ///
+    pub fn record(&mut self, processor: &Processor) {
+        let due = self
+            .checkpoints
+            .back()
+            .is_none_or(|(step, _)| self.step >= step + CHECKPOINT_INTERVAL);
+        if due {
+            let mut snapshot = Vec::new();
+            processor
+                .save_snapshot(&mut snapshot)
+                .expect("snapshot to memory");
+            self.checkpoint_bytes += snapshot.len();
+            self.checkpoints.push_back((self.step, snapshot));
+            while self.checkpoint_bytes > MAX_HISTORY_BYTES && self.checkpoints.len() > 1 {
+                let (_, dropped) = self.checkpoints.pop_front().unwrap();
+                self.checkpoint_bytes -= dropped.len();
+            }
+            let oldest = self.checkpoints[0].0;
+            let mut log = self.semihosting.borrow_mut();
+            while log
+                .responses
+                .front()
+                .is_some_and(|(step, _)| *step < oldest)
+            {
+                log.responses.pop_front();
+            }
+        }
+        self.semihosting.borrow_mut().step = self.step;
+    }
+
+    /// Called after the processor ran a step
+    pub fn advanced(&mut self) {
+        self.step += 1;
+        let mut log = self.semihosting.borrow_mut();
+        log.frontier = log.frontier.max(self.step);
+    }
+
+    /// Step of the latest snapshot at or before `step`
+    pub fn checkpoint_at_or_before(&self, step: u64) -> Option<u64> {
+        let index = self
+            .checkpoints
+            .partition_point(|(checkpoint, _)| *checkpoint <= step);
+        index.checked_sub(1).map(|index| self.checkpoints[index].0)
+    }
+
+    ///
+    /// Restore the latest snapshot at or before `step`. Returns false if
+    /// the history does not reach back to `step`.
+    ///
+    pub fn rewind(&mut self, processor: &mut Processor, step: u64) -> bool {
+        let Some(checkpoint) = self.checkpoint_at_or_before(step) else {
+            return false;
+        };
+        let (_, snapshot) = self
+            .checkpoints
+            .iter()
+            .find(|(step, _)| *step == checkpoint)
+            .unwrap();
+        processor
+            .load_snapshot(&mut snapshot.as_slice())
+            .expect("snapshot of the same processor");
+        self.step = checkpoint;
+        true
+    }
+
+    ///
+    /// Forget the recorded execution, the state was changed in a way the
+    /// history cannot replay (register or memory writes, reset).
+    ///
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.checkpoint_bytes = 0;
+        let mut log = self.semihosting.borrow_mut();
+        log.responses.clear();
+        log.frontier = self.step;
+    }
+}
@@ -2,7 +2,10 @@
 //! Gdb Module
 //!
 
+mod arch;
 mod conn;
+mod history;
+mod monitor;
 pub mod server;
 mod simulation;
 mod target;
@@ -0,0 +1,360 @@
+//!
+//! Commands for the gdb `monitor` command
+//!
+
+use std::fmt::Write;
+
+use crate::core::bits::Bits;
+use crate::core::exception::Exception;
+use crate::core::fault::FaultTrapMode;
+use crate::core::register::{BaseReg, Ipsr};
+use crate::gdb::simulation::Simulation;
+
+const HELP: &str = "\
+reset [halt|init]   reset the target, it stays halted
+halt                show the state of the halted target
+faults              show the fault status registers
+exceptions          show the pending and active exceptions
+nvic                show the enabled, pending and active interrupts
+trace [on|off]      show or switch the instruction trace
+fault-trap [all|none|default]
+fault-trap <hardfault|memmanage|busfault|usagefault> <on|off>
+                    show or change the faults that stop the target
+cycles              show the cycle and instruction counts";
+
+#[cfg(not(feature = "armv6m"))]
+const CFSR_BITS: [(usize, &str); 19] = [
+    (0, "IACCVIOL"),
+    (1, "DACCVIOL"),
+    (3, "MUNSTKERR"),
+    (4, "MSTKERR"),
+    (5, "MLSPERR"),
+    (7, "MMARVALID"),
+    (8, "IBUSERR"),
+    (9, "PRECISERR"),
+    (10, "IMPRECISERR"),
+    (11, "UNSTKERR"),
+    (12, "STKERR"),
+    (13, "LSPERR"),
+    (15, "BFARVALID"),
+    (16, "UNDEFINSTR"),
+    (17, "INVSTATE"),
+    (18, "INVPC"),
+    (19, "NOCP"),
+    (24, "UNALIGNED"),
+    (25, "DIVBYZERO"),
+];
+
+#[cfg(not(feature = "armv6m"))]
+const HFSR_BITS: [(usize, &str); 3] = [(1, "VECTTBL"), (30, "FORCED"), (31, "DEBUGEVT")];
+
+const FAULT_TRAPS: [(&str, Exception); 4] = [
+    ("hardfault", Exception::HardFault),
+    ("memmanage", Exception::MemoryManagementFault),
+    ("busfault", Exception::BusFault),
+    ("usagefault", Exception::UsageFault),
+];
+
+///
+/// Execute a monitor command, returns the text to show in the debugger.
+/// Unknown commands and bad arguments are reported in the text, an error
+/// is returned only when the target could not be reset.
+///
+pub fn execute(simulation: &mut Simulation, command: &str) -> Result<String, &'static str> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let output = match words.as_slice() {
+        [] | ["help"] => HELP.to_string(),
+        ["reset"] | ["reset", "halt" | "init"] => {
+            if simulation.reset().is_err() {
+                return Err("Error resetting target");
+            }
+            "Target reset".to_string()
+        }
+        ["halt"] => halt(simulation),
+        ["faults"] => faults(simulation),
+        ["exceptions"] => exceptions(simulation),
+        ["nvic"] => nvic(simulation),
+        ["trace", args @ ..] => trace(simulation, args),
+        ["fault-trap", args @ ..] => fault_trap(simulation, args),
+        ["cycles"] => format!(
+            "cycles: {}\ninstructions: {}",
+            simulation.processor.cycle_count, simulation.processor.instruction_count
+        ),
+        _ => format!("Unknown command: {command:?}"),
+    };
+    Ok(output)
+}
+
+fn exception_name(exception_number: usize) -> String {
+    match Exception::from(exception_number) {
+        Exception::Interrupt { n } => format!("IRQ{n}"),
+        exception => format!("{exception:?}"),
+    }
+}
+
+fn halt(simulation: &Simulation) -> String {
+    let processor = &simulation.processor;
+    let mode = match processor.psr.get_isr_number() {
+        0 => "Thread".to_string(),
+        isr => format!("Handler {}", exception_name(isr)),
+    };
+    format!(
+        "target halted due to debug-request, current mode: {mode}\n\
+         xPSR: {:#010x} pc: {:#010x} msp: {:#010x} psp: {:#010x}",
+        processor.psr.value,
+        processor.pc,
+        processor.get_msp(),
+        processor.get_psp()
+    )
+}
+
+#[cfg(not(feature = "armv6m"))]
+fn decode(value: u32, bits: &[(usize, &str)]) -> String {
+    let mut names = String::new();
+    for (_, name) in bits.iter().filter(|(bit, _)| value.get_bit(*bit)) {
+        names.push(' ');
+        names.push_str(name);
+    }
+    names
+}
+
+#[cfg(not(feature = "armv6m"))]
+fn faults(simulation: &Simulation) -> String {
+    let processor = &simulation.processor;
+    format!(
+        "CFSR:  {:#010x}{}\nHFSR:  {:#010x}{}\nMMFAR: {:#010x}\nBFAR:  {:#010x}",
+        processor.cfsr,
+        decode(processor.cfsr, &CFSR_BITS),
+        processor.hfsr,
+        decode(processor.hfsr, &HFSR_BITS),
+        processor.mmfar,
+        processor.bfar
+    )
+}
+
+#[cfg(feature = "armv6m")]
+fn faults(_simulation: &Simulation) -> String {
+    "Fault status registers are not implemented on ARMv6-M".to_string()
+}
+
+fn exceptions(simulation: &Simulation) -> String {
+    let processor = &simulation.processor;
+    let mut numbers: Vec<usize> = processor
+        .exceptions
+        .iter()
+        .filter(|(_, state)| state.pending || state.active)
+        .map(|(number, _)| *number)
+        .collect();
+    numbers.sort_unstable();
+
+    let mut output = format!("execution priority: {}", processor.execution_priority);
+    if numbers.is_empty() {
+        output.push_str("\nno pending or active exceptions");
+    }
+    for number in numbers {
+        let state = &processor.exceptions[&number];
+        let _ = write!(
+            output,
+            "\n{:<24} priority {:>4}{}{}",
+            exception_name(number),
+            state.priority,
+            if state.pending { " pending" } else { "" },
+            if state.active { " active" } else { "" }
+        );
+    }
+    output
+}
+
+fn nvic(simulation: &Simulation) -> String {
+    let processor = &simulation.processor;
+    let mut output = String::new();
+    for (index, enabled) in processor.nvic_interrupt_enabled.iter().enumerate() {
+        let pending = processor.nvic_interrupt_pending[index];
+        let asserted = processor.nvic_interrupt_asserted[index];
+        for bit in 0..32 {
+            let irqn = index * 32 + bit;
+            let Some(state) = processor.exceptions.get(&(irqn + 16)) else {
+                continue;
+            };
+            let flags = [
+                (enabled.get_bit(bit), " enabled"),
+                (pending.get_bit(bit), " pending"),
+                (state.active, " active"),
+                (asserted.get_bit(bit), " asserted"),
+            ];
+            if !flags[..3].iter().any(|(set, _)| *set) {
+                continue;
+            }
+            let _ = write!(output, "IRQ{irqn:<4} priority {:>4}", state.priority);
+            for (_, flag) in flags.iter().filter(|(set, _)| *set) {
+                output.push_str(flag);
+            }
+            output.push('\n');
+        }
+    }
+    if output.is_empty() {
+        return "no enabled, pending or active interrupts".to_string();
+    }
+    output.pop();
+    output
+}
+
+fn trace(simulation: &mut Simulation, args: &[&str]) -> String {
+    match args {
+        [] => {}
+        ["on"] => simulation.trace_enabled = true,
+        ["off"] => simulation.trace_enabled = false,
+        _ => return "usage: trace [on|off]".to_string(),
+    }
+    if simulation.trace.is_none() {
+        return "no instruction trace attached".to_string();
+    }
+    format!(
+        "trace {}",
+        if simulation.trace_enabled {
+            "on"
+        } else {
+            "off"
+        }
+    )
+}
+
+fn fault_trap(simulation: &mut Simulation, args: &[&str]) -> String {
+    let mut mode = simulation.processor.get_fault_trap_mode();
+    match args {
+        [] => {}
+        ["all"] => mode = FaultTrapMode::all(),
+        ["none"] => mode = FaultTrapMode::none(),
+        ["default"] => mode = FaultTrapMode::default(),
+        [name, state @ ("on" | "off")] => {
+            let Some((_, exception)) = FAULT_TRAPS.iter().find(|(n, _)| n == name) else {
+                return format!("Unknown fault: {name:?}");
+            };
+            mode.set_trap(*exception, *state == "on");
+        }
+        _ => return "usage: fault-trap [all|none|default|<fault> <on|off>]".to_string(),
+    }
+    simulation.processor.fault_trap_mode(mode);
+
+    let trapped: Vec<&str> = FAULT_TRAPS
+        .iter()
+        .filter(|(_, exception)| mode.should_trap(*exception))
+        .map(|(name, _)| *name)
+        .collect();
+    if trapped.is_empty() {
+        "fault trap: none".to_string()
+    } else {
+        format!("fault trap: {}", trapped.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::region::MemoryRegion;
+    use crate::semihosting::{SemihostingCommand, SemihostingResponse};
+
+    fn simulation() -> Simulation {
+        let code: [u8; 10] = [
+            0x00, 0x10, 0x00, 0x20, // initial SP
+            0x09, 0x00, 0x00, 0x00, // reset vector
+            0xfe, 0xe7, // 0x08: b 0x08
+        ];
+        let mut flash = MemoryRegion::rom("flash", 0, 0x100);
+        flash.load(0, &code);
+        let semihost =
+            Box::new(|_: &SemihostingCommand| SemihostingResponse::SysClock { result: Ok(0) });
+        Simulation::new(vec![flash], None, semihost, FaultTrapMode::default(), false).unwrap()
+    }
+
+    #[test]
+    fn test_cycles_and_halt_report_the_processor_state() {
+        // Arrange
+        let mut simulation = simulation();
+        simulation.step();
+        simulation.step();
+
+        // Act
+        let cycles = execute(&mut simulation, "cycles").unwrap();
+        let halt = execute(&mut simulation, "halt").unwrap();
+
+        // Assert
+        assert_eq!(
+            cycles,
+            format!(
+                "cycles: {}\ninstructions: 2",
+                simulation.processor.cycle_count
+            )
+        );
+        assert_eq!(
+            halt,
+            "target halted due to debug-request, current mode: Thread\n\
+             xPSR: 0x01000000 pc: 0x00000008 msp: 0x20001000 psp: 0x00000000"
+        );
+    }
+
+    #[test]
+    fn test_fault_trap_toggles_single_faults() {
+        // Arrange
+        let mut simulation = simulation();
+
+        // Act
+        let none = execute(&mut simulation, "fault-trap none").unwrap();
+        let bus = execute(&mut simulation, "fault-trap busfault on").unwrap();
+        let unknown = execute(&mut simulation, "fault-trap nmi on").unwrap();
+
+        // Assert
+        assert_eq!(none, "fault trap: none");
+        let mode = simulation.processor.get_fault_trap_mode();
+        assert!(!mode.should_trap(Exception::HardFault));
+        #[cfg(not(feature = "armv6m"))]
+        {
+            assert_eq!(bus, "fault trap: busfault");
+            assert!(mode.should_trap(Exception::BusFault));
+        }
+        #[cfg(feature = "armv6m")]
+        let _ = bus;
+        assert_eq!(unknown, "Unknown fault: \"nmi\"");
+    }
+
+    #[test]
+    fn test_trace_and_unknown_commands() {
+        // Arrange
+        let mut simulation = simulation();
+
+        // Act
+        let detached = execute(&mut simulation, "trace on").unwrap();
+        simulation.trace = Some(Box::new(|_| {}));
+        let off = execute(&mut simulation, "trace off").unwrap();
+        let unknown = execute(&mut simulation, "frobnicate").unwrap();
+
+        // Assert
+        assert_eq!(detached, "no instruction trace attached");
+        assert_eq!(off, "trace off");
+        assert!(!simulation.trace_enabled);
+        assert_eq!(unknown, "Unknown command: \"frobnicate\"");
+    }
+
+    #[test]
+    fn test_exceptions_and_nvic_list_pending_interrupts() {
+        // Arrange
+        let mut simulation = simulation();
+        simulation.processor.nvic_interrupt_enabled[0] = 1 << 3;
+        simulation.processor.nvic_interrupt_pending[0] = 1 << 3;
+        simulation
+            .processor
+            .exceptions
+            .get_mut(&19)
+            .unwrap()
+            .pending = true;
+
+        // Act
+        let exceptions = execute(&mut simulation, "exceptions").unwrap();
+        let nvic = execute(&mut simulation, "nvic").unwrap();
+
+        // Assert
+        assert!(exceptions.contains("IRQ3"));
+        assert!(exceptions.ends_with("pending"));
+        assert_eq!(nvic, "IRQ3    priority    0 enabled pending");
+    }
+}
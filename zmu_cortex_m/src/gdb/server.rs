@@ -11,20 +11,23 @@ use gdbstub::stub::GdbStub;
 use gdbstub::stub::SingleThreadStopReason;
 use gdbstub::stub::run_blocking;
 use gdbstub::target::Target;
+use gdbstub::target::ext::base::reverse_exec::ReplayLogPosition;
 
 use crate::DeviceBus;
-use crate::MemoryMapConfig;
 use crate::core::fault::{FaultContext, FaultTrapMode};
-use crate::gdb::conn;
+use crate::gdb::conn::{GdbConnection, GdbListener};
 use crate::gdb::simulation::SimulationEvent;
 use crate::gdb::simulation::SimulationRunEvent;
-use conn::TcpConnection;
+pub use crate::gdb::simulation::TraceFunc;
+use crate::memory::region::MemoryRegion;
 
 use crate::gdb::target::ZmuTarget;
 
 use crate::semihosting::SemihostingCommand;
 use crate::semihosting::SemihostingResponse;
 
+pub use crate::gdb::conn::{DEFAULT_GDB_PORT, GdbListen};
+
 ///
 /// The gdb Server
 ///
@@ -36,7 +39,7 @@ pub struct GdbServer {
 /// Errors that can occur in the GDB server
 pub enum GdbServerError {
     /// Error related to the connection
-    #[error("Connection error")]
+    #[error("connection error: {0}")]
     ConnectionError(String),
     /// Error related to the target
     #[error("Target error")]
@@ -51,44 +54,55 @@ impl GdbServer {
     ///
     /// # Arguments
     ///
-    /// * `code` - The binary code to run in the emulator
+    /// * `memory` - Memory regions holding the program, replacing the default
+    ///   flash and RAM regions of the same name
     /// * `semihost_func` - A function that will be called when a semihosting command is issued
-    /// * `map` - The memory map configuration
-    /// * `flash_size` - The size of the flash memory
+    /// * `big_endian` - Data accesses are big endian (BE8)
     pub fn new(
-        code: &[u8],
+        memory: Vec<MemoryRegion>,
         device: Option<DeviceBus>,
         semihost_func: Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>,
-        map: Option<MemoryMapConfig>,
-        flash_size: usize,
         fault_trap_mode: FaultTrapMode,
+        big_endian: bool,
     ) -> Result<GdbServer, GdbServerError> {
-        let target = ZmuTarget::new(
-            code,
-            device,
-            semihost_func,
-            map,
-            flash_size,
-            fault_trap_mode,
-        )
-        .map_err(|err| match err {
-            crate::system::simulation::SimulationError::FaultTrap { context } => {
-                GdbServerError::FaultTrap(context)
-            }
-        })?;
+        let target = ZmuTarget::new(memory, device, semihost_func, fault_trap_mode, big_endian)
+            .map_err(|err| match err {
+                crate::system::simulation::SimulationError::FaultTrap { context } => {
+                    GdbServerError::FaultTrap(context)
+                }
+                crate::system::simulation::SimulationError::Snapshot { .. } => {
+                    GdbServerError::TargetError
+                }
+            })?;
 
         Ok(GdbServer { target })
     }
 
-    /// Start the GDB Server. This function will block until the GDB client disconnects.
-    /// or program execution is complete..
-    pub fn start(&mut self) -> Result<u32, GdbServerError> {
-        println!("GDB Server listening on port 9001");
+    ///
+    /// Attach an instruction trace, `monitor trace on|off` switches it on
+    /// and off. `enabled` is the initial state.
+    ///
+    pub fn trace(&mut self, trace: TraceFunc, enabled: bool) -> &mut Self {
+        self.target.trace(trace, enabled);
+        self
+    }
+
+    /// Start the GDB Server on the given address. This function will block until
+    /// the GDB client disconnects or program execution is complete.
+    ///
+    /// The address the debugger should connect to is printed on stdout, also
+    /// when a free TCP port was picked for port 0.
+    pub fn start(&mut self, listen: &GdbListen) -> Result<u32, GdbServerError> {
         let mut exit_code = 0;
-        let conn = match conn::TcpConnection::new_localhost(9001) {
-            Ok(conn) => conn,
-            Err(e) => return Err(GdbServerError::ConnectionError(e.to_string())),
-        };
+        let connection_error = |e: std::io::Error| GdbServerError::ConnectionError(e.to_string());
+        let listener = GdbListener::bind(listen).map_err(connection_error)?;
+        println!(
+            "GDB Server listening on {}",
+            listener.local_addr().map_err(connection_error)?
+        );
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        let conn = listener.accept().map_err(connection_error)?;
+        drop(listener);
 
         let gdb = GdbStub::new(conn);
 
@@ -140,7 +154,7 @@ enum EventLoop {}
 
 impl run_blocking::BlockingEventLoop for EventLoop {
     type Target = ZmuTarget;
-    type Connection = TcpConnection;
+    type Connection = GdbConnection;
     type StopReason = SingleThreadStopReason<u32>;
 
     #[allow(clippy::type_complexity)]
@@ -186,12 +200,21 @@ impl run_blocking::BlockingEventLoop for EventLoop {
                         kind: WatchKind::Read,
                         addr,
                     },
+                    SimulationEvent::WatchAccess(addr) => SingleThreadStopReason::Watch {
+                        tid: (),
+                        kind: WatchKind::ReadWrite,
+                        addr,
+                    },
                     SimulationEvent::FaultTrap(_) => {
                         SingleThreadStopReason::Signal(Signal::SIGTRAP)
                     }
                     SimulationEvent::Finalized(exit_code) => {
                         SingleThreadStopReason::Exited(exit_code as u8)
                     }
+                    SimulationEvent::ReplayBegin => SingleThreadStopReason::ReplayLog {
+                        tid: None,
+                        pos: ReplayLogPosition::Begin,
+                    },
                 };
 
                 Ok(run_blocking::Event::TargetStopped(stop_reason))
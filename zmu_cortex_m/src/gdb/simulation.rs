@@ -3,15 +3,20 @@
 //!
 
 use crate::DeviceBus;
-use crate::MemoryMapConfig;
 use crate::Processor;
 use crate::core::fault::{FaultContext, FaultTrapMode};
 use crate::core::register::BaseReg;
 use crate::core::reset::Reset;
+use crate::core::watchpoint::WatchKind;
 use crate::executor::Executor;
+use crate::gdb::history::History;
+use crate::memory::region::MemoryRegion;
 use crate::semihosting::SemihostingCommand;
 use crate::semihosting::SemihostingResponse;
 
+/// Number of steps run between checks for data from the debugger
+const POLL_INTERVAL: u64 = 16 * 1024;
+
 ///
 /// Cortex System simulation framework
 ///
@@ -20,12 +25,21 @@ pub struct Simulation {
     pub processor: Processor,
     /// Simulation Execution mode
     pub exec_mode: SimulationExecMode,
-    /// Watchpoints
-    pub watchpoints: Vec<u32>,
     /// Breakpoints
     pub breakpoints: Vec<u32>,
+    /// Recorded execution for reverse stepping
+    history: History,
+    /// The device was told that recorded steps are replayed
+    replaying: bool,
+    /// Instruction trace output
+    pub trace: Option<TraceFunc>,
+    /// Trace the instructions when stepping forward
+    pub trace_enabled: bool,
 }
 
+/// Function called with the processor after each traced instruction
+pub type TraceFunc = Box<dyn FnMut(&Processor) + 'static>;
+
 ///
 /// A simulation event.
 ///
@@ -38,14 +52,17 @@ pub enum SimulationEvent {
     /// A breakpoint was hit
     Break,
     /// A write watchpoint was hit
-    #[allow(dead_code)]
     WatchWrite(u32),
     /// A read watchpoint was hit
     WatchRead(u32),
+    /// An access watchpoint was hit
+    WatchAccess(u32),
     /// Execution stopped because a fault trap was hit.
     FaultTrap(FaultContext),
     /// Simulation is finalized
     Finalized(u32),
+    /// Reverse execution reached the beginning of the recorded history
+    ReplayBegin,
 }
 
 ///
@@ -58,6 +75,10 @@ pub enum SimulationExecMode {
     Continue,
     /// Range Step execution (from start to end)
     RangeStep(u32, u32),
+    /// Step one instruction backwards
+    ReverseStep,
+    /// Run backwards to the previous breakpoint
+    ReverseContinue,
 }
 
 impl Simulation {
@@ -65,19 +86,21 @@ impl Simulation {
     /// Prepare a new simulation instance
     ///
     pub fn new(
-        code: &[u8],
+        memory: Vec<MemoryRegion>,
         device: Option<DeviceBus>,
         semihost_func: Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>,
-        map: Option<MemoryMapConfig>,
-        flash_size: usize,
         fault_trap_mode: FaultTrapMode,
+        big_endian: bool,
     ) -> Result<Simulation, crate::system::simulation::SimulationError> {
         let mut processor = Processor::new();
+        let (history, semihost_func) = History::new(semihost_func);
         processor.device(device);
         processor.semihost(Some(semihost_func));
-        processor.memory_map(map);
         processor.fault_trap_mode(fault_trap_mode);
-        processor.flash_memory(flash_size, code);
+        processor.big_endian_data(big_endian);
+        for region in memory {
+            processor.memory_region(region);
+        }
         processor.cache_instructions();
         processor.running = true;
         match processor.reset() {
@@ -92,8 +115,11 @@ impl Simulation {
         Ok(Simulation {
             processor,
             exec_mode: SimulationExecMode::Step,
-            watchpoints: Vec::new(),
             breakpoints: Vec::new(),
+            history,
+            replaying: false,
+            trace: None,
+            trace_enabled: false,
         })
     }
 
@@ -104,17 +130,11 @@ impl Simulation {
             SimulationExecMode::Continue => {
                 let mut cycles = 0;
                 loop {
-                    // The poll_incoming_data function checks if there is any data available
-                    // to be read from the connection to the GDB server.
-                    // Currently, each call to poll_incoming_data blocks until either data
-                    // is available or a timeout occurs. This provides a simple mechanism
-                    // to detect if the GDB client has sent any commands to the server.
-                    // However, this approach is inefficient because the simulation is
-                    // effectively stalled until either the GDB client sends data or the
-                    // timeout expires.
-                    // To reduce the performance impact, we only poll for incoming data
-                    // every 1024 cycles.
-                    if cycles % 1024 == 0 && poll_incoming_data() {
+                    // The debugger may send data at any time, e.g. to interrupt
+                    // the execution. poll_incoming_data does not block, but it
+                    // costs system calls, so it is called only between batches
+                    // of steps.
+                    if cycles % POLL_INTERVAL == 0 && poll_incoming_data() {
                         return SimulationRunEvent::IncomingData;
                     }
                     cycles += 1;
@@ -125,10 +145,14 @@ impl Simulation {
                     }
                 }
             }
+            SimulationExecMode::ReverseStep => SimulationRunEvent::Event(self.reverse_step()),
+            SimulationExecMode::ReverseContinue => {
+                SimulationRunEvent::Event(self.reverse_continue())
+            }
             SimulationExecMode::RangeStep(start, end) => {
                 let mut cycles = 0;
                 loop {
-                    if cycles % 1024 == 0 && poll_incoming_data() {
+                    if cycles % POLL_INTERVAL == 0 && poll_incoming_data() {
                         return SimulationRunEvent::IncomingData;
                     }
                     cycles += 1;
@@ -152,6 +176,7 @@ impl Simulation {
     /// Reset the simulation
     ///
     pub fn reset(&mut self) -> Result<(), crate::system::simulation::SimulationError> {
+        self.history.clear();
         match self.processor.reset() {
             Ok(()) => Ok(()),
             Err(fault) => Err(crate::system::simulation::SimulationError::from_fault(
@@ -165,20 +190,116 @@ impl Simulation {
     /// Single step the simulation
     ///
     pub fn step(&mut self) -> SimulationEvent {
+        let executing = self.processor.running && !self.processor.sleeping;
+        self.advance();
+        if executing
+            && self.trace_enabled
+            && let Some(trace) = self.trace.as_mut()
+        {
+            trace(&self.processor);
+        }
+        self.event()
+    }
+
+    ///
+    /// Forget the recorded execution after the debugger changed the
+    /// processor state
+    ///
+    pub fn state_changed(&mut self) {
+        self.history.clear();
+    }
+
+    ///
+    /// Step one instruction backwards
+    ///
+    pub fn reverse_step(&mut self) -> SimulationEvent {
+        match self.history.step().checked_sub(1) {
+            Some(step) if self.replay_to(step) => SimulationEvent::DoneStep,
+            _ => SimulationEvent::ReplayBegin,
+        }
+    }
+
+    ///
+    /// Run backwards until the state where forward execution last stopped
+    /// on a breakpoint, watchpoint or fault trap
+    ///
+    pub fn reverse_continue(&mut self) -> SimulationEvent {
+        let current = self.history.step();
+        let mut end = current;
+        while let Some(start) = end
+            .checked_sub(1)
+            .and_then(|step| self.history.checkpoint_at_or_before(step))
+        {
+            self.replay_to(start);
+            let mut stop = None;
+            while self.history.step() < end && self.advance() {
+                match self.event() {
+                    SimulationEvent::DoneStep | SimulationEvent::Finalized(_) => {}
+                    event if self.history.step() < current => {
+                        stop = Some((self.history.step(), event));
+                    }
+                    _ => {}
+                }
+            }
+            if let Some((step, event)) = stop {
+                self.replay_to(step);
+                return event;
+            }
+            end = start;
+        }
+        self.replay_to(end);
+        SimulationEvent::ReplayBegin
+    }
+
+    /// Restore the state after `step` steps from the history
+    fn replay_to(&mut self, step: u64) -> bool {
+        if !self.history.rewind(&mut self.processor, step) {
+            return false;
+        }
+        while self.history.step() < step && self.advance() {
+            self.processor.take_pending_fault_trap();
+            self.processor.take_watchpoint_hit();
+        }
+        true
+    }
+
+    /// Run one step, returns false if the processor has stopped
+    fn advance(&mut self) -> bool {
+        if !self.processor.running && !self.processor.sleeping {
+            return false;
+        }
+        self.history.record(&self.processor);
+        let replaying = self.history.replaying();
+        if replaying != self.replaying {
+            self.replaying = replaying;
+            if let Some(device) = self.processor.device.as_mut() {
+                device.replay(replaying);
+            }
+        }
         if self.processor.running {
             self.processor.step();
-        } else if self.processor.sleeping {
-            self.processor.step_sleep();
+        } else {
+            self.processor.step_sleep_until_event();
         }
+        self.history.advanced();
+        true
+    }
+
+    fn event(&mut self) -> SimulationEvent {
+        let watchpoint_hit = self.processor.take_watchpoint_hit();
         if let Some(context) = self.processor.take_pending_fault_trap() {
             return SimulationEvent::FaultTrap(context);
         }
+        if let Some(hit) = watchpoint_hit {
+            return match hit.kind {
+                WatchKind::Write => SimulationEvent::WatchWrite(hit.address),
+                WatchKind::Read => SimulationEvent::WatchRead(hit.address),
+                WatchKind::Access => SimulationEvent::WatchAccess(hit.address),
+            };
+        }
         if self.breakpoints.contains(&self.processor.get_pc()) {
             return SimulationEvent::Break;
         }
-        if self.watchpoints.contains(&self.processor.get_pc()) {
-            return SimulationEvent::WatchRead(self.processor.get_pc());
-        }
         if !self.processor.running && !self.processor.sleeping {
             SimulationEvent::Finalized(self.processor.exit_code)
         } else {
@@ -196,3 +317,349 @@ pub enum SimulationRunEvent {
     /// A simulation event
     Event(SimulationEvent),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, Peripheral};
+    use crate::core::fault::Fault;
+    use crate::core::register::Reg;
+    use crate::core::watchpoint::Watchpoint;
+    use crate::snapshot::{StateReader, StateWriter};
+    use std::cell::{Cell, RefCell};
+    use std::io;
+    use std::rc::Rc;
+
+    /// Loop calling `SYS_CLOCK` and counting in r1, the host call count is shared
+    fn clock_loop() -> (Simulation, Rc<Cell<u32>>) {
+        let code: [u8; 16] = [
+            0x00, 0x10, 0x00, 0x20, // initial SP
+            0x09, 0x00, 0x00, 0x00, // reset vector
+            0x10, 0x20, // 0x08: movs r0, #0x10
+            0xab, 0xbe, // 0x0a: bkpt 0xab
+            0x01, 0x31, // 0x0c: adds r1, #1
+            0xfb, 0xe7, // 0x0e: b 0x08
+        ];
+        let mut flash = MemoryRegion::rom("flash", 0, 0x100);
+        flash.load(0, &code);
+        let calls = Rc::new(Cell::new(0));
+        let host_calls = Rc::clone(&calls);
+        let semihost = Box::new(move |_: &SemihostingCommand| {
+            host_calls.set(host_calls.get() + 1);
+            SemihostingResponse::SysClock {
+                result: Ok(host_calls.get()),
+            }
+        });
+        let simulation =
+            Simulation::new(vec![flash], None, semihost, FaultTrapMode::default(), false).unwrap();
+        (simulation, calls)
+    }
+
+    #[test]
+    fn test_reverse_step_restores_previous_state() {
+        // Arrange
+        let (mut simulation, calls) = clock_loop();
+        for _ in 0..9 {
+            simulation.step();
+        }
+        let expected = (
+            simulation.processor.get_r(Reg::R0),
+            simulation.processor.get_r(Reg::R1),
+            simulation.processor.get_pc(),
+        );
+        simulation.step();
+
+        // Act
+        let event = simulation.reverse_step();
+
+        // Assert
+        assert!(matches!(event, SimulationEvent::DoneStep));
+        let state = (
+            simulation.processor.get_r(Reg::R0),
+            simulation.processor.get_r(Reg::R1),
+            simulation.processor.get_pc(),
+        );
+        assert_eq!(state, expected);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn test_replay_reuses_semihosting_responses() {
+        // Arrange
+        let (mut simulation, calls) = clock_loop();
+        for _ in 0..12 {
+            simulation.step();
+        }
+        for _ in 0..6 {
+            simulation.reverse_step();
+        }
+
+        // Act
+        for _ in 0..6 {
+            simulation.step();
+        }
+
+        // Assert: r0 holds the logged response of the third call
+        assert_eq!(calls.get(), 3);
+        assert_eq!(simulation.processor.get_r(Reg::R0), 3);
+        simulation.step();
+        simulation.step();
+        assert_eq!(calls.get(), 4);
+    }
+
+    /// Transmits the bytes written to its data register to the host
+    struct SerialDevice {
+        sent: Rc<RefCell<Vec<u8>>>,
+        count: u32,
+        replaying: bool,
+    }
+
+    impl Bus for SerialDevice {
+        fn read32(&mut self, _addr: u32) -> Result<u32, Fault> {
+            Ok(self.count)
+        }
+
+        fn read16(&self, _addr: u32) -> Result<u16, Fault> {
+            Err(Fault::Preciserr)
+        }
+
+        fn read8(&self, _addr: u32) -> Result<u8, Fault> {
+            Err(Fault::Preciserr)
+        }
+
+        fn write32(&mut self, _addr: u32, value: u32) -> Result<(), Fault> {
+            if !self.replaying {
+                self.sent.borrow_mut().push(value as u8);
+            }
+            self.count += 1;
+            Ok(())
+        }
+
+        fn write16(&mut self, _addr: u32, _value: u16) -> Result<(), Fault> {
+            Err(Fault::Preciserr)
+        }
+
+        fn write8(&mut self, _addr: u32, _value: u8) -> Result<(), Fault> {
+            Err(Fault::Preciserr)
+        }
+
+        fn in_range(&self, addr: u32) -> bool {
+            (0x4000_0000..0x4000_0004).contains(&addr)
+        }
+    }
+
+    impl Peripheral for SerialDevice {
+        fn next_event(&self) -> Option<u32> {
+            None
+        }
+
+        fn save_state(&self, state: &mut StateWriter) -> io::Result<()> {
+            state.write_u32(self.count)
+        }
+
+        fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+            self.count = state.read_u32()?;
+            Ok(())
+        }
+
+        fn replay(&mut self, replaying: bool) {
+            self.replaying = replaying;
+        }
+    }
+
+    /// Loop counting in r1 and transmitting the count to a serial device
+    fn serial_loop() -> (Simulation, Rc<RefCell<Vec<u8>>>) {
+        let code: [u8; 20] = [
+            0x00, 0x10, 0x00, 0x20, // initial SP
+            0x09, 0x00, 0x00, 0x00, // reset vector
+            0x01, 0x4a, // 0x08: ldr r2, [pc, #4]
+            0x01, 0x31, // 0x0a: adds r1, #1
+            0x11, 0x60, // 0x0c: str r1, [r2]
+            0xfc, 0xe7, // 0x0e: b 0x0a
+            0x00, 0x00, 0x00, 0x40, // 0x10: serial device address
+        ];
+        let mut flash = MemoryRegion::rom("flash", 0, 0x100);
+        flash.load(0, &code);
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let device = SerialDevice {
+            sent: Rc::clone(&sent),
+            count: 0,
+            replaying: false,
+        };
+        let semihost =
+            Box::new(|_: &SemihostingCommand| SemihostingResponse::SysClock { result: Ok(0) });
+        let simulation = Simulation::new(
+            vec![flash],
+            Some(Box::new(device)),
+            semihost,
+            FaultTrapMode::default(),
+            false,
+        )
+        .unwrap();
+        (simulation, sent)
+    }
+
+    #[test]
+    fn test_replay_does_not_transmit_again() {
+        // Arrange
+        let (mut simulation, sent) = serial_loop();
+        for _ in 0..9 {
+            simulation.step();
+        }
+        let expected = (
+            simulation.processor.get_r(Reg::R1),
+            simulation.processor.get_pc(),
+            simulation.processor.read32(0x4000_0000).unwrap(),
+        );
+        for _ in 0..3 {
+            simulation.step();
+        }
+        assert_eq!(*sent.borrow(), [1, 2, 3, 4]);
+
+        // Act
+        for _ in 0..3 {
+            simulation.reverse_step();
+        }
+
+        // Assert
+        let state = (
+            simulation.processor.get_r(Reg::R1),
+            simulation.processor.get_pc(),
+            simulation.processor.read32(0x4000_0000).unwrap(),
+        );
+        assert_eq!(state, expected);
+        assert_eq!(*sent.borrow(), [1, 2, 3, 4]);
+
+        // Act: run forward past the recorded steps
+        for _ in 0..9 {
+            simulation.step();
+        }
+
+        // Assert
+        assert_eq!(*sent.borrow(), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_reverse_continue_stops_at_previous_breakpoint() {
+        // Arrange
+        let (mut simulation, _) = clock_loop();
+        for _ in 0..20 {
+            simulation.step();
+        }
+        simulation.breakpoints.push(0x0c);
+
+        // Act
+        let event = simulation.reverse_continue();
+
+        // Assert
+        assert!(matches!(event, SimulationEvent::Break));
+        assert_eq!(simulation.processor.get_pc(), 0x0c);
+        assert_eq!(simulation.processor.get_r(Reg::R1), 4);
+
+        // Act
+        simulation.reverse_continue();
+
+        // Assert
+        assert_eq!(simulation.processor.get_r(Reg::R1), 3);
+    }
+
+    #[test]
+    fn test_reverse_at_start_of_history() {
+        // Arrange
+        let (mut simulation, _) = clock_loop();
+        simulation.step();
+
+        // Act & Assert
+        assert!(matches!(
+            simulation.reverse_step(),
+            SimulationEvent::DoneStep
+        ));
+        assert_eq!(simulation.processor.get_pc(), 0x08);
+        assert!(matches!(
+            simulation.reverse_step(),
+            SimulationEvent::ReplayBegin
+        ));
+        assert!(matches!(
+            simulation.reverse_continue(),
+            SimulationEvent::ReplayBegin
+        ));
+    }
+
+    #[test]
+    fn test_store_to_watched_address_stops_after_the_store() {
+        // Arrange
+        let code: [u8; 16] = [
+            0x00, 0x01, 0x00, 0x20, // initial SP
+            0x09, 0x00, 0x00, 0x00, // reset vector
+            0x20, 0x21, // 0x08: movs r1, #0x20
+            0x09, 0x06, // 0x0a: lsls r1, r1, #24
+            0x08, 0x61, // 0x0c: str r0, [r1, #0x10]
+            0xfe, 0xe7, // 0x0e: b 0x0e
+        ];
+        let mut flash = MemoryRegion::rom("flash", 0, 0x100);
+        flash.load(0, &code);
+        let ram = MemoryRegion::ram("ram", 0x2000_0000, 0x100);
+        let semihost =
+            Box::new(|_: &SemihostingCommand| SemihostingResponse::SysClock { result: Ok(0) });
+        let mut simulation = Simulation::new(
+            vec![flash, ram],
+            None,
+            semihost,
+            FaultTrapMode::default(),
+            false,
+        )
+        .unwrap();
+        simulation.processor.add_watchpoint(Watchpoint {
+            address: 0x2000_0012,
+            len: 2,
+            kind: WatchKind::Write,
+        });
+
+        // Act
+        let events = [simulation.step(), simulation.step(), simulation.step()];
+
+        // Assert
+        assert!(matches!(events[1], SimulationEvent::DoneStep));
+        assert!(matches!(
+            events[2],
+            SimulationEvent::WatchWrite(0x2000_0012)
+        ));
+        assert_eq!(simulation.processor.get_pc(), 0x0e);
+    }
+
+    #[test]
+    fn test_debugger_write_to_flash_replaces_cached_instruction() {
+        // Arrange
+        let (mut simulation, _) = clock_loop();
+
+        // Act
+        simulation
+            .processor
+            .debug_write(0x0c, &[0x05, 0x31]) // adds r1, #5
+            .unwrap();
+        for _ in 0..3 {
+            simulation.step();
+        }
+
+        // Assert
+        assert_eq!(simulation.processor.get_r(Reg::R1), 5);
+    }
+
+    #[test]
+    fn test_trace_is_called_only_while_enabled() {
+        // Arrange
+        let (mut simulation, _) = clock_loop();
+        let traced = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&traced);
+        simulation.trace = Some(Box::new(move |_| counter.set(counter.get() + 1)));
+
+        // Act
+        simulation.step();
+        simulation.trace_enabled = true;
+        simulation.step();
+        simulation.step();
+
+        // Assert
+        assert_eq!(traced.get(), 2);
+    }
+}
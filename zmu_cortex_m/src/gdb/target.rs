@@ -3,16 +3,25 @@ use gdbstub::outputln;
 use gdbstub::target;
 use gdbstub::target::Target;
 use gdbstub::target::TargetResult;
+use gdbstub::target::ext::breakpoints::WatchKind;
 use gdbstub::target::ext::monitor_cmd::MonitorCmd;
 
 use log::debug;
 
 use crate::DeviceBus;
-use crate::MemoryMapConfig;
 use crate::bus::Bus;
+use crate::core::bits::Bits;
+use crate::core::exception::ExceptionHandling;
 use crate::core::fault::FaultTrapMode;
+use crate::core::watchpoint::{self, Watchpoint};
+use crate::gdb::arch::{CortexM, CortexMRegs};
+use crate::gdb::monitor;
 use crate::gdb::simulation;
+use crate::memory::region::MemoryRegion;
 
+use gdbstub::target::ext::base::reverse_exec::{
+    ReverseCont, ReverseContOps, ReverseStep, ReverseStepOps,
+};
 use gdbstub::target::ext::base::singlethread::SingleThreadBase;
 use gdbstub::target::ext::base::singlethread::SingleThreadRangeStepping;
 use gdbstub::target::ext::base::singlethread::SingleThreadRangeSteppingOps;
@@ -23,6 +32,7 @@ use gdbstub::target::ext::base::singlethread::SingleThreadSingleStepOps;
 
 use crate::gdb::simulation::SimulationEvent;
 use crate::gdb::simulation::SimulationRunEvent;
+use crate::gdb::simulation::TraceFunc;
 
 use crate::core::register::BaseReg;
 use crate::core::register::Reg;
@@ -36,20 +46,18 @@ pub struct ZmuTarget {
 
 impl ZmuTarget {
     pub fn new(
-        code: &[u8],
+        memory: Vec<MemoryRegion>,
         device: Option<DeviceBus>,
         semihost_func: Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>,
-        map: Option<MemoryMapConfig>,
-        flash_size: usize,
         fault_trap_mode: FaultTrapMode,
+        big_endian: bool,
     ) -> Result<ZmuTarget, crate::system::simulation::SimulationError> {
         let simulation = simulation::Simulation::new(
-            code,
+            memory,
             device,
             semihost_func,
-            map,
-            flash_size,
             fault_trap_mode,
+            big_endian,
         )?;
         Ok(ZmuTarget { simulation })
     }
@@ -61,10 +69,15 @@ impl ZmuTarget {
     pub fn step(&mut self) -> SimulationEvent {
         self.simulation.step()
     }
+
+    pub fn trace(&mut self, trace: TraceFunc, enabled: bool) {
+        self.simulation.trace = Some(trace);
+        self.simulation.trace_enabled = enabled;
+    }
 }
 
 impl Target for ZmuTarget {
-    type Arch = gdbstub_arch::arm::Armv4t;
+    type Arch = CortexM;
     type Error = &'static str;
 
     #[inline(always)]
@@ -96,30 +109,73 @@ impl Target for ZmuTarget {
 }
 
 impl SingleThreadBase for ZmuTarget {
-    fn read_registers(
-        &mut self,
-        regs: &mut gdbstub_arch::arm::reg::ArmCoreRegs,
-    ) -> TargetResult<(), Self> {
+    fn read_registers(&mut self, regs: &mut CortexMRegs) -> TargetResult<(), Self> {
         debug!("> read_registers");
-        regs.r = self.simulation.processor.r0_12;
-        regs.sp = self.simulation.processor.get_r(Reg::SP);
-        regs.lr = self.simulation.processor.lr;
-        regs.pc = self.simulation.processor.pc;
-        regs.cpsr = self.simulation.processor.cfsr;
+        let processor = &self.simulation.processor;
+        regs.r = processor.r0_12;
+        regs.sp = processor.get_r(Reg::SP);
+        regs.lr = processor.lr;
+        regs.pc = processor.pc;
+        regs.xpsr = processor.psr.value;
+        regs.msp = processor.msp;
+        regs.psp = processor.psp;
+        regs.primask = u32::from(processor.primask);
+        #[cfg(not(feature = "armv6m"))]
+        {
+            regs.basepri = u32::from(processor.basepri);
+            regs.faultmask = u32::from(processor.faultmask);
+        }
+        regs.control = 0;
+        regs.control.set_bit(0, processor.control.n_priv);
+        regs.control.set_bit(1, processor.control.sp_sel);
+        #[cfg(feature = "has-fp")]
+        regs.control.set_bit(2, processor.control.fpca);
+        regs.s = processor.fp_regs;
+        regs.fpscr = processor.fpscr;
+        if processor.big_endian() {
+            regs.swap_bytes();
+        }
         Ok(())
     }
 
     #[inline(never)]
-    fn write_registers(
-        &mut self,
-        regs: &gdbstub_arch::arm::reg::ArmCoreRegs,
-    ) -> TargetResult<(), Self> {
+    fn write_registers(&mut self, regs: &CortexMRegs) -> TargetResult<(), Self> {
         debug!("> write_registers");
-        self.simulation.processor.r0_12 = regs.r;
-        self.simulation.processor.set_r(Reg::SP, regs.sp);
-        self.simulation.processor.lr = regs.lr;
-        self.simulation.processor.pc = regs.pc;
-        self.simulation.processor.cfsr = regs.cpsr;
+        let processor = &mut self.simulation.processor;
+        let mut swapped;
+        let regs = if processor.big_endian() {
+            swapped = regs.clone();
+            swapped.swap_bytes();
+            &swapped
+        } else {
+            regs
+        };
+        let sp = processor.get_r(Reg::SP);
+        processor.r0_12 = regs.r;
+        processor.lr = regs.lr;
+        processor.pc = regs.pc;
+        processor.psr.value = regs.xpsr;
+        processor.msp = regs.msp;
+        processor.psp = regs.psp;
+        processor.primask = regs.primask.get_bit(0);
+        #[cfg(not(feature = "armv6m"))]
+        {
+            processor.basepri = regs.basepri.get_bits(0..8) as u8;
+            processor.faultmask = regs.faultmask.get_bit(0);
+        }
+        processor.control.n_priv = regs.control.get_bit(0);
+        processor.control.sp_sel = regs.control.get_bit(1);
+        #[cfg(feature = "has-fp")]
+        {
+            processor.control.fpca = regs.control.get_bit(2);
+        }
+        if regs.sp != sp {
+            processor.set_r(Reg::SP, regs.sp);
+        }
+        processor.fp_regs = regs.s;
+        processor.fpscr = regs.fpscr;
+        processor.execution_priority = processor.get_execution_priority();
+        self.simulation.state_changed();
         Ok(())
     }
 
@@ -138,19 +194,11 @@ impl SingleThreadBase for ZmuTarget {
 
     #[inline(never)]
     fn write_addrs(&mut self, start_addr: u32, data: &[u8]) -> TargetResult<(), Self> {
-        for (i, &byte) in data.iter().enumerate() {
-            match self
-                .simulation
-                .processor
-                .write8(start_addr + i as u32, byte)
-            {
-                Ok(()) => (),
-                Err(_) => {
-                    return Err(target::TargetError::NonFatal);
-                }
-            }
-        }
-        Ok(())
+        self.simulation.state_changed();
+        self.simulation
+            .processor
+            .debug_write(start_addr, data)
+            .map_err(|_| target::TargetError::NonFatal)
     }
 
     #[inline(always)]
@@ -176,6 +224,32 @@ impl SingleThreadResume for ZmuTarget {
     fn support_range_step(&mut self) -> Option<SingleThreadRangeSteppingOps<'_, Self>> {
         Some(self)
     }
+
+    #[inline(always)]
+    fn support_reverse_step(&mut self) -> Option<ReverseStepOps<'_, (), Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_reverse_cont(&mut self) -> Option<ReverseContOps<'_, (), Self>> {
+        Some(self)
+    }
+}
+
+impl ReverseStep<()> for ZmuTarget {
+    #[inline(never)]
+    fn reverse_step(&mut self, _tid: ()) -> Result<(), Self::Error> {
+        self.simulation.exec_mode = simulation::SimulationExecMode::ReverseStep;
+        Ok(())
+    }
+}
+
+impl ReverseCont<()> for ZmuTarget {
+    #[inline(never)]
+    fn reverse_cont(&mut self) -> Result<(), Self::Error> {
+        self.simulation.exec_mode = simulation::SimulationExecMode::ReverseContinue;
+        Ok(())
+    }
 }
 
 impl SingleThreadSingleStep for ZmuTarget {
@@ -201,6 +275,13 @@ impl target::ext::breakpoints::Breakpoints for ZmuTarget {
     ) -> Option<target::ext::breakpoints::SwBreakpointOps<'_, Self>> {
         Some(self)
     }
+
+    #[inline(always)]
+    fn support_hw_watchpoint(
+        &mut self,
+    ) -> Option<target::ext::breakpoints::HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
 }
 
 impl target::ext::breakpoints::SwBreakpoint for ZmuTarget {
@@ -225,6 +306,48 @@ impl target::ext::breakpoints::SwBreakpoint for ZmuTarget {
     }
 }
 
+fn watchpoint(addr: u32, len: u32, kind: WatchKind) -> Watchpoint {
+    Watchpoint {
+        address: addr,
+        len,
+        kind: match kind {
+            WatchKind::Write => watchpoint::WatchKind::Write,
+            WatchKind::Read => watchpoint::WatchKind::Read,
+            WatchKind::ReadWrite => watchpoint::WatchKind::Access,
+        },
+    }
+}
+
+impl target::ext::breakpoints::HwWatchpoint for ZmuTarget {
+    #[inline(never)]
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: u32,
+        len: u32,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        debug!("> add_hw_watchpoint {addr:#x} {len} {kind:?}");
+        self.simulation
+            .processor
+            .add_watchpoint(watchpoint(addr, len, kind));
+        Ok(true)
+    }
+
+    #[inline(never)]
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: u32,
+        len: u32,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        debug!("> remove_hw_watchpoint {addr:#x} {len} {kind:?}");
+        Ok(self
+            .simulation
+            .processor
+            .remove_watchpoint(watchpoint(addr, len, kind)))
+    }
+}
+
 impl MonitorCmd for ZmuTarget {
     #[inline(never)]
     fn handle_monitor_cmd(
@@ -234,17 +357,15 @@ impl MonitorCmd for ZmuTarget {
     ) -> Result<(), Self::Error> {
         debug!("> handle_monitor_cmd {cmd:?}");
         let cmd = core::str::from_utf8(cmd).map_err(|_| "Invalid UTF-8")?;
-        match cmd {
-            "reset" => {
-                if self.simulation.reset().is_ok() {
-                    outputln!(out, "Target reset");
-                } else {
-                    outputln!(out, "Error resetting target");
-                    return Err("Error resetting target");
+        match monitor::execute(&mut self.simulation, cmd) {
+            Ok(output) => {
+                for line in output.lines() {
+                    outputln!(out, "{}", line);
                 }
             }
-            _ => {
-                outputln!(out, "Unknown command: {:?}", cmd);
+            Err(e) => {
+                outputln!(out, "{}", e);
+                return Err(e);
             }
         }
         Ok(())
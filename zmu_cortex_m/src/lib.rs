@@ -36,19 +36,26 @@ pub mod gdb;
 pub mod memory;
 pub mod peripheral;
 pub mod semihosting;
+pub mod snapshot;
 pub mod system;
 
 use crate::core::instruction::instruction_size;
 
+use crate::bus::IrqLine;
+
 use crate::core::exception::Exception;
 use crate::core::fault::{Fault, FaultContext, FaultStatusContext, FaultTrapMode};
 use crate::core::fetch::Fetch;
 use crate::core::instruction::Instruction;
+use crate::core::memory_trace::MemoryTraceFunc;
+use crate::core::monitor::ExclusiveAccess;
 use crate::core::register::{Apsr, BaseReg, Control, PSR, Reg};
+use crate::core::trace_event::EventTraceFunc;
+use crate::core::watchpoint::{Watchpoint, WatchpointHit};
 
-use crate::memory::flash::FlashMemory;
-use crate::memory::map::MemoryMapConfig;
-use crate::memory::ram::RAM;
+use crate::memory::region::{MemoryRegion, MemoryRegions, RegionKind};
+use crate::peripheral::mpu::MPU_REGION_COUNT;
+use crate::peripheral::scb::AIRCR_ENDIANNESS;
 use crate::semihosting::SemihostingCommand;
 use crate::semihosting::SemihostingResponse;
 
@@ -70,10 +77,12 @@ pub enum ProcessorMode {
 type SemihostingCall = Option<Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse>>;
 
 /// External device/peripheral bus attachment owned by the caller.
-pub type DeviceBus = Box<dyn crate::bus::Bus + 'static>;
+pub type DeviceBus = Box<dyn crate::bus::Peripheral + 'static>;
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 enum CachedInstruction {
+    /// not decoded yet or invalidated by a write, decoded on the next fetch
+    Invalid,
     Decoded {
         instruction: Instruction,
         instruction_size: usize,
@@ -164,13 +173,30 @@ pub struct Processor {
     itstate: u8,
 
     ///
-    /// flash memory data
+    /// local exclusive monitor, tagged by LDREX
+    ///
+    exclusive_monitor: Option<ExclusiveAccess>,
+
+    ///
+    /// debugger data watchpoints and the first one hit during the current step
+    ///
+    watchpoints: Vec<Watchpoint>,
+    watchpoint_hit: Option<WatchpointHit>,
+
+    ///
+    /// called with each data access when the memory trace is enabled
     ///
-    pub code: FlashMemory,
+    memory_trace_func: Option<MemoryTraceFunc>,
+
+    ///
+    /// called with each processor event when the event trace is enabled
+    ///
+    event_trace_func: Option<EventTraceFunc>,
+
     ///
-    /// ram data
+    /// flash, ram and other memory regions
     ///
-    pub sram: RAM,
+    memory: MemoryRegions,
 
     pub cpuid: u32,
     pub icsr: u32,
@@ -209,6 +235,10 @@ pub struct Processor {
 
     pub nvic_interrupt_enabled: [u32; 16],
     pub nvic_interrupt_pending: [u32; 16],
+    ///
+    /// current level of the external interrupt lines driven by the device
+    ///
+    pub nvic_interrupt_asserted: [u32; 16],
 
     pub dwt_ctrl: u32,
     pub dwt_cyccnt: u32,
@@ -217,6 +247,11 @@ pub struct Processor {
     pub syst_cvr: u32,
     pub syst_csr: u32,
 
+    pub mpu_ctrl: u32,
+    pub mpu_rnr: u32,
+    pub mpu_rbar: [u32; MPU_REGION_COUNT],
+    pub mpu_rasr: [u32; MPU_REGION_COUNT],
+
     ///
     /// file handle to which to write ITM data
     ///
@@ -227,7 +262,21 @@ pub struct Processor {
     ///
     semihost_func: SemihostingCall,
 
-    instruction_cache: Vec<CachedInstruction>,
+    ///
+    /// decoded instructions of each executable ROM and RAM region, indexed
+    /// by region. ROM is decoded up front, RAM on the first fetch.
+    ///
+    instruction_cache: Vec<Vec<CachedInstruction>>,
+    /// an instruction has been decoded lazily, data writes must invalidate it
+    lazy_instructions_cached: bool,
+    /// `step_block` is running, system register accesses end the block
+    in_block: bool,
+    /// cycles of the running block not yet counted by `SysTick` and DWT
+    block_cycles: u32,
+    /// region whose instruction cache is held by the running block
+    block_region: Option<usize>,
+    /// cache entries of `block_region` written during the block
+    block_invalidated: Vec<std::ops::Range<usize>>,
 
     fault_trap_mode: FaultTrapMode,
     pending_fault_trap: Option<FaultContext>,
@@ -235,9 +284,8 @@ pub struct Processor {
 
     pub last_pc: u32,
 
-    mem_map: Option<MemoryMapConfig>,
-
     device: Option<DeviceBus>,
+    irq_lines: Vec<IrqLine>,
 }
 
 #[cfg(feature = "has-fp")]
@@ -261,6 +309,13 @@ pub(crate) const FP_MVFR2_RESET: u32 = 0x0000_0040;
 #[cfg(feature = "fpv4-sp-d16")]
 pub(crate) const FP_MVFR2_RESET: u32 = 0x0000_0000;
 
+/// Number of external interrupts supported by the NVIC
+#[cfg(feature = "armv6m")]
+pub const NVIC_IRQ_COUNT: usize = 32;
+/// Number of external interrupts supported by the NVIC
+#[cfg(not(feature = "armv6m"))]
+pub const NVIC_IRQ_COUNT: usize = 240;
+
 fn make_default_exception_priorities() -> HashMap<usize, ExceptionState> {
     let mut priorities = HashMap::new();
 
@@ -312,7 +367,7 @@ fn make_default_exception_priorities() -> HashMap<usize, ExceptionState> {
         ExceptionState::new(Exception::SysTick, 0),
     );
 
-    for irqn in 0..32 {
+    for irqn in 0..NVIC_IRQ_COUNT {
         let irq = Exception::Interrupt { n: irqn };
         priorities.insert(irq.into(), ExceptionState::new(irq, 0));
     }
@@ -320,6 +375,13 @@ fn make_default_exception_priorities() -> HashMap<usize, ExceptionState> {
     priorities
 }
 
+fn make_default_memory() -> MemoryRegions {
+    let mut memory = MemoryRegions::default();
+    memory.add(MemoryRegion::rom("flash", 0, 64 * 1024));
+    memory.add(MemoryRegion::ram("ram", 0x2000_0000, 128 * 1024).with_fill(0xcd));
+    memory
+}
+
 type SemihostingStaticCall =
     Option<Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>>;
 
@@ -349,10 +411,7 @@ impl Processor {
             msp: 0,
             psp: 0,
             lr: 0,
-            // TODO make flash size configurable
-            code: FlashMemory::new(65536, &vec![0; 65536].into_boxed_slice()),
-            // TODO make RAM size configurable
-            sram: RAM::new_with_fill(0x2000_0000, 128 * 1024, 0xcd),
+            memory: make_default_memory(),
             itm_file: None,
             sleeping: false,
             running: true,
@@ -363,6 +422,11 @@ impl Processor {
             execution_priority: 0,
             pending_exception_count: 0,
             itstate: 0,
+            exclusive_monitor: None,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            memory_trace_func: None,
+            event_trace_func: None,
             semihost_func: None,
             cpuid: 0,
             icsr: 0,
@@ -394,7 +458,7 @@ impl Processor {
             #[cfg(feature = "has-fp")]
             mvfr2: FP_MVFR2_RESET,
 
-            ictr: 0,
+            ictr: (NVIC_IRQ_COUNT.div_ceil(32) - 1) as u32,
             actlr: 0,
 
             dwt_ctrl: 0x4000_0000,
@@ -402,31 +466,57 @@ impl Processor {
 
             nvic_interrupt_enabled: [0; 16],
             nvic_interrupt_pending: [0; 16],
+            nvic_interrupt_asserted: [0; 16],
             syst_rvr: 0,
             syst_cvr: 0,
             syst_csr: 0,
+            mpu_ctrl: 0,
+            mpu_rnr: 0,
+            mpu_rbar: [0; MPU_REGION_COUNT],
+            mpu_rasr: [0; MPU_REGION_COUNT],
             instruction_cache: Vec::new(),
+            lazy_instructions_cached: false,
+            in_block: false,
+            block_cycles: 0,
+            block_region: None,
+            block_invalidated: Vec::new(),
             fault_trap_mode: FaultTrapMode::hardfault(),
             pending_fault_trap: None,
             pending_fault_status: None,
             last_pc: 0,
-            mem_map: None,
             device: None,
+            irq_lines: Vec::new(),
         }
     }
 
-    /// Configure flash memory
+    /// Configure flash memory at address 0 holding `code`
     pub fn flash_memory<'a>(&'a mut self, flash_size: usize, code: &[u8]) -> &'a mut Self {
-        self.code = FlashMemory::new(flash_size, code);
-        self
+        let mut flash = MemoryRegion::rom("flash", 0, flash_size as u32);
+        flash.load(0, code);
+        self.memory_region(flash)
     }
 
-    /// Configure memory mapping
-    pub fn memory_map(&mut self, map: Option<MemoryMapConfig>) -> &mut Self {
-        self.mem_map = map;
+    /// Configure RAM memory location and size
+    pub fn ram_memory(&mut self, start_address: u32, size: usize) -> &mut Self {
+        self.memory_region(MemoryRegion::ram("ram", start_address, size as u32).with_fill(0xcd))
+    }
+
+    ///
+    /// Add a memory region, replacing the region with the same name.
+    /// The default regions are "flash" (64 KiB ROM at 0) and "ram"
+    /// (128 KiB at `0x2000_0000`).
+    ///
+    pub fn memory_region(&mut self, region: MemoryRegion) -> &mut Self {
+        self.memory.add(region);
+        self.instruction_cache.clear();
         self
     }
 
+    /// Configured memory regions
+    pub fn memory_regions(&self) -> &MemoryRegions {
+        &self.memory
+    }
+
     /// Attach or replace the external device/peripheral bus implementation.
     pub fn device(&mut self, device: Option<DeviceBus>) -> &mut Self {
         self.device = device;
@@ -445,6 +535,22 @@ impl Processor {
         self
     }
 
+    ///
+    /// Configure big endian (BE8) data accesses, fixed at reset and reported
+    /// in `AIRCR.ENDIANNESS`. Instruction fetches and the private peripheral
+    /// bus stay little endian.
+    ///
+    pub fn big_endian_data(&mut self, big_endian: bool) -> &mut Self {
+        if big_endian {
+            self.aircr |= AIRCR_ENDIANNESS;
+        } else {
+            self.aircr &= !AIRCR_ENDIANNESS;
+        }
+        self.memory.set_big_endian(big_endian);
+        self.instruction_cache.clear();
+        self
+    }
+
     /// Configure fault-trap behavior.
     pub fn fault_trap_mode(&mut self, mode: FaultTrapMode) -> &mut Self {
         self.fault_trap_mode = mode;
@@ -474,36 +580,113 @@ impl Processor {
     /// Pre cache (decode) instructions to speed up simulation
     ///
     pub fn cache_instructions(&mut self) {
-        // pre-cache the decoded instructions
+        self.instruction_cache.clear();
+        self.lazy_instructions_cached = false;
+        for index in 0..self.memory.len() {
+            let region = self.memory.region(index);
+            let mut cache = Vec::new();
+            if region.kind == RegionKind::Rom && region.permissions.execute {
+                let (base, size) = (region.base, region.size);
+                let mut offset = 0;
+                while offset < size {
+                    cache.push(self.cached_instruction(base.wrapping_add(offset)));
+                    offset += 2;
+                }
+            } else if region.kind == RegionKind::Ram && region.permissions.execute {
+                cache = vec![CachedInstruction::Invalid; region.size.div_ceil(2) as usize];
+            }
+            self.instruction_cache.push(cache);
+        }
+    }
+
+    fn cached_instruction(&mut self, pc: u32) -> CachedInstruction {
+        match self.fetch(pc) {
+            Ok(thumb) => {
+                let instruction = self.decode(thumb);
+                CachedInstruction::Decoded {
+                    instruction,
+                    instruction_size: instruction_size(&instruction),
+                }
+            }
+            Err(fault) => {
+                let status = self.take_pending_fault_status();
+                CachedInstruction::FetchFault { fault, status }
+            }
+        }
+    }
+
+    ///
+    /// Invalidate the cached instructions overlapping `len` bytes at
+    /// `address`, they are decoded again when fetched
+    ///
+    #[inline(always)]
+    pub(crate) fn invalidate_instructions(&mut self, address: u32, len: usize) {
+        let Some((region, offset)) = self.memory.resolve(address) else {
+            return;
+        };
+        if let RegionKind::BitBand { target } = self.memory.region(region).kind {
+            // the write changes a bit in the word of the bit-band region
+            self.invalidate_instructions(target.wrapping_add(offset >> 5) & !3, 4);
+            return;
+        }
+        let remaining = u64::from(self.memory.region(region).size - offset);
+        if len as u64 > remaining {
+            // the rest of the range is in other regions
+            self.invalidate_instructions(
+                address.wrapping_add(remaining as u32),
+                len - remaining as usize,
+            );
+        }
+        // a 32 bit instruction starting on the previous halfword changes too
+        let first = (offset >> 1).saturating_sub(1) as usize;
+        let last = (u64::from(offset) + len as u64).div_ceil(2) as usize;
+        if self.block_region == Some(region) {
+            // the cache of the region is held by the running block
+            let entries = self.memory.region(region).size.div_ceil(2) as usize;
+            self.block_invalidated.push(first..last.min(entries));
+        } else if let Some(cache) = self
+            .instruction_cache
+            .get_mut(region)
+            .filter(|cache| !cache.is_empty())
         {
-            let mut pc = 0;
-
-            while pc < (self.code.len() as u32) {
-                match self.fetch(pc) {
-                    Ok(thumb) => {
-                        let instruction = self.decode(thumb);
-                        self.instruction_cache.push(CachedInstruction::Decoded {
-                            instruction,
-                            instruction_size: instruction_size(&instruction),
-                        });
-                    }
-                    Err(fault) => {
-                        let status = self.take_pending_fault_status();
-                        self.instruction_cache
-                            .push(CachedInstruction::FetchFault { fault, status });
-                    }
+            let last = last.min(cache.len());
+            cache[first..last].fill(CachedInstruction::Invalid);
+        }
+        if offset < 2 && address >= 2 {
+            // the previous halfword is in another region
+            self.invalidate_instructions((address & !1) - 2, 1);
+        }
+    }
+
+    ///
+    /// Write memory on behalf of a debugger. ROM, including flash the
+    /// program cannot write, and RAM are written directly, other addresses
+    /// through the bus.
+    ///
+    pub fn debug_write(&mut self, address: u32, data: &[u8]) -> Result<(), Fault> {
+        let mut written = 0;
+        while written < data.len() {
+            let at = address.wrapping_add(written as u32);
+            match self.memory.program(at, &data[written..]) {
+                0 => {
+                    bus::Bus::write8(self, at, data[written])?;
+                    written += 1;
+                }
+                n => {
+                    self.invalidate_instructions(at, n);
+                    written += n;
                 }
-                pc += 2;
             }
         }
+        Ok(())
     }
 
     ///
-    /// `BigEndian()` returns true if the processor is big endian
-    /// TODO: check architectures where this is supported
+    /// `BigEndian()` returns true if the processor is big endian,
+    /// `AIRCR.ENDIANNESS` is 1
+    ///
     pub fn big_endian(&self) -> bool {
-        // TODO: should be AIRCR.ENDIANNESS==1
-        false
+        self.aircr & AIRCR_ENDIANNESS != 0
     }
 
     /// Get the IT state register value
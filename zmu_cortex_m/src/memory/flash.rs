@@ -31,6 +31,11 @@ impl FlashMemory {
     pub fn is_empty(&self) -> bool {
         self.data.len() == 0
     }
+
+    /// Mutable contents of the memory, for programming the flash
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
 }
 
 impl Bus for FlashMemory {
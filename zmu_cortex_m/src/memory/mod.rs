@@ -4,5 +4,5 @@
 //!
 
 pub mod flash;
-pub mod map;
 pub mod ram;
+pub mod region;
@@ -33,6 +33,16 @@ impl RAM {
             data,
         }
     }
+
+    /// Contents of the memory
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Mutable contents of the memory
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
 }
 
 impl Bus for RAM {
@@ -0,0 +1,895 @@
+//!
+//! Table of configurable memory regions
+//!
+//! A region is described with [`MemoryRegion`] and added to the processor
+//! with `Processor::memory_region`. The table then owns the storage of the
+//! ROM and RAM regions and resolves aliases and MMIO windows for the bus.
+//!
+
+use crate::DeviceBus;
+use crate::bus::Bus;
+use crate::core::fault::Fault;
+use crate::memory::flash::FlashMemory;
+use crate::memory::ram::RAM;
+use std::cell::Cell;
+
+///
+/// Access rights of a memory region
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct Permissions {
+    /// Data reads are allowed
+    pub read: bool,
+    /// Data writes are allowed
+    pub write: bool,
+    /// Instruction fetches are allowed
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// Read and execute, the default for ROM
+    pub const RX: Self = Self {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    /// Read and write, the default for MMIO
+    pub const RW: Self = Self {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    /// Read, write and execute, the default for RAM
+    pub const RWX: Self = Self {
+        read: true,
+        write: true,
+        execute: true,
+    };
+}
+
+///
+/// What backs the addresses of a memory region
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum RegionKind {
+    /// Read-only memory holding the program image, writes fault
+    Rom,
+    /// Read-write memory
+    Ram,
+    /// Accesses are forwarded to `target + (address - base)`
+    Alias {
+        /// Start address of the aliased memory
+        target: u32,
+    },
+    /// Accesses are forwarded to the attached device, a precise bus error
+    /// is raised if the device does not respond
+    Mmio,
+    /// Each word maps to a bit of the memory at `target`: bit `n` of the
+    /// byte at `target + k` is the word at `base + 32 * k + 4 * n`
+    BitBand {
+        /// Start address of the bit-band region
+        target: u32,
+    },
+}
+
+///
+/// Description of a named memory region
+///
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    /// Name of the region, adding a region with the same name replaces it
+    pub name: String,
+    /// Start address
+    pub base: u32,
+    /// Size in bytes
+    pub size: u32,
+    /// Type of the region
+    pub kind: RegionKind,
+    /// Access rights
+    pub permissions: Permissions,
+    /// Initial value of bytes not covered by loaded contents
+    pub fill: u8,
+    contents: Vec<(u32, Vec<u8>)>,
+}
+
+impl MemoryRegion {
+    fn new(name: &str, base: u32, size: u32, kind: RegionKind, permissions: Permissions) -> Self {
+        Self {
+            name: name.to_string(),
+            base,
+            size,
+            kind,
+            permissions,
+            fill: 0,
+            contents: Vec::new(),
+        }
+    }
+
+    /// Read-only, executable memory
+    pub fn rom(name: &str, base: u32, size: u32) -> Self {
+        Self::new(name, base, size, RegionKind::Rom, Permissions::RX)
+    }
+
+    /// Read-write, executable memory
+    pub fn ram(name: &str, base: u32, size: u32) -> Self {
+        Self::new(name, base, size, RegionKind::Ram, Permissions::RWX)
+    }
+
+    /// Mirror of the memory at `target`
+    pub fn alias(name: &str, base: u32, size: u32, target: u32) -> Self {
+        Self::new(
+            name,
+            base,
+            size,
+            RegionKind::Alias { target },
+            Permissions::RWX,
+        )
+    }
+
+    /// Window served by the attached device
+    pub fn mmio(name: &str, base: u32, size: u32) -> Self {
+        Self::new(name, base, size, RegionKind::Mmio, Permissions::RW)
+    }
+
+    ///
+    /// Bit-band alias of the memory at `target`. Reading a word of the
+    /// alias returns the bit as 0 or 1, writing it sets the bit to bit 0
+    /// of the value with a read-modify-write of the target.
+    ///
+    pub fn bit_band(name: &str, base: u32, size: u32, target: u32) -> Self {
+        Self::new(
+            name,
+            base,
+            size,
+            RegionKind::BitBand { target },
+            Permissions::RW,
+        )
+    }
+
+    /// Override the default access rights
+    #[must_use]
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Set the initial value of the bytes not covered by loaded contents
+    #[must_use]
+    pub fn with_fill(mut self, fill: u8) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Check if `address` is within the region
+    pub fn contains(&self, address: u32) -> bool {
+        address.wrapping_sub(self.base) < self.size
+    }
+
+    ///
+    /// Place `data` at `address` in the initial contents of a ROM or RAM
+    /// region. Returns false if the data does not fit in the region.
+    ///
+    pub fn load(&mut self, address: u32, data: &[u8]) -> bool {
+        let offset = address.wrapping_sub(self.base);
+        let fits = offset < self.size && data.len() as u64 <= u64::from(self.size - offset);
+        if fits && matches!(self.kind, RegionKind::Rom | RegionKind::Ram) {
+            self.contents.push((offset, data.to_vec()));
+            return true;
+        }
+        fits && data.is_empty()
+    }
+
+    fn storage(&mut self) -> Storage {
+        let contents = std::mem::take(&mut self.contents);
+        match self.kind {
+            RegionKind::Rom => {
+                let mut data = vec![self.fill; self.size as usize];
+                for (offset, bytes) in contents {
+                    let offset = offset as usize;
+                    data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                }
+                Storage::Rom(FlashMemory::new(data.len(), &data))
+            }
+            RegionKind::Ram => {
+                let mut ram = RAM::new_with_fill(0, self.size as usize, self.fill);
+                for (offset, bytes) in contents {
+                    for (address, byte) in (offset..).zip(bytes) {
+                        ram.write8(address, byte).unwrap();
+                    }
+                }
+                Storage::Ram(ram)
+            }
+            RegionKind::BitBand { target } => Storage::BitBand(target),
+            RegionKind::Alias { .. } | RegionKind::Mmio => Storage::Device,
+        }
+    }
+}
+
+enum Storage {
+    Rom(FlashMemory),
+    Ram(RAM),
+    /// MMIO served by the device, aliases are never accessed directly
+    Device,
+    /// Bit-band alias of the memory at the address
+    BitBand(u32),
+}
+
+/// What the bus needs to serve an access, kept compact for the lookup
+struct Slot {
+    base: u32,
+    size: u32,
+    /// target address of an alias region
+    alias: Option<u32>,
+    permissions: Permissions,
+    storage: Storage,
+}
+
+///
+/// The memory regions of a processor. Regions added later take precedence
+/// where regions overlap.
+///
+#[derive(Default)]
+pub struct MemoryRegions {
+    regions: Vec<MemoryRegion>,
+    slots: Vec<Slot>,
+    /// no two regions overlap, the last hit can be tried first
+    disjoint: bool,
+    last_code: Cell<usize>,
+    last_data: Cell<usize>,
+    /// halfwords and words of flash and RAM are stored big endian (BE8)
+    big_endian: bool,
+}
+
+impl MemoryRegions {
+    ///
+    /// Add a region, replacing the region with the same name if there is one.
+    ///
+    pub fn add(&mut self, mut region: MemoryRegion) {
+        let slot = Slot {
+            base: region.base,
+            size: region.size,
+            alias: match region.kind {
+                RegionKind::Alias { target } => Some(target),
+                _ => None,
+            },
+            permissions: region.permissions,
+            storage: region.storage(),
+        };
+        if let Some(index) = self
+            .regions
+            .iter()
+            .position(|existing| existing.name == region.name)
+        {
+            self.regions[index] = region;
+            self.slots[index] = slot;
+        } else {
+            self.regions.push(region);
+            self.slots.push(slot);
+        }
+        self.disjoint = self.slots.iter().enumerate().all(|(n, slot)| {
+            self.slots[n + 1..].iter().all(|other| {
+                u64::from(slot.base) + u64::from(slot.size) <= u64::from(other.base)
+                    || u64::from(other.base) + u64::from(other.size) <= u64::from(slot.base)
+            })
+        });
+    }
+
+    /// Descriptions of the regions in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.regions.iter()
+    }
+
+    /// Number of regions in the table
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Check if the table has no regions
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    #[inline(always)]
+    fn find(&self, address: u32) -> Option<usize> {
+        self.slots
+            .iter()
+            .rposition(|slot| address.wrapping_sub(slot.base) < slot.size)
+    }
+
+    ///
+    /// Find the region serving `address`, following an alias to its target.
+    /// Returns the index of the region and the offset within it.
+    ///
+    #[inline(always)]
+    pub(crate) fn resolve(&self, address: u32) -> Option<(usize, u32)> {
+        self.resolve_from(address, &self.last_data)
+    }
+
+    ///
+    /// Same as `resolve`, for instruction fetches.
+    ///
+    #[inline(always)]
+    pub(crate) fn resolve_code(&self, address: u32) -> Option<(usize, u32)> {
+        self.resolve_from(address, &self.last_code)
+    }
+
+    #[inline(always)]
+    fn resolve_from(&self, address: u32, last: &Cell<usize>) -> Option<(usize, u32)> {
+        let index = match self.slots.get(last.get()) {
+            Some(slot) if self.disjoint && address.wrapping_sub(slot.base) < slot.size => {
+                last.get()
+            }
+            _ => {
+                let index = self.find(address)?;
+                last.set(index);
+                index
+            }
+        };
+        let slot = &self.slots[index];
+        match slot.alias {
+            Some(target) => {
+                let address = target.wrapping_add(address - slot.base);
+                let index = self.find(address)?;
+                let target = &self.slots[index];
+                // aliases of aliases are not followed
+                target
+                    .alias
+                    .is_none()
+                    .then_some((index, address - target.base))
+            }
+            None => Some((index, address - slot.base)),
+        }
+    }
+
+    /// Check if instructions can be fetched from `address`
+    #[inline(always)]
+    pub fn executable(&self, address: u32) -> bool {
+        self.resolve_code(address).is_some_and(|(index, _)| {
+            let slot = &self.slots[index];
+            slot.permissions.execute && matches!(slot.storage, Storage::Rom(_) | Storage::Ram(_))
+        })
+    }
+
+    /// Check if `address` is served by a region
+    pub fn in_range(&self, address: u32) -> bool {
+        self.resolve(address).is_some()
+    }
+
+    /// The region at `index` returned by `resolve`
+    pub(crate) fn region(&self, index: usize) -> &MemoryRegion {
+        &self.regions[index]
+    }
+
+    /// Contents of the RAM region at `index`, None for other kinds of regions
+    pub(crate) fn ram(&self, index: usize) -> Option<&[u8]> {
+        match &self.slots[index].storage {
+            Storage::Ram(ram) => Some(ram.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// Mutable contents of the RAM region at `index`
+    pub(crate) fn ram_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        match &mut self.slots[index].storage {
+            Storage::Ram(ram) => Some(ram.as_mut_slice()),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Write `data` at `address` into ROM or RAM regardless of the access
+    /// permissions, as a debugger programming the flash does. Returns the
+    /// number of bytes written, stopping at the first byte that is not
+    /// backed by ROM or RAM.
+    ///
+    pub fn program(&mut self, address: u32, data: &[u8]) -> usize {
+        for (n, &byte) in data.iter().enumerate() {
+            let Some((index, offset)) = self.resolve(address.wrapping_add(n as u32)) else {
+                return n;
+            };
+            let contents = match &mut self.slots[index].storage {
+                Storage::Rom(rom) => rom.as_mut_slice(),
+                Storage::Ram(ram) => ram.as_mut_slice(),
+                Storage::Device | Storage::BitBand(_) => return n,
+            };
+            contents[offset as usize] = byte;
+        }
+        data.len()
+    }
+
+    #[inline(always)]
+    fn readable(&self, index: usize) -> Result<&Storage, Fault> {
+        let slot = &self.slots[index];
+        if slot.permissions.read {
+            Ok(&slot.storage)
+        } else {
+            Err(Fault::DAccViol)
+        }
+    }
+
+    #[inline(always)]
+    fn readable_mut(&mut self, index: usize) -> Result<&mut Storage, Fault> {
+        let slot = &mut self.slots[index];
+        if slot.permissions.read {
+            Ok(&mut slot.storage)
+        } else {
+            Err(Fault::DAccViol)
+        }
+    }
+
+    #[inline(always)]
+    fn writable(&mut self, index: usize) -> Result<&mut Storage, Fault> {
+        let slot = &mut self.slots[index];
+        if slot.permissions.write {
+            Ok(&mut slot.storage)
+        } else {
+            Err(Fault::DAccViol)
+        }
+    }
+
+    ///
+    /// Resolve an access of `size` bytes, the access must not cross the end
+    /// of the region. Returns the region index, offset and the address used
+    /// for MMIO forwarding.
+    ///
+    #[inline(always)]
+    fn access(&self, address: u32, size: u32) -> Option<Result<(usize, u32, u32), Fault>> {
+        let (index, offset) = self.resolve(address)?;
+        let slot = &self.slots[index];
+        if slot.size - offset < size {
+            return Some(Err(Fault::Preciserr));
+        }
+        Some(Ok((index, offset, slot.base.wrapping_add(offset))))
+    }
+
+    /// Byte order of halfword and word accesses to flash and RAM
+    pub(crate) fn set_big_endian(&mut self, big_endian: bool) {
+        self.big_endian = big_endian;
+    }
+
+    /// Halfwords and words of flash and RAM are stored big endian
+    pub(crate) fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    /// Target address of the bit-band alias at `index`
+    #[inline(always)]
+    fn bit_band(&self, index: usize) -> Option<u32> {
+        match self.slots[index].storage {
+            Storage::BitBand(target) => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Read a byte or a halfword of a bit-band region, from the device if no region serves it
+    fn target_read(
+        &self,
+        address: u32,
+        size: u32,
+        device: Option<&DeviceBus>,
+    ) -> Result<u32, Fault> {
+        let result = if size == 1 {
+            self.read8(address, device).map(|r| r.map(u32::from))
+        } else {
+            self.read16(address, device).map(|r| r.map(u32::from))
+        };
+        result.unwrap_or_else(|| match device {
+            Some(device) if device.in_range(address) && size == 1 => {
+                device.read8(address).map(u32::from)
+            }
+            Some(device) if device.in_range(address) => device.read16(address).map(u32::from),
+            _ => Err(Fault::Preciserr),
+        })
+    }
+
+    /// Read a word of a bit-band region, from the device if no region serves it
+    fn target_read32(
+        &mut self,
+        address: u32,
+        mut device: Option<&mut DeviceBus>,
+    ) -> Result<u32, Fault> {
+        self.read32(address, device.as_deref_mut())
+            .unwrap_or_else(|| match device {
+                Some(device) if device.in_range(address) => device.read32(address),
+                _ => Err(Fault::Preciserr),
+            })
+    }
+
+    /// Write `size` bytes of a bit-band region, to the device if no region serves it
+    fn target_write(
+        &mut self,
+        address: u32,
+        size: u32,
+        value: u32,
+        mut device: Option<&mut DeviceBus>,
+    ) -> Result<(), Fault> {
+        let result = match size {
+            1 => self.write8(address, value as u8, device.as_deref_mut()),
+            2 => self.write16(address, value as u16, device.as_deref_mut()),
+            _ => self.write32(address, value, device.as_deref_mut()),
+        };
+        result.unwrap_or_else(|| match device {
+            Some(device) if device.in_range(address) => match size {
+                1 => device.write8(address, value as u8),
+                2 => device.write16(address, value as u16),
+                _ => device.write32(address, value),
+            },
+            _ => Err(Fault::Preciserr),
+        })
+    }
+
+    /// Set the bit an alias access of `size` bytes at `offset` maps to bit 0 of `value`
+    fn bit_band_write(
+        &mut self,
+        target: u32,
+        offset: u32,
+        size: u32,
+        value: u32,
+        mut device: Option<&mut DeviceBus>,
+    ) -> Result<(), Fault> {
+        let (address, bit) = bit_band_bit(target, offset, size, self.big_endian);
+        let old = if size == 4 {
+            self.target_read32(address, device.as_deref_mut())?
+        } else {
+            self.target_read(address, size, device.as_deref())?
+        };
+        let new = (old & !(1 << bit)) | ((value & 1) << bit);
+        self.target_write(address, size, new, device)
+    }
+}
+
+///
+/// Address and bit number in the bit-band region of an alias access of
+/// `size` bytes at `offset`. The bit-band region is accessed with the size
+/// of the alias access, aligned to it.
+///
+#[inline(always)]
+fn bit_band_bit(target: u32, offset: u32, size: u32, big_endian: bool) -> (u32, u32) {
+    let byte = target.wrapping_add(offset >> 5);
+    let address = byte & !(size - 1);
+    let lane = if big_endian {
+        size - 1 - (byte - address)
+    } else {
+        byte - address
+    };
+    (address, lane * 8 + ((offset >> 2) & 7))
+}
+
+/// Halfword in the byte order of the memory
+#[inline(always)]
+fn order16(value: u16, big_endian: bool) -> u16 {
+    if big_endian {
+        value.swap_bytes()
+    } else {
+        value
+    }
+}
+
+/// Word in the byte order of the memory
+#[inline(always)]
+fn order32(value: u32, big_endian: bool) -> u32 {
+    if big_endian {
+        value.swap_bytes()
+    } else {
+        value
+    }
+}
+
+///
+/// Accesses from the processor bus. Each returns `None` if no region serves
+/// the address, MMIO accesses are forwarded to `device`. Halfwords and words
+/// of flash and RAM are in the configured byte order, device registers are
+/// exchanged by value.
+///
+impl MemoryRegions {
+    /// Read a byte
+    #[inline(always)]
+    pub fn read8(&self, address: u32, device: Option<&DeviceBus>) -> Option<Result<u8, Fault>> {
+        Some(
+            self.access(address, 1)?
+                .and_then(|(index, offset, address)| match self.readable(index)? {
+                    Storage::Rom(rom) => rom.read8(offset),
+                    Storage::Ram(ram) => ram.read8(offset),
+                    Storage::Device => match device {
+                        Some(device) if device.in_range(address) => device.read8(address),
+                        _ => Err(Fault::Preciserr),
+                    },
+                    &Storage::BitBand(target) => {
+                        let (address, bit) = bit_band_bit(target, offset, 1, self.big_endian);
+                        let value = self.target_read(address, 1, device)?;
+                        Ok(((value >> bit) & 1) as u8)
+                    }
+                }),
+        )
+    }
+
+    /// Read a halfword
+    #[inline(always)]
+    pub fn read16(&self, address: u32, device: Option<&DeviceBus>) -> Option<Result<u16, Fault>> {
+        Some(
+            self.access(address, 2)?
+                .and_then(|(index, offset, address)| match self.readable(index)? {
+                    Storage::Rom(rom) => rom.read16(offset).map(|v| order16(v, self.big_endian)),
+                    Storage::Ram(ram) => ram.read16(offset).map(|v| order16(v, self.big_endian)),
+                    Storage::Device => match device {
+                        Some(device) if device.in_range(address) => device.read16(address),
+                        _ => Err(Fault::Preciserr),
+                    },
+                    &Storage::BitBand(target) => {
+                        let (address, bit) = bit_band_bit(target, offset, 2, self.big_endian);
+                        let value = self.target_read(address, 2, device)?;
+                        Ok(((value >> bit) & 1) as u16)
+                    }
+                }),
+        )
+    }
+
+    /// Read a word
+    #[inline(always)]
+    pub fn read32(
+        &mut self,
+        address: u32,
+        device: Option<&mut DeviceBus>,
+    ) -> Option<Result<u32, Fault>> {
+        Some(
+            self.access(address, 4)?
+                .and_then(|(index, offset, address)| {
+                    if let Some(target) = self.bit_band(index) {
+                        self.readable(index)?;
+                        let (address, bit) = bit_band_bit(target, offset, 4, self.big_endian);
+                        return Ok((self.target_read32(address, device)? >> bit) & 1);
+                    }
+                    let big_endian = self.big_endian;
+                    match self.readable_mut(index)? {
+                        Storage::Rom(rom) => rom.read32(offset).map(|v| order32(v, big_endian)),
+                        Storage::Ram(ram) => ram.read32(offset).map(|v| order32(v, big_endian)),
+                        Storage::Device => match device {
+                            Some(device) if device.in_range(address) => device.read32(address),
+                            _ => Err(Fault::Preciserr),
+                        },
+                        Storage::BitBand(_) => unreachable!("bit-band alias handled above"),
+                    }
+                }),
+        )
+    }
+
+    /// Write a byte
+    #[inline(always)]
+    pub fn write8(
+        &mut self,
+        address: u32,
+        value: u8,
+        device: Option<&mut DeviceBus>,
+    ) -> Option<Result<(), Fault>> {
+        Some(
+            self.access(address, 1)?
+                .and_then(|(index, offset, address)| {
+                    if let Some(target) = self.bit_band(index) {
+                        self.writable(index)?;
+                        return self.bit_band_write(target, offset, 1, u32::from(value), device);
+                    }
+                    match self.writable(index)? {
+                        Storage::Rom(rom) => rom.write8(offset, value),
+                        Storage::Ram(ram) => ram.write8(offset, value),
+                        Storage::Device => match device {
+                            Some(device) if device.in_range(address) => {
+                                device.write8(address, value)
+                            }
+                            _ => Err(Fault::Preciserr),
+                        },
+                        Storage::BitBand(_) => unreachable!("bit-band alias handled above"),
+                    }
+                }),
+        )
+    }
+
+    /// Write a halfword
+    #[inline(always)]
+    pub fn write16(
+        &mut self,
+        address: u32,
+        value: u16,
+        device: Option<&mut DeviceBus>,
+    ) -> Option<Result<(), Fault>> {
+        Some(
+            self.access(address, 2)?
+                .and_then(|(index, offset, address)| {
+                    if let Some(target) = self.bit_band(index) {
+                        self.writable(index)?;
+                        return self.bit_band_write(target, offset, 2, u32::from(value), device);
+                    }
+                    let big_endian = self.big_endian;
+                    match self.writable(index)? {
+                        Storage::Rom(rom) => rom.write16(offset, order16(value, big_endian)),
+                        Storage::Ram(ram) => ram.write16(offset, order16(value, big_endian)),
+                        Storage::Device => match device {
+                            Some(device) if device.in_range(address) => {
+                                device.write16(address, value)
+                            }
+                            _ => Err(Fault::Preciserr),
+                        },
+                        Storage::BitBand(_) => unreachable!("bit-band alias handled above"),
+                    }
+                }),
+        )
+    }
+
+    /// Write a word
+    #[inline(always)]
+    pub fn write32(
+        &mut self,
+        address: u32,
+        value: u32,
+        device: Option<&mut DeviceBus>,
+    ) -> Option<Result<(), Fault>> {
+        Some(
+            self.access(address, 4)?
+                .and_then(|(index, offset, address)| {
+                    if let Some(target) = self.bit_band(index) {
+                        self.writable(index)?;
+                        return self.bit_band_write(target, offset, 4, value, device);
+                    }
+                    let big_endian = self.big_endian;
+                    match self.writable(index)? {
+                        Storage::Rom(rom) => rom.write32(offset, order32(value, big_endian)),
+                        Storage::Ram(ram) => ram.write32(offset, order32(value, big_endian)),
+                        Storage::Device => match device {
+                            Some(device) if device.in_range(address) => {
+                                device.write32(address, value)
+                            }
+                            _ => Err(Fault::Preciserr),
+                        },
+                        Storage::BitBand(_) => unreachable!("bit-band alias handled above"),
+                    }
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions_load_contents_and_fill() {
+        // Arrange
+        let mut rom = MemoryRegion::rom("flash", 0x0800_0000, 0x100).with_fill(0xff);
+        assert!(rom.load(0x0800_0010, &[1, 2, 3, 4]));
+        assert!(!rom.load(0x0800_00fe, &[1, 2, 3, 4]));
+        assert!(!rom.load(0x2000_0000, &[1]));
+        let mut memory = MemoryRegions::default();
+
+        // Act
+        memory.add(rom);
+        memory.add(MemoryRegion::ram("ram", 0x2000_0000, 0x100).with_fill(0xcd));
+
+        // Assert
+        assert_eq!(memory.read8(0x0800_0000, None), Some(Ok(0xff)));
+        assert_eq!(memory.read32(0x0800_0010, None), Some(Ok(0x0403_0201)));
+        assert_eq!(memory.read16(0x2000_00fe, None), Some(Ok(0xcdcd)));
+        assert_eq!(memory.read8(0x2000_0100, None), None);
+    }
+
+    #[test]
+    fn test_program_ignores_permissions_and_stops_at_device() {
+        // Arrange
+        let mut memory = MemoryRegions::default();
+        memory.add(MemoryRegion::rom("flash", 0, 0x100));
+        memory.add(MemoryRegion::mmio("apb", 0x100, 0x100));
+
+        // Act
+        let written = memory.program(0xfe, &[1, 2, 3, 4]);
+
+        // Assert
+        assert_eq!(written, 2);
+        assert_eq!(memory.read16(0xfe, None), Some(Ok(0x0201)));
+        assert_eq!(memory.write8(0xfe, 0, None), Some(Err(Fault::DAccViol)));
+        assert_eq!(memory.program(0x1000, &[1]), 0);
+    }
+
+    #[test]
+    fn test_regions_enforce_permissions_and_bounds() {
+        // Arrange
+        let mut memory = MemoryRegions::default();
+        memory.add(MemoryRegion::rom("flash", 0, 0x100));
+        memory.add(MemoryRegion::ram("dtcm", 0x2000_0000, 0x100).with_permissions(Permissions::RW));
+        memory.add(MemoryRegion::mmio("apb", 0x4000_0000, 0x1000));
+
+        // Act & Assert
+        assert_eq!(memory.write32(0x10, 1, None), Some(Err(Fault::DAccViol)));
+        assert_eq!(memory.write32(0x2000_00fc, 1, None), Some(Ok(())));
+        assert_eq!(
+            memory.read32(0x2000_00fe, None),
+            Some(Err(Fault::Preciserr))
+        );
+        assert_eq!(memory.read8(0x4000_0000, None), Some(Err(Fault::Preciserr)));
+        assert!(memory.executable(0x10));
+        assert!(!memory.executable(0x2000_0000));
+        assert!(!memory.executable(0x4000_0000));
+    }
+
+    #[test]
+    fn test_regions_resolve_aliases_and_precedence() {
+        // Arrange
+        let mut memory = MemoryRegions::default();
+        memory.add(MemoryRegion::ram("ram", 0x2000_0000, 0x1000));
+        memory.add(MemoryRegion::alias("mirror", 0x0, 0x100, 0x2000_0100));
+        memory.add(MemoryRegion::ram("overlay", 0x2000_0800, 0x100));
+
+        // Act
+        memory.write8(0x10, 0x5a, None);
+        memory.write8(0x2000_0800, 0xa5, None);
+
+        // Assert
+        assert_eq!(memory.read8(0x2000_0110, None), Some(Ok(0x5a)));
+        assert_eq!(memory.read8(0x2000_0800, None), Some(Ok(0xa5)));
+
+        // replacing the target by name leaves the alias dangling
+        memory.add(MemoryRegion::ram("ram", 0x3000_0000, 0x10));
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.read8(0x10, None), None);
+        assert!(memory.in_range(0x3000_000f));
+    }
+
+    #[test]
+    fn test_bit_band_alias_accesses_single_bits() {
+        // Arrange
+        let mut memory = MemoryRegions::default();
+        memory.add(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        memory.add(MemoryRegion::bit_band(
+            "bitband",
+            0x2200_0000,
+            0x2000,
+            0x2000_0000,
+        ));
+        memory.add(MemoryRegion::bit_band(
+            "unmapped",
+            0x4200_0000,
+            0x2000,
+            0x4000_0000,
+        ));
+        memory.write8(0x2000_0001, 0x81, None);
+
+        // Act
+        let set = memory.write32(0x2200_0000 + 32 + 4 * 3, 0xffff_fff1, None);
+        let cleared = memory.write8(0x2200_0000 + 32 + 4 * 7, 0xfe, None);
+
+        // Assert
+        assert_eq!(set, Some(Ok(())));
+        assert_eq!(cleared, Some(Ok(())));
+        assert_eq!(memory.read8(0x2000_0001, None), Some(Ok(0x09)));
+        assert_eq!(memory.read32(0x2200_0000 + 32 + 4 * 3, None), Some(Ok(1)));
+        assert_eq!(memory.read16(0x2200_0000 + 32, None), Some(Ok(1)));
+        assert_eq!(memory.read8(0x2200_0000 + 32 + 4, None), Some(Ok(0)));
+        assert_eq!(
+            memory.read32(0x4200_0000, None),
+            Some(Err(Fault::Preciserr))
+        );
+    }
+
+    #[test]
+    fn test_big_endian_words_and_bit_band_bytes() {
+        // Arrange
+        let mut memory = MemoryRegions::default();
+        memory.add(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        memory.add(MemoryRegion::bit_band(
+            "bitband",
+            0x2200_0000,
+            0x2000,
+            0x2000_0000,
+        ));
+        memory.set_big_endian(true);
+
+        // Act
+        memory.write32(0x2000_0000, 0x8000_0001, None);
+        memory.write16(0x2200_0000 + 32 * 5 + 4 * 2, 1, None);
+
+        // Assert
+        assert_eq!(memory.read8(0x2000_0000, None), Some(Ok(0x80)));
+        assert_eq!(memory.read8(0x2000_0003, None), Some(Ok(0x01)));
+        assert_eq!(memory.read16(0x2000_0002, None), Some(Ok(0x0001)));
+        assert_eq!(memory.read32(0x2200_0000 + 4 * 7, None), Some(Ok(1)));
+        assert_eq!(memory.read32(0x2200_0000 + 32 * 3, None), Some(Ok(1)));
+        assert_eq!(memory.read8(0x2000_0005, None), Some(Ok(0x04)));
+    }
+}
@@ -1,52 +1,240 @@
 //!
 //! Cortex Memory Protection Unit simulation
 //!
+//! Models the PMSAv6/PMSAv7 register interface (`MPU_TYPE`, `MPU_CTRL`,
+//! `MPU_RNR`, `MPU_RBAR`, `MPU_RASR` and the Armv7-M alias registers) and the
+//! permission checks made by the core for instruction fetches and data
+//! accesses.
+//!
 
 use crate::Processor;
+use crate::core::bits::Bits;
 use crate::core::fault::Fault;
 
 /// Memory access type for MPU validation.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum AccType {
-    /// Privileged data access.
+    /// Data access with the privilege of the current mode.
     Normal,
     /// Unprivileged data access.
     UnPriv,
+    /// Instruction fetch with the privilege of the current mode.
+    IFetch,
 }
 
+/// Number of MPU regions implemented.
+pub const MPU_REGION_COUNT: usize = 8;
+
+const MPU_CTRL_ENABLE: u32 = 1;
+const MPU_CTRL_HFNMIENA: u32 = 1 << 1;
+const MPU_CTRL_PRIVDEFENA: u32 = 1 << 2;
+const MPU_CTRL_MASK: u32 = MPU_CTRL_ENABLE | MPU_CTRL_HFNMIENA | MPU_CTRL_PRIVDEFENA;
+
+const MPU_RBAR_VALID: u32 = 1 << 4;
+#[cfg(feature = "armv6m")]
+const MPU_RBAR_ADDR_MASK: u32 = 0xffff_ff00;
+#[cfg(not(feature = "armv6m"))]
+const MPU_RBAR_ADDR_MASK: u32 = 0xffff_ffe0;
+
+const MPU_RASR_ENABLE: u32 = 1;
+const MPU_RASR_XN: usize = 28;
+#[cfg(feature = "armv6m")]
+const MPU_RASR_MASK: u32 = 0x1707_ff3f;
+#[cfg(not(feature = "armv6m"))]
+const MPU_RASR_MASK: u32 = 0x173f_ff3f;
+
 /// Register API for MPU address validation.
 pub trait Mpu {
     ///
     /// Validate memory access at `address`.
     ///
+    /// Returns the address to use for the access, or the `MemManage` fault
+    /// (`IAccViol` for instruction fetches, `DAccViol` otherwise) raised by
+    /// the MPU.
+    ///
     fn validate_address(
         &mut self,
         address: u32,
         acctype: AccType,
         write: bool,
     ) -> Result<u32, Fault>;
+
+    ///
+    /// Read `MPU_TYPE`, the number of supported regions
+    ///
+    fn mpu_read_type(&self) -> u32;
+
+    ///
+    /// Write `MPU_CTRL`, the control register
+    ///
+    fn mpu_write_ctrl(&mut self, value: u32);
+
+    ///
+    /// Write `MPU_RNR`, the region number register
+    ///
+    fn mpu_write_rnr(&mut self, value: u32);
+
+    ///
+    /// Read `MPU_RBAR` of the region selected by `MPU_RNR`
+    ///
+    fn mpu_read_rbar(&self) -> u32;
+
+    ///
+    /// Write `MPU_RBAR`. When the VALID bit is set the REGION field
+    /// also updates `MPU_RNR` before the write.
+    ///
+    fn mpu_write_rbar(&mut self, value: u32);
+
+    ///
+    /// Read `MPU_RASR` of the region selected by `MPU_RNR`
+    ///
+    fn mpu_read_rasr(&self) -> u32;
+
+    ///
+    /// Write `MPU_RASR` of the region selected by `MPU_RNR`
+    ///
+    fn mpu_write_rasr(&mut self, value: u32);
+}
+
+impl Processor {
+    pub(crate) fn reset_mpu_state(&mut self) {
+        self.mpu_ctrl = 0;
+        self.mpu_rnr = 0;
+        for rasr in &mut self.mpu_rasr {
+            *rasr &= !MPU_RASR_ENABLE;
+        }
+    }
+
+    /// `MPU_RASR` of the highest numbered enabled region containing `address`
+    /// in one of its enabled subregions.
+    fn mpu_region_attributes(&self, address: u32) -> Option<u32> {
+        (0..MPU_REGION_COUNT).rev().find_map(|n| {
+            let rasr = self.mpu_rasr[n];
+            if rasr & MPU_RASR_ENABLE == 0 {
+                return None;
+            }
+            let size_log2 = rasr.get_bits(1..6) + 1;
+            let offset = if size_log2 >= 32 {
+                address
+            } else {
+                let mask = (1 << size_log2) - 1;
+                if (address & !mask) != (self.mpu_rbar[n] & MPU_RBAR_ADDR_MASK & !mask) {
+                    return None;
+                }
+                address & mask
+            };
+            // regions of 256 bytes or more are split in eight subregions
+            if size_log2 >= 8 {
+                let subregion = (u64::from(offset) >> (size_log2 - 3)) as usize;
+                if rasr.get_bit(8 + subregion) {
+                    return None;
+                }
+            }
+            Some(rasr)
+        })
+    }
+
+    fn mpu_permits(&self, address: u32, acctype: AccType, write: bool) -> bool {
+        // HardFault, NMI and FAULTMASK handlers bypass the MPU unless HFNMIENA
+        if self.mpu_ctrl & MPU_CTRL_HFNMIENA == 0 && self.execution_priority < 0 {
+            return true;
+        }
+        // the private peripheral bus always uses the default memory map
+        if (0xE000_0000..=0xE00F_FFFF).contains(&address) {
+            return true;
+        }
+
+        let privileged = acctype != AccType::UnPriv && self.current_mode_is_privileged();
+        match self.mpu_region_attributes(address) {
+            Some(rasr) => {
+                if acctype == AccType::IFetch && rasr.get_bit(MPU_RASR_XN) {
+                    return false;
+                }
+                match rasr.get_bits(24..27) {
+                    0b001 => privileged,
+                    0b010 => privileged || !write,
+                    0b011 => true,
+                    0b101 => privileged && !write,
+                    0b110 | 0b111 => !write,
+                    _ => false,
+                }
+            }
+            None => privileged && self.mpu_ctrl & MPU_CTRL_PRIVDEFENA != 0,
+        }
+    }
 }
 
 impl Mpu for Processor {
+    #[inline(always)]
     fn validate_address(
         &mut self,
         address: u32,
-        _acctype: AccType,
-        _write: bool,
+        acctype: AccType,
+        write: bool,
     ) -> Result<u32, Fault> {
-        // TODO MPU check
-        Ok(address)
+        if self.mpu_ctrl & MPU_CTRL_ENABLE == 0 || self.mpu_permits(address, acctype, write) {
+            Ok(address)
+        } else if acctype == AccType::IFetch {
+            Err(Fault::IAccViol)
+        } else {
+            Err(Fault::DAccViol)
+        }
+    }
+
+    fn mpu_read_type(&self) -> u32 {
+        (MPU_REGION_COUNT as u32) << 8
+    }
+
+    fn mpu_write_ctrl(&mut self, value: u32) {
+        self.mpu_ctrl = value & MPU_CTRL_MASK;
+    }
+
+    fn mpu_write_rnr(&mut self, value: u32) {
+        self.mpu_rnr = value % MPU_REGION_COUNT as u32;
+    }
+
+    fn mpu_read_rbar(&self) -> u32 {
+        self.mpu_rbar[self.mpu_rnr as usize] | self.mpu_rnr
+    }
+
+    fn mpu_write_rbar(&mut self, value: u32) {
+        if value & MPU_RBAR_VALID != 0 {
+            self.mpu_write_rnr(value.get_bits(0..4));
+        }
+        self.mpu_rbar[self.mpu_rnr as usize] = value & MPU_RBAR_ADDR_MASK;
+    }
+
+    fn mpu_read_rasr(&self) -> u32 {
+        self.mpu_rasr[self.mpu_rnr as usize]
+    }
+
+    fn mpu_write_rasr(&mut self, value: u32) {
+        self.mpu_rasr[self.mpu_rnr as usize] = value & MPU_RASR_MASK;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{AccType, Mpu};
-    use crate::Processor;
+    use crate::core::fault::Fault;
+    use crate::{Processor, ProcessorMode};
+
+    // AP=011 full access, SIZE=9 (1 KiB)
+    const RASR_FULL_1K: u32 = (0b011 << 24) | (9 << 1) | 1;
+    // AP=001 privileged only, SIZE=9 (1 KiB)
+    const RASR_PRIV_1K: u32 = (0b001 << 24) | (9 << 1) | 1;
+    // AP=110 read only, SIZE=31 (4 GiB)
+    const RASR_RO_4G: u32 = (0b110 << 24) | (31 << 1) | 1;
+
+    fn unprivileged_thread(processor: &mut Processor) {
+        processor.mode = ProcessorMode::ThreadMode;
+        processor.control.n_priv = true;
+    }
 
     #[test]
-    fn test_validate_address_returns_input_for_privileged_and_unprivileged_access() {
+    fn test_validate_address_returns_input_when_mpu_disabled() {
         let mut processor = Processor::new();
+        unprivileged_thread(&mut processor);
 
         assert_eq!(
             processor
@@ -61,4 +249,148 @@ mod tests {
             0x2000_0200
         );
     }
+
+    #[test]
+    fn test_rbar_valid_bit_selects_region() {
+        // Arrange
+        let mut processor = Processor::new();
+
+        // Act
+        processor.mpu_write_rbar(0x2000_0400 | 0x10 | 3);
+        processor.mpu_write_rasr(RASR_FULL_1K);
+
+        // Assert
+        assert_eq!(processor.mpu_rnr, 3);
+        assert_eq!(processor.mpu_read_rbar(), 0x2000_0403);
+        assert_eq!(processor.mpu_rasr[3], RASR_FULL_1K);
+        assert_eq!(processor.mpu_read_type(), 8 << 8);
+    }
+
+    #[test]
+    fn test_unprivileged_access_outside_regions_faults() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.mpu_write_rbar(0x2000_0000 | 0x10);
+        processor.mpu_write_rasr(RASR_FULL_1K);
+        processor.mpu_write_ctrl(0b101); // ENABLE | PRIVDEFENA
+        unprivileged_thread(&mut processor);
+
+        // Act & Assert
+        assert!(
+            processor
+                .validate_address(0x2000_03fc, AccType::Normal, true)
+                .is_ok()
+        );
+        assert_eq!(
+            processor.validate_address(0x2000_0400, AccType::Normal, false),
+            Err(Fault::DAccViol)
+        );
+        assert_eq!(
+            processor.validate_address(0x2000_0400, AccType::IFetch, false),
+            Err(Fault::IAccViol)
+        );
+
+        // privileged code falls back to the default map
+        processor.control.n_priv = false;
+        assert!(
+            processor
+                .validate_address(0x2000_0400, AccType::Normal, true)
+                .is_ok()
+        );
+        assert_eq!(
+            processor.validate_address(0x2000_0400, AccType::UnPriv, true),
+            Err(Fault::DAccViol)
+        );
+    }
+
+    #[test]
+    fn test_access_permissions_and_overlapping_regions() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.mpu_write_rbar(0x10);
+        processor.mpu_write_rasr(RASR_RO_4G | (1 << 28)); // XN
+        processor.mpu_write_rbar(0x2000_0000 | 0x10 | 1);
+        processor.mpu_write_rasr(RASR_PRIV_1K);
+        processor.mpu_write_ctrl(1);
+
+        // Act & Assert
+        assert!(
+            processor
+                .validate_address(0x2000_0000, AccType::Normal, true)
+                .is_ok()
+        );
+        assert!(
+            processor
+                .validate_address(0x0800_0000, AccType::Normal, false)
+                .is_ok()
+        );
+        assert_eq!(
+            processor.validate_address(0x0800_0000, AccType::Normal, true),
+            Err(Fault::DAccViol)
+        );
+        assert_eq!(
+            processor.validate_address(0x0800_0000, AccType::IFetch, false),
+            Err(Fault::IAccViol)
+        );
+
+        unprivileged_thread(&mut processor);
+        assert_eq!(
+            processor.validate_address(0x2000_0000, AccType::Normal, false),
+            Err(Fault::DAccViol)
+        );
+        assert!(
+            processor
+                .validate_address(0x2000_0400, AccType::Normal, false)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_disabled_subregion_falls_through_to_lower_region() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.mpu_write_rbar(0x10);
+        processor.mpu_write_rasr(RASR_RO_4G);
+        processor.mpu_write_rbar(0x2000_0000 | 0x10 | 1);
+        // disable subregion 1 (0x2000_0080..0x2000_0100)
+        processor.mpu_write_rasr(RASR_FULL_1K | (0b10 << 8));
+        processor.mpu_write_ctrl(1);
+        unprivileged_thread(&mut processor);
+
+        // Act & Assert
+        assert!(
+            processor
+                .validate_address(0x2000_007c, AccType::Normal, true)
+                .is_ok()
+        );
+        assert_eq!(
+            processor.validate_address(0x2000_0080, AccType::Normal, true),
+            Err(Fault::DAccViol)
+        );
+        assert!(
+            processor
+                .validate_address(0x2000_0100, AccType::Normal, true)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_hardfault_bypasses_mpu_unless_hfnmiena() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.mpu_write_ctrl(1);
+        processor.execution_priority = -1;
+
+        // Act & Assert
+        assert!(
+            processor
+                .validate_address(0x2000_0000, AccType::Normal, true)
+                .is_ok()
+        );
+        processor.mpu_write_ctrl(0b011);
+        assert_eq!(
+            processor.validate_address(0x2000_0000, AccType::Normal, true),
+            Err(Fault::DAccViol)
+        );
+    }
 }
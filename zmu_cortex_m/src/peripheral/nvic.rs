@@ -92,6 +92,18 @@ pub trait NVIC {
     /// Mark interrupt no longer pending in NVIC point of view.
     ///
     fn nvic_unpend_interrupt(&mut self, irqn: usize);
+
+    ///
+    /// Drive external interrupt line ```irqn``` to given level.
+    /// A rising edge makes the interrupt pending.
+    ///
+    fn nvic_set_irq_line(&mut self, irqn: usize, asserted: bool);
+
+    ///
+    /// Interrupt handler of ```irqn``` returned. If the line is still
+    /// asserted the interrupt becomes pending again.
+    ///
+    fn nvic_interrupt_returned(&mut self, irqn: usize);
 }
 
 trait NVICHelper {
@@ -118,7 +130,7 @@ fn clear_bits_array(array: &mut [u32; 16], index: usize, value: u32) {
 impl NVICHelper for Processor {
     fn nvic_set_pending_exceptions(&mut self, index: usize) {
         let mut active = self.nvic_interrupt_pending[index] & self.nvic_interrupt_enabled[index];
-        let mut irqn = index * 4;
+        let mut irqn = index * 32;
         while active != 0 {
             if active & 1 != 0 {
                 self.set_exception_pending(Interrupt { n: irqn });
@@ -130,7 +142,7 @@ impl NVICHelper for Processor {
 
     fn nvic_clear_unpended_exceptions(&mut self, index: usize) {
         let mut active = self.nvic_interrupt_pending[index] & self.nvic_interrupt_enabled[index];
-        for irqn in (index * 4)..(index * 4) + 32 {
+        for irqn in (index * 32)..(index * 32) + 32 {
             if active & 1 == 0 {
                 self.clear_pending_exception(Interrupt { n: irqn });
             }
@@ -160,6 +172,29 @@ impl NVIC for Processor {
         clear_bits_array(&mut self.nvic_interrupt_pending, index, 1 << bit);
     }
 
+    fn nvic_set_irq_line(&mut self, irqn: usize, asserted: bool) {
+        let index = irqn / 32;
+        let bit = 1 << (irqn % 32);
+        let was_asserted = self.nvic_interrupt_asserted[index] & bit != 0;
+
+        if asserted {
+            self.nvic_interrupt_asserted[index] |= bit;
+            if !was_asserted {
+                self.nvic_write_ispr(index, bit);
+            }
+        } else {
+            self.nvic_interrupt_asserted[index] &= !bit;
+        }
+    }
+
+    fn nvic_interrupt_returned(&mut self, irqn: usize) {
+        let index = irqn / 32;
+        let bit = 1 << (irqn % 32);
+        if self.nvic_interrupt_asserted[index] & bit != 0 {
+            self.nvic_write_ispr(index, bit);
+        }
+    }
+
     fn nvic_read_icer(&self, index: usize) -> u32 {
         self.nvic_interrupt_enabled[index] ^ 0xFFFF_FFFF
     }
@@ -233,11 +268,54 @@ impl NVIC for Processor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bus::{Bus, IrqLine, Peripheral};
     use crate::core::exception::ExceptionHandling;
+    use crate::core::fault::Fault;
     use crate::core::instruction::Instruction;
+    use crate::core::register::Ipsr;
     use crate::core::reset::Reset;
     use crate::executor::Executor;
 
+    struct TimerDevice {
+        remaining: u32,
+        irqn: usize,
+    }
+
+    impl Bus for TimerDevice {
+        fn read32(&mut self, _addr: u32) -> Result<u32, Fault> {
+            Ok(0)
+        }
+        fn read16(&self, _addr: u32) -> Result<u16, Fault> {
+            Ok(0)
+        }
+        fn read8(&self, _addr: u32) -> Result<u8, Fault> {
+            Ok(0)
+        }
+        fn write32(&mut self, _addr: u32, _value: u32) -> Result<(), Fault> {
+            Ok(())
+        }
+        fn write16(&mut self, _addr: u32, _value: u16) -> Result<(), Fault> {
+            Ok(())
+        }
+        fn write8(&mut self, _addr: u32, _value: u8) -> Result<(), Fault> {
+            Ok(())
+        }
+        fn in_range(&self, _addr: u32) -> bool {
+            false
+        }
+    }
+
+    impl Peripheral for TimerDevice {
+        fn tick(&mut self, cycles: u32, irqs: &mut Vec<IrqLine>) {
+            if self.remaining > 0 {
+                self.remaining = self.remaining.saturating_sub(cycles);
+                if self.remaining == 0 {
+                    irqs.push(IrqLine::assert(self.irqn));
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_nvic_iser_icer() {
         // Arrange
@@ -443,4 +521,86 @@ mod tests {
             assert_eq!(processor.nvic_read_ipr_u16(n), value);
         }
     }
+
+    #[test]
+    fn test_nvic_irq_line_rising_edge_pends() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.reset().unwrap();
+
+        // Act
+        processor.nvic_set_irq_line(20, true);
+
+        // Assert
+        assert_eq!(processor.nvic_read_ispr(0), 1 << 20);
+        assert!(!processor.exception_pending(Exception::Interrupt { n: 20 }));
+
+        // Act
+        processor.nvic_write_iser(0, 1 << 20);
+
+        // Assert
+        assert!(processor.exception_pending(Exception::Interrupt { n: 20 }));
+
+        // Act: deasserting does not clear the latched pending state
+        processor.nvic_set_irq_line(20, false);
+
+        // Assert
+        assert_eq!(processor.nvic_read_ispr(0), 1 << 20);
+        assert!(processor.exception_pending(Exception::Interrupt { n: 20 }));
+    }
+
+    #[test]
+    fn test_nvic_irq_line_repends_on_return_while_asserted() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.reset().unwrap();
+        processor.nvic_set_irq_line(3, true);
+        processor.nvic_write_icpr(0, 1 << 3);
+
+        // Act
+        processor.nvic_interrupt_returned(3);
+
+        // Assert
+        assert_eq!(processor.nvic_read_ispr(0), 1 << 3);
+
+        // Act
+        processor.nvic_set_irq_line(3, false);
+        processor.nvic_write_icpr(0, 1 << 3);
+        processor.nvic_interrupt_returned(3);
+
+        // Assert
+        assert_eq!(processor.nvic_read_ispr(0), 0);
+    }
+
+    #[test]
+    fn test_device_tick_raises_interrupt() {
+        // Arrange
+        let irqn = 30;
+        let mut data = vec![0; 65536].into_boxed_slice();
+        data[1] = 0x10; // stack pointer -> 0x2000_1000
+        data[3] = 0x20;
+        data[4] = 0x01; // reset vector -> 0x200
+        data[5] = 0x02;
+        let vector = (16 + irqn) * 4;
+        data[vector] = 0x01; // irq handler -> 0x300
+        data[vector + 1] = 0x03;
+
+        let mut processor = Processor::new();
+        processor.flash_memory(65536, &data);
+        processor.device(Some(Box::new(TimerDevice {
+            remaining: 10,
+            irqn,
+        })));
+        processor.reset().unwrap();
+        processor.nvic_write_iser(0, 1 << irqn);
+
+        // Act
+        for _ in 0..20 {
+            processor.step();
+        }
+
+        // Assert
+        assert_eq!(processor.psr.get_isr_number(), 16 + irqn);
+        assert!(processor.exception_active(Exception::Interrupt { n: irqn }));
+    }
 }
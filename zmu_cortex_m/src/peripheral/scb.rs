@@ -27,6 +27,9 @@ pub(crate) const CCR_STKALIGN: usize = 9;
 #[allow(dead_code)]
 const CCR_DC: usize = 16;
 
+// Application Interrupt and Reset Control Register bits
+pub(crate) const AIRCR_ENDIANNESS: u32 = 1 << 15;
+
 // System Handler Control and State Register bit positions
 const SHCSR_MEMFAULTACT: u32 = 1 << 0;
 const SHCSR_BUSFAULTACT: u32 = 1 << 1;
@@ -111,6 +114,7 @@ const SHCSR_STATUS_MASK: u32 = SHCSR_SVCALLPENDED;
 // Configurable Fault Status Register bit positions
 const CFSR_IACCVIOL: u32 = 1 << 0;
 const CFSR_DACCVIOL: u32 = 1 << 1;
+const CFSR_MUNSTKERR: u32 = 1 << 3;
 const CFSR_MSTKERR: u32 = 1 << 4;
 const CFSR_MMARVALID: u32 = 1 << 7;
 const CFSR_IBUSERR: u32 = 1 << 8;
@@ -308,6 +312,7 @@ impl Processor {
                 }
             }
             Fault::Msunskerr => self.cfsr |= CFSR_UNSTKERR,
+            Fault::Munstkerr => self.cfsr |= CFSR_MUNSTKERR,
             Fault::Mstkerr => self.cfsr |= CFSR_MSTKERR,
             Fault::IBusErr => {
                 self.cfsr |= CFSR_IBUSERR;
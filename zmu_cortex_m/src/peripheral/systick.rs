@@ -6,6 +6,7 @@ use crate::Processor;
 use crate::core::bits::Bits;
 use crate::core::exception::Exception;
 use crate::core::exception::ExceptionHandling;
+use crate::core::trace_event::TraceEventKind;
 
 ///
 /// Register API for ```SysTick``` peripheral
@@ -50,6 +51,12 @@ pub trait SysTick {
     /// Step systick ```cycles``` clock cycles forward
     ///
     fn syst_step(&mut self, cycles: u32);
+
+    ///
+    /// Clock cycles until the counter next reaches zero, `None` when the
+    /// counter is disabled or the reload value is zero
+    ///
+    fn syst_cycles_to_wrap(&self) -> Option<u32>;
 }
 
 const SYST_CSR_ENABLE: u32 = 1;
@@ -96,21 +103,42 @@ impl SysTick for Processor {
             return;
         }
 
-        for _ in 0..cycles {
+        let mut remaining = cycles;
+        while remaining > 0 {
             if self.syst_cvr > 0 {
-                self.syst_cvr -= 1;
+                // count down to zero or as far as the cycles go
+                let step = remaining.min(self.syst_cvr);
+                self.syst_cvr -= step;
+                remaining -= step;
 
                 if self.syst_cvr == 0 {
                     self.syst_csr |= SYST_CSR_COUNTFLAG;
+                    self.trace_event(TraceEventKind::SysTickWrap);
                     if (self.syst_csr & SYST_CSR_TICKINT) == SYST_CSR_TICKINT {
                         self.set_exception_pending(Exception::SysTick);
                     }
                 }
             } else {
+                // reloading takes a cycle, a zero reload value stops the counter
                 self.syst_cvr = self.syst_rvr & 0x00ff_ffff;
+                remaining -= 1;
+                if self.syst_cvr == 0 {
+                    break;
+                }
             }
         }
     }
+
+    fn syst_cycles_to_wrap(&self) -> Option<u32> {
+        if (self.syst_csr & SYST_CSR_ENABLE) == 0 {
+            return None;
+        }
+        match (self.syst_cvr, self.syst_rvr & 0x00ff_ffff) {
+            (0, 0) => None,
+            (0, reload) => Some(reload + 1),
+            (current, _) => Some(current),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -238,4 +266,33 @@ mod tests {
             SYST_CSR_COUNTFLAG | SYST_CSR_ENABLE | SYST_CSR_TICKINT
         );
     }
+
+    #[test]
+    fn test_syst_step_in_bulk_matches_single_cycles() {
+        // Arrange
+        let mut single = Processor::new();
+        single.reset().unwrap();
+        single.syst_write_rvr(5);
+        single.syst_cvr = 3;
+        single.syst_write_csr(SYST_CSR_ENABLE | SYST_CSR_TICKINT);
+        let mut bulk = Processor::new();
+        bulk.reset().unwrap();
+        bulk.syst_write_rvr(5);
+        bulk.syst_cvr = 3;
+        bulk.syst_write_csr(SYST_CSR_ENABLE | SYST_CSR_TICKINT);
+        let wrap = bulk.syst_cycles_to_wrap();
+
+        // Act
+        for _ in 0..11 {
+            single.syst_step(1);
+        }
+        bulk.syst_step(11);
+
+        // Assert
+        assert_eq!(wrap, Some(3));
+        assert_eq!(bulk.syst_cvr, single.syst_cvr);
+        assert_eq!(bulk.syst_csr, single.syst_csr);
+        assert_eq!(bulk.syst_cycles_to_wrap(), Some(4));
+        assert_eq!(bulk.get_pending_exception(), Some(Exception::SysTick));
+    }
 }
@@ -113,6 +113,17 @@ pub enum SemihostingCommand {
         data: u8,
     },
     ///
+    /// Write a zero terminated string to the debug console
+    ///
+    SysWrite0 {
+        /// string without the terminating zero
+        data: Vec<u8>,
+    },
+    ///
+    /// Read a single char from the debug console
+    ///
+    SysReadC,
+    ///
     /// Write data to open file handle
     ///
     SysWrite {
@@ -143,6 +154,33 @@ pub enum SemihostingCommand {
         len: u32,
     },
     ///
+    /// Get a name for a temporary file
+    ///
+    SysTmpnam {
+        /// location in memory to which the name is written
+        memoryptr: u32,
+        /// identifier of the temporary file, 0 - 255
+        id: u32,
+        /// length of the buffer in bytes
+        len: u32,
+    },
+    ///
+    /// Delete a file
+    ///
+    SysRemove {
+        /// name of the file
+        name: String,
+    },
+    ///
+    /// Rename a file
+    ///
+    SysRename {
+        /// current name of the file
+        from: String,
+        /// new name of the file
+        to: String,
+    },
+    ///
     /// Trigger an exception
     ///
     SysException {
@@ -163,9 +201,61 @@ pub enum SemihostingCommand {
     ///
     SysClock,
     ///
+    /// Get the number of seconds since 1970-01-01
+    ///
+    SysTime,
+    ///
+    /// Get the number of elapsed target ticks
+    ///
+    SysElapsed {
+        /// simulated cycles since reset, one tick per cycle
+        cycles: u64,
+    },
+    ///
+    /// Get the frequency of the ticks of `SysElapsed`
+    ///
+    SysTickFreq,
+    ///
     /// Get the value of errno
     ///
     SysErrno,
+    ///
+    /// Check if a return value of another call is an error
+    ///
+    SysIserror {
+        /// the return value
+        status: u32,
+    },
+    ///
+    /// Run a command on the host
+    ///
+    SysSystem {
+        /// the command line
+        command: String,
+    },
+    ///
+    /// Get the command line of the program
+    ///
+    SysGetCmdline {
+        /// location in memory to which the command line is written
+        memoryptr: u32,
+        /// length of the buffer in bytes
+        len: u32,
+    },
+    ///
+    /// Get the memory layout of the heap and the stack
+    ///
+    SysHeapinfo {
+        /// location in memory of the four word result block
+        memoryptr: u32,
+    },
+    ///
+    /// Operation this implementation does not know
+    ///
+    Unknown {
+        /// operation number in r0
+        operation: u32,
+    },
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -208,6 +298,21 @@ pub enum SemihostingResponse {
         /// result Ok = data, Err = error code
         result: Result<(u32, Vec<u8>, u32), i32>,
     },
+    /// systmpnam command response
+    SysTmpnam {
+        /// result Ok = memory location and the name with a terminating zero, Err = error code
+        result: Result<(u32, Vec<u8>), i32>,
+    },
+    /// sysremove command response
+    SysRemove {
+        /// result
+        success: bool,
+    },
+    /// sysrename command response
+    SysRename {
+        /// result
+        success: bool,
+    },
     /// sysexception command response
     SysException {
         /// result
@@ -234,20 +339,84 @@ pub enum SemihostingResponse {
         /// result
         result: u32,
     },
+    /// sysreadc command response
+    SysReadC {
+        /// result Ok = the char, Err = error code
+        result: Result<u8, i32>,
+    },
+    /// systime command response
+    SysTime {
+        /// result Ok = seconds, Err = error code
+        result: Result<u32, i32>,
+    },
+    /// syselapsed command response
+    SysElapsed {
+        /// result Ok = ticks, Err = error code
+        result: Result<u64, i32>,
+    },
+    /// systickfreq command response
+    SysTickFreq {
+        /// result Ok = ticks per second, Err = error code
+        result: Result<u32, i32>,
+    },
+    /// sysiserror command response
+    SysIserror {
+        /// result, nonzero if the status is an error
+        result: u32,
+    },
+    /// syssystem command response
+    SysSystem {
+        /// result Ok = exit status of the command, Err = error code
+        result: Result<u32, i32>,
+    },
+    /// sysgetcmdline command response
+    SysGetCmdline {
+        /// result Ok = memory location and the command line with a terminating zero, Err = error code
+        result: Result<(u32, Vec<u8>), i32>,
+    },
+    /// sysheapinfo command response
+    SysHeapinfo {
+        /// location in memory of the result block
+        memoryptr: u32,
+        /// heap base, heap limit, stack base and stack limit
+        heap_info: [u32; 4],
+    },
+    /// response to an unknown operation
+    Unknown,
 }
 
 const SYS_OPEN: u32 = 0x01;
 const SYS_CLOSE: u32 = 0x02;
 const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
 const SYS_WRITE: u32 = 0x05;
 const SYS_READ: u32 = 0x06;
+const SYS_READC: u32 = 0x07;
+const SYS_ISERROR: u32 = 0x08;
 const SYS_ISTTY: u32 = 0x09;
 const SYS_SEEK: u32 = 0x0a;
 const SYS_FLEN: u32 = 0x0c;
+const SYS_TMPNAM: u32 = 0x0d;
+const SYS_REMOVE: u32 = 0x0e;
+const SYS_RENAME: u32 = 0x0f;
 const SYS_CLOCK: u32 = 0x10;
+const SYS_TIME: u32 = 0x11;
+const SYS_SYSTEM: u32 = 0x12;
 const SYS_ERRNO: u32 = 0x13;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_HEAPINFO: u32 = 0x16;
 const SYS_EXIT: u32 = 0x18;
 const SYS_EXIT_EXTENDED: u32 = 0x20;
+const SYS_ELAPSED: u32 = 0x30;
+const SYS_TICKFREQ: u32 = 0x31;
+
+fn read_string(processor: &mut Processor, address: u32, len: u32) -> Result<String, Fault> {
+    let mut string_bytes: Vec<u8> = Vec::new();
+    for offset in 0..len {
+        string_bytes.push(processor.read8(address + offset)?);
+    }
+    Ok(String::from_utf8_lossy(&string_bytes).into_owned())
+}
 
 ///
 /// Decode semihosting command based on register values
@@ -261,19 +430,12 @@ pub fn decode_semihostcmd(
         SYS_OPEN => {
             let argument_block = r1;
 
-            let mut string_ptr = processor.read32(argument_block)?;
+            let string_ptr = processor.read32(argument_block)?;
             let mode = processor.read32(argument_block + 4)?;
-            let mut filename_len = processor.read32(argument_block + 8)?;
-            let mut string_bytes: Vec<u8> = Vec::new();
-
-            while filename_len > 0 {
-                string_bytes.push(processor.read8(string_ptr)?);
-                string_ptr += 1;
-                filename_len -= 1;
-            }
+            let filename_len = processor.read32(argument_block + 8)?;
 
             SemihostingCommand::SysOpen {
-                name: String::from_utf8(string_bytes).unwrap(),
+                name: read_string(processor, string_ptr, filename_len)?,
                 mode,
             }
         }
@@ -287,6 +449,26 @@ pub fn decode_semihostcmd(
             let ch = processor.read8(params_ptr)?;
             SemihostingCommand::SysWriteC { data: ch }
         }
+        SYS_WRITE0 => {
+            let mut ptr = r1;
+            let mut data: Vec<u8> = Vec::new();
+            loop {
+                let ch = processor.read8(ptr)?;
+                if ch == 0 {
+                    break;
+                }
+                data.push(ch);
+                ptr += 1;
+            }
+            SemihostingCommand::SysWrite0 { data }
+        }
+        SYS_READC => SemihostingCommand::SysReadC,
+        SYS_ISERROR => {
+            let params_ptr = r1;
+            let status = processor.read32(params_ptr)?;
+
+            SemihostingCommand::SysIserror { status }
+        }
         SYS_WRITE => {
             let params_ptr = r1;
             let handle = processor.read32(params_ptr)?;
@@ -334,8 +516,64 @@ pub fn decode_semihostcmd(
 
             SemihostingCommand::SysSeek { handle, position }
         }
+        SYS_TMPNAM => {
+            let params_ptr = r1;
+            let memoryptr = processor.read32(params_ptr)?;
+            let id = processor.read32(params_ptr + 4)?;
+            let len = processor.read32(params_ptr + 8)?;
+
+            SemihostingCommand::SysTmpnam { memoryptr, id, len }
+        }
+        SYS_REMOVE => {
+            let params_ptr = r1;
+            let string_ptr = processor.read32(params_ptr)?;
+            let len = processor.read32(params_ptr + 4)?;
+
+            SemihostingCommand::SysRemove {
+                name: read_string(processor, string_ptr, len)?,
+            }
+        }
+        SYS_RENAME => {
+            let params_ptr = r1;
+            let from_ptr = processor.read32(params_ptr)?;
+            let from_len = processor.read32(params_ptr + 4)?;
+            let to_ptr = processor.read32(params_ptr + 8)?;
+            let to_len = processor.read32(params_ptr + 12)?;
+
+            SemihostingCommand::SysRename {
+                from: read_string(processor, from_ptr, from_len)?,
+                to: read_string(processor, to_ptr, to_len)?,
+            }
+        }
         SYS_CLOCK => SemihostingCommand::SysClock,
+        SYS_TIME => SemihostingCommand::SysTime,
+        SYS_SYSTEM => {
+            let params_ptr = r1;
+            let string_ptr = processor.read32(params_ptr)?;
+            let len = processor.read32(params_ptr + 4)?;
+
+            SemihostingCommand::SysSystem {
+                command: read_string(processor, string_ptr, len)?,
+            }
+        }
         SYS_ERRNO => SemihostingCommand::SysErrno,
+        SYS_GET_CMDLINE => {
+            let params_ptr = r1;
+            let memoryptr = processor.read32(params_ptr)?;
+            let len = processor.read32(params_ptr + 4)?;
+
+            SemihostingCommand::SysGetCmdline { memoryptr, len }
+        }
+        SYS_HEAPINFO => {
+            let params_ptr = r1;
+            let memoryptr = processor.read32(params_ptr)?;
+
+            SemihostingCommand::SysHeapinfo { memoryptr }
+        }
+        SYS_ELAPSED => SemihostingCommand::SysElapsed {
+            cycles: processor.cycle_count,
+        },
+        SYS_TICKFREQ => SemihostingCommand::SysTickFreq,
         SYS_EXIT_EXTENDED => {
             let params_ptr = r1;
 
@@ -350,9 +588,7 @@ pub fn decode_semihostcmd(
         SYS_EXIT => SemihostingCommand::SysException {
             reason: SysExceptionReason::from_u32(r1),
         },
-        _ => {
-            todo!("unknown semihosting command {}", r0);
-        }
+        _ => SemihostingCommand::Unknown { operation: r0 },
     };
     Ok(result)
 }
@@ -392,7 +628,10 @@ pub fn semihost_return(processor: &mut Processor, response: &SemihostingResponse
                 }
             }
         }
-        SemihostingResponse::SysClose { success } | SemihostingResponse::SysSeek { success } => {
+        SemihostingResponse::SysClose { success }
+        | SemihostingResponse::SysSeek { success }
+        | SemihostingResponse::SysRemove { success }
+        | SemihostingResponse::SysRename { success } => {
             if success {
                 processor.set_r(Reg::R0, 0);
             } else {
@@ -414,12 +653,171 @@ pub fn semihost_return(processor: &mut Processor, response: &SemihostingResponse
             }
             Err(error_code) => processor.set_r(Reg::R0, *error_code as u32),
         },
+        SemihostingResponse::SysTmpnam { ref result } => match result {
+            Ok((memoryptr, name)) => {
+                for (addr, x) in (*memoryptr..).zip(name) {
+                    processor.write8(addr, *x);
+                }
+                processor.set_r(Reg::R0, 0);
+            }
+            Err(error_code) => processor.set_r(Reg::R0, *error_code as u32),
+        },
         SemihostingResponse::SysClock { result } => match result {
             Ok(centiseconds) => processor.set_r(Reg::R0, centiseconds),
             Err(error_code) => processor.set_r(Reg::R0, error_code as u32),
         },
-        SemihostingResponse::SysErrno { result } => {
+        SemihostingResponse::SysErrno { result } | SemihostingResponse::SysIserror { result } => {
             processor.set_r(Reg::R0, result);
         }
+        SemihostingResponse::SysReadC { result } => match result {
+            Ok(ch) => processor.set_r(Reg::R0, u32::from(ch)),
+            Err(error_code) => processor.set_r(Reg::R0, error_code as u32),
+        },
+        SemihostingResponse::SysTime { result }
+        | SemihostingResponse::SysTickFreq { result }
+        | SemihostingResponse::SysSystem { result } => match result {
+            Ok(value) => processor.set_r(Reg::R0, value),
+            Err(error_code) => processor.set_r(Reg::R0, error_code as u32),
+        },
+        SemihostingResponse::SysElapsed { result } => match result {
+            Ok(ticks) => {
+                // r1 still points to the two word result block
+                let params_ptr = processor.get_r(Reg::R1);
+                processor.write32(params_ptr, ticks as u32);
+                processor.write32(params_ptr + 4, (ticks >> 32) as u32);
+                processor.set_r(Reg::R0, 0);
+            }
+            Err(error_code) => processor.set_r(Reg::R0, error_code as u32),
+        },
+        SemihostingResponse::SysGetCmdline { ref result } => match result {
+            Ok((memoryptr, cmdline)) => {
+                for (addr, x) in (*memoryptr..).zip(cmdline) {
+                    processor.write8(addr, *x);
+                }
+                // the length in the parameter block excludes the terminating zero
+                let params_ptr = processor.get_r(Reg::R1);
+                processor.write32(params_ptr + 4, cmdline.len().saturating_sub(1) as u32);
+                processor.set_r(Reg::R0, 0);
+            }
+            Err(error_code) => processor.set_r(Reg::R0, *error_code as u32),
+        },
+        SemihostingResponse::SysHeapinfo {
+            memoryptr,
+            heap_info,
+        } => {
+            for (addr, value) in (memoryptr..).step_by(4).zip(heap_info) {
+                processor.write32(addr, value);
+            }
+        }
+        SemihostingResponse::Unknown => {
+            processor.set_r(Reg::R0, (-1_i32) as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::region::MemoryRegion;
+
+    #[test]
+    fn test_unknown_operation_returns_error() {
+        // Arrange
+        let mut processor = Processor::new();
+
+        // Act
+        let command = decode_semihostcmd(0x77, 0, &mut processor).unwrap();
+        semihost_return(&mut processor, &SemihostingResponse::Unknown);
+
+        // Assert
+        assert_eq!(command, SemihostingCommand::Unknown { operation: 0x77 });
+        assert_eq!(processor.get_r(Reg::R0), u32::MAX);
+    }
+
+    #[test]
+    fn test_elapsed_and_cmdline_results_are_written_to_the_parameter_block() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        processor.cycle_count = 0x1_0000_0002;
+        processor.write32(0x2000_0000, 0x2000_0080).unwrap();
+        processor.write32(0x2000_0004, 0x40).unwrap();
+        processor.set_r(Reg::R1, 0x2000_0000);
+
+        // Act
+        let elapsed = decode_semihostcmd(SYS_ELAPSED, 0x2000_0000, &mut processor).unwrap();
+        let cmdline = decode_semihostcmd(SYS_GET_CMDLINE, 0x2000_0000, &mut processor).unwrap();
+        semihost_return(
+            &mut processor,
+            &SemihostingResponse::SysGetCmdline {
+                result: Ok((0x2000_0080, b"fw a\0".to_vec())),
+            },
+        );
+        let length = processor.read32(0x2000_0004).unwrap();
+        semihost_return(
+            &mut processor,
+            &SemihostingResponse::SysElapsed {
+                result: Ok(0x1_0000_0002),
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            elapsed,
+            SemihostingCommand::SysElapsed {
+                cycles: 0x1_0000_0002
+            }
+        );
+        assert_eq!(
+            cmdline,
+            SemihostingCommand::SysGetCmdline {
+                memoryptr: 0x2000_0080,
+                len: 0x40
+            }
+        );
+        assert_eq!(processor.read32(0x2000_0080).unwrap(), 0x6120_7766);
+        assert_eq!(length, 4);
+        assert_eq!(processor.read32(0x2000_0000).unwrap(), 2);
+        assert_eq!(processor.read32(0x2000_0004).unwrap(), 1);
+        assert_eq!(processor.get_r(Reg::R0), 0);
+    }
+
+    #[test]
+    fn test_big_endian_parameter_block() {
+        // Arrange
+        let mut processor = Processor::new();
+        processor.memory_region(MemoryRegion::ram("ram", 0x2000_0000, 0x100));
+        processor.big_endian_data(true);
+        processor
+            .debug_write(
+                0x2000_0000,
+                &[0x20, 0x00, 0x00, 0x80, 0, 0, 0, 4, 0, 0, 0, 3],
+            )
+            .unwrap();
+        processor.debug_write(0x2000_0080, b"abc").unwrap();
+        processor.set_r(Reg::R1, 0x2000_0040);
+
+        // Act
+        let open = decode_semihostcmd(SYS_OPEN, 0x2000_0000, &mut processor).unwrap();
+        decode_semihostcmd(SYS_ELAPSED, 0x2000_0040, &mut processor).unwrap();
+        semihost_return(
+            &mut processor,
+            &SemihostingResponse::SysElapsed {
+                result: Ok(0x1_0000_0002),
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            open,
+            SemihostingCommand::SysOpen {
+                name: "abc".to_string(),
+                mode: 4
+            }
+        );
+        let elapsed: Vec<u8> = (0..8)
+            .map(|n| processor.read8(0x2000_0040 + n).unwrap())
+            .collect();
+        assert_eq!(elapsed, [0, 0, 0, 2, 0, 0, 0, 1]);
     }
 }
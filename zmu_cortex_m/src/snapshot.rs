@@ -0,0 +1,536 @@
+//!
+//! Saving and restoring the processor state
+//!
+//! A snapshot holds the core registers, the system peripherals (NVIC, SCB,
+//! `SysTick`, DWT, MPU), the contents of the RAM regions and the state of the
+//! attached device. ROM contents are not saved, a snapshot is restored on top
+//! of a processor configured with the same program and memory regions.
+//!
+
+use crate::Processor;
+use crate::ProcessorMode;
+use crate::core::monitor::ExclusiveAccess;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read};
+
+const MAGIC: &[u8; 8] = b"ZMUSNAP\0";
+
+/// Version of the snapshot file format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+///
+/// Reasons for failing to save or restore a snapshot
+///
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    /// Reading or writing the snapshot failed
+    #[error("i/o error")]
+    Io(#[from] io::Error),
+    /// The data is not a snapshot
+    #[error("not a snapshot file")]
+    BadMagic,
+    /// The snapshot was written by an unsupported version
+    #[error("unsupported snapshot version {0}")]
+    Version(u32),
+    /// The snapshot was taken with a different processor configuration
+    #[error("snapshot was taken with a different processor architecture or FPU")]
+    Configuration,
+    /// The snapshot does not match the memory regions or the device
+    #[error("snapshot does not match the simulated system: {0}")]
+    Mismatch(String),
+}
+
+///
+/// Serializer for the state of the processor and the devices
+///
+pub struct StateWriter<'a> {
+    out: &'a mut dyn io::Write,
+}
+
+impl<'a> StateWriter<'a> {
+    /// Write the state to `out`
+    pub fn new(out: &'a mut dyn io::Write) -> Self {
+        Self { out }
+    }
+
+    /// Write a byte
+    pub fn write_u8(&mut self, value: u8) -> io::Result<()> {
+        self.out.write_u8(value)
+    }
+
+    /// Write a 16 bit value
+    pub fn write_u16(&mut self, value: u16) -> io::Result<()> {
+        self.out.write_u16::<LittleEndian>(value)
+    }
+
+    /// Write a 32 bit value
+    pub fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.out.write_u32::<LittleEndian>(value)
+    }
+
+    /// Write a 64 bit value
+    pub fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.out.write_u64::<LittleEndian>(value)
+    }
+
+    /// Write a boolean
+    pub fn write_bool(&mut self, value: bool) -> io::Result<()> {
+        self.write_u8(u8::from(value))
+    }
+
+    /// Write a length prefixed block of bytes
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_u32(bytes.len() as u32)?;
+        self.out.write_all(bytes)
+    }
+}
+
+///
+/// Deserializer for the data written with `StateWriter`
+///
+pub struct StateReader<'a> {
+    input: &'a mut dyn io::Read,
+}
+
+impl<'a> StateReader<'a> {
+    /// Read the state from `input`
+    pub fn new(input: &'a mut dyn io::Read) -> Self {
+        Self { input }
+    }
+
+    /// Read a byte
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        self.input.read_u8()
+    }
+
+    /// Read a 16 bit value
+    pub fn read_u16(&mut self) -> io::Result<u16> {
+        self.input.read_u16::<LittleEndian>()
+    }
+
+    /// Read a 32 bit value
+    pub fn read_u32(&mut self) -> io::Result<u32> {
+        self.input.read_u32::<LittleEndian>()
+    }
+
+    /// Read a 64 bit value
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        self.input.read_u64::<LittleEndian>()
+    }
+
+    /// Read a boolean
+    pub fn read_bool(&mut self) -> io::Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    /// Read a length prefixed block of bytes
+    pub fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+
+    /// Read a block of bytes written with `write_bytes` into `buffer`
+    /// which must be of the same length
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        if self.read_u32()? as usize != buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected block length",
+            ));
+        }
+        self.input.read_exact(buffer)
+    }
+}
+
+/// Architecture and FPU options the snapshot depends on
+fn configuration() -> u32 {
+    [
+        cfg!(feature = "armv6m"),
+        cfg!(feature = "armv7m"),
+        cfg!(feature = "armv7em"),
+        cfg!(feature = "has-fp"),
+        cfg!(feature = "fpv4-sp-d16"),
+        cfg!(feature = "fpv5-sp-d16"),
+        cfg!(feature = "fpv5-d16"),
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |config, (bit, enabled)| {
+        config | u32::from(*enabled) << bit
+    })
+}
+
+impl Processor {
+    ///
+    /// Save the processor state, the RAM contents and the device state to `out`.
+    ///
+    pub fn save_snapshot(&self, out: &mut dyn io::Write) -> Result<(), SnapshotError> {
+        let mut w = StateWriter::new(out);
+        w.out.write_all(MAGIC)?;
+        w.write_u32(SNAPSHOT_VERSION)?;
+        w.write_u32(configuration())?;
+
+        self.save_core(&mut w)?;
+        self.save_exceptions(&mut w)?;
+        self.save_system_registers(&mut w)?;
+
+        let rams: Vec<_> = (0..self.memory.len())
+            .filter_map(|index| Some((self.memory.region(index), self.memory.ram(index)?)))
+            .collect();
+        w.write_u32(rams.len() as u32)?;
+        for (region, contents) in rams {
+            w.write_bytes(region.name.as_bytes())?;
+            w.write_u32(region.base)?;
+            w.write_bytes(contents)?;
+        }
+
+        w.write_bool(self.device.is_some())?;
+        if let Some(device) = &self.device {
+            let mut state = Vec::new();
+            device.save_state(&mut StateWriter::new(&mut state))?;
+            w.write_bytes(&state)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Restore the state saved with `save_snapshot`. The processor must be
+    /// configured with the same memory regions and device as when the
+    /// snapshot was taken. On error the processor is left partially restored.
+    ///
+    pub fn load_snapshot(&mut self, input: &mut dyn io::Read) -> Result<(), SnapshotError> {
+        let mut r = StateReader::new(input);
+        let mut magic = [0; 8];
+        r.input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.read_u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        if r.read_u32()? != configuration() {
+            return Err(SnapshotError::Configuration);
+        }
+
+        self.load_core(&mut r)?;
+        self.load_exceptions(&mut r)?;
+        self.load_system_registers(&mut r)?;
+        if self.big_endian() != self.memory.big_endian() {
+            return Err(SnapshotError::Mismatch("endianness".to_string()));
+        }
+
+        let rams: Vec<_> = (0..self.memory.len())
+            .filter(|index| self.memory.ram(*index).is_some())
+            .collect();
+        if r.read_u32()? as usize != rams.len() {
+            return Err(SnapshotError::Mismatch("number of RAM regions".to_string()));
+        }
+        for index in rams {
+            let name = r.read_bytes()?;
+            let base = r.read_u32()?;
+            let region = self.memory.region(index);
+            if name != region.name.as_bytes() || base != region.base {
+                return Err(SnapshotError::Mismatch(format!(
+                    "RAM region \"{}\" at 0x{:08x}",
+                    region.name, region.base
+                )));
+            }
+            let (name, size) = (region.name.clone(), region.size);
+            let contents = self.memory.ram_mut(index).unwrap();
+            r.read_bytes_into(contents)
+                .map_err(|_| SnapshotError::Mismatch(format!("size of RAM region \"{name}\"")))?;
+            self.invalidate_instructions(base, size as usize);
+        }
+
+        if r.read_bool()? != self.device.is_some() {
+            return Err(SnapshotError::Mismatch("attached device".to_string()));
+        }
+        if let Some(device) = &mut self.device {
+            let state = r.read_bytes()?;
+            let mut remaining = state.as_slice();
+            device
+                .load_state(&mut StateReader::new(&mut remaining))
+                .map_err(|e| SnapshotError::Mismatch(format!("device state: {e}")))?;
+            if !remaining.is_empty() {
+                return Err(SnapshotError::Mismatch("device state".to_string()));
+            }
+        }
+
+        self.running = true;
+        self.pending_fault_trap = None;
+        self.pending_fault_status = None;
+        Ok(())
+    }
+
+    fn save_core(&self, w: &mut StateWriter) -> io::Result<()> {
+        for value in self.r0_12.iter().chain(&self.fp_regs) {
+            w.write_u32(*value)?;
+        }
+        for value in [self.msp, self.psp, self.lr, self.pc, self.psr.value] {
+            w.write_u32(value)?;
+        }
+        w.write_bool(self.primask)?;
+        #[cfg(not(feature = "armv6m"))]
+        {
+            w.write_bool(self.faultmask)?;
+            w.write_u8(self.basepri)?;
+        }
+        w.write_bool(self.control.n_priv)?;
+        w.write_bool(self.control.sp_sel)?;
+        #[cfg(feature = "has-fp")]
+        w.write_bool(self.control.fpca)?;
+        w.write_bool(self.mode == ProcessorMode::HandlerMode)?;
+        w.write_u8(self.itstate)?;
+        w.write_bool(self.exclusive_monitor.is_some())?;
+        if let Some(tagged) = self.exclusive_monitor {
+            w.write_u32(tagged.address)?;
+            w.write_u32(tagged.size as u32)?;
+        }
+        w.write_u64(self.cycle_count)?;
+        w.write_u64(self.instruction_count)?;
+        w.write_bool(self.sleeping)?;
+        w.write_u32(self.exit_code)?;
+        w.write_u32(self.last_pc)
+    }
+
+    fn load_core(&mut self, r: &mut StateReader) -> io::Result<()> {
+        for value in self.r0_12.iter_mut().chain(&mut self.fp_regs) {
+            *value = r.read_u32()?;
+        }
+        for value in [
+            &mut self.msp,
+            &mut self.psp,
+            &mut self.lr,
+            &mut self.pc,
+            &mut self.psr.value,
+        ] {
+            *value = r.read_u32()?;
+        }
+        self.primask = r.read_bool()?;
+        #[cfg(not(feature = "armv6m"))]
+        {
+            self.faultmask = r.read_bool()?;
+            self.basepri = r.read_u8()?;
+        }
+        self.control.n_priv = r.read_bool()?;
+        self.control.sp_sel = r.read_bool()?;
+        #[cfg(feature = "has-fp")]
+        {
+            self.control.fpca = r.read_bool()?;
+        }
+        self.mode = if r.read_bool()? {
+            ProcessorMode::HandlerMode
+        } else {
+            ProcessorMode::ThreadMode
+        };
+        self.itstate = r.read_u8()?;
+        self.exclusive_monitor = if r.read_bool()? {
+            Some(ExclusiveAccess {
+                address: r.read_u32()?,
+                size: r.read_u32()? as usize,
+            })
+        } else {
+            None
+        };
+        self.cycle_count = r.read_u64()?;
+        self.instruction_count = r.read_u64()?;
+        self.sleeping = r.read_bool()?;
+        self.exit_code = r.read_u32()?;
+        self.last_pc = r.read_u32()?;
+        Ok(())
+    }
+
+    fn save_exceptions(&self, w: &mut StateWriter) -> io::Result<()> {
+        w.write_u32(self.pending_exception_count)?;
+        w.write_u16(self.execution_priority as u16)?;
+        let mut numbers: Vec<_> = self.exceptions.keys().copied().collect();
+        numbers.sort_unstable();
+        w.write_u32(numbers.len() as u32)?;
+        for number in numbers {
+            let state = &self.exceptions[&number];
+            w.write_u32(number as u32)?;
+            w.write_u16(state.priority as u16)?;
+            w.write_bool(state.pending)?;
+            w.write_bool(state.active)?;
+        }
+        Ok(())
+    }
+
+    fn load_exceptions(&mut self, r: &mut StateReader) -> Result<(), SnapshotError> {
+        self.pending_exception_count = r.read_u32()?;
+        self.execution_priority = r.read_u16()? as i16;
+        for _ in 0..r.read_u32()? {
+            let number = r.read_u32()? as usize;
+            let state = self.exceptions.get_mut(&number).ok_or_else(|| {
+                SnapshotError::Mismatch(format!("unknown exception number {number}"))
+            })?;
+            state.priority = r.read_u16()? as i16;
+            state.pending = r.read_bool()?;
+            state.active = r.read_bool()?;
+        }
+        Ok(())
+    }
+
+    fn save_system_registers(&self, w: &mut StateWriter) -> io::Result<()> {
+        for value in [
+            self.cpuid,
+            self.icsr,
+            self.vtor,
+            self.aircr,
+            self.scr,
+            self.ccr,
+            self.shcsr,
+            self.cfsr,
+            self.hfsr,
+            self.dfsr,
+            self.demcr,
+            self.mmfar,
+            self.bfar,
+            self.afsr,
+            #[cfg(feature = "has-fp")]
+            self.cpacr,
+            #[cfg(feature = "has-fp")]
+            self.fpccr,
+            #[cfg(feature = "has-fp")]
+            self.fpcar,
+            #[cfg(feature = "has-fp")]
+            self.fpdscr,
+            self.fpscr,
+            self.ictr,
+            self.actlr,
+            self.dwt_ctrl,
+            self.dwt_cyccnt,
+            self.syst_rvr,
+            self.syst_cvr,
+            self.syst_csr,
+            self.mpu_ctrl,
+            self.mpu_rnr,
+        ]
+        .iter()
+        .chain(&self.nvic_interrupt_enabled)
+        .chain(&self.nvic_interrupt_pending)
+        .chain(&self.nvic_interrupt_asserted)
+        .chain(&self.mpu_rbar)
+        .chain(&self.mpu_rasr)
+        {
+            w.write_u32(*value)?;
+        }
+        Ok(())
+    }
+
+    fn load_system_registers(&mut self, r: &mut StateReader) -> io::Result<()> {
+        for value in [
+            &mut self.cpuid,
+            &mut self.icsr,
+            &mut self.vtor,
+            &mut self.aircr,
+            &mut self.scr,
+            &mut self.ccr,
+            &mut self.shcsr,
+            &mut self.cfsr,
+            &mut self.hfsr,
+            &mut self.dfsr,
+            &mut self.demcr,
+            &mut self.mmfar,
+            &mut self.bfar,
+            &mut self.afsr,
+            #[cfg(feature = "has-fp")]
+            &mut self.cpacr,
+            #[cfg(feature = "has-fp")]
+            &mut self.fpccr,
+            #[cfg(feature = "has-fp")]
+            &mut self.fpcar,
+            #[cfg(feature = "has-fp")]
+            &mut self.fpdscr,
+            &mut self.fpscr,
+            &mut self.ictr,
+            &mut self.actlr,
+            &mut self.dwt_ctrl,
+            &mut self.dwt_cyccnt,
+            &mut self.syst_rvr,
+            &mut self.syst_cvr,
+            &mut self.syst_csr,
+            &mut self.mpu_ctrl,
+            &mut self.mpu_rnr,
+        ]
+        .into_iter()
+        .chain(&mut self.nvic_interrupt_enabled)
+        .chain(&mut self.nvic_interrupt_pending)
+        .chain(&mut self.nvic_interrupt_asserted)
+        .chain(&mut self.mpu_rbar)
+        .chain(&mut self.mpu_rasr)
+        {
+            *value = r.read_u32()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::core::exception::{Exception, ExceptionHandling};
+    use crate::core::register::{BaseReg, Reg};
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        // Arrange
+        let mut core = Processor::new();
+        core.set_r(Reg::R3, 0x1234_5678);
+        core.set_pc(0x100);
+        core.cycle_count = 42;
+        core.syst_rvr = 0xfff;
+        core.write32(0x2000_0010, 0xdead_beef).unwrap();
+        core.set_exception_pending(Exception::SysTick);
+        let mut snapshot = Vec::new();
+        core.save_snapshot(&mut snapshot).unwrap();
+
+        // Act
+        let mut restored = Processor::new();
+        restored.load_snapshot(&mut snapshot.as_slice()).unwrap();
+
+        // Assert
+        assert_eq!(restored.get_r(Reg::R3), 0x1234_5678);
+        assert_eq!(restored.get_pc(), 0x100);
+        assert_eq!(restored.cycle_count, 42);
+        assert_eq!(restored.syst_rvr, 0xfff);
+        assert_eq!(restored.read32(0x2000_0010).unwrap(), 0xdead_beef);
+        assert!(restored.exception_pending(Exception::SysTick));
+        assert_eq!(restored.pending_exception_count, 1);
+    }
+
+    #[test]
+    fn test_snapshot_rejects_different_ram() {
+        // Arrange
+        let core = Processor::new();
+        let mut snapshot = Vec::new();
+        core.save_snapshot(&mut snapshot).unwrap();
+        let mut other = Processor::new();
+        other.ram_memory(0x2000_0000, 1024);
+
+        // Act
+        let result = other.load_snapshot(&mut snapshot.as_slice());
+
+        // Assert
+        assert!(matches!(result, Err(SnapshotError::Mismatch(_))));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_garbage() {
+        // Arrange
+        let mut core = Processor::new();
+
+        // Act
+        let result = core.load_snapshot(&mut b"not a snapshot".as_slice());
+
+        // Assert
+        assert!(matches!(result, Err(SnapshotError::BadMagic)));
+    }
+}
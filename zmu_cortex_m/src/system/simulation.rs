@@ -3,15 +3,20 @@
 //!
 
 use crate::DeviceBus;
-use crate::MemoryMapConfig;
 use crate::Processor;
 use crate::core::fault::{Fault, FaultContext, FaultTrapMode, FaultTrapReason};
+use crate::core::memory_trace::MemoryTraceFunc;
 use crate::core::register::{BaseReg, Ipsr, Reg};
 use crate::core::reset::Reset;
+use crate::core::trace_event::EventTraceFunc;
 use crate::executor::Executor;
+use crate::memory::region::MemoryRegion;
 use crate::semihosting::SemihostingCommand;
 use crate::semihosting::SemihostingResponse;
+use crate::snapshot::SnapshotError;
+use std::fs::File;
 use std::io;
+use std::path::PathBuf;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -28,6 +33,88 @@ pub enum SimulationError {
         /// Detailed trap context.
         context: FaultContext,
     },
+
+    ///
+    /// Saving or restoring a snapshot failed
+    ///
+    #[error("snapshot {}", path.display())]
+    Snapshot {
+        /// Snapshot file
+        path: PathBuf,
+        /// Reason for the failure
+        source: SnapshotError,
+    },
+}
+
+///
+/// Point of execution where a snapshot is saved
+///
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum SnapshotPoint {
+    /// Before executing the instruction when this many instructions have run
+    InstructionCount(u64),
+    /// Before executing the instruction at the address
+    Address(u32),
+}
+
+///
+/// Snapshot handling of a simulation run
+///
+#[derive(Default, Debug, Clone)]
+pub struct SnapshotOptions {
+    /// Resume from the snapshot instead of resetting the processor
+    pub load: Option<PathBuf>,
+    /// Save a snapshot at the point to the file and stop the simulation
+    pub save: Option<(SnapshotPoint, PathBuf)>,
+}
+
+impl SnapshotOptions {
+    fn start(&self, processor: &mut Processor) -> Result<(), SimulationError> {
+        match &self.load {
+            Some(path) => File::open(path)
+                .map_err(SnapshotError::from)
+                .and_then(|file| processor.load_snapshot(&mut io::BufReader::new(file)))
+                .map_err(|source| SimulationError::Snapshot {
+                    path: path.clone(),
+                    source,
+                }),
+            None => processor
+                .reset()
+                .map_err(|fault| SimulationError::from_fault(processor, fault)),
+        }
+    }
+
+    ///
+    /// Save the snapshot if the processor is at the save point.
+    /// Returns true if the snapshot was saved.
+    ///
+    #[inline(always)]
+    fn save_at(&self, processor: &mut Processor) -> Result<bool, SimulationError> {
+        let Some((point, path)) = &self.save else {
+            return Ok(false);
+        };
+        let reached = match *point {
+            SnapshotPoint::InstructionCount(count) => processor.instruction_count == count,
+            SnapshotPoint::Address(address) => processor.get_pc() == address & !1,
+        };
+        if !reached {
+            return Ok(false);
+        }
+        File::create(path)
+            .map_err(SnapshotError::from)
+            .and_then(|file| {
+                let mut out = io::BufWriter::new(file);
+                processor.save_snapshot(&mut out)?;
+                io::Write::flush(&mut out)?;
+                Ok(())
+            })
+            .map_err(|source| SimulationError::Snapshot {
+                path: path.clone(),
+                source,
+            })?;
+        processor.running = false;
+        Ok(true)
+    }
 }
 
 ///
@@ -54,6 +141,11 @@ pub struct SimulationStatistics {
     /// exit code from process, if any
     ///
     pub exit_code: u32,
+
+    ///
+    /// true if the simulation stopped after saving a snapshot
+    ///
+    pub snapshot_saved: bool,
 }
 
 impl SimulationError {
@@ -79,36 +171,48 @@ impl SimulationError {
 /// Run simulation until processing gets terminated
 ///
 pub fn simulate(
-    code: &[u8],
+    memory: Vec<MemoryRegion>,
     device: Option<DeviceBus>,
     semihost_func: Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>,
     itm_file: Option<Box<dyn io::Write + 'static>>,
-    map: Option<MemoryMapConfig>,
-    flash_size: usize,
+    memory_trace: Option<MemoryTraceFunc>,
     fault_trap_mode: FaultTrapMode,
+    big_endian: bool,
+    snapshot: &SnapshotOptions,
 ) -> Result<SimulationStatistics, SimulationError> {
     let mut processor = Processor::new();
 
     processor.device(device);
     processor.itm(itm_file);
+    processor.memory_trace(memory_trace);
     processor.semihost(Some(semihost_func));
-    processor.memory_map(map);
     processor.fault_trap_mode(fault_trap_mode);
-    processor.flash_memory(flash_size, code);
-    //processor.ram_memory(ram_size);
+    processor.big_endian_data(big_endian);
+    for region in memory {
+        processor.memory_region(region);
+    }
 
     processor.cache_instructions();
 
     let start = Instant::now();
-    processor
-        .reset()
-        .map_err(|fault| SimulationError::from_fault(&processor, fault))?;
+    snapshot.start(&mut processor)?;
     processor.running = true;
+    let mut snapshot_saved = false;
+    // the save point is checked before each instruction
+    let single_step = snapshot.save.is_some();
 
     while processor.running {
         while !processor.sleeping && processor.running {
             //running, !sleeping
-            processor.step();
+            if snapshot.save_at(&mut processor)? {
+                snapshot_saved = true;
+                break;
+            }
+            if single_step {
+                processor.step();
+            } else {
+                processor.step_block();
+            }
             if let Some(context) = processor.take_pending_fault_trap() {
                 return Err(SimulationError::FaultTrap { context });
             }
@@ -116,7 +220,7 @@ pub fn simulate(
 
         while processor.sleeping && processor.running {
             //running, sleeping
-            processor.step_sleep();
+            processor.step_sleep_until_event();
             if let Some(context) = processor.take_pending_fault_trap() {
                 return Err(SimulationError::FaultTrap { context });
             }
@@ -129,6 +233,7 @@ pub fn simulate(
         cycle_count: processor.cycle_count,
         duration: end.duration_since(start),
         exit_code: processor.exit_code,
+        snapshot_saved,
     })
 }
 
@@ -136,14 +241,16 @@ pub fn simulate(
 /// Run System simulation with tracing support
 ///
 pub fn simulate_trace<F>(
-    code: &[u8],
+    memory: Vec<MemoryRegion>,
     device: Option<DeviceBus>,
     mut trace_func: F,
+    event_trace: Option<EventTraceFunc>,
     semihost_func: Box<dyn FnMut(&SemihostingCommand) -> SemihostingResponse + 'static>,
     itm_file: Option<Box<dyn io::Write + 'static>>,
-    map: Option<MemoryMapConfig>,
-    flash_size: usize,
+    memory_trace: Option<MemoryTraceFunc>,
     fault_trap_mode: FaultTrapMode,
+    big_endian: bool,
+    snapshot: &SnapshotOptions,
 ) -> Result<SimulationStatistics, SimulationError>
 where
     F: FnMut(&Processor),
@@ -151,22 +258,28 @@ where
     let mut processor = Processor::new();
     processor.device(device);
     processor.itm(itm_file);
+    processor.memory_trace(memory_trace);
+    processor.event_trace(event_trace);
     processor.semihost(Some(semihost_func));
-    processor.memory_map(map);
     processor.fault_trap_mode(fault_trap_mode);
-    processor.flash_memory(flash_size, code);
+    processor.big_endian_data(big_endian);
+    for region in memory {
+        processor.memory_region(region);
+    }
     processor.cache_instructions();
 
     let start = Instant::now();
-
-    processor
-        .reset()
-        .map_err(|fault| SimulationError::from_fault(&processor, fault))?;
+    snapshot.start(&mut processor)?;
     processor.running = true;
+    let mut snapshot_saved = false;
 
     while processor.running {
         while !processor.sleeping && processor.running {
             //running, !sleeping
+            if snapshot.save_at(&mut processor)? {
+                snapshot_saved = true;
+                break;
+            }
             processor.last_pc = processor.get_pc();
             processor.step();
             trace_func(&processor);
@@ -177,7 +290,7 @@ where
         processor.last_pc = processor.get_pc();
         while processor.sleeping && processor.running {
             //running, sleeping
-            processor.step_sleep();
+            processor.step_sleep_until_event();
             if let Some(context) = processor.take_pending_fault_trap() {
                 return Err(SimulationError::FaultTrap { context });
             }
@@ -191,5 +304,6 @@ where
         cycle_count: processor.cycle_count,
         duration: end.duration_since(start),
         exit_code: processor.exit_code,
+        snapshot_saved,
     })
 }